@@ -0,0 +1,393 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+#![deny(
+    clippy::pedantic,
+    //clippy::cargo,
+    clippy::nursery,
+    clippy::style,
+    clippy::correctness,
+    clippy::all,
+    clippy::unwrap_used,
+    clippy::expect_used
+)]
+// #![warn(missing_docs)]
+
+// Sensitivity benchmark for the ACARS decoder. Synthetic messages are sent through the decoder
+// over a sweep of SNR, frequency offset, parity errors and block length, and the decode and false positive
+// rates of each point are written out as CSV. The same seed gives the same CSV, so a run before
+// and after a change to the demodulator or error correction shows what the change did.
+// baseline.csv is the default sweep from the current decoder; passing it as the baseline fails
+// the run if a change makes any point decode worse or let through a single false positive.
+
+#[macro_use]
+extern crate log;
+
+use custom_error::custom_error;
+use num::Complex;
+use oxide_config::bench::OxideBenchInput;
+use oxide_config::clap::Parser;
+use oxide_decoders::decoders::acars::{ACARSDecoder, AssembledACARSMessage, INTRATE_F32};
+use oxide_decoders::decoders::modulator::{
+    decode_samples, downlink_block, AcarsBlock, AcarsModulator,
+};
+use oxide_decoders::Decoder;
+use sdre_rust_logging::SetupLogging;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::process::ExitCode;
+use tokio::sync::mpsc;
+
+custom_error! {BenchError
+    Io { source: io::Error } = "{source}",
+    Baseline { line: usize } = "Line {line} of the baseline is not a row of benchmark results",
+    Regression { points: String } = "Decode rate below the baseline, or false positives, at {points}",
+}
+
+const CSV_HEADER: &str = "snr_db,frequency_offset_hz,parity_errors,block_length,trials,decoded,decode_rate,false_positives,false_positive_rate,corrected";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Point {
+    snr: f64,
+    frequency_offset: f64,
+    parity_errors: usize,
+    block_length: usize,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct PointResult {
+    trials: u32,
+    /// Trials the message was decoded intact
+    decoded: u32,
+    /// Messages passed as valid that are not what was sent
+    false_positives: u32,
+    /// Intact messages that needed error correction
+    corrected: u32,
+}
+
+/// A point of an earlier run, to compare against
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BaselineRow {
+    point: Point,
+    trials: u32,
+    decoded: u32,
+}
+
+impl PointResult {
+    fn csv_row(&self, point: &Point) -> String {
+        let rate = |count: u32| f64::from(count) / f64::from(self.trials);
+        format!(
+            "{},{},{},{},{},{},{:.4},{},{:.4},{}",
+            point.snr,
+            point.frequency_offset,
+            point.parity_errors,
+            point.block_length,
+            self.trials,
+            self.decoded,
+            rate(self.decoded),
+            self.false_positives,
+            rate(self.false_positives),
+            self.corrected
+        )
+    }
+}
+
+fn parse_baseline(csv: &str) -> Result<Vec<BaselineRow>, BenchError> {
+    csv.lines()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let row = || {
+                Some(BaselineRow {
+                    point: Point {
+                        snr: fields.first()?.parse().ok()?,
+                        frequency_offset: fields.get(1)?.parse().ok()?,
+                        parity_errors: fields.get(2)?.parse().ok()?,
+                        block_length: fields.get(3)?.parse().ok()?,
+                    },
+                    trials: fields.get(4)?.parse().ok().filter(|trials| *trials > 0)?,
+                    decoded: fields.get(5)?.parse().ok()?,
+                })
+            };
+
+            row().ok_or(BenchError::Baseline { line: index + 1 })
+        })
+        .collect()
+}
+
+/// Describe the point if it decoded at a lower rate than the baseline did, or passed any wrong
+/// message as valid
+fn regression(baseline: &BaselineRow, result: &PointResult) -> Option<String> {
+    let rate = u64::from(result.decoded) * u64::from(baseline.trials);
+    let baseline_rate = u64::from(baseline.decoded) * u64::from(result.trials);
+
+    (rate < baseline_rate || result.false_positives > 0).then(|| {
+        format!(
+            "{} dB, {} Hz, {} parity errors, {} characters ({}/{} decoded, baseline {}/{}, {} false positives)",
+            baseline.point.snr,
+            baseline.point.frequency_offset,
+            baseline.point.parity_errors,
+            baseline.point.block_length,
+            result.decoded,
+            result.trials,
+            baseline.decoded,
+            baseline.trials,
+            result.false_positives
+        )
+    })
+}
+
+/// Flip one data bit in `count` characters of the body, spread over the body and moved along
+/// by `trial` so each trial damages different characters.
+fn inject_parity_errors(block: &AcarsBlock, count: usize, trial: u32) -> Vec<u8> {
+    let mut bytes = block.to_bytes();
+    let body_len = block.body().len();
+    // The body is followed by the two CRC characters and DEL
+    let body_start = bytes.len() - body_len - 3;
+    let trial = trial as usize;
+
+    for error in 0..count.min(body_len) {
+        let index = body_start + (error * body_len / count + trial) % body_len;
+        bytes[index] ^= 1 << (trial % 7);
+    }
+
+    bytes
+}
+
+fn is_sent_message(block: &AcarsBlock, message: &AssembledACARSMessage) -> bool {
+    let tail: String = message
+        .aircraft_tail
+        .iter()
+        .flatten()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    let text: String = message
+        .message_number
+        .iter()
+        .flatten()
+        .chain(message.flight_id.iter().flatten())
+        .chain(message.message_text.iter().flatten())
+        .collect();
+
+    message.mode == block.mode
+        && tail == block.tail
+        && message.label == block.label
+        && message.block_id == block.block_id
+        && text == block.text
+}
+
+fn run_point(point: &Point, trials: u32, seed: u64) -> PointResult {
+    // A downlink, so the whole text is checked: message number, flight id and free text
+    let block = downlink_block(['H', '1'], point.block_length);
+    let mut result = PointResult {
+        trials,
+        ..PointResult::default()
+    };
+
+    for trial in 0..trials {
+        let mut modulator = AcarsModulator::new(f64::from(INTRATE_F32), 0.0)
+            .snr(Some(point.snr))
+            .frequency_offset(point.frequency_offset)
+            .seed(seed.wrapping_add(u64::from(trial)));
+        let samples =
+            modulator.modulate_bytes(&inject_parity_errors(&block, point.parity_errors, trial));
+
+        let mut decoder = ACARSDecoder::new(0, 131_550_000, [Complex::new(0.0, 0.0); 192]);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        decoder.set_output_channel(tx);
+        decode_samples(&mut decoder, &samples);
+
+        while let Ok(message) = rx.try_recv() {
+            if is_sent_message(&block, &message) {
+                result.decoded += 1;
+                if message.corrected_bits > 0 {
+                    result.corrected += 1;
+                }
+            } else {
+                result.false_positives += 1;
+            }
+        }
+    }
+
+    result
+}
+
+fn bench(args: &OxideBenchInput) -> Result<(), BenchError> {
+    let mut output: Box<dyn Write> = match &args.output_file {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+
+    let baseline = match &args.baseline {
+        Some(path) => parse_baseline(&fs::read_to_string(path)?)?,
+        None => vec![],
+    };
+    let mut regressions = vec![];
+
+    writeln!(output, "{CSV_HEADER}")?;
+
+    for block_length in &args.block_length {
+        for parity_errors in &args.parity_errors {
+            for frequency_offset in &args.frequency_offset {
+                for snr in &args.snr {
+                    let point = Point {
+                        snr: *snr,
+                        frequency_offset: *frequency_offset,
+                        parity_errors: *parity_errors,
+                        block_length: *block_length,
+                    };
+                    let result = run_point(&point, args.trials, args.seed);
+                    debug!("{point:?}: {result:?}");
+                    writeln!(output, "{}", result.csv_row(&point))?;
+
+                    regressions.extend(
+                        baseline
+                            .iter()
+                            .filter(|row| row.point == point)
+                            .filter_map(|row| regression(row, &result)),
+                    );
+                }
+            }
+        }
+    }
+
+    output.flush()?;
+
+    if regressions.is_empty() {
+        Ok(())
+    } else {
+        Err(BenchError::Regression {
+            points: regressions.join(", "),
+        })
+    }
+}
+
+fn main() -> ExitCode {
+    let args = OxideBenchInput::parse();
+    args.logging.enable_logging();
+
+    match bench(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("Benchmark failed: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inject_parity_errors() {
+        let block = downlink_block(['H', '1'], 47);
+        let clean = block.to_bytes();
+        assert_eq!(inject_parity_errors(&block, 0, 3), clean);
+
+        let damaged = inject_parity_errors(&block, 2, 3);
+        let bad_parity = damaged
+            .iter()
+            .zip(&clean)
+            .filter(|(damaged, clean)| damaged != clean)
+            .inspect(|(damaged, _)| assert_eq!(damaged.count_ones() % 2, 0))
+            .count();
+        assert_eq!(bad_parity, 2);
+    }
+
+    #[test]
+    fn test_run_point() {
+        // The longest block, so a timing error anywhere in the block shows
+        let clean = Point {
+            snr: 30.0,
+            frequency_offset: 0.0,
+            parity_errors: 0,
+            block_length: 220,
+        };
+        let result = run_point(&clean, 3, 1);
+        assert_eq!(result.decoded, 3);
+        assert_eq!(result.false_positives, 0);
+        assert_eq!(result.corrected, 0);
+        assert_eq!(result.csv_row(&clean), "30,0,0,220,3,3,1.0000,0,0.0000,0");
+
+        // A parity error is fixed by error correction
+        let damaged = Point {
+            parity_errors: 1,
+            ..clean
+        };
+        let result = run_point(&damaged, 3, 1);
+        assert_eq!(result.decoded, 3);
+        assert_eq!(result.corrected, 3);
+    }
+
+    #[test]
+    fn test_baseline() {
+        // the checked in baseline is the default sweep with the default seed, so running its
+        // points again must decode at least as many messages
+        let baseline = match parse_baseline(include_str!("../baseline.csv")) {
+            Ok(baseline) => baseline,
+            Err(e) => panic!("{e}"),
+        };
+        assert_eq!(baseline.len(), 72);
+
+        let regressions: Vec<String> = baseline
+            .iter()
+            .filter_map(|row| regression(row, &run_point(&row.point, row.trials, 1)))
+            .collect();
+        assert!(regressions.is_empty(), "{}", regressions.join(", "));
+
+        let worse = PointResult {
+            trials: 3,
+            decoded: 2,
+            ..PointResult::default()
+        };
+        let row = BaselineRow {
+            point: Point {
+                snr: 8.0,
+                frequency_offset: 0.0,
+                parity_errors: 0,
+                block_length: 47,
+            },
+            trials: 100,
+            decoded: 67,
+        };
+        assert_eq!(
+            regression(&row, &worse).as_deref(),
+            Some("8 dB, 0 Hz, 0 parity errors, 47 characters (2/3 decoded, baseline 67/100, 0 false positives)")
+        );
+        assert_eq!(
+            regression(&BaselineRow { decoded: 66, ..row }, &worse),
+            None
+        );
+        // A wrong message passed as valid fails the point however well it decodes
+        let wrong = PointResult {
+            decoded: 3,
+            false_positives: 1,
+            ..worse
+        };
+        assert_eq!(
+            regression(&row, &wrong).as_deref(),
+            Some("8 dB, 0 Hz, 0 parity errors, 47 characters (3/3 decoded, baseline 67/100, 1 false positives)")
+        );
+
+        assert!(matches!(
+            parse_baseline(&format!("{CSV_HEADER}\n8,0,0,47,100,91\n8,0,x,47,100,91")),
+            Err(BenchError::Baseline { line: 3 })
+        ));
+    }
+}
@@ -33,21 +33,47 @@ extern crate oxide_rtlsdr;
 use oxide_config::clap::Parser;
 use oxide_config::OxideInput;
 use oxide_decoders::ValidDecoderType;
+use oxide_metrics::Metrics;
+use oxide_output::mqtt::{MqttConfig, MqttSink};
+use oxide_output::rules::RuleSet;
+use oxide_output::websocket::WebSocketSink;
 use oxide_rtlsdr::RtlSdr;
 use sdre_rust_logging::SetupLogging;
-use tokio::time::{sleep, Duration};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::Duration;
+
+/// How long to wait for the MQTT broker to take the offline status on shutdown
+const MQTT_DISCONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Wait for ctrl-c, or for SIGTERM, as sent by `docker stop` and service managers
+#[cfg(unix)]
+async fn shutdown_requested() -> std::io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
+/// Wait for ctrl-c
+#[cfg(not(unix))]
+async fn shutdown_requested() -> std::io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
 
 #[tokio::main]
+#[allow(clippy::too_many_lines)]
 async fn main() {
     let args: OxideInput = OxideInput::parse();
     args.logging.enable_logging();
     // print current version
     info!("ACARS Oxide v{}", env!("CARGO_PKG_VERSION"));
 
-    debug!(
-        "Starting ACARS Oxide with the following options: {:#?}",
-        args
-    );
+    debug!("Starting ACARS Oxide with the following options: {args:#?}");
 
     // Create a vector of all configured RTLSDR devices
 
@@ -56,13 +82,15 @@ async fn main() {
     match args.sdr1serial {
         Some(serial) => {
             let ppm = args.sdr1ppm.unwrap_or(0);
+            let auto_ppm = args.sdr1autoppm.unwrap_or(false);
             let gain = args.sdr1gain.unwrap_or(0);
+            let auto_gain = args.sdr1autogain.unwrap_or(false);
             let bias_tee = args.sdr1biastee.unwrap_or(false);
             let rtl_mult = args.sdr1mult.unwrap_or(160);
             let frequencies = args.sdr1freqs.unwrap_or_default();
             let decoder_type = args.sdr1decoding_type.unwrap_or(ValidDecoderType::ACARS);
 
-            let sdr = RtlSdr::new(
+            let mut sdr = RtlSdr::new(
                 serial,
                 ppm,
                 gain,
@@ -72,23 +100,29 @@ async fn main() {
                 decoder_type,
             );
 
+            sdr.set_auto_ppm(auto_ppm);
+            sdr.set_auto_gain(auto_gain, args.state_dir.clone());
+            sdr.set_emit_errors(args.output_errors);
+
             rtlsdr.push(sdr);
         }
         None => {
-            trace!("No SDR1 configured")
+            trace!("No SDR1 configured");
         }
     }
 
     match args.sdr2serial {
         Some(serial) => {
             let ppm = args.sdr2ppm.unwrap_or(0);
+            let auto_ppm = args.sdr2autoppm.unwrap_or(false);
             let gain = args.sdr2gain.unwrap_or(0);
+            let auto_gain = args.sdr2autogain.unwrap_or(false);
             let bias_tee = args.sdr2biastee.unwrap_or(false);
             let rtl_mult = args.sdr2mult.unwrap_or(160);
             let frequencies = args.sdr2freqs.unwrap_or_default();
             let decoder_type = args.sdr2decoding_type.unwrap_or(ValidDecoderType::ACARS);
 
-            let sdr = RtlSdr::new(
+            let mut sdr = RtlSdr::new(
                 serial,
                 ppm,
                 gain,
@@ -98,23 +132,29 @@ async fn main() {
                 decoder_type,
             );
 
+            sdr.set_auto_ppm(auto_ppm);
+            sdr.set_auto_gain(auto_gain, args.state_dir.clone());
+            sdr.set_emit_errors(args.output_errors);
+
             rtlsdr.push(sdr);
         }
         None => {
-            trace!("No SDR2 configured")
+            trace!("No SDR2 configured");
         }
     }
 
     match args.sdr3serial {
         Some(serial) => {
             let ppm = args.sdr3ppm.unwrap_or(0);
+            let auto_ppm = args.sdr3autoppm.unwrap_or(false);
             let gain = args.sdr3gain.unwrap_or(0);
+            let auto_gain = args.sdr3autogain.unwrap_or(false);
             let bias_tee = args.sdr3biastee.unwrap_or(false);
             let rtl_mult = args.sdr3mult.unwrap_or(160);
             let frequencies = args.sdr3freqs.unwrap_or_default();
             let decoder_type = args.sdr3decoding_type.unwrap_or(ValidDecoderType::ACARS);
 
-            let sdr = RtlSdr::new(
+            let mut sdr = RtlSdr::new(
                 serial,
                 ppm,
                 gain,
@@ -124,23 +164,29 @@ async fn main() {
                 decoder_type,
             );
 
+            sdr.set_auto_ppm(auto_ppm);
+            sdr.set_auto_gain(auto_gain, args.state_dir.clone());
+            sdr.set_emit_errors(args.output_errors);
+
             rtlsdr.push(sdr);
         }
         None => {
-            trace!("No SDR3 configured")
+            trace!("No SDR3 configured");
         }
     }
 
     match args.sdr4serial {
         Some(serial) => {
             let ppm = args.sdr4ppm.unwrap_or(0);
+            let auto_ppm = args.sdr4autoppm.unwrap_or(false);
             let gain = args.sdr4gain.unwrap_or(0);
+            let auto_gain = args.sdr4autogain.unwrap_or(false);
             let bias_tee = args.sdr4biastee.unwrap_or(false);
             let rtl_mult = args.sdr4mult.unwrap_or(160);
             let frequencies = args.sdr4freqs.unwrap_or_default();
             let decoder_type = args.sdr4decoding_type.unwrap_or(ValidDecoderType::ACARS);
 
-            let sdr = RtlSdr::new(
+            let mut sdr = RtlSdr::new(
                 serial,
                 ppm,
                 gain,
@@ -150,23 +196,29 @@ async fn main() {
                 decoder_type,
             );
 
+            sdr.set_auto_ppm(auto_ppm);
+            sdr.set_auto_gain(auto_gain, args.state_dir.clone());
+            sdr.set_emit_errors(args.output_errors);
+
             rtlsdr.push(sdr);
         }
         None => {
-            trace!("No SDR4 configured")
+            trace!("No SDR4 configured");
         }
     }
 
     match args.sdr5serial {
         Some(serial) => {
             let ppm = args.sdr5ppm.unwrap_or(0);
+            let auto_ppm = args.sdr5autoppm.unwrap_or(false);
             let gain = args.sdr5gain.unwrap_or(0);
+            let auto_gain = args.sdr5autogain.unwrap_or(false);
             let bias_tee = args.sdr5biastee.unwrap_or(false);
             let rtl_mult = args.sdr5mult.unwrap_or(160);
             let frequencies = args.sdr5freqs.unwrap_or_default();
             let decoder_type = args.sdr5decoding_type.unwrap_or(ValidDecoderType::ACARS);
 
-            let sdr = RtlSdr::new(
+            let mut sdr = RtlSdr::new(
                 serial,
                 ppm,
                 gain,
@@ -176,23 +228,29 @@ async fn main() {
                 decoder_type,
             );
 
+            sdr.set_auto_ppm(auto_ppm);
+            sdr.set_auto_gain(auto_gain, args.state_dir.clone());
+            sdr.set_emit_errors(args.output_errors);
+
             rtlsdr.push(sdr);
         }
         None => {
-            trace!("No SDR5 configured")
+            trace!("No SDR5 configured");
         }
     }
 
     match args.sdr6serial {
         Some(serial) => {
             let ppm = args.sdr6ppm.unwrap_or(0);
+            let auto_ppm = args.sdr6autoppm.unwrap_or(false);
             let gain = args.sdr6gain.unwrap_or(0);
+            let auto_gain = args.sdr6autogain.unwrap_or(false);
             let bias_tee = args.sdr6biastee.unwrap_or(false);
             let rtl_mult = args.sdr6mult.unwrap_or(160);
             let frequencies = args.sdr6freqs.unwrap_or_default();
             let decoder_type = args.sdr6decoding_type.unwrap_or(ValidDecoderType::ACARS);
 
-            let sdr = RtlSdr::new(
+            let mut sdr = RtlSdr::new(
                 serial,
                 ppm,
                 gain,
@@ -202,23 +260,29 @@ async fn main() {
                 decoder_type,
             );
 
+            sdr.set_auto_ppm(auto_ppm);
+            sdr.set_auto_gain(auto_gain, args.state_dir.clone());
+            sdr.set_emit_errors(args.output_errors);
+
             rtlsdr.push(sdr);
         }
         None => {
-            trace!("No SDR6 configured")
+            trace!("No SDR6 configured");
         }
     }
 
     match args.sdr7serial {
         Some(serial) => {
             let ppm = args.sdr7ppm.unwrap_or(0);
+            let auto_ppm = args.sdr7autoppm.unwrap_or(false);
             let gain = args.sdr7gain.unwrap_or(0);
+            let auto_gain = args.sdr7autogain.unwrap_or(false);
             let bias_tee = args.sdr7biastee.unwrap_or(false);
             let rtl_mult = args.sdr7mult.unwrap_or(160);
             let frequencies = args.sdr7freqs.unwrap_or_default();
             let decoder_type = args.sdr7decoding_type.unwrap_or(ValidDecoderType::ACARS);
 
-            let sdr = RtlSdr::new(
+            let mut sdr = RtlSdr::new(
                 serial,
                 ppm,
                 gain,
@@ -228,23 +292,29 @@ async fn main() {
                 decoder_type,
             );
 
+            sdr.set_auto_ppm(auto_ppm);
+            sdr.set_auto_gain(auto_gain, args.state_dir.clone());
+            sdr.set_emit_errors(args.output_errors);
+
             rtlsdr.push(sdr);
         }
         None => {
-            trace!("No SDR7 configured")
+            trace!("No SDR7 configured");
         }
     }
 
     match args.sdr8serial {
         Some(serial) => {
             let ppm = args.sdr8ppm.unwrap_or(0);
+            let auto_ppm = args.sdr8autoppm.unwrap_or(false);
             let gain = args.sdr8gain.unwrap_or(0);
+            let auto_gain = args.sdr8autogain.unwrap_or(false);
             let bias_tee = args.sdr8biastee.unwrap_or(false);
             let rtl_mult = args.sdr8mult.unwrap_or(160);
             let frequencies = args.sdr8freqs.unwrap_or_default();
             let decoder_type = args.sdr8decoding_type.unwrap_or(ValidDecoderType::ACARS);
 
-            let sdr = RtlSdr::new(
+            let mut sdr = RtlSdr::new(
                 serial,
                 ppm,
                 gain,
@@ -254,10 +324,14 @@ async fn main() {
                 decoder_type,
             );
 
+            sdr.set_auto_ppm(auto_ppm);
+            sdr.set_auto_gain(auto_gain, args.state_dir.clone());
+            sdr.set_emit_errors(args.output_errors);
+
             rtlsdr.push(sdr);
         }
         None => {
-            trace!("No SDR8 configured")
+            trace!("No SDR8 configured");
         }
     }
 
@@ -269,7 +343,7 @@ async fn main() {
 
     while rtlsdr.len() < 8 {
         rtlsdr.push(RtlSdr::new(
-            String::from(""),
+            String::new(),
             0,
             0,
             false,
@@ -281,15 +355,159 @@ async fn main() {
 
     // create an array of length 8 to hold the SDRs
 
-    let rtlsdr_array: [RtlSdr; 8] = array_init::from_iter(rtlsdr.into_iter()).unwrap();
+    let Some(rtlsdr_array): Option<[RtlSdr; 8]> = array_init::from_iter(rtlsdr) else {
+        error!("Unable to build the SDR list. Exiting program.");
+        return;
+    };
+
+    let rules = match &args.rules_file {
+        Some(rules_file) => match RuleSet::from_file(rules_file) {
+            Ok(rules) => rules,
+            Err(e) => {
+                error!("{e}. Exiting program.");
+                return;
+            }
+        },
+        None => RuleSet::new(),
+    };
+
+    let mut scanner = oxide_scanner::OxideScanner::new(
+        rtlsdr_array,
+        sdr_len,
+        args.output_to_console,
+        args.output_json,
+        false,
+        (!args.disable_dedup).then(|| Duration::from_millis(args.dedup_window)),
+    );
+    scanner.set_rules(rules);
+
+    if let Some(metrics_address) = args.metrics_address {
+        let metrics = match Metrics::new() {
+            Ok(metrics) => Arc::new(metrics),
+            Err(e) => {
+                error!("{e}. Exiting program.");
+                return;
+            }
+        };
+
+        let listener = match TcpListener::bind(metrics_address).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Unable to serve metrics on {metrics_address}: {e}. Exiting program.");
+                return;
+            }
+        };
+
+        scanner.set_metrics(metrics.clone());
+        tokio::spawn(async move {
+            if let Err(e) = oxide_scanner::http::serve(listener, metrics).await {
+                error!("Metrics server failed: {e}");
+            }
+        });
+    }
+
+    if let Some(websocket_address) = args.websocket_address {
+        let listener = match TcpListener::bind(websocket_address).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Unable to stream messages on {websocket_address}: {e}. Exiting program.");
+                return;
+            }
+        };
+
+        let websocket = WebSocketSink::new(args.websocket_buffer);
+        scanner.set_websocket_sink(websocket.clone());
+        tokio::spawn(async move {
+            if let Err(e) = websocket.serve(listener).await {
+                error!("WebSocket server failed: {e}");
+            }
+        });
+    }
+
+    let mqtt = match (&args.mqtt_host, &args.mqtt_station) {
+        (Some(mqtt_host), Some(mqtt_station)) => {
+            let mut config = MqttConfig::new(mqtt_host, args.mqtt_port, mqtt_station)
+                .topic(&args.mqtt_topic)
+                .status_topic(&args.mqtt_status_topic)
+                .qos(args.mqtt_qos)
+                .tls(args.mqtt_tls, args.mqtt_ca_file.clone());
+            if let (Some(username), Some(password)) = (&args.mqtt_username, &args.mqtt_password) {
+                config = config.credentials(username, password);
+            }
+            if let (Some(certificate), Some(key)) = (&args.mqtt_client_cert, &args.mqtt_client_key)
+            {
+                config = config.client_auth(certificate.clone(), key.clone());
+            }
+
+            let (mqtt, connection) = match MqttSink::new(config) {
+                Ok(mqtt) => mqtt,
+                Err(e) => {
+                    error!("{e}. Exiting program.");
+                    return;
+                }
+            };
+
+            scanner.set_mqtt_sink(mqtt.clone());
+            Some((mqtt, tokio::spawn(connection.run())))
+        }
+        _ => None,
+    };
 
-    let scanner =
-        oxide_scanner::OxideScanner::new(rtlsdr_array, sdr_len, args.output_to_console, false);
-    scanner.run().await;
+    scanner.set_statistics_interval(
+        (args.statistics_interval > 0)
+            .then(|| Duration::from_secs(args.statistics_interval.saturating_mul(60))),
+    );
+
+    // Bound before the SDRs are opened, so a taken port doesn't leave them running
+    let api_listener = match args.api_address {
+        Some(api_address) => match TcpListener::bind(api_address).await {
+            Ok(listener) => Some(listener),
+            Err(e) => {
+                error!("Unable to serve the API on {api_address}: {e}. Exiting program.");
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let handle = match scanner.run() {
+        Ok(handle) => handle,
+        Err(e) => {
+            error!("{e}. Exiting program.");
+            return;
+        }
+    };
+
+    if let Some(listener) = api_listener {
+        let control = handle.control();
+        tokio::spawn(async move {
+            if let Err(e) = oxide_scanner::http::serve_api(listener, control).await {
+                error!("API server failed: {e}");
+            }
+        });
+    }
 
-    trace!("Starting the sleep loop");
+    if let Err(e) = shutdown_requested().await {
+        error!("Unable to listen for shutdown: {e}");
+        return;
+    }
 
-    loop {
-        sleep(Duration::from_millis(100)).await;
+    info!("Shutting down");
+    handle.shutdown().await;
+
+    if let Some((mqtt, connection)) = mqtt {
+        let disconnect = async {
+            if let Err(e) = mqtt.disconnect().await {
+                error!("{e}");
+            }
+            // The connection finishes once the disconnect has been sent
+            let _ = connection.await;
+        };
+        if tokio::time::timeout(MQTT_DISCONNECT_TIMEOUT, disconnect)
+            .await
+            .is_err()
+        {
+            warn!("Timed out disconnecting from the MQTT broker");
+        }
     }
 }
@@ -0,0 +1,68 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// Command line of the sensitivity benchmark, which decodes synthetic messages over a sweep of
+// signal conditions
+
+use clap::Parser;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug, Clone)]
+#[command(
+    name = "ACARS Oxide Bench",
+    author,
+    version,
+    about = "Measure how well the ACARS decoder copes with noise, frequency offset and bit errors"
+)]
+pub struct OxideBenchInput {
+    /// Set the log level. debug, trace, info are valid options. Info is default.
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    pub logging: u8,
+    /// Signal to noise ratios to test, in dB measured in the 12.5 kHz channel, separated by a semicolon.
+    #[clap(
+        long,
+        value_delimiter = ';',
+        default_value = "0;2;4;6;8;10;12;15;20",
+        allow_hyphen_values = true
+    )]
+    pub snr: Vec<f64>,
+    /// Transmitter frequency errors to test, in Hz, separated by a semicolon.
+    #[clap(
+        long,
+        value_delimiter = ';',
+        default_value = "0;1000",
+        allow_hyphen_values = true
+    )]
+    pub frequency_offset: Vec<f64>,
+    /// Numbers of characters to send with a bad parity bit, separated by a semicolon.
+    #[clap(long, value_delimiter = ';', default_value = "0;2")]
+    pub parity_errors: Vec<usize>,
+    /// Lengths of the message text, in characters, separated by a semicolon. 220 is the longest a block carries.
+    #[clap(long, value_delimiter = ';', default_value = "47;220")]
+    pub block_length: Vec<usize>,
+    /// Messages sent at each point of the sweep. Default is 100.
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..), default_value = "100")]
+    pub trials: u32,
+    /// Seed of the noise, so runs can be compared. Default is 1.
+    #[clap(long, default_value = "1")]
+    pub seed: u64,
+    /// Write the CSV to this file instead of stdout.
+    #[clap(long)]
+    pub output_file: Option<PathBuf>,
+    /// CSV from an earlier run. Exit with an error if any point of the sweep that is also in this file decodes fewer messages than it did then, or if any point passes a wrong message as valid.
+    #[clap(long)]
+    pub baseline: Option<PathBuf>,
+}
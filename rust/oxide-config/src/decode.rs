@@ -0,0 +1,96 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// Command line of the offline decoder, which decodes a capture file instead of a live SDR
+
+use crate::{validate_freq, OxideInputError};
+use clap::{Parser, ValueEnum};
+use std::path::PathBuf;
+
+/// Sample rates the channel mixer supports, which are the decoder rate times 160 or 192
+pub const SAMPLE_RATES: [u32; 2] = [2_000_000, 2_400_000];
+pub const DEFAULT_SAMPLE_RATE: u32 = 2_000_000;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SampleFormat {
+    /// Unsigned 8 bit interleaved IQ, as recorded by `rtl_sdr` and sample-grabber
+    #[default]
+    Cu8,
+    /// Signed 8 bit interleaved IQ, as recorded by `HackRF` tools
+    Cs8,
+    /// Signed 16 bit little endian interleaved IQ, as recorded by SDR++, `SDRangel` and airspy tools
+    Cs16,
+    /// 32 bit float little endian interleaved IQ, as recorded by GQRX
+    Cf32,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// One line per message, as logged by acars-oxide
+    #[default]
+    Text,
+    /// One line of JSON per message, as described in the JSON spec
+    Json,
+}
+
+#[derive(Parser, Debug, Clone)]
+#[command(
+    name = "ACARS Oxide Decode",
+    author,
+    version,
+    about = "Decode ACARS messages from a capture file"
+)]
+pub struct OxideDecodeInput {
+    /// Set the log level. debug, trace, info are valid options. Info is default.
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    pub logging: u8,
+    /// The capture file to decode. Raw IQ, a WAV file, or a `SigMF` recording given by its data or metadata file.
+    pub capture: PathBuf,
+    /// Sample format of the capture. If not set, it is read from the `SigMF` metadata or WAV header of the capture,
+    /// and raw captures are taken to be cu8.
+    #[clap(long, value_enum)]
+    pub format: Option<SampleFormat>,
+    /// Sample rate of the capture, in samples per second. 2000000 and 2400000 are supported. If not set, it is read
+    /// from the `SigMF` metadata or WAV header of the capture, and raw captures are taken to be 2000000.
+    #[clap(long, value_parser = validate_sample_rate)]
+    pub sample_rate: Option<u32>,
+    /// Frequency the capture was centered on, in MHz. If not set, it is read from the `SigMF` metadata of the capture.
+    /// Otherwise the center is picked from the frequencies the same way acars-oxide picks it for a live SDR.
+    #[clap(long)]
+    pub center_frequency: Option<f32>,
+    /// Frequencies to decode, in MHz, separated by a semicolon.
+    #[clap(long, value_parser = validate_freq, num_args = 1..17, value_delimiter = ';', required = true)]
+    pub freqs: Vec<f32>,
+    /// Format the decoded messages are written in. Default is text.
+    #[clap(long, value_enum, default_value = "text")]
+    pub output_format: OutputFormat,
+    /// Write the decoded messages to this file instead of stdout.
+    #[clap(long)]
+    pub output_file: Option<PathBuf>,
+    /// Also write messages that failed the CRC or parity checks, flagged as failed. They are always counted in the summary.
+    /// Default is false.
+    #[clap(long, value_parser, default_value = "false")]
+    pub output_errors: bool,
+}
+
+fn validate_sample_rate(input: &str) -> Result<u32, OxideInputError> {
+    let sample_rate = input.parse::<u32>()?;
+    if SAMPLE_RATES.contains(&sample_rate) {
+        Ok(sample_rate)
+    } else {
+        Err(OxideInputError::SampleRate { input: sample_rate })
+    }
+}
@@ -31,11 +31,16 @@ extern crate custom_error;
 
 use custom_error::custom_error;
 use oxide_decoders::ValidDecoderType;
+use std::net::SocketAddr;
 use std::num::ParseFloatError;
 use std::num::ParseIntError;
+use std::path::PathBuf;
 
 use clap::Parser;
 
+pub mod bench;
+pub mod decode;
+
 const MIN_GAIN: f32 = 0.0;
 const MAX_GAIN: f32 = 60.0;
 
@@ -47,6 +52,8 @@ const MAX_GAIN: f32 = 60.0;
     about,
     long_about = "ACARS Oxide is a program that allows you to receive and decode ACARS and VDLM2 messages."
 )]
+// Every on/off command line flag is a bool
+#[allow(clippy::struct_excessive_bools)]
 pub struct OxideInput {
     /// General Program Options
     /// Set the log level. debug, trace, info are valid options. Info is default.
@@ -57,7 +64,7 @@ pub struct OxideInput {
     /// SDR specific options.
     /// For each option, the format for the command line flag is: --sdrYoptionname where Y is an integer between 1 and 8.
     /// For example, --sdr1gain 20 --sdr2gain 20
-    /// The options are: gain, ppm, biastee, mult, freq, `decoding_type`, and serial.
+    /// The options are: gain, autogain, ppm, autoppm, biastee, mult, freq, `decoding_type`, and serial.
     /// Please note that using the device index, as reported by `rtl_test` or other tools, is not supported. The serial number must be used.
     /// Of special note, `decoding_type` indicates if the message is decoded using the VDLM2 protocol or the ACARS protocol. `acars` and `vdlm2` are valid options.
     #[clap(
@@ -67,6 +74,101 @@ pub struct OxideInput {
         default_value = "false"
     )]
     pub output_to_console: bool,
+    /// Print each received message to stdout as a single line of JSON. Default is false.
+    #[clap(long, env = "AO_OUTPUT_JSON", value_parser, default_value = "false")]
+    pub output_json: bool,
+    /// Output messages that failed the CRC or parity checks, clearly flagged as failed, instead of dropping them.
+    /// Their content is unreliable. Default is false.
+    #[clap(long, env = "AO_OUTPUT_ERRORS", value_parser, default_value = "false")]
+    pub output_errors: bool,
+    /// Copies of the same message received within this many milliseconds, on any channel or SDR, are merged into one,
+    /// keeping the copy with the strongest signal. Default is 2000.
+    #[clap(long, env = "AO_DEDUP_WINDOW", value_parser, default_value = "2000")]
+    pub dedup_window: u64,
+    /// Output every copy of a message instead of merging copies received within the dedup window. Default is false.
+    #[clap(long, env = "AO_DISABLE_DEDUP", value_parser, default_value = "false")]
+    pub disable_dedup: bool,
+    /// Directory used to keep state between restarts, such as the gain chosen by auto gain.
+    /// If not set, nothing is saved.
+    #[clap(long, env = "AO_STATE_DIR", value_parser, default_value = None)]
+    pub state_dir: Option<PathBuf>,
+    /// TOML file with the rules deciding which messages each output gets. If not set, every output gets every message.
+    #[clap(long, env = "AO_RULES_FILE", value_parser, default_value = None)]
+    pub rules_file: Option<PathBuf>,
+    /// Address to serve Prometheus metrics on, at `/metrics`. For example 0.0.0.0:9090. If not set, metrics are not served.
+    #[clap(long, env = "AO_METRICS_ADDRESS", value_parser, default_value = None)]
+    pub metrics_address: Option<SocketAddr>,
+    /// Address to serve the REST API on, at `/api`, to check on the SDRs and change their gain, PPM and frequencies
+    /// while running. The live web UI is served at `/` on the same address. For example 0.0.0.0:8080. If not set,
+    /// the API and web UI are off.
+    #[clap(long, env = "AO_API_ADDRESS", value_parser, default_value = None)]
+    pub api_address: Option<SocketAddr>,
+    /// Address to stream the messages on, as JSON over WebSocket, at `/`. Clients can pick the messages they get with
+    /// the `label`, `frequency` and `tail` parameters, for example ws://host:port/?label=H1,SA. For example
+    /// 0.0.0.0:8081. If not set, messages are not streamed.
+    #[clap(long, env = "AO_WEBSOCKET_ADDRESS", value_parser, default_value = None)]
+    pub websocket_address: Option<SocketAddr>,
+    /// Number of messages a WebSocket client can fall behind before it is disconnected. Default is 100.
+    #[clap(long, env = "AO_WEBSOCKET_BUFFER", value_parser, default_value = "100")]
+    pub websocket_buffer: usize,
+    /// MQTT broker to publish the messages to, as JSON. Needs the station name. If not set, messages are not published.
+    #[clap(long, env = "AO_MQTT_HOST", value_parser, default_value = None, requires = "mqtt_station")]
+    pub mqtt_host: Option<String>,
+    /// Port of the MQTT broker. Default is 1883.
+    #[clap(long, env = "AO_MQTT_PORT", value_parser, default_value = "1883")]
+    pub mqtt_port: u16,
+    /// Name of this station, used in the MQTT topics and client ID. Must be unique among the stations publishing to
+    /// the broker, as the broker drops a client when another connects with the same ID.
+    #[clap(long, env = "AO_MQTT_STATION", value_parser, default_value = None)]
+    pub mqtt_station: Option<String>,
+    /// Topic each message is published to. {station}, {label}, {tail}, {flight} and {frequency} are filled in from
+    /// the message. Default is acars/{station}/{label}/{tail}.
+    #[clap(
+        long,
+        env = "AO_MQTT_TOPIC",
+        value_parser,
+        default_value = "acars/{station}/{label}/{tail}"
+    )]
+    pub mqtt_topic: String,
+    /// Topic the station publishes whether it is online to, retained. {station} is filled in.
+    /// Default is acars/{station}/status.
+    #[clap(
+        long,
+        env = "AO_MQTT_STATUS_TOPIC",
+        value_parser,
+        default_value = "acars/{station}/status"
+    )]
+    pub mqtt_status_topic: String,
+    /// MQTT quality of service of the messages. 0, 1 or 2. Default is 0.
+    #[clap(long, env = "AO_MQTT_QOS", value_parser = parse_mqtt_qos, default_value = "0")]
+    pub mqtt_qos: u8,
+    /// Username for the MQTT broker. If not set, no credentials are sent.
+    #[clap(long, env = "AO_MQTT_USERNAME", value_parser, default_value = None, requires = "mqtt_password")]
+    pub mqtt_username: Option<String>,
+    #[clap(long, env = "AO_MQTT_PASSWORD", value_parser, default_value = None, requires = "mqtt_username", hide_env_values = true)]
+    pub mqtt_password: Option<String>,
+    /// Connect to the MQTT broker over TLS. Default is false.
+    #[clap(long, env = "AO_MQTT_TLS", value_parser, default_value = "false")]
+    pub mqtt_tls: bool,
+    /// PEM file with the CA certificate to check the MQTT broker against. Needs TLS. If not set, the system's root
+    /// certificates are used.
+    #[clap(long, env = "AO_MQTT_CA_FILE", value_parser, default_value = None, requires = "mqtt_tls")]
+    pub mqtt_ca_file: Option<PathBuf>,
+    /// PEM files with the client certificate and key to authenticate to the MQTT broker with. Needs TLS and the CA
+    /// file.
+    #[clap(long, env = "AO_MQTT_CLIENT_CERT", value_parser, default_value = None, requires_all = ["mqtt_client_key", "mqtt_ca_file", "mqtt_tls"])]
+    pub mqtt_client_cert: Option<PathBuf>,
+    #[clap(long, env = "AO_MQTT_CLIENT_KEY", value_parser, default_value = None, requires = "mqtt_client_cert")]
+    pub mqtt_client_key: Option<PathBuf>,
+    /// Log a summary of what each SDR and frequency decoded every this many minutes, and the totals on shutdown.
+    /// 0 turns the summaries off. Default is 15.
+    #[clap(
+        long,
+        env = "AO_STATISTICS_INTERVAL",
+        value_parser,
+        default_value = "15"
+    )]
+    pub statistics_interval: u64,
 
     #[clap(
         long,
@@ -88,6 +190,15 @@ pub struct OxideInput {
         requires = "sdr1serial"
     )]
     pub sdr1gain: Option<i32>,
+    #[clap(
+        long,
+        env = "AO_SDR1AUTOGAIN",
+        value_parser,
+        default_value = "false",
+        hide = true,
+        requires = "sdr1serial"
+    )]
+    pub sdr1autogain: Option<bool>,
     #[clap(
         long,
         env = "AO_SDR1PPM",
@@ -97,6 +208,15 @@ pub struct OxideInput {
         requires = "sdr1serial"
     )]
     pub sdr1ppm: Option<i32>,
+    #[clap(
+        long,
+        env = "AO_SDR1AUTOPPM",
+        value_parser,
+        default_value = "false",
+        hide = true,
+        requires = "sdr1serial"
+    )]
+    pub sdr1autoppm: Option<bool>,
     #[clap(
         long,
         env = "AO_SDR1BIASTEE",
@@ -154,6 +274,15 @@ pub struct OxideInput {
         requires = "sdr2serial"
     )]
     pub sdr2gain: Option<i32>,
+    #[clap(
+        long,
+        env = "AO_SDR2AUTOGAIN",
+        value_parser,
+        default_value = "false",
+        hide = true,
+        requires = "sdr2serial"
+    )]
+    pub sdr2autogain: Option<bool>,
     #[clap(
         long,
         env = "AO_SDR2PPM",
@@ -163,6 +292,15 @@ pub struct OxideInput {
         requires = "sdr2serial"
     )]
     pub sdr2ppm: Option<i32>,
+    #[clap(
+        long,
+        env = "AO_SDR2AUTOPPM",
+        value_parser,
+        default_value = "false",
+        hide = true,
+        requires = "sdr2serial"
+    )]
+    pub sdr2autoppm: Option<bool>,
     #[clap(
         long,
         env = "AO_SDR2BIASTEE",
@@ -220,6 +358,15 @@ pub struct OxideInput {
         requires = "sdr3serial"
     )]
     pub sdr3gain: Option<i32>,
+    #[clap(
+        long,
+        env = "AO_SDR3AUTOGAIN",
+        value_parser,
+        default_value = "false",
+        hide = true,
+        requires = "sdr3serial"
+    )]
+    pub sdr3autogain: Option<bool>,
     #[clap(
         long,
         env = "AO_SDR3PPM",
@@ -229,6 +376,15 @@ pub struct OxideInput {
         requires = "sdr3serial"
     )]
     pub sdr3ppm: Option<i32>,
+    #[clap(
+        long,
+        env = "AO_SDR3AUTOPPM",
+        value_parser,
+        default_value = "false",
+        hide = true,
+        requires = "sdr3serial"
+    )]
+    pub sdr3autoppm: Option<bool>,
     #[clap(
         long,
         env = "AO_SDR3BIASTEE",
@@ -286,6 +442,15 @@ pub struct OxideInput {
         requires = "sdr4serial"
     )]
     pub sdr4gain: Option<i32>,
+    #[clap(
+        long,
+        env = "AO_SDR4AUTOGAIN",
+        value_parser,
+        default_value = "false",
+        hide = true,
+        requires = "sdr4serial"
+    )]
+    pub sdr4autogain: Option<bool>,
     #[clap(
         long,
         env = "AO_SDR4PPM",
@@ -295,6 +460,15 @@ pub struct OxideInput {
         requires = "sdr4serial"
     )]
     pub sdr4ppm: Option<i32>,
+    #[clap(
+        long,
+        env = "AO_SDR4AUTOPPM",
+        value_parser,
+        default_value = "false",
+        hide = true,
+        requires = "sdr4serial"
+    )]
+    pub sdr4autoppm: Option<bool>,
     #[clap(
         long,
         env = "AO_SDR4BIASTEE",
@@ -352,6 +526,15 @@ pub struct OxideInput {
         requires = "sdr5serial"
     )]
     pub sdr5gain: Option<i32>,
+    #[clap(
+        long,
+        env = "AO_SDR5AUTOGAIN",
+        value_parser,
+        default_value = "false",
+        hide = true,
+        requires = "sdr5serial"
+    )]
+    pub sdr5autogain: Option<bool>,
     #[clap(
         long,
         env = "AO_SDR5PPM",
@@ -361,6 +544,15 @@ pub struct OxideInput {
         requires = "sdr5serial"
     )]
     pub sdr5ppm: Option<i32>,
+    #[clap(
+        long,
+        env = "AO_SDR5AUTOPPM",
+        value_parser,
+        default_value = "false",
+        hide = true,
+        requires = "sdr5serial"
+    )]
+    pub sdr5autoppm: Option<bool>,
     #[clap(
         long,
         env = "AO_SDR5BIASTEE",
@@ -418,6 +610,15 @@ pub struct OxideInput {
         requires = "sdr6serial"
     )]
     pub sdr6gain: Option<i32>,
+    #[clap(
+        long,
+        env = "AO_SDR6AUTOGAIN",
+        value_parser,
+        default_value = "false",
+        hide = true,
+        requires = "sdr6serial"
+    )]
+    pub sdr6autogain: Option<bool>,
     #[clap(
         long,
         env = "AO_SDR6PPM",
@@ -427,6 +628,15 @@ pub struct OxideInput {
         requires = "sdr6serial"
     )]
     pub sdr6ppm: Option<i32>,
+    #[clap(
+        long,
+        env = "AO_SDR6AUTOPPM",
+        value_parser,
+        default_value = "false",
+        hide = true,
+        requires = "sdr6serial"
+    )]
+    pub sdr6autoppm: Option<bool>,
     #[clap(
         long,
         env = "AO_SDR6BIASTEE",
@@ -484,6 +694,15 @@ pub struct OxideInput {
         requires = "sdr7serial"
     )]
     pub sdr7gain: Option<i32>,
+    #[clap(
+        long,
+        env = "AO_SDR7AUTOGAIN",
+        value_parser,
+        default_value = "false",
+        hide = true,
+        requires = "sdr7serial"
+    )]
+    pub sdr7autogain: Option<bool>,
     #[clap(
         long,
         env = "AO_SDR7PPM",
@@ -493,6 +712,15 @@ pub struct OxideInput {
         requires = "sdr7serial"
     )]
     pub sdr7ppm: Option<i32>,
+    #[clap(
+        long,
+        env = "AO_SDR7AUTOPPM",
+        value_parser,
+        default_value = "false",
+        hide = true,
+        requires = "sdr7serial"
+    )]
+    pub sdr7autoppm: Option<bool>,
     #[clap(
         long,
         env = "AO_SDR7BIASTEE",
@@ -550,6 +778,15 @@ pub struct OxideInput {
         requires = "sdr8serial"
     )]
     pub sdr8gain: Option<i32>,
+    #[clap(
+        long,
+        env = "AO_SDR8AUTOGAIN",
+        value_parser,
+        default_value = "false",
+        hide = true,
+        requires = "sdr8serial"
+    )]
+    pub sdr8autogain: Option<bool>,
     #[clap(
         long,
         env = "AO_SDR8PPM",
@@ -559,6 +796,15 @@ pub struct OxideInput {
         requires = "sdr8serial"
     )]
     pub sdr8ppm: Option<i32>,
+    #[clap(
+        long,
+        env = "AO_SDR8AUTOPPM",
+        value_parser,
+        default_value = "false",
+        hide = true,
+        requires = "sdr8serial"
+    )]
+    pub sdr8autoppm: Option<bool>,
     #[clap(
         long,
         env = "AO_SDR8BIASTEE",
@@ -607,6 +853,8 @@ custom_error! { OxideInputError
     DecodingType { input: String } = "Decoding type {input} is not supported. Please use one of the following: VDLM2, ACARS",
     FrequencyMinMaxRange { max_freq: String, min_freq: String, range: String } = "Range between {min_freq} and {max_freq} is {range} MHz. Should be less than or equal to 2Mhz",
     FrequencyOutsideOfAirband { freq: String } = "Frequency {freq} is outside of the airband. Should be between 108 and 137 MHz",
+    SampleRate { input: u32 } = "Sample rate {input} is not supported. Should be 2000000 or 2400000.",
+    MqttQos { input: u8 } = "MQTT QoS {input} is not supported. Should be 0, 1 or 2.",
 }
 
 fn validate_freq(freqs_string: &str) -> Result<f32, OxideInputError> {
@@ -642,6 +890,16 @@ fn validate_decoding_type(env: &str) -> Result<ValidDecoderType, OxideInputError
     })
 }
 
+fn parse_mqtt_qos(env: &str) -> Result<u8, OxideInputError> {
+    let qos = env.parse::<u8>()?;
+    if qos > 2 {
+        return Err(OxideInputError::MqttQos { input: qos });
+    }
+    Ok(qos)
+}
+
+// The gain has been range checked above, so truncating it to an integer is safe
+#[allow(clippy::cast_possible_truncation)]
 fn parse_sdr_gain(env: &str) -> Result<i32, OxideInputError> {
     let gain = env.parse::<f32>()?;
     if !(MIN_GAIN..=MAX_GAIN).contains(&gain) {
@@ -0,0 +1,329 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+#![deny(
+    clippy::pedantic,
+    //clippy::cargo,
+    clippy::nursery,
+    clippy::style,
+    clippy::correctness,
+    clippy::all,
+    clippy::unwrap_used,
+    clippy::expect_used
+)]
+// #![warn(missing_docs)]
+
+// Decodes a capture file with the same channel mixing and decoders acars-oxide runs on a live
+// SDR, writes the messages out as they are decoded and exits with a summary. Messages are timed
+// by the end of the buffer they were completed in, counted from the start of the recording when
+// its SigMF metadata has one, and from now otherwise.
+
+#[macro_use]
+extern crate log;
+
+use custom_error::custom_error;
+use oxide_config::clap::Parser;
+use oxide_config::decode::{
+    OutputFormat, OxideDecodeInput, SampleFormat, DEFAULT_SAMPLE_RATE, SAMPLE_RATES,
+};
+use oxide_decoders::decoders::acars::{self, AssembledACARSMessage, MessageStatus};
+use oxide_decoders::ValidDecoderType;
+use oxide_output::json::OxideJsonMessage;
+use oxide_rtlsdr::iq::{self, IqError, IqReader};
+use oxide_rtlsdr::{RTLSDRError, RtlSdr};
+use sdre_rust_logging::SetupLogging;
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process::ExitCode;
+use std::time::SystemTime;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+custom_error! {DecodeError
+    Io { source: io::Error } = "{source}",
+    Rtlsdr { source: RTLSDRError } = "{source}",
+    Iq { source: IqError } = "{source}",
+    SampleRate { sample_rate: u32 } = "Sample rate {sample_rate} is not supported. Should be 2000000 or 2400000.",
+}
+
+#[derive(Debug, Default)]
+struct Summary {
+    buffers: u64,
+    messages: u64,
+    corrected: u64,
+    failed: u64,
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Decoded {} buffers. Messages: {}, CRC fixes: {}, Failed: {}",
+            self.buffers, self.messages, self.corrected, self.failed
+        )
+    }
+}
+
+fn write_message(
+    output: &mut dyn Write,
+    format: OutputFormat,
+    message: &AssembledACARSMessage,
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Text => writeln!(output, "{message}"),
+        OutputFormat::Json => {
+            let received = message.received.unwrap_or_else(SystemTime::now);
+            let json = OxideJsonMessage::new(message, received).to_json()?;
+            writeln!(output, "{json}")
+        }
+    }
+}
+
+/// Write out the messages decoded so far and count them
+fn write_messages(
+    rx: &mut UnboundedReceiver<AssembledACARSMessage>,
+    output: &mut dyn Write,
+    args: &OxideDecodeInput,
+    summary: &mut Summary,
+) -> io::Result<()> {
+    while let Ok(message) = rx.try_recv() {
+        summary.messages += 1;
+
+        if message.status != MessageStatus::Valid {
+            summary.failed += 1;
+            if !args.output_errors {
+                continue;
+            }
+        } else if message.corrected_bits > 0 {
+            summary.corrected += 1;
+        }
+
+        write_message(output, args.output_format, &message)?;
+    }
+
+    Ok(())
+}
+
+const fn to_iq_format(format: SampleFormat) -> iq::SampleFormat {
+    match format {
+        SampleFormat::Cu8 => iq::SampleFormat::Cu8,
+        SampleFormat::Cs8 => iq::SampleFormat::Cs8,
+        SampleFormat::Cs16 => iq::SampleFormat::Cs16,
+        SampleFormat::Cf32 => iq::SampleFormat::Cf32,
+    }
+}
+
+fn decode(args: &OxideDecodeInput) -> Result<Summary, DecodeError> {
+    let name = args.capture.file_name().map_or_else(
+        || "CAPTURE".to_string(),
+        |name| name.to_string_lossy().to_string(),
+    );
+
+    // The command line wins over what the capture says about itself
+    let (capture, info) = iq::open_capture(&args.capture)?;
+    let format = args
+        .format
+        .map(to_iq_format)
+        .or(info.format)
+        .unwrap_or(iq::SampleFormat::Cu8);
+    let sample_rate = args
+        .sample_rate
+        .or(info.sample_rate)
+        .unwrap_or(DEFAULT_SAMPLE_RATE);
+    if !SAMPLE_RATES.contains(&sample_rate) {
+        return Err(DecodeError::SampleRate { sample_rate });
+    }
+    // Center frequencies are well within f32 precision once in MHz
+    #[allow(clippy::cast_possible_truncation)]
+    let center_frequency = args.center_frequency.or_else(|| {
+        info.center_frequency
+            .map(|center_frequency| (center_frequency / 1_000_000.0) as f32)
+    });
+
+    debug!(
+        "[{name: <13}] Format: {format:?}, Sample rate: {sample_rate}, Center frequency: {center_frequency:?}"
+    );
+
+    // The sample rate was validated to be a multiple of the decoder rate
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    let rtl_mult = (sample_rate / acars::INTRATE as u32) as i32;
+
+    let mut sdr = RtlSdr::new(
+        name,
+        0,
+        0,
+        false,
+        rtl_mult,
+        args.freqs.clone(),
+        ValidDecoderType::ACARS,
+    );
+    // Failed messages are always decoded so they can be counted
+    sdr.set_emit_errors(true);
+    sdr.set_center_frequency(center_frequency);
+    // Messages are timed from the start of the recording, when it is known
+    sdr.set_start_time(info.start_time);
+
+    let (tx_channel, mut rx) = mpsc::unbounded_channel();
+    sdr.init_capture(&tx_channel)?;
+
+    let mut output: Box<dyn Write> = match &args.output_file {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+
+    let mut summary = Summary::default();
+    summary.buffers = sdr.process_iq_with(&mut IqReader::new(capture, format), |_| {
+        write_messages(&mut rx, &mut output, args, &mut summary)?;
+        output.flush()
+    })?;
+
+    Ok(summary)
+}
+
+fn main() -> ExitCode {
+    let args = OxideDecodeInput::parse();
+    args.logging.enable_logging();
+
+    match decode(&args) {
+        Ok(summary) => {
+            info!("{summary}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            error!("Unable to decode {}: {}", args.capture.display(), e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oxide_decoders::decoders::modulator::{downlink_block, to_cu8, AcarsModulator};
+    use oxide_rtlsdr::sigmf::{self, SigMfMetadata, DATATYPE_CU8};
+    use std::path::{Path, PathBuf};
+
+    const SAMPLE_RATE: u32 = 2_000_000;
+    /// 2024-03-01T12:30:00Z
+    const START: f64 = 1_709_296_200.0;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("oxide-decode-{name}-{}", std::process::id()))
+    }
+
+    /// A position report on 131.55 MHz and, starting `gap` seconds after it, a link test on
+    /// 130.025 MHz, recorded centered on 131 MHz
+    fn write_capture(dir: &Path, gap: u32) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let first = to_cu8(
+            &AcarsModulator::new(f64::from(SAMPLE_RATE), 550_000.0)
+                .snr(Some(20.0))
+                .modulate(&downlink_block(['H', '1'], 60)),
+        );
+        let second = to_cu8(
+            &AcarsModulator::new(f64::from(SAMPLE_RATE), -975_000.0)
+                .snr(Some(20.0))
+                .modulate(&downlink_block(['Q', '0'], 10)),
+        );
+
+        let mut capture = first;
+        capture.resize((gap * SAMPLE_RATE) as usize * 2, 127);
+        capture.extend_from_slice(&second);
+        capture.resize(capture.len() + 1_000_000, 127);
+
+        std::fs::create_dir_all(dir)?;
+        let data_path = dir.join("capture.sigmf-data");
+        std::fs::write(&data_path, capture)?;
+
+        let mut metadata = SigMfMetadata::new(DATATYPE_CU8, f64::from(SAMPLE_RATE), 131_000_000.0);
+        metadata.captures[0].datetime = Some("2024-03-01T12:30:00.000Z".to_string());
+        metadata.write(&sigmf::meta_path(&data_path))?;
+
+        Ok(data_path)
+    }
+
+    fn args(capture: &Path, extra: &[&str]) -> OxideDecodeInput {
+        let mut args = vec!["acars-oxide-decode", capture.to_str().unwrap_or_default()];
+        args.extend_from_slice(extra);
+        OxideDecodeInput::parse_from(args)
+    }
+
+    #[test]
+    fn test_decode_capture() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = temp_dir("json");
+        let capture = write_capture(&dir, 2)?;
+        let output_file = dir.join("messages.json");
+
+        let summary = decode(&args(
+            &capture,
+            &[
+                "--freqs",
+                "131.55;130.025",
+                "--output-format",
+                "json",
+                "--output-file",
+                output_file.to_str().unwrap_or_default(),
+            ],
+        ))?;
+        assert_eq!(summary.messages, 2);
+        assert_eq!(summary.failed, 0);
+
+        let output = std::fs::read_to_string(&output_file)?;
+        let messages: Vec<serde_json::Value> = output
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["acars"]["label"], "H1");
+        assert_eq!(messages[0]["message_information"]["frequency"], 131_550_000);
+        assert_eq!(messages[1]["acars"]["label"], "Q0");
+        assert_eq!(messages[1]["message_information"]["frequency"], 130_025_000);
+
+        // Timed from the start of the recording, to the start of each message
+        let seconds = |message: &serde_json::Value| {
+            let timestamp = &message["message_information"]["message_timestamp"];
+            timestamp["sec"].as_f64().unwrap_or_default()
+                + timestamp["usec"].as_f64().unwrap_or_default() / 1_000_000.0
+                - START
+        };
+        let (first, second) = (seconds(&messages[0]), seconds(&messages[1]));
+        assert!((0.0..0.5).contains(&first), "First message at {first}");
+        assert!(
+            (second - first - 2.0).abs() < 0.02,
+            "Second message at {second}"
+        );
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_frequency_outside_capture() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = temp_dir("band");
+        let capture = write_capture(&dir, 1)?;
+
+        // The recording is centered on 131 MHz and only reaches 1 MHz either side
+        let result = decode(&args(&capture, &["--freqs", "129.5"]));
+        assert!(matches!(
+            result,
+            Err(DecodeError::Rtlsdr {
+                source: RTLSDRError::FrequencyOutsideBand { .. }
+            })
+        ));
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// Arbitrary blocks through the error checks, error correction and message parsing. The first
+// two bytes are the CRC, the rest is the block from the mode character on.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use num::Complex;
+use oxide_decoders::decoders::acars::ACARSDecoder;
+use oxide_decoders::Decoder;
+use tokio::sync::mpsc;
+
+fuzz_target!(|data: &[u8]| {
+    let [crc0, crc1, txt @ ..] = data else {
+        return;
+    };
+
+    let mut decoder = ACARSDecoder::new(0, 131_550_000, [Complex::new(0.0, 0.0); 192]);
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    decoder.set_output_channel(tx);
+    decoder.set_emit_errors(true);
+    decoder.process_block(txt, [*crc0, *crc1]);
+
+    while let Ok(message) = rx.try_recv() {
+        let _ = message.to_string();
+    }
+});
@@ -0,0 +1,67 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// Arbitrary channel samples through the demodulator and everything after it. An even first
+// byte reads the rest as signed 8 bit IQ, which keeps the samples in the range the channel
+// mixer produces. An odd one reads 32 bit floats, NaN and infinity included.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use num::Complex;
+use oxide_decoders::decoders::acars::ACARSDecoder;
+use oxide_decoders::decoders::modulator::decode_samples;
+use oxide_decoders::Decoder;
+use tokio::sync::mpsc;
+
+fuzz_target!(|data: &[u8]| {
+    let [format, samples @ ..] = data else {
+        return;
+    };
+
+    let samples: Vec<Complex<f32>> = if format & 1 == 0 {
+        samples
+            .chunks_exact(2)
+            .map(|iq| {
+                Complex::new(
+                    f32::from(iq[0] as i8) / 128.0,
+                    f32::from(iq[1] as i8) / 128.0,
+                )
+            })
+            .collect()
+    } else {
+        samples
+            .chunks_exact(8)
+            .map(|iq| {
+                Complex::new(
+                    f32::from_le_bytes([iq[0], iq[1], iq[2], iq[3]]),
+                    f32::from_le_bytes([iq[4], iq[5], iq[6], iq[7]]),
+                )
+            })
+            .collect()
+    };
+
+    let mut decoder = ACARSDecoder::new(0, 131_550_000, [Complex::new(0.0, 0.0); 192]);
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    decoder.set_output_channel(tx);
+    decoder.set_emit_errors(true);
+
+    decode_samples(&mut decoder, &samples);
+
+    while let Ok(message) = rx.try_recv() {
+        let _ = message.to_string();
+    }
+});
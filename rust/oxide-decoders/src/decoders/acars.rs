@@ -14,21 +14,43 @@
 // along with this program; if not, write to the Free Software
 // Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
 
+// The demodulator is a port of acarsdec and leans heavily on conversions between sample
+// indices, bit counts and floats. Those casts are intentional.
+#![allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::too_many_lines
+)]
+
+use crate::decoders::arinc622::{self, Arinc622Message};
+use crate::decoders::labels::{LabelContent, LabelDecoderRegistry};
+use crate::decoders::miam::{MiamMessage, MiamReassembler};
+use crate::ChannelStatistics;
 use crate::Decoder;
 use custom_error::custom_error;
 // use num_complex::Complex;
 use num::Complex;
 use oxide_helpers::round;
+use oxide_metrics::ChannelMetrics;
+use std::cmp::Reverse;
 use std::fmt::Display;
 use std::fmt::Formatter;
 use std::ops::Add;
-use std::time::SystemTime;
-use std::time::UNIX_EPOCH;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc::UnboundedSender;
 
 pub const INTRATE: usize = 12500;
 pub const INTRATE_F32: f32 = 12500.0;
 pub const RTLOUTBUFSZ: usize = 1024;
+/// Weight given to each new bit level when updating the noise floor
+const NOISE_ALPHA: f32 = 0.001;
+/// Bit levels this many times above the noise floor are treated as signal, not noise
+const NOISE_GATE: f32 = 10.0;
+/// Bits of noise averaged, one time constant of the average, before the noise floor is reported
+const NOISE_SETTLE_BITS: u32 = 1000;
+
 const FLEN: usize = (INTRATE / 1200) + 1;
 const MFLTOVER: usize = 12;
 const MFLTOVER_F32: f32 = 12.0;
@@ -36,13 +58,20 @@ const FLENO: usize = FLEN * MFLTOVER + 1;
 const FLENO_F32: f32 = FLENO as f32;
 const PLLG: f32 = 38e-4;
 const PLLC: f32 = 0.52;
-const SYN: u8 = 0x16;
-const SOH: u8 = 0x01;
-const STX: u8 = 0x02;
-const ETX: u8 = 0x83;
-const ETB: u8 = 0x97;
-const DLE: u8 = 0x7f;
+pub(crate) const SYN: u8 = 0x16;
+pub(crate) const SOH: u8 = 0x01;
+pub(crate) const STX: u8 = 0x02;
+pub(crate) const ETX: u8 = 0x83;
+pub(crate) const ETB: u8 = 0x97;
+pub(crate) const DLE: u8 = 0x7f;
+/// Longest block the demodulator accepts, from the mode character to the ETX or ETB
+pub const MAX_BLOCK_LEN: usize = 240;
 const MAXPERR: usize = 3;
+/// Maximum parity errors in a block before we give up on receiving it. Blocks with more than
+/// `MAXPERR` errors can only be fixed by the soft decision decoder
+const MAXSOFTPERR: usize = 8;
+/// Number of least reliable bits the soft decision decoder tries flipping
+const CHASE_BITS: usize = 10;
 
 const NUMBITS: [u8; 256] = [
     0, 1, 1, 2, 1, 2, 2, 3, 1, 2, 2, 3, 2, 3, 3, 4, 1, 2, 2, 3, 2, 3, 3, 4, 2, 3, 3, 4, 3, 4, 4, 5,
@@ -248,6 +277,30 @@ const SYNDROM: [u32; 1936] = [
 custom_error! {pub ACARSDecodingError
     FixDB = "Not able to fix DB Error",
     FixPR = "Not able to fix PR Error",
+    FixSoft = "Not able to fix errors using soft decisions",
+}
+
+/// Outcome of the error checks on a received message. Anything other than `Valid` means the
+/// message content can not be trusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MessageStatus {
+    /// The message passed the parity and CRC checks, possibly after error correction
+    #[default]
+    Valid,
+    /// The CRC check failed and the errors could not be corrected
+    CrcFailed,
+    /// The CRC check passed but parity errors remain
+    ParityFailed,
+}
+
+impl Display for MessageStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Valid => write!(f, "Valid"),
+            Self::CrcFailed => write!(f, "CRC FAILED"),
+            Self::ParityFailed => write!(f, "PARITY FAILED"),
+        }
+    }
 }
 
 /// Enum to represent the state of the ACARS decoding
@@ -269,7 +322,7 @@ enum ACARSState {
 }
 
 /// Enum to represent the downlink status of an ACARS frame
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DownlinkStatus {
     AirToGround,
     GroundToAir,
@@ -278,14 +331,14 @@ pub enum DownlinkStatus {
 impl Display for DownlinkStatus {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            DownlinkStatus::AirToGround => write!(f, "Air to Ground"),
-            DownlinkStatus::GroundToAir => write!(f, "Ground to Air"),
+            Self::AirToGround => write!(f, "Air to Ground"),
+            Self::GroundToAir => write!(f, "Ground to Air"),
         }
     }
 }
 
 /// Enum to represent the acknowledgement status of an ACARS frame
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AckStatus {
     Nack,
     Ack(char),
@@ -294,8 +347,8 @@ pub enum AckStatus {
 impl Display for AckStatus {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            AckStatus::Nack => write!(f, "NACK"),
-            AckStatus::Ack(ack_status) => write!(f, "ACK({})", ack_status),
+            Self::Nack => write!(f, "NACK"),
+            Self::Ack(ack_status) => write!(f, "ACK({ack_status})"),
         }
     }
 }
@@ -304,7 +357,9 @@ impl Display for AckStatus {
 #[derive(Debug, Clone, PartialEq)]
 
 pub struct AssembledACARSMessage {
-    // TODO: Need to save time of message
+    /// Time the message started, from the system clock, or from the start of the capture when
+    /// decoding one. `None` for messages that weren't demodulated.
+    pub received: Option<SystemTime>,
     /// Type of transmission. (AGCS-1, AGCS-D etc).
     pub mode: char,
     /// Aircraft Registration number. Filled with a special sequence if a squitter burst.
@@ -327,26 +382,49 @@ pub struct AssembledACARSMessage {
     pub block_end: char,
     /// The text message itself. Just as with AX25 packet, the actual length is variable but limited to a maximum of 220 characters.
     pub message_text: Option<Vec<char>>,
+    /// Result of the error checks. Messages that failed are only output when asked for, and
+    /// their content is unreliable.
+    pub status: MessageStatus,
     /// The number of parity errors encountered during the decoding of the message.
     pub parity_errors: u8,
+    /// The number of bits flipped by error correction to produce the message.
+    pub corrected_bits: u8,
     /// Signal level of the message.
     pub signal_level: f32,
+    /// Noise floor of the channel, in the same units as the signal level, just before the message was received.
+    /// `None` if the noise floor had not been measured yet.
+    pub noise_level: Option<f32>,
+    /// Signal to noise ratio of the message in dB. `None` if the noise floor had not been measured yet.
+    pub snr: Option<f32>,
     /// Frequency the message was received on.
     pub frequency: f32,
+    /// Offset, in Hz, of the received carrier from the channel frequency. Positive values mean
+    /// the signal was received above the expected frequency.
+    pub frequency_offset: f32,
     /// Indicates if the message was downlinked from an aircraft or broadcast from a ground station.
     pub downlink_status: DownlinkStatus,
     /// Message snumber. Same as the message number but without the sequence number.
     pub message_number_without_sequence: Option<[char; 3]>,
     /// Message sequence number. Same as the message number but without the sequence start.
     pub message_number_sequence: Option<char>,
+    /// ARINC 622 application data (ADS-C, CPDLC) decoded from the message text, if present.
+    pub arinc622: Option<Arinc622Message>,
+    /// MIAM frame and, once complete, the decompressed message it carries.
+    pub miam: Option<MiamMessage>,
+    /// Structured content of the message text, for the labels we have a decoder for.
+    pub label_content: Option<LabelContent>,
     // reassembly_status: ReassemblyStatus,
 }
 
 impl Display for AssembledACARSMessage {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.status != MessageStatus::Valid {
+            write!(f, "*** {} - UNRELIABLE MESSAGE *** ", self.status)?;
+        }
+
         write!(
             f,
-            "Frequency: {:.3}, Mode: {}, {}Downlink Status: {}, Ack: {}, Label: {}, {}{}{}{}{}Reception Errors: {}, Signal Level: {:.1}, {}{}{}",
+            "Frequency: {:.3}, Mode: {}, {}Downlink Status: {}, Ack: {}, Label: {}, {}{}{}{}{}Reception Errors: {}, Corrected Bits: {}, Signal Level: {:.1}, Noise Level: {}, SNR: {}, {}{}{}{}{}{}",
             self.frequency,
             self.mode,
             self.get_tail_addr_display(),
@@ -359,19 +437,35 @@ impl Display for AssembledACARSMessage {
             self.get_sub_label_display(),
             self.get_mfi_display(),
             self.parity_errors,
+            self.corrected_bits,
             self.signal_level,
+            self.noise_level
+                .map_or_else(|| "n/a".to_string(), |level| format!("{level:.1}")),
+            self.snr
+                .map_or_else(|| "n/a".to_string(), |snr| format!("{snr:.1}")),
             self.get_msn_display(),
             self.get_msn_seq_display(),
             self.get_text_display(),
+            self.get_arinc622_display(),
+            self.get_miam_display(),
+            self.get_label_content_display(),
         )
     }
 }
 
+impl Default for AssembledACARSMessage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AssembledACARSMessage {
-    fn new() -> Self {
+    const fn new() -> Self {
         Self {
+            received: None,
             mode: ' ',
             frequency: 0.0,
+            frequency_offset: 0.0,
             aircraft_tail: None,
             downlink_status: DownlinkStatus::AirToGround,
             acknowledgement: AckStatus::Nack,
@@ -384,130 +478,139 @@ impl AssembledACARSMessage {
             block_start: ' ',
             block_end: ' ',
             message_text: None,
+            status: MessageStatus::Valid,
             parity_errors: 0,
+            corrected_bits: 0,
             signal_level: 0.0,
+            noise_level: None,
+            snr: None,
             message_number_without_sequence: None,
             message_number_sequence: None,
+            arinc622: None,
+            miam: None,
+            label_content: None,
         }
     }
 
     fn get_text_display(&self) -> String {
-        match &self.message_text {
-            Some(txt) => {
-                if txt.len() > 1 {
-                    format!("Text: {}", txt.iter().collect::<String>())
-                } else {
-                    "".to_string()
-                }
+        self.message_text.as_ref().map_or_else(String::new, |txt| {
+            if txt.len() > 1 {
+                format!("Text: {}", txt.iter().collect::<String>())
+            } else {
+                String::new()
             }
-            None => "".to_string(),
-        }
+        })
+    }
+
+    fn get_arinc622_display(&self) -> String {
+        self.arinc622
+            .as_ref()
+            .map_or_else(String::new, |arinc622| format!(", ARINC 622: {arinc622}"))
+    }
+
+    fn get_miam_display(&self) -> String {
+        self.miam
+            .as_ref()
+            .map_or_else(String::new, |miam| format!(", MIAM: {miam}"))
+    }
+
+    fn get_label_content_display(&self) -> String {
+        self.label_content
+            .as_ref()
+            .map_or_else(String::new, |content| format!(", Decoded: {content}"))
     }
 
     fn get_tail_addr_display(&self) -> String {
-        match &self.aircraft_tail {
-            Some(tail_addr) => {
+        self.aircraft_tail
+            .as_ref()
+            .map_or_else(String::new, |tail_addr| {
                 let output = tail_addr.iter().collect::<String>();
-                if !output.trim().is_empty() {
-                    format!("Tail: {}, ", output.trim())
+                if output.trim().is_empty() {
+                    String::new()
                 } else {
-                    "".to_string()
+                    format!("Tail: {}, ", output.trim())
                 }
-            }
-            None => "".to_string(),
-        }
+            })
     }
 
     fn get_no_display(&self) -> String {
-        match &self.message_number {
-            Some(no) => {
-                let output = no.iter().collect::<String>();
-                if !output.trim().is_empty() {
-                    format!("No: {}, ", output.trim())
-                } else {
-                    "".to_string()
-                }
+        self.message_number.as_ref().map_or_else(String::new, |no| {
+            let output = no.iter().collect::<String>();
+            if output.trim().is_empty() {
+                String::new()
+            } else {
+                format!("No: {}, ", output.trim())
             }
-            None => "".to_string(),
-        }
+        })
     }
 
     fn get_flight_id_display(&self) -> String {
-        match &self.flight_id {
-            Some(flight_id) => {
+        self.flight_id
+            .as_ref()
+            .map_or_else(String::new, |flight_id| {
                 let output = flight_id.iter().collect::<String>();
-                if !output.trim().is_empty() {
-                    format!("Flight ID: {}, ", output.trim())
+                if output.trim().is_empty() {
+                    String::new()
                 } else {
-                    "".to_string()
+                    format!("Flight ID: {}, ", output.trim())
                 }
-            }
-            None => "".to_string(),
-        }
+            })
     }
 
     fn get_sub_label_display(&self) -> String {
-        match &self.sublabel {
-            Some(sublabel) => {
-                let output = sublabel.iter().collect::<String>();
-                if !output.trim().is_empty() {
-                    format!("Sublabel: {}, ", output.trim())
-                } else {
-                    "".to_string()
-                }
+        self.sublabel.as_ref().map_or_else(String::new, |sublabel| {
+            let output = sublabel.iter().collect::<String>();
+            if output.trim().is_empty() {
+                String::new()
+            } else {
+                format!("Sublabel: {}, ", output.trim())
             }
-            None => "".to_string(),
-        }
+        })
     }
 
     fn get_mfi_display(&self) -> String {
-        match &self.mfi {
-            Some(mfi) => {
-                let output = mfi.iter().collect::<String>();
-                if !output.trim().is_empty() {
-                    format!("MFI: {}, ", output.trim())
-                } else {
-                    "".to_string()
-                }
+        self.mfi.as_ref().map_or_else(String::new, |mfi| {
+            let output = mfi.iter().collect::<String>();
+            if output.trim().is_empty() {
+                String::new()
+            } else {
+                format!("MFI: {}, ", output.trim())
             }
-            None => "".to_string(),
-        }
+        })
     }
 
     fn get_msn_display(&self) -> String {
-        match &self.message_number_without_sequence {
-            Some(msn) => {
+        self.message_number_without_sequence
+            .as_ref()
+            .map_or_else(String::new, |msn| {
                 let output = msn.iter().collect::<String>();
-                if !output.trim().is_empty() {
-                    format!("MSN: {}, ", output.trim())
+                if output.trim().is_empty() {
+                    String::new()
                 } else {
-                    "".to_string()
+                    format!("MSN: {}, ", output.trim())
                 }
-            }
-            None => "".to_string(),
-        }
+            })
     }
 
     fn get_msn_seq_display(&self) -> String {
-        match &self.message_number_sequence {
-            Some(msn_seq) => {
+        self.message_number_sequence
+            .as_ref()
+            .map_or_else(String::new, |msn_seq| {
                 let output = String::from(*msn_seq);
-                if !output.trim().is_empty() {
-                    format!("MSN Seq: {}, ", output.trim())
+                if output.trim().is_empty() {
+                    String::new()
                 } else {
-                    "".to_string()
+                    format!("MSN Seq: {}, ", output.trim())
                 }
-            }
-            None => "".to_string(),
-        }
+            })
     }
 
     fn get_bid_display(&self) -> String {
         if self.block_id as u8 == 0 {
-            "".to_string()
+            String::new()
         } else {
             match self.block_id {
-                ' ' => "".to_string(),
+                ' ' => String::new(),
                 _ => format!("Block ID: {}, ", self.block_id),
             }
         }
@@ -517,26 +620,37 @@ impl AssembledACARSMessage {
 #[derive(Clone)]
 struct Mskblks {
     chn: i32,
-    timeval: u64,
+    received: SystemTime,
     len: usize,
     pub err: usize,
+    corrected: usize,
     lvl: f32,
+    noise_lvl: Option<f32>,
+    freq_offset: f32,
     pub txt: [u8; 250],
     pub crc: [u8; 2],
-    prev: Option<Box<Mskblks>>,
+    // Confidence of each bit of the text and crc bytes, as received from the demodulator
+    confidence: [[f32; 8]; 250],
+    crc_confidence: [[f32; 8]; 2],
+    prev: Option<Box<Self>>,
     pub init: bool,
 }
 
 impl Mskblks {
-    pub fn new() -> Self {
+    pub const fn new() -> Self {
         Self {
             chn: 0,
-            timeval: 0,
+            received: UNIX_EPOCH,
             len: 0,
             err: 0,
+            corrected: 0,
             lvl: 0.0,
+            noise_lvl: None,
+            freq_offset: 0.0,
             txt: [0; 250],
             crc: [0; 2],
+            confidence: [[0.0; 8]; 250],
+            crc_confidence: [[0.0; 8]; 2],
             prev: None,
             init: true,
         }
@@ -544,12 +658,17 @@ impl Mskblks {
 
     pub fn reset(&mut self) {
         self.chn = 0;
-        self.timeval = 0;
+        self.received = UNIX_EPOCH;
         self.len = 0;
         self.err = 0;
+        self.corrected = 0;
         self.lvl = 0.0;
+        self.noise_lvl = None;
+        self.freq_offset = 0.0;
         self.txt = [0; 250];
         self.crc = [0; 2];
+        self.confidence = [[0.0; 8]; 250];
+        self.crc_confidence = [[0.0; 8]; 2];
         self.prev = None;
         self.init = true;
     }
@@ -558,8 +677,8 @@ impl Mskblks {
         self.chn = chn;
     }
 
-    pub fn set_timeval(&mut self, timeval: u64) {
-        self.timeval = timeval;
+    pub fn set_received(&mut self, received: SystemTime) {
+        self.received = received;
     }
 
     pub fn set_len(&mut self, len: usize) {
@@ -574,7 +693,7 @@ impl Mskblks {
         self.txt[index] = txt;
     }
 
-    pub fn len(&self) -> usize {
+    pub const fn len(&self) -> usize {
         self.len
     }
 }
@@ -586,20 +705,42 @@ pub struct ACARSDecoder {
     frequency: f32,
     wf: [Complex<f32>; 192],
     dm_buffer: [f32; RTLOUTBUFSZ],
+    iq_buffer: [Complex<f32>; RTLOUTBUFSZ],
+    // Phase rotation of the carrier accumulated while a message is being received
+    carrier_rotation: Complex<f32>,
+    last_iq: Complex<f32>,
+    // Carrier offset of the last valid message, waiting to be picked up by the SDR
+    frequency_offset: Option<f32>,
     msk_phi: f32,
     msk_df: f32,
     msk_clk: f32,
     msk_lvl_sum: f32,
     msk_bit_count: i32,
+    // Running estimate of the bit level while no message is being received
+    noise_power: f32,
+    // Number of bits averaged into the noise floor
+    noise_bits: u32,
+    statistics: ChannelStatistics,
     msk_s: u32,
     idx: u32,
     inb: [Complex<f32>; FLEN],
     outbits: u8, // original was unsigned char.....
+    // Confidence of each bit in outbits, oldest bit first
+    outbits_confidence: [f32; 8],
     nbits: i32,
     acars_state: ACARSState,
     h: [f32; FLENO],
     blk: Mskblks,
     output_channel: Option<UnboundedSender<AssembledACARSMessage>>,
+    // Output messages that failed the error checks instead of dropping them
+    emit_errors: bool,
+    miam: MiamReassembler,
+    label_decoders: Arc<LabelDecoderRegistry>,
+    metrics: Option<ChannelMetrics>,
+    // Time of the first sample when decoding a capture, None to time messages from the system clock
+    start_time: Option<SystemTime>,
+    // Samples demodulated so far
+    samples: u64,
 }
 
 impl Decoder for ACARSDecoder {
@@ -607,8 +748,17 @@ impl Decoder for ACARSDecoder {
         self.demod_msk(length);
     }
 
-    fn set_dm_buffer_at_index(&mut self, index: usize, value: f32) {
-        self.dm_buffer[index] = value;
+    fn set_dm_buffer_at_index(&mut self, index: usize, value: Complex<f32>) {
+        self.dm_buffer[index] = value.norm();
+        self.iq_buffer[index] = value;
+    }
+
+    fn take_frequency_offset(&mut self) -> Option<f32> {
+        self.frequency_offset.take()
+    }
+
+    fn get_channel_statistics(&self) -> ChannelStatistics {
+        self.statistics.clone()
     }
 
     fn set_output_channel(&mut self, output_channel: UnboundedSender<AssembledACARSMessage>) {
@@ -621,6 +771,7 @@ impl Decoder for ACARSDecoder {
 }
 
 impl ACARSDecoder {
+    #[must_use]
     pub fn new(channel_number: i32, freq: i32, wf: [Complex<f32>; 192]) -> Self {
         // TODO: Should this be moved to a static?
         let mut h: [f32; FLENO] = [0.0; FLENO];
@@ -631,44 +782,120 @@ impl ACARSDecoder {
             );
             if *h_item < 0.0 {
                 *h_item = 0.0;
-            };
+            }
         }
 
         Self {
             channel_number,
-            frequency: freq as f32 / 1000000.0,
+            frequency: freq as f32 / 1_000_000.0,
             wf,
             dm_buffer: [0.0; RTLOUTBUFSZ],
+            iq_buffer: [Complex::new(0.0, 0.0); RTLOUTBUFSZ],
+            carrier_rotation: Complex::new(0.0, 0.0),
+            last_iq: Complex::new(0.0, 0.0),
+            frequency_offset: None,
             msk_phi: 0.0,
             msk_df: 0.0,
             msk_clk: 0.0,
             msk_lvl_sum: 0.0,
             msk_bit_count: 0,
+            noise_power: 0.0,
+            noise_bits: 0,
+            statistics: ChannelStatistics::new(freq as f32 / 1_000_000.0),
             msk_s: 0,
             idx: 0,
             inb: [Complex::new(0.0, 0.0); FLEN],
             outbits: 0,
+            outbits_confidence: [0.0; 8],
             nbits: 8,
             acars_state: ACARSState::Wsyn,
             h,
             blk: Mskblks::new(),
             output_channel: None,
+            emit_errors: false,
+            miam: MiamReassembler::new(),
+            label_decoders: Arc::new(LabelDecoderRegistry::with_default_decoders()),
+            metrics: None,
+            start_time: None,
+            samples: 0,
         }
     }
 
+    /// Time messages from `start_time`, the time of the first sample, and the number of samples
+    /// demodulated since, instead of the system clock. Used to decode captures.
+    pub fn set_start_time(&mut self, start_time: Option<SystemTime>) {
+        self.start_time = start_time;
+    }
+
+    /// The time of the sample being demodulated
+    // Sample counts stay well within f64 precision
+    #[allow(clippy::cast_precision_loss)]
+    fn now(&self) -> SystemTime {
+        self.start_time.map_or_else(SystemTime::now, |start_time| {
+            start_time + Duration::from_secs_f64(self.samples as f64 / INTRATE as f64)
+        })
+    }
+
+    /// Replace the label decoders, to add decoders for airline specific formats. The registry
+    /// is shared, so one can be built once for all channels.
+    pub fn set_label_decoders(&mut self, label_decoders: Arc<LabelDecoderRegistry>) {
+        self.label_decoders = label_decoders;
+    }
+
+    /// Output messages that could not be corrected, flagged with their `MessageStatus`, instead
+    /// of dropping them.
+    pub fn set_emit_errors(&mut self, emit_errors: bool) {
+        self.emit_errors = emit_errors;
+    }
+
+    /// Count the messages, failures and levels of this channel in `metrics`.
+    pub fn set_metrics(&mut self, metrics: ChannelMetrics) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Run a block, from the mode character to the ETX or ETB, and its two CRC characters
+    /// through the error checks and on to the output channel as if it had just been
+    /// demodulated. This is the block parsing without the DSP front end, for tests and fuzzing.
+    /// Characters past `MAX_BLOCK_LEN` are dropped, as the demodulator would.
+    pub fn process_block(&mut self, txt: &[u8], crc: [u8; 2]) {
+        let len = txt.len().min(MAX_BLOCK_LEN);
+
+        self.blk.reset();
+        self.blk.set_chn(self.channel_number);
+        self.blk.set_received(self.now());
+        self.blk.txt[..len].copy_from_slice(&txt[..len]);
+        self.blk.set_len(len);
+        self.blk.crc = crc;
+
+        self.parity_and_crc_check();
+        self.blk.reset();
+    }
+
     pub fn demod_msk(&mut self, len: usize) {
         /* MSK demod */
 
-        for in_ in &mut self.dm_buffer.into_iter().take(len) {
-            let s: f32 = 1800.0 / INTRATE_F32 * 2.0 * std::f32::consts::PI + self.msk_df;
+        for (in_, iq) in self.dm_buffer.into_iter().zip(self.iq_buffer).take(len) {
+            self.samples += 1;
+            let in_block = matches!(
+                self.acars_state,
+                ACARSState::Txt | ACARSState::Crc1 | ACARSState::Crc2
+            );
+
+            /* carrier tracking. The AM envelope hides the carrier, so follow its phase instead */
+            if in_block {
+                self.carrier_rotation += iq * self.last_iq.conj();
+            }
+            self.last_iq = iq;
+
+            let s: f32 = (1800.0 / INTRATE_F32 * 2.0).mul_add(std::f32::consts::PI, self.msk_df);
             let mut v: Complex<f32> = Complex::new(0.0, 0.0);
             let mut o: f32;
 
             /* VCO */
             self.msk_phi += s;
             if self.msk_phi >= 2.0 * std::f32::consts::PI {
-                self.msk_phi -= 2.0 * std::f32::consts::PI
-            };
+                self.msk_phi -= 2.0 * std::f32::consts::PI;
+            }
 
             /* mixer */
 
@@ -686,8 +913,8 @@ impl ACARSDecoder {
                 /* matched filter */
                 o = MFLTOVER_F32 * (self.msk_clk / s + 0.5);
                 if o > MFLTOVER_F32 {
-                    o = MFLTOVER_F32
-                };
+                    o = MFLTOVER_F32;
+                }
 
                 for j in 0..FLEN {
                     v = v.add(self.h[o as usize] * self.inb[(j + self.idx as usize) % FLEN]);
@@ -697,8 +924,16 @@ impl ACARSDecoder {
                 /* normalize */
                 let lvl: f32 = v.norm();
                 v /= lvl + 1e-8;
-                self.msk_lvl_sum += lvl * lvl / 4.0;
-                self.msk_bit_count += 1;
+                // Only the level of the block is reported, and counting between blocks would
+                // overflow on a long running receiver
+                if in_block {
+                    self.msk_lvl_sum += lvl * lvl / 4.0;
+                    self.msk_bit_count += 1;
+                }
+
+                if matches!(self.acars_state, ACARSState::Wsyn) {
+                    self.update_noise_floor(lvl * lvl / 4.0);
+                }
 
                 if self.msk_s & 1 != 0 {
                     vo = v.im;
@@ -706,26 +941,63 @@ impl ACARSDecoder {
                         dphi = -v.re;
                     } else {
                         dphi = v.re;
-                    };
+                    }
                 } else {
                     vo = v.re;
                     if vo >= 0.0 {
                         dphi = v.im;
                     } else {
                         dphi = -v.im;
-                    };
+                    }
                 }
                 if self.msk_s & 2 != 0 {
                     self.put_bit(-vo);
                 } else {
                     self.put_bit(vo);
                 }
-                self.msk_s += 1;
+                // Only the two low bits are used
+                self.msk_s = self.msk_s.wrapping_add(1);
 
                 /* PLL filter */
-                self.msk_df = PLLC * self.msk_df + (1.0 - PLLC) * PLLG * dphi;
+                self.msk_df = PLLC.mul_add(self.msk_df, (1.0 - PLLC) * PLLG * dphi);
+                // A NaN or infinite sample would otherwise stop the VCO and bit clock for good
+                if !self.msk_df.is_finite() {
+                    self.msk_df = 0.0;
+                }
+            }
+        }
+    }
+
+    fn update_noise_floor(&mut self, power: f32) {
+        if self.noise_power <= 0.0 {
+            self.noise_power = power;
+            if power > 0.0 {
+                self.noise_bits = 1;
             }
+        } else if power > self.noise_power * NOISE_GATE {
+            // Probably an unsynchronized burst. Creep up slowly so a raised noise floor,
+            // from a gain change for instance, is still followed
+            self.noise_power *= 1.0 + NOISE_ALPHA / NOISE_GATE;
+        } else {
+            self.noise_power = NOISE_ALPHA.mul_add(power - self.noise_power, self.noise_power);
+            self.noise_bits = self.noise_bits.saturating_add(1);
         }
+
+        if let Some(noise_level) = self.noise_level() {
+            let noise_level = round(noise_level, 1);
+            self.statistics.noise_level = Some(noise_level);
+
+            if let Some(metrics) = &self.metrics {
+                metrics.set_noise_level(noise_level);
+            }
+        }
+    }
+
+    /// The noise floor in dB, once enough noise has been heard to trust it. Until then a block
+    /// would be measured against its own prekey, or nothing at all.
+    fn noise_level(&self) -> Option<f32> {
+        (self.noise_bits >= NOISE_SETTLE_BITS && self.noise_power > 0.0)
+            .then(|| 10.0 * self.noise_power.log10())
     }
 
     fn reset_acars(&mut self) {
@@ -739,6 +1011,8 @@ impl ACARSDecoder {
         if bit > 0.0 {
             self.outbits |= 0x80;
         }
+        self.outbits_confidence.copy_within(1.., 0);
+        self.outbits_confidence[7] = bit.abs();
 
         self.nbits -= 1;
         if self.nbits <= 0 {
@@ -789,18 +1063,15 @@ impl ACARSDecoder {
                     }
 
                     self.blk.set_chn(self.channel_number);
-                    self.blk.set_timeval(
-                        SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap()
-                            .as_secs(),
-                    );
+                    self.blk.set_received(self.now());
                     self.blk.set_len(0);
                     self.blk.set_err(0);
+                    self.blk.corrected = 0;
 
                     self.acars_state = ACARSState::Txt;
                     self.nbits = 8;
                     self.msk_lvl_sum = 0.0;
+                    self.carrier_rotation = Complex::new(0.0, 0.0);
                     self.msk_bit_count = 0;
                     return;
                 }
@@ -809,8 +1080,16 @@ impl ACARSDecoder {
             ACARSState::Txt => {
                 trace!("[{: <13}] TXT", format!("{}:{}", "ACARS", self.frequency));
                 self.blk.set_text_by_index(self.blk.len, self.outbits);
+                self.blk.confidence[self.blk.len] = self.outbits_confidence;
                 self.blk.len += 1;
 
+                // Before the ETX/ETB check, so the error correction never sees a block longer
+                // than the syndrome table covers
+                if self.blk.len > MAX_BLOCK_LEN {
+                    self.reset_acars();
+                    return;
+                }
+
                 if (NUMBITS[self.outbits as usize] & 1) == 0 {
                     trace!(
                         "[{: <13}] TXT ERROR",
@@ -818,7 +1097,7 @@ impl ACARSDecoder {
                     );
                     self.blk.err += 1;
 
-                    if self.blk.err > MAXPERR + 1 {
+                    if self.blk.err > MAXSOFTPERR + 1 {
                         trace!(
                             "[{: <13}] TXT TOO MANY ERRORS",
                             format!("{}:{}", "ACARS", self.frequency)
@@ -836,18 +1115,17 @@ impl ACARSDecoder {
                     self.blk.len -= 3;
                     self.blk.crc[0] = self.blk.txt[self.blk.len];
                     self.blk.crc[1] = self.blk.txt[self.blk.len + 1];
+                    self.blk.crc_confidence[0] = self.blk.confidence[self.blk.len];
+                    self.blk.crc_confidence[1] = self.blk.confidence[self.blk.len + 1];
                     self.acars_state = ACARSState::Crc2;
                     self.put_msg_label();
                 }
-                if self.blk.len > 240 {
-                    self.reset_acars();
-                    return;
-                }
                 self.nbits = 8;
             }
             ACARSState::Crc1 => {
                 trace!("[{: <13}] CRC1", format!("{}:{}", "ACARS", self.frequency));
                 self.blk.crc[0] = self.outbits;
+                self.blk.crc_confidence[0] = self.outbits_confidence;
                 self.acars_state = ACARSState::Crc2;
                 self.nbits = 8;
             }
@@ -855,6 +1133,7 @@ impl ACARSDecoder {
             ACARSState::Crc2 => {
                 trace!("[{: <13}] CRC2", format!("{}:{}", "ACARS", self.frequency));
                 self.blk.crc[1] = self.outbits;
+                self.blk.crc_confidence[1] = self.outbits_confidence;
                 self.put_msg_label();
             }
             ACARSState::End => {
@@ -867,6 +1146,9 @@ impl ACARSDecoder {
 
     fn put_msg_label(&mut self) {
         self.blk.lvl = 10.0 * (self.msk_lvl_sum / self.msk_bit_count as f32).log10();
+        self.blk.noise_lvl = self.noise_level();
+        self.blk.freq_offset =
+            self.carrier_rotation.arg() * INTRATE_F32 / (2.0 * std::f32::consts::PI);
 
         self.blk.prev = None;
         self.parity_and_crc_check();
@@ -876,15 +1158,25 @@ impl ACARSDecoder {
         self.nbits = 8;
     }
 
-    fn update_crc(&self, crc: u32, c: u32) -> u32 {
+    pub(crate) const fn update_crc(crc: u32, c: u32) -> u32 {
         // #define update_crc(crc,c) crc= (crc>> 8)^crc_ccitt_table[(crc^(c))&0xff];
         (crc >> 8) ^ CRC[((crc ^ c) & 0xff) as usize]
     }
 
+    /// Whether error correction may leave `byte` at `position`. In the text, between the STX and
+    /// the ETX or ETB, only printable characters and line breaks are sent. A correction to
+    /// anything else is far more likely a CRC collision than the sent message.
+    const fn is_correctable_to(&self, position: usize, byte: u8) -> bool {
+        position <= 12
+            || position + 1 >= self.blk.len
+            || matches!(byte & 0x7f, 0x20..=0x7e | b'\r' | b'\n')
+    }
+
     fn fixdberr(&mut self, crc: u32) -> Result<(), ACARSDecodingError> {
         /* test remaining error in crc */
         for item in SYNDROM.iter().take(16_usize) {
             if *item == crc {
+                self.blk.corrected += 1;
                 return Ok(());
             }
         }
@@ -894,12 +1186,13 @@ impl ACARSDecoder {
             let bo: usize = 8 * (self.blk.len - k + 1);
             for i in 0..8_usize {
                 for j in 0..8_usize {
-                    if i == j {
+                    if i == j || !self.is_correctable_to(k, self.blk.txt[k] ^ (1 << i) ^ (1 << j)) {
                         continue;
-                    };
+                    }
                     if (crc ^ SYNDROM[i + bo] ^ SYNDROM[j + bo]) == 0 {
                         self.blk.txt[k] ^= 1 << i;
                         self.blk.txt[k] ^= 1 << j;
+                        self.blk.corrected += 2;
                         return Ok(());
                     }
                 }
@@ -911,35 +1204,137 @@ impl ACARSDecoder {
     fn fixprerr(
         &mut self,
         crc: u32,
-        pr: &[u8; 3],
+        pr: [u8; 3],
         pr_index: usize,
         pn: usize,
     ) -> Result<(), ACARSDecodingError> {
         if pn > 0 {
             /* try to recursievly fix parity error */
+            let position = pr[pr_index] as usize;
             for i in 0..8 {
+                if !self.is_correctable_to(position, self.blk.txt[position] ^ (1 << i)) {
+                    continue;
+                }
                 //syndrom[i + 8 * (blk->len - *pr + 1)]
                 let test = crc ^ SYNDROM[i + 8 * (self.blk.len - pr[pr_index] as usize + 1)];
                 if self.fixprerr(test, pr, pr_index + 1, pn - 1).is_ok() {
-                    self.blk.txt[pr[pr_index] as usize] ^= 1 << i;
+                    self.blk.txt[position] ^= 1 << i;
+                    self.blk.corrected += 1;
                     return Ok(());
                 }
             }
-            Err(ACARSDecodingError::FixPR)
         } else {
             /* end of recursion : no more parity error */
             if crc == 0 {
                 return Ok(());
             }
 
-            /* test remaining error in crc */
-            for item in SYNDROM.iter().take(16_usize) {
-                if *item == crc {
-                    return Ok(());
+            /* test remaining error in crc. With more than one parity error fixed, so many
+            corrections have been tried that one more bit would match wrong blocks too often */
+            if pr_index == 1 && SYNDROM[..16].contains(&crc) {
+                self.blk.corrected += 1;
+                return Ok(());
+            }
+        }
+        Err(ACARSDecodingError::FixPR)
+    }
+
+    /// Chase style soft decision decoding. Try every combination of flips of the least reliable
+    /// bits, including the crc bytes, and keep the first that passes both the parity and CRC checks.
+    /// Combinations are tried fewest flips first, then weakest bits first, so the most likely
+    /// correction wins when several pass.
+    fn fixsofterr(&mut self, crc: u32) -> Result<(), ACARSDecodingError> {
+        let len = self.blk.len;
+
+        // (confidence, byte position, bit). Positions past the text are the crc bytes
+        let mut bits: Vec<(f32, usize, usize)> = Vec::with_capacity((len + 2) * 8);
+        for (position, confidence) in self.blk.confidence[..len]
+            .iter()
+            .chain(self.blk.crc_confidence.iter())
+            .enumerate()
+        {
+            for (bit, value) in confidence.iter().enumerate() {
+                bits.push((*value, position, bit));
+            }
+        }
+        bits.sort_by(|a, b| a.0.total_cmp(&b.0));
+        bits.truncate(CHASE_BITS);
+
+        let parity_error = |txt: &[u8; 250], position: usize| {
+            position < len && (NUMBITS[txt[position] as usize] & 1) == 0
+        };
+        let parity_errors = (0..len)
+            .filter(|position| parity_error(&self.blk.txt, *position))
+            .count();
+
+        // Bit n of a pattern flips the nth weakest bit. Among patterns with the same number of
+        // flips, the one whose lowest flipped bit is weakest comes first
+        let mut patterns: Vec<u32> = (1_u32..(1 << bits.len())).collect();
+        patterns.sort_by_key(|pattern| (pattern.count_ones(), Reverse(pattern.reverse_bits())));
+
+        for pattern in patterns {
+            let flips: Vec<&(f32, usize, usize)> = bits
+                .iter()
+                .enumerate()
+                .filter(|(n, _)| pattern & (1 << n) != 0)
+                .map(|(_, bit)| bit)
+                .collect();
+
+            let syndrome = flips.iter().fold(crc, |syndrome, (_, position, bit)| {
+                syndrome ^ SYNDROM[bit + 8 * (len + 1 - position)]
+            });
+
+            if syndrome != 0 {
+                continue;
+            }
+
+            /* every text byte must end up with odd parity */
+            let mut fixed_parity_errors = 0;
+            let mut parity_ok = true;
+            for (n, (_, position, _)) in flips.iter().enumerate() {
+                if *position >= len || flips[..n].iter().any(|(_, p, _)| p == position) {
+                    continue;
+                }
+                let flipped = flips.iter().filter(|(_, p, _)| p == position).count();
+                if parity_error(&self.blk.txt, *position) {
+                    fixed_parity_errors += 1;
+                    parity_ok &= flipped % 2 == 1;
+                } else {
+                    parity_ok &= flipped % 2 == 0;
                 }
             }
-            Err(ACARSDecodingError::FixPR)
+
+            if !parity_ok || fixed_parity_errors != parity_errors {
+                continue;
+            }
+
+            let corrected_to = |position: usize| {
+                flips
+                    .iter()
+                    .filter(|(_, p, _)| *p == position)
+                    .fold(self.blk.txt[position], |byte, (_, _, bit)| {
+                        byte ^ (1 << bit)
+                    })
+            };
+            if flips.iter().any(|(_, position, _)| {
+                *position < len && !self.is_correctable_to(*position, corrected_to(*position))
+            }) {
+                continue;
+            }
+
+            for (_, position, bit) in &flips {
+                if *position < len {
+                    self.blk.txt[*position] ^= 1 << bit;
+                } else {
+                    self.blk.crc[position - len] ^= 1 << bit;
+                }
+            }
+            self.blk.corrected += flips.len();
+
+            return Ok(());
         }
+
+        Err(ACARSDecodingError::FixSoft)
     }
 
     fn parity_and_crc_check(&mut self) {
@@ -968,13 +1363,6 @@ impl ACARSDecoder {
                 pn += 1;
             }
         }
-        if pn > MAXPERR {
-            error!(
-                "[{: <13}] Too many parity errors",
-                format!("{}:{}", "ACARS", self.frequency)
-            );
-            return;
-        }
         if pn > 0 {
             warn!(
                 "[{: <13}] Initial parity error{}: {}",
@@ -988,11 +1376,11 @@ impl ACARSDecoder {
         /* crc check */
         let mut crc: u32 = 0;
         for i in 0..self.blk.len {
-            crc = self.update_crc(crc, self.blk.txt[i] as u32);
+            crc = Self::update_crc(crc, u32::from(self.blk.txt[i]));
         }
 
-        crc = self.update_crc(crc, self.blk.crc[0] as u32);
-        crc = self.update_crc(crc, self.blk.crc[1] as u32);
+        crc = Self::update_crc(crc, u32::from(self.blk.crc[0]));
+        crc = Self::update_crc(crc, u32::from(self.blk.crc[1]));
         if crc != 0 {
             warn!(
                 "[{: <13}] CRC error",
@@ -1006,38 +1394,52 @@ impl ACARSDecoder {
         }
 
         /* try to fix error */
-        if pn > 0 {
-            match self.fixprerr(crc, &pr, 0, pn) {
-                Ok(_) => {}
-                Err(_) => {
-                    error!(
-                        "[{: <13}] Not able to fix parity errors",
-                        format!("{}:{}", "ACARS", self.frequency),
-                    );
-                    return;
-                }
-            }
-            info!(
-                "[{: <13}] {:#} total parity error{} fixed",
-                format!("{}:{}", "ACARS", self.frequency),
-                pn,
-                if pn > 1 { "s" } else { "" }
-            );
+        let fixed = if pn > MAXPERR {
+            Err(ACARSDecodingError::FixPR)
+        } else if pn > 0 {
+            self.fixprerr(crc, pr, 0, pn)
         } else if crc > 0 {
-            match self.fixdberr(crc) {
-                Ok(_) => {}
-                Err(_) => {
-                    error!(
-                        "[{: <13}] Not able to fix CRC errors",
-                        format!("{}:{}", "ACARS", self.frequency),
-                    );
+            self.fixdberr(crc)
+        } else {
+            Ok(())
+        };
+
+        match fixed {
+            Ok(()) if pn > 0 => {
+                info!(
+                    "[{: <13}] {:#} total parity error{} fixed",
+                    format!("{}:{}", "ACARS", self.frequency),
+                    pn,
+                    if pn > 1 { "s" } else { "" }
+                );
+            }
+            Ok(()) if crc > 0 => {
+                info!(
+                    "[{: <13}] CRC errors fixed",
+                    format!("{}:{}", "ACARS", self.frequency),
+                );
+            }
+            Ok(()) => {}
+            Err(e) => {
+                debug!(
+                    "[{: <13}] {}. Trying soft decisions",
+                    format!("{}:{}", "ACARS", self.frequency),
+                    e
+                );
+
+                if let Err(e) = self.fixsofterr(crc) {
+                    error!("[{: <13}] {}", format!("{}:{}", "ACARS", self.frequency), e);
+                    self.generate_failed_message(MessageStatus::CrcFailed);
                     return;
                 }
+
+                info!(
+                    "[{: <13}] {} bit{} fixed using soft decisions",
+                    format!("{}:{}", "ACARS", self.frequency),
+                    self.blk.corrected,
+                    if self.blk.corrected > 1 { "s" } else { "" }
+                );
             }
-            info!(
-                "[{: <13}] CRC errors fixed",
-                format!("{}:{}", "ACARS", self.frequency),
-            );
         }
 
         // /* redo parity checking and removing */
@@ -1056,6 +1458,7 @@ impl ACARSDecoder {
                 if pn > 1 { "s" } else { "" },
                 pn
             );
+            self.generate_failed_message(MessageStatus::ParityFailed);
 
             return;
         }
@@ -1065,19 +1468,55 @@ impl ACARSDecoder {
             format!("{}:{}", "ACARS", self.frequency)
         );
 
-        self.generate_output_message();
+        self.generate_output_message(MessageStatus::Valid);
+    }
+
+    fn generate_failed_message(&mut self, status: MessageStatus) {
+        self.statistics.add_failed_message(&status);
+
+        if let Some(metrics) = &self.metrics {
+            match status {
+                MessageStatus::CrcFailed => metrics.crc_failure(),
+                MessageStatus::ParityFailed => metrics.parity_failure(),
+                MessageStatus::Valid => (),
+            }
+        }
+
+        if !self.emit_errors {
+            return;
+        }
+
+        for blk_item in self.blk.txt.iter_mut().take(self.blk.len) {
+            *blk_item &= 0x7f;
+        }
+
+        self.generate_output_message(status);
     }
 
-    fn generate_output_message(&mut self) {
+    fn generate_output_message(&mut self, status: MessageStatus) {
         trace!(
             "[{: <13}] Generating output message",
             format!("{}:{}", "ACARS", self.frequency)
         );
 
         let mut output_message = AssembledACARSMessage::new();
+        output_message.received = Some(self.blk.received);
+        output_message.status = status;
         output_message.signal_level = round(self.blk.lvl, 1);
+        output_message.noise_level = self.blk.noise_lvl.map(|level| round(level, 1));
+        output_message.snr = self
+            .blk
+            .noise_lvl
+            .map(|level| round(self.blk.lvl - level, 1));
         output_message.parity_errors = self.blk.err as u8;
+        output_message.corrected_bits = self.blk.corrected as u8;
         output_message.frequency = self.frequency;
+        output_message.frequency_offset = round(self.blk.freq_offset, 1);
+
+        // Only trust measurements from messages we know were received correctly
+        if status == MessageStatus::Valid {
+            self.frequency_offset = Some(self.blk.freq_offset);
+        }
 
         let mut k: usize = 0;
         let mut j: usize = 0;
@@ -1139,7 +1578,10 @@ impl ACARSDecoder {
         k += 1;
         output_message.block_end = self.blk.txt[self.blk.len - 1] as char;
 
-        if output_message.block_start != 0x03 as char {
+        if output_message.block_start == 0x03 as char {
+            // empty message text
+            output_message.message_text = Some(vec!['\0']);
+        } else {
             if is_downlink {
                 /* message no */
                 let mut no: [char; 4] = [' '; 4];
@@ -1173,13 +1615,13 @@ impl ACARSDecoder {
                 output_message.flight_id = Some(output_flight_id);
             }
 
-            let mut txt_len = self.blk.len - k - 1;
+            let mut txt_len = self.blk.len.saturating_sub(k + 1);
 
             // Extract sublabel and MFI if present
             let offset = self.acars_extract_sublabel_and_mfi(&mut output_message, k);
 
             k += offset;
-            txt_len -= offset;
+            txt_len = txt_len.saturating_sub(offset);
 
             i = 0;
             if txt_len > 0 {
@@ -1191,13 +1633,49 @@ impl ACARSDecoder {
 
                 output_message.message_text = Some(output_text);
             }
-        } else {
-            // empty message text
-            output_message.message_text = Some(vec!['\0']);
+        }
+
+        // Content decoded from a block that failed the checks would look as trustworthy as any
+        // other, so those are only output as text. Reassembling from them would also poison the
+        // other blocks
+        if status == MessageStatus::Valid {
+            if let Some(message_text) = &output_message.message_text {
+                output_message.arinc622 = arinc622::decode(
+                    output_message.label,
+                    message_text,
+                    &output_message.downlink_status,
+                );
+            }
+
+            output_message.label_content = self.label_decoders.decode(&output_message);
+            let received = self
+                .blk
+                .received
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs());
+            output_message.miam =
+                self.miam
+                    .process(&output_message, received, &self.label_decoders);
+            self.statistics.add_message(&output_message);
+
+            if let Some(metrics) = &self.metrics {
+                metrics.valid_message(
+                    &output_message.label.iter().collect::<String>(),
+                    output_message.signal_level,
+                    output_message.parity_errors,
+                    output_message.corrected_bits,
+                );
+            }
         }
 
         if let Some(ref mut output_channel) = self.output_channel {
-            output_channel.send(output_message).unwrap();
+            if let Err(e) = output_channel.send(output_message) {
+                error!(
+                    "[{: <13}] Failed to send message to output channel: {}",
+                    format!("{}:{}", "ACARS", self.frequency),
+                    e
+                );
+            }
         }
     }
 
@@ -1262,3 +1740,325 @@ impl ACARSDecoder {
         consumed
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoders::modulator::{
+        decode_samples, downlink_block, downlink_text, AcarsBlock, AcarsModulator,
+    };
+    use tokio::sync::mpsc;
+
+    fn process_block(txt: &[u8], crc: [u8; 2]) -> Vec<AssembledACARSMessage> {
+        let mut decoder = ACARSDecoder::new(0, 131_550_000, [Complex::new(0.0, 0.0); 192]);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        decoder.set_output_channel(tx);
+        decoder.set_emit_errors(true);
+        decoder.process_block(txt, crc);
+
+        let mut messages = vec![];
+        while let Ok(message) = rx.try_recv() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    /// Run a block through the error checks as `process_block` does, with the demodulator
+    /// confident in every bit but the `weak` ones, given as (byte position, bit). Positions past
+    /// the text are the crc bytes.
+    fn process_soft_block(
+        txt: &[u8],
+        crc: [u8; 2],
+        weak: &[(usize, usize)],
+    ) -> Vec<AssembledACARSMessage> {
+        let mut decoder = ACARSDecoder::new(0, 131_550_000, [Complex::new(0.0, 0.0); 192]);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        decoder.set_output_channel(tx);
+        decoder.set_emit_errors(true);
+
+        decoder.blk.reset();
+        decoder.blk.txt[..txt.len()].copy_from_slice(txt);
+        decoder.blk.set_len(txt.len());
+        decoder.blk.crc = crc;
+        decoder.blk.confidence = [[1.0; 8]; 250];
+        decoder.blk.crc_confidence = [[1.0; 8]; 2];
+        // The weakest first, so the order of the flips is known
+        for (n, (position, bit)) in weak.iter().enumerate() {
+            let confidence = 0.01 * (n + 1) as f32;
+            if *position < txt.len() {
+                decoder.blk.confidence[*position][*bit] = confidence;
+            } else {
+                decoder.blk.crc_confidence[position - txt.len()][*bit] = confidence;
+            }
+        }
+        decoder.parity_and_crc_check();
+
+        let mut messages = vec![];
+        while let Ok(message) = rx.try_recv() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[test]
+    fn test_process_block() {
+        let crc = |body: &[u8]| {
+            let [crc0, crc1, ..] = body
+                .iter()
+                .fold(0, |crc, c| ACARSDecoder::update_crc(crc, u32::from(*c)))
+                .to_le_bytes();
+            [crc0, crc1]
+        };
+
+        // No text, text that ends inside the message number and flight id, and the longest
+        // text a block can carry. An empty text is output as a single NUL
+        let cases: [(usize, Option<[char; 6]>, usize); 5] = [
+            (0, None, 1),
+            (3, Some([' '; 6]), 0),
+            (6, Some(['U', 'A', ' ', ' ', ' ', ' ']), 0),
+            (10, Some(['U', 'A', '0', '1', '2', '3']), 0),
+            (220, Some(['U', 'A', '0', '1', '2', '3']), 210),
+        ];
+        for (length, flight_id, text_length) in cases {
+            let body = downlink_block(['H', '1'], length).body();
+            let messages = process_block(&body, crc(&body));
+            assert_eq!(messages.len(), 1, "{length}");
+            assert_eq!(messages[0].status, MessageStatus::Valid, "{length}");
+            assert_eq!(messages[0].label, ['H', '1'], "{length}");
+            assert_eq!(messages[0].flight_id, flight_id, "{length}");
+            assert_eq!(
+                messages[0].message_text.as_ref().map_or(0, Vec::len),
+                text_length,
+                "{length}"
+            );
+        }
+
+        let mut body = downlink_block(['H', '1'], 60).body();
+        let [crc0, crc1] = crc(&body);
+
+        // A parity error is fixed
+        body[20] ^= 0x04;
+        let messages = process_block(&body, [crc0, crc1]);
+        assert_eq!(messages[0].status, MessageStatus::Valid);
+        assert_eq!(messages[0].corrected_bits, 1);
+
+        // Malformed blocks are dropped or flagged, never parsed out of bounds
+        assert!(process_block(&body[..12], [crc0, crc1]).is_empty());
+        let messages = process_block(&[0xff; 400], [0xff, 0xff]);
+        assert_eq!(messages[0].status, MessageStatus::CrcFailed);
+        assert_eq!(process_block(&[ETX; 13], [0, 0]).len(), 1);
+    }
+
+    #[test]
+    fn test_miscorrection() {
+        let body = AcarsBlock::new("N923US", ['H', '1'], '3', &downlink_text(60)).body();
+        let crc = |body: &[u8]| {
+            let [crc0, crc1, ..] = body
+                .iter()
+                .fold(0, |crc, c| ACARSDecoder::update_crc(crc, u32::from(*c)))
+                .to_le_bytes();
+            [crc0, crc1]
+        };
+
+        // The only correction the CRC allows puts a control character in the text
+        let mut control = body.clone();
+        control[30] = 0x10;
+        let crc_control = crc(&control);
+        control[30] ^= 0x40;
+        let messages = process_block(&control, crc_control);
+        assert_eq!(messages[0].status, MessageStatus::CrcFailed);
+        let mut line_break = body.clone();
+        line_break[30] = 0x0d;
+        let crc_line_break = crc(&line_break);
+        line_break[30] ^= 0x40;
+        let messages = process_block(&line_break, crc_line_break);
+        assert_eq!(messages[0].status, MessageStatus::Valid);
+
+        // A bit of the CRC is only guessed along with a single parity error
+        let [crc0, crc1] = crc(&body);
+        let mut damaged = body;
+        damaged[20] ^= 0x04;
+        let messages = process_block(&damaged, [crc0 ^ 0x10, crc1]);
+        assert_eq!(messages[0].status, MessageStatus::Valid);
+        assert_eq!(messages[0].corrected_bits, 2);
+        damaged[40] ^= 0x04;
+        let messages = process_block(&damaged, [crc0, crc1]);
+        assert_eq!(messages[0].status, MessageStatus::Valid);
+        let messages = process_block(&damaged, [crc0 ^ 0x10, crc1]);
+        assert_eq!(messages[0].status, MessageStatus::CrcFailed);
+    }
+
+    #[test]
+    fn test_soft_decisions() {
+        // A long block from a distant aircraft, with the errors spread over all of it
+        let body = downlink_block(['H', '1'], 220).body();
+        let [crc0, crc1, ..] = body
+            .iter()
+            .fold(0, |crc, c| ACARSDecoder::update_crc(crc, u32::from(*c)))
+            .to_le_bytes();
+        let crc_position = body.len();
+
+        // Too many parity errors for the hard decision decoder, two flips in one byte that
+        // leave its parity alone, and a flip in the crc. All of them among the weakest bits
+        let flipped = [
+            (14, 2),
+            (60, 5),
+            (110, 0),
+            (160, 1),
+            (160, 6),
+            (crc_position - 2, 3),
+            (crc_position + 1, 4),
+        ];
+        let weak: Vec<(usize, usize)> = flipped
+            .iter()
+            .copied()
+            .chain([(24, 1), (125, 2), (200, 3)])
+            .collect();
+        let mut txt = body.clone();
+        let mut crc = [crc0, crc1];
+        for (position, bit) in flipped {
+            if position < crc_position {
+                txt[position] ^= 1 << bit;
+            } else {
+                crc[position - crc_position] ^= 1 << bit;
+            }
+        }
+
+        let messages = process_soft_block(&txt, crc, &weak);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].status, MessageStatus::Valid);
+        assert_eq!(messages[0].corrected_bits, 7);
+        assert_eq!(
+            messages[0].message_text,
+            Some(downlink_text(220).chars().skip(10).collect())
+        );
+
+        // A flipped bit the demodulator was sure of can't be found
+        let mut weak = weak;
+        weak.remove(0);
+        weak.push((90, 4));
+        let messages = process_soft_block(&txt, crc, &weak);
+        assert_eq!(messages[0].status, MessageStatus::CrcFailed);
+
+        // Nor can more flips than the number of bits tried
+        let flipped: Vec<(usize, usize)> = (0..=CHASE_BITS).map(|n| (14 + n * 20, 1)).collect();
+        let mut txt = body;
+        for (position, bit) in &flipped {
+            txt[*position] ^= 1 << bit;
+        }
+        let messages = process_soft_block(&txt, [crc0, crc1], &flipped);
+        assert_eq!(messages[0].status, MessageStatus::CrcFailed);
+        assert_eq!(messages[0].corrected_bits, 0);
+    }
+
+    #[test]
+    fn test_oversized_block() {
+        // One character more than the buffer holds, with errors at the start of the body so the
+        // error correction would search the whole block: two bits of one character, which only
+        // the CRC sees, or a parity error
+        let block = AcarsBlock::new("N923US", ['H', '1'], '3', &downlink_text(227));
+        assert_eq!(block.body().len(), MAX_BLOCK_LEN + 1);
+        let clean = block.to_bytes();
+        let body = clean.len() - 3 - block.body().len();
+        let mut bad_crc = clean.clone();
+        bad_crc[body + 1] ^= 0x03;
+        let mut bad_parity = clean;
+        bad_parity[body + 1] ^= 0x04;
+
+        for bytes in [bad_crc, bad_parity] {
+            let mut decoder = ACARSDecoder::new(0, 131_550_000, [Complex::new(0.0, 0.0); 192]);
+            let (tx, mut rx) = mpsc::unbounded_channel();
+            decoder.set_output_channel(tx);
+            decoder.set_emit_errors(true);
+            let samples = AcarsModulator::new(f64::from(INTRATE_F32), 0.0).modulate_bytes(&bytes);
+            decode_samples(&mut decoder, &samples);
+            assert!(rx.try_recv().is_err());
+        }
+    }
+
+    #[test]
+    fn test_snr() {
+        let block = AcarsBlock::new("N923US", ['H', '1'], '3', &downlink_text(100));
+        let decode = |mut modulator: AcarsModulator| {
+            let mut decoder = ACARSDecoder::new(0, 131_550_000, [Complex::new(0.0, 0.0); 192]);
+            let (tx, mut rx) = mpsc::unbounded_channel();
+            decoder.set_output_channel(tx);
+            decode_samples(&mut decoder, &modulator.modulate(&block));
+            let Ok(message) = rx.try_recv() else {
+                panic!("Message not decoded");
+            };
+            message
+        };
+
+        // The noise before the block sets the floor the message is measured against
+        for snr in [20.0, 30.0] {
+            for seed in 1..=3 {
+                let message = decode(
+                    AcarsModulator::new(f64::from(INTRATE_F32), 0.0)
+                        .snr(Some(snr))
+                        .seed(seed)
+                        .silence(1.0),
+                );
+                let Some(measured) = message.snr else {
+                    panic!("No SNR at {snr} dB");
+                };
+                assert!(
+                    (f64::from(measured) - snr).abs() < 3.0,
+                    "Measured {measured} dB for {snr} dB with seed {seed}"
+                );
+                assert!(message.noise_level.is_some());
+            }
+        }
+
+        // Not enough noise before the block to measure the floor against, or none at all
+        let message = decode(
+            AcarsModulator::new(f64::from(INTRATE_F32), 0.0)
+                .snr(Some(20.0))
+                .silence(0.2),
+        );
+        assert_eq!(message.noise_level, None);
+        assert_eq!(message.snr, None);
+        let message = decode(AcarsModulator::new(f64::from(INTRATE_F32), 0.0).silence(1.0));
+        assert_eq!(message.noise_level, None);
+        assert_eq!(message.snr, None);
+    }
+
+    #[test]
+    fn test_metrics() -> Result<(), oxide_metrics::MetricsError> {
+        let crc = |body: &[u8]| {
+            let [crc0, crc1, ..] = body
+                .iter()
+                .fold(0, |crc, c| ACARSDecoder::update_crc(crc, u32::from(*c)))
+                .to_le_bytes();
+            [crc0, crc1]
+        };
+        let position = downlink_block(['H', '1'], 40).body();
+        let link_test = downlink_block(['Q', '0'], 0).body();
+
+        let metrics = Arc::new(oxide_metrics::Metrics::new()?);
+        let mut decoder = ACARSDecoder::new(0, 131_550_000, [Complex::new(0.0, 0.0); 192]);
+        decoder.set_metrics(ChannelMetrics::new(metrics.clone(), "00012785", 131.55));
+        decoder.process_block(&position, crc(&position));
+        decoder.process_block(&link_test, crc(&link_test));
+        // A parity error that is fixed
+        let mut damaged = position.clone();
+        damaged[20] ^= 0x04;
+        decoder.process_block(&damaged, crc(&position));
+        let [crc0, crc1] = crc(&link_test);
+        decoder.process_block(&link_test, [crc1, crc0]);
+
+        // Messages are counted per label, and failures are counted even when they are not output
+        let text = metrics.encode()?;
+        for series in [
+            r#"acars_oxide_messages_total{frequency="131.550",label="H1",sdr="00012785"} 2"#,
+            r#"acars_oxide_messages_total{frequency="131.550",label="Q0",sdr="00012785"} 1"#,
+            r#"acars_oxide_parity_fixes_total{frequency="131.550",sdr="00012785"} 1"#,
+            r#"acars_oxide_corrected_bits_total{frequency="131.550",sdr="00012785"} 1"#,
+            r#"acars_oxide_crc_failures_total{frequency="131.550",sdr="00012785"} 1"#,
+        ] {
+            assert!(text.contains(series), "{series}");
+        }
+
+        Ok(())
+    }
+}
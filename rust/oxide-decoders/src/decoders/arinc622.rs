@@ -0,0 +1,1895 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// ARINC 622 application decoding. ATS applications (ADS-C, FANS-1/A CPDLC) are carried over
+// ACARS as text of the form
+//
+//   /<ground address>.<IMI><aircraft registration><hex encoded payload><hex encoded CRC>
+//
+// where the IMI (Imbedded Message Identifier) is three characters identifying the application
+// and the registration is seven characters, padded on the left with dots. The CRC covers the IMI,
+// the registration and the binary payload.
+
+// The application formats are bit packed, so plenty of sign and precision juggling is needed
+#![allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap,
+    clippy::cast_sign_loss
+)]
+
+use crate::decoders::acars::DownlinkStatus;
+use serde::Serialize;
+use std::fmt::{self, Display, Formatter};
+
+/// Labels that carry ARINC 622 messages
+const ARINC622_LABELS: [[char; 2]; 5] =
+    [['H', '1'], ['A', 'A'], ['B', 'A'], ['A', '6'], ['B', '6']];
+const IMI_LEN: usize = 3;
+const REGISTRATION_LEN: usize = 7;
+const CRC_LEN: usize = 2;
+/// Element numbers are a PER choice index, sized for UM0 to UM182 and DM0 to DM80
+const UPLINK_ELEMENT_BITS: usize = 8;
+const DOWNLINK_ELEMENT_BITS: usize = 7;
+/// Length of the up to four elements that follow the first
+const ADDITIONAL_ELEMENTS_BITS: usize = 2;
+
+/// The application a message is addressed to, from its IMI
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Imi {
+    /// ADS-C
+    Ads,
+    /// FANS-1/A CPDLC
+    At1,
+    /// CPDLC connect request
+    Cr1,
+    /// CPDLC connect confirm
+    Cc1,
+    /// CPDLC disconnect request
+    Dr1,
+    /// An IMI we do not decode
+    Other(String),
+}
+
+impl Imi {
+    fn parse(imi: &str) -> Self {
+        match imi {
+            "ADS" => Self::Ads,
+            "AT1" => Self::At1,
+            "CR1" => Self::Cr1,
+            "CC1" => Self::Cc1,
+            "DR1" => Self::Dr1,
+            other => Self::Other(other.to_string()),
+        }
+    }
+}
+
+impl Display for Imi {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ads => write!(f, "ADS-C"),
+            Self::At1 => write!(f, "CPDLC"),
+            Self::Cr1 => write!(f, "CPDLC Connect Request"),
+            Self::Cc1 => write!(f, "CPDLC Connect Confirm"),
+            Self::Dr1 => write!(f, "CPDLC Disconnect Request"),
+            Self::Other(imi) => write!(f, "{imi}"),
+        }
+    }
+}
+
+/// A decoded ARINC 622 message
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Arinc622Message {
+    /// Seven character address of the ground system
+    pub ground_address: String,
+    pub imi: Imi,
+    /// Aircraft registration, without the padding dots
+    pub aircraft_registration: String,
+    /// True if the CRC of the message was correct. If false, the decoded data should not be trusted
+    pub crc_ok: bool,
+    pub application: Arinc622Application,
+}
+
+/// The decoded application data of an ARINC 622 message
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Arinc622Application {
+    /// ADS-C downlink, the reports from the aircraft
+    AdsC(Vec<AdscGroup>),
+    /// ADS-C uplink, the contract requests from the ground
+    AdsCRequest(Vec<AdscRequest>),
+    Cpdlc(CpdlcMessage),
+    /// The application is known but was not decoded. Holds the binary payload
+    Undecoded(Vec<u8>),
+}
+
+/// A position as reported by ADS-C
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct AdscPosition {
+    /// Degrees, positive north
+    pub latitude: f64,
+    /// Degrees, positive east
+    pub longitude: f64,
+    /// Feet
+    pub altitude: i32,
+}
+
+/// A single group from an ADS-C downlink
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdscGroup {
+    Acknowledgement {
+        contract_number: u8,
+    },
+    NegativeAcknowledgement {
+        contract_number: u8,
+        reason: u8,
+    },
+    CancelEmergency,
+    /// Basic report. The tag tells which kind of report triggered it
+    BasicReport {
+        report_type: AdscReportType,
+        position: AdscPosition,
+        /// Seconds past the hour
+        timestamp: f64,
+        navigation_redundancy: bool,
+        /// Figure of merit of the position, 0 (unknown) to 7 (best)
+        position_accuracy: u8,
+        tcas_healthy: bool,
+    },
+    FlightId(String),
+    PredictedRoute {
+        next_waypoint: AdscPosition,
+        /// Seconds until the next waypoint
+        next_waypoint_eta: u32,
+        next_next_waypoint: AdscPosition,
+    },
+    EarthReference {
+        /// Degrees, None if not valid
+        true_track: Option<f64>,
+        /// Knots
+        ground_speed: f64,
+        /// Feet per minute
+        vertical_speed: i32,
+    },
+    AirReference {
+        /// Degrees, None if not valid
+        true_heading: Option<f64>,
+        mach: f64,
+        /// Feet per minute
+        vertical_speed: i32,
+    },
+    Meteo {
+        /// Knots
+        wind_speed: f64,
+        /// Degrees, None if not valid
+        wind_direction: Option<f64>,
+        /// Degrees Celsius
+        temperature: f64,
+    },
+    AirframeId {
+        icao_address: u32,
+    },
+    /// A tag we do not know the format of. Decoding stops here since the length is not known
+    Unknown {
+        tag: u8,
+        data: Vec<u8>,
+    },
+}
+
+/// What triggered an ADS-C basic report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdscReportType {
+    Periodic,
+    Emergency,
+    LateralDeviationChange,
+    VerticalRateChange,
+    AltitudeRange,
+    WaypointChange,
+}
+
+impl Display for AdscReportType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Periodic => write!(f, "Basic Report"),
+            Self::Emergency => write!(f, "Emergency Basic Report"),
+            Self::LateralDeviationChange => write!(f, "Lateral Deviation Change Event"),
+            Self::VerticalRateChange => write!(f, "Vertical Rate Change Event"),
+            Self::AltitudeRange => write!(f, "Altitude Range Event"),
+            Self::WaypointChange => write!(f, "Waypoint Change Event"),
+        }
+    }
+}
+
+/// A single group from an ADS-C uplink
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdscRequest {
+    CancelAllContracts,
+    CancelContract {
+        contract_number: u8,
+    },
+    /// Starts a contract. The groups that follow, up to the next contract, make up its terms
+    Contract {
+        contract_type: AdscContractType,
+        contract_number: u8,
+    },
+    LateralDeviationChange {
+        /// Nautical miles
+        threshold: f64,
+    },
+    ReportingInterval {
+        /// Seconds
+        interval: u32,
+    },
+    /// Include a group in every modulus'th report
+    Modulus {
+        group: AdscDataGroup,
+        modulus: u8,
+    },
+    VerticalRateChange {
+        /// Feet per minute
+        threshold: i32,
+    },
+    AltitudeRange {
+        /// Feet
+        ceiling: i32,
+        /// Feet
+        floor: i32,
+    },
+    WaypointChange,
+    /// A tag we do not know the format of. Decoding stops here since the length is not known
+    Unknown {
+        tag: u8,
+        data: Vec<u8>,
+    },
+}
+
+/// The kind of contract an ADS-C uplink requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdscContractType {
+    Periodic,
+    Event,
+    EmergencyPeriodic,
+}
+
+impl Display for AdscContractType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Periodic => write!(f, "Periodic Contract"),
+            Self::Event => write!(f, "Event Contract"),
+            Self::EmergencyPeriodic => write!(f, "Emergency Periodic Contract"),
+        }
+    }
+}
+
+/// The optional groups a contract can ask to be added to the reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdscDataGroup {
+    FlightId,
+    PredictedRoute,
+    EarthReference,
+    AirReference,
+    Meteo,
+    AirframeId,
+}
+
+impl Display for AdscDataGroup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FlightId => write!(f, "Flight ID"),
+            Self::PredictedRoute => write!(f, "Predicted Route"),
+            Self::EarthReference => write!(f, "Earth Reference"),
+            Self::AirReference => write!(f, "Air Reference"),
+            Self::Meteo => write!(f, "Meteo"),
+            Self::AirframeId => write!(f, "Airframe ID"),
+        }
+    }
+}
+
+/// A FANS-1/A CPDLC message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CpdlcMessage {
+    pub downlink: bool,
+    pub message_id: u8,
+    pub message_reference: Option<u8>,
+    /// Hours, minutes and seconds
+    pub timestamp: Option<(u8, u8, u8)>,
+    /// The message elements, in the order they were sent. There is always at least one
+    pub elements: Vec<CpdlcElement>,
+    /// Decoding stopped at an element whose parameters we cannot decode. That element's
+    /// parameters, and any elements after it, are missing
+    pub truncated: bool,
+}
+
+/// A single element of a CPDLC message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CpdlcElement {
+    /// UM or DM number of the element
+    pub id: u8,
+    /// Text of the element, with placeholders for any parameters
+    pub text: Option<&'static str>,
+    pub parameters: Vec<CpdlcParameter>,
+}
+
+/// A decoded parameter of a CPDLC message element
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CpdlcParameter {
+    /// Feet, QNH
+    Altitude(u32),
+    FlightLevel(u16),
+    /// Four octal digits
+    BeaconCode(String),
+    FreeText(String),
+}
+
+/// The parameters a message element carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParameterType {
+    Altitude,
+    BeaconCode,
+    FreeText,
+    /// A parameter we do not decode. Since its length is not known, neither is where the next
+    /// element starts
+    Unsupported,
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    const fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    const fn remaining(&self) -> usize {
+        self.data.len() * 8 - self.position
+    }
+
+    /// Read up to 32 bits, most significant bit first
+    fn read(&mut self, bits: usize) -> Option<u32> {
+        if bits > 32 || self.remaining() < bits {
+            return None;
+        }
+
+        let mut value: u32 = 0;
+        for _ in 0..bits {
+            let byte = self.data[self.position / 8];
+            let bit = (byte >> (7 - self.position % 8)) & 1;
+            value = (value << 1) | u32::from(bit);
+            self.position += 1;
+        }
+
+        Some(value)
+    }
+
+    /// Read a two's complement value
+    fn read_signed(&mut self, bits: usize) -> Option<i32> {
+        let value = self.read(bits)?;
+        let shift = 32 - bits;
+        Some(((value << shift) as i32) >> shift)
+    }
+
+    fn read_bool(&mut self) -> Option<bool> {
+        self.read(1).map(|bit| bit == 1)
+    }
+}
+
+/// Decode an ARINC 622 message from the text of an ACARS message, if the label and text look
+/// like one.
+#[must_use]
+pub fn decode(
+    label: [char; 2],
+    message_text: &[char],
+    downlink_status: &DownlinkStatus,
+) -> Option<Arinc622Message> {
+    if !ARINC622_LABELS.contains(&label) {
+        return None;
+    }
+
+    let text: String = message_text.iter().collect();
+    let text = text.trim_end_matches(['\0', '\r', '\n']);
+    let text = text.strip_prefix('/')?;
+    let (ground_address, rest) = text.split_once('.')?;
+
+    if ground_address.is_empty() || rest.len() < IMI_LEN + REGISTRATION_LEN + CRC_LEN * 2 {
+        return None;
+    }
+
+    let imi = rest.get(..IMI_LEN)?;
+    let registration = rest.get(IMI_LEN..IMI_LEN + REGISTRATION_LEN)?;
+    let payload = hex_decode(rest.get(IMI_LEN + REGISTRATION_LEN..)?)?;
+
+    if !imi
+        .chars()
+        .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+    {
+        return None;
+    }
+
+    let mut crc_data = Vec::with_capacity(IMI_LEN + REGISTRATION_LEN + payload.len());
+    crc_data.extend_from_slice(imi.as_bytes());
+    crc_data.extend_from_slice(registration.as_bytes());
+    crc_data.extend_from_slice(&payload);
+    let crc_ok = crc_ok(&crc_data);
+
+    let payload = &payload[..payload.len() - CRC_LEN];
+    let imi = Imi::parse(imi);
+    let downlink = *downlink_status == DownlinkStatus::AirToGround;
+
+    let application = match imi {
+        Imi::Ads if downlink => Arinc622Application::AdsC(decode_adsc(payload)),
+        Imi::Ads => Arinc622Application::AdsCRequest(decode_adsc_request(payload)),
+        Imi::At1 => decode_cpdlc(payload, downlink).map_or_else(
+            || Arinc622Application::Undecoded(payload.to_vec()),
+            Arinc622Application::Cpdlc,
+        ),
+        _ => Arinc622Application::Undecoded(payload.to_vec()),
+    };
+
+    Some(Arinc622Message {
+        ground_address: ground_address.to_string(),
+        imi,
+        aircraft_registration: registration.trim_start_matches('.').to_string(),
+        crc_ok,
+        application,
+    })
+}
+
+fn hex_decode(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// CRC-16/CCITT over the data, which ends with the CRC itself, most significant byte first.
+/// The CRC is sent inverted.
+fn crc_ok(data: &[u8]) -> bool {
+    if data.len() < CRC_LEN {
+        return false;
+    }
+
+    let (data, received) = data.split_at(data.len() - CRC_LEN);
+    let received = u16::from_be_bytes([received[0], received[1]]);
+
+    crc16(data) == received
+}
+
+pub(crate) fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xffff;
+    for byte in data {
+        crc ^= u16::from(*byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 == 0 {
+                crc << 1
+            } else {
+                (crc << 1) ^ 0x1021
+            };
+        }
+    }
+    !crc
+}
+
+/// Latitude or longitude, 21 bit two's complement with the top bit being 180 degrees
+fn read_coordinate(reader: &mut BitReader) -> Option<f64> {
+    Some(f64::from(reader.read_signed(21)?) * 180.0 / f64::from(1 << 20))
+}
+
+fn read_position(reader: &mut BitReader) -> Option<AdscPosition> {
+    Some(AdscPosition {
+        latitude: read_coordinate(reader)?,
+        longitude: read_coordinate(reader)?,
+        altitude: reader.read_signed(16)? * 4,
+    })
+}
+
+/// Track, heading or direction with a leading invalid flag. Returns if the angle is valid and
+/// the angle in degrees
+fn read_angle(reader: &mut BitReader, bits: usize, signed: bool) -> Option<(bool, f64)> {
+    let invalid = reader.read_bool()?;
+    let value = if signed {
+        f64::from(reader.read_signed(bits)?) * 180.0 / f64::from(1 << (bits - 1))
+    } else {
+        f64::from(reader.read(bits)?) * 180.0 / f64::from(1 << (bits - 1))
+    };
+    let value = if value < 0.0 { value + 360.0 } else { value };
+
+    Some((!invalid, value))
+}
+
+fn decode_adsc_group(tag: u8, reader: &mut BitReader) -> Option<AdscGroup> {
+    let report_type = match tag {
+        7 => Some(AdscReportType::Periodic),
+        9 => Some(AdscReportType::Emergency),
+        10 => Some(AdscReportType::LateralDeviationChange),
+        18 => Some(AdscReportType::VerticalRateChange),
+        19 => Some(AdscReportType::AltitudeRange),
+        20 => Some(AdscReportType::WaypointChange),
+        _ => None,
+    };
+
+    if let Some(report_type) = report_type {
+        let position = read_position(reader)?;
+        let timestamp = f64::from(reader.read(15)?) * 0.125;
+        let navigation_redundancy = reader.read_bool()?;
+        let position_accuracy = reader.read(3)? as u8;
+        let tcas_healthy = reader.read_bool()?;
+        // two spare bits pad the report out to 10 bytes
+        reader.read(2)?;
+
+        return Some(AdscGroup::BasicReport {
+            report_type,
+            position,
+            timestamp,
+            navigation_redundancy,
+            position_accuracy,
+            tcas_healthy,
+        });
+    }
+
+    match tag {
+        3 => Some(AdscGroup::Acknowledgement {
+            contract_number: reader.read(8)? as u8,
+        }),
+        4 => Some(AdscGroup::NegativeAcknowledgement {
+            contract_number: reader.read(8)? as u8,
+            reason: reader.read(8)? as u8,
+        }),
+        6 => Some(AdscGroup::CancelEmergency),
+        12 => {
+            let mut flight_id = String::with_capacity(8);
+            for _ in 0..8 {
+                // ISO 5 six bit characters. Letters have the top bit clear
+                let c = reader.read(6)? as u8;
+                flight_id.push(char::from(if c & 0x20 == 0 { c | 0x40 } else { c }));
+            }
+            Some(AdscGroup::FlightId(flight_id.trim().to_string()))
+        }
+        13 => {
+            let next_waypoint = read_position(reader)?;
+            let next_waypoint_eta = reader.read(14)?;
+            let next_next_waypoint = read_position(reader)?;
+            reader.read(6)?;
+            Some(AdscGroup::PredictedRoute {
+                next_waypoint,
+                next_waypoint_eta,
+                next_next_waypoint,
+            })
+        }
+        14 => {
+            let (true_track_valid, true_track) = read_angle(reader, 12, true)?;
+            let ground_speed = f64::from(reader.read(13)?) * 0.5;
+            let vertical_speed = reader.read_signed(12)? * 16;
+            reader.read(2)?;
+            Some(AdscGroup::EarthReference {
+                true_track: true_track_valid.then_some(true_track),
+                ground_speed,
+                vertical_speed,
+            })
+        }
+        15 => {
+            let (true_heading_valid, true_heading) = read_angle(reader, 12, true)?;
+            let mach = f64::from(reader.read(13)?) * 0.0005;
+            let vertical_speed = reader.read_signed(12)? * 16;
+            reader.read(2)?;
+            Some(AdscGroup::AirReference {
+                true_heading: true_heading_valid.then_some(true_heading),
+                mach,
+                vertical_speed,
+            })
+        }
+        16 => {
+            let wind_speed = f64::from(reader.read(9)?) * 0.5;
+            let (wind_direction_valid, wind_direction) = read_angle(reader, 9, false)?;
+            let temperature = f64::from(reader.read_signed(12)?) * 0.25;
+            reader.read(1)?;
+            Some(AdscGroup::Meteo {
+                wind_speed,
+                wind_direction: wind_direction_valid.then_some(wind_direction),
+                temperature,
+            })
+        }
+        17 => Some(AdscGroup::AirframeId {
+            icao_address: reader.read(24)?,
+        }),
+        _ => None,
+    }
+}
+
+fn decode_adsc_request_group(tag: u8, reader: &mut BitReader) -> Option<AdscRequest> {
+    let contract_type = match tag {
+        7 => Some(AdscContractType::Periodic),
+        8 => Some(AdscContractType::Event),
+        9 => Some(AdscContractType::EmergencyPeriodic),
+        _ => None,
+    };
+
+    if let Some(contract_type) = contract_type {
+        return Some(AdscRequest::Contract {
+            contract_type,
+            contract_number: reader.read(8)? as u8,
+        });
+    }
+
+    let group = match tag {
+        12 => Some(AdscDataGroup::FlightId),
+        13 => Some(AdscDataGroup::PredictedRoute),
+        14 => Some(AdscDataGroup::EarthReference),
+        15 => Some(AdscDataGroup::AirReference),
+        16 => Some(AdscDataGroup::Meteo),
+        17 => Some(AdscDataGroup::AirframeId),
+        _ => None,
+    };
+
+    if let Some(group) = group {
+        return Some(AdscRequest::Modulus {
+            group,
+            modulus: reader.read(8)? as u8,
+        });
+    }
+
+    match tag {
+        1 => Some(AdscRequest::CancelAllContracts),
+        2 => Some(AdscRequest::CancelContract {
+            contract_number: reader.read(8)? as u8,
+        }),
+        10 => Some(AdscRequest::LateralDeviationChange {
+            threshold: f64::from(reader.read(8)?) * 0.125,
+        }),
+        11 => {
+            // two bits pick the scaling factor for the six bit rate
+            let scale = [1, 8, 64, 512][reader.read(2)? as usize];
+            Some(AdscRequest::ReportingInterval {
+                interval: reader.read(6)? * scale,
+            })
+        }
+        18 => Some(AdscRequest::VerticalRateChange {
+            threshold: reader.read_signed(8)? * 64,
+        }),
+        19 => Some(AdscRequest::AltitudeRange {
+            ceiling: reader.read_signed(16)? * 4,
+            floor: reader.read_signed(16)? * 4,
+        }),
+        20 => Some(AdscRequest::WaypointChange),
+        _ => None,
+    }
+}
+
+/// Split an ADS-C message into its tagged groups. A tag we cannot decode ends the message,
+/// since we do not know how long it is
+fn decode_tagged<T>(
+    payload: &[u8],
+    decode_group: fn(u8, &mut BitReader) -> Option<T>,
+    unknown: fn(u8, Vec<u8>) -> T,
+) -> Vec<T> {
+    let mut groups = vec![];
+    let mut reader = BitReader::new(payload);
+
+    while let Some(tag) = reader.read(8) {
+        let tag = tag as u8;
+        let start = reader.position;
+
+        if let Some(group) = decode_group(tag, &mut reader) {
+            groups.push(group);
+        } else {
+            groups.push(unknown(tag, payload[start / 8..].to_vec()));
+            break;
+        }
+    }
+
+    groups
+}
+
+fn decode_adsc(payload: &[u8]) -> Vec<AdscGroup> {
+    decode_tagged(payload, decode_adsc_group, |tag, data| AdscGroup::Unknown {
+        tag,
+        data,
+    })
+}
+
+fn decode_adsc_request(payload: &[u8]) -> Vec<AdscRequest> {
+    decode_tagged(payload, decode_adsc_request_group, |tag, data| {
+        AdscRequest::Unknown { tag, data }
+    })
+}
+
+/// The parameters of a message element, from the placeholders in its text
+fn parameter_types(text: &str) -> impl Iterator<Item = ParameterType> + '_ {
+    text.split('[')
+        .skip(1)
+        .map(|placeholder| match placeholder.split(']').next() {
+            Some("altitude") => ParameterType::Altitude,
+            Some("beacon code") => ParameterType::BeaconCode,
+            Some("free text") => ParameterType::FreeText,
+            _ => ParameterType::Unsupported,
+        })
+}
+
+fn read_parameter(parameter_type: ParameterType, reader: &mut BitReader) -> Option<CpdlcParameter> {
+    match parameter_type {
+        // a choice of eight altitude types. Only feet QNH and flight levels are decoded
+        ParameterType::Altitude => match reader.read(3)? {
+            0 => Some(CpdlcParameter::Altitude(reader.read(12)? * 10)),
+            6 => Some(CpdlcParameter::FlightLevel(reader.read(10)? as u16 + 30)),
+            _ => None,
+        },
+        ParameterType::BeaconCode => (0..4)
+            .map(|_| char::from_digit(reader.read(3)?, 8))
+            .collect::<Option<String>>()
+            .map(CpdlcParameter::BeaconCode),
+        // IA5String (SIZE (1..256)), seven bits a character
+        ParameterType::FreeText => {
+            let length = reader.read(8)? + 1;
+            (0..length)
+                .map(|_| reader.read(7).map(|c| char::from(c as u8)))
+                .collect::<Option<String>>()
+                .map(CpdlcParameter::FreeText)
+        }
+        ParameterType::Unsupported => None,
+    }
+}
+
+/// Decode one message element. The flag is false if its parameters could not be decoded, in
+/// which case we do not know where the next element starts
+fn decode_cpdlc_element(reader: &mut BitReader, downlink: bool) -> Option<(CpdlcElement, bool)> {
+    let (id, text) = if downlink {
+        let id = reader.read(DOWNLINK_ELEMENT_BITS)? as u8;
+        (id, downlink_element_text(id))
+    } else {
+        let id = reader.read(UPLINK_ELEMENT_BITS)? as u8;
+        (id, uplink_element_text(id))
+    };
+
+    let parameters = text.and_then(|text| {
+        parameter_types(text)
+            .map(|parameter_type| read_parameter(parameter_type, reader))
+            .collect::<Option<Vec<_>>>()
+    });
+    let complete = parameters.is_some();
+
+    Some((
+        CpdlcElement {
+            id,
+            text,
+            parameters: parameters.unwrap_or_default(),
+        },
+        complete,
+    ))
+}
+
+fn decode_cpdlc(payload: &[u8], downlink: bool) -> Option<CpdlcMessage> {
+    // Unaligned PER of
+    //
+    //   SEQUENCE { header, element, SEQUENCE SIZE (1..4) OF element OPTIONAL }
+    //
+    // The presence bits of a sequence's optional components come before all of its components,
+    // so the bit for the additional elements is sent ahead of the header, which then starts
+    // with its own presence bits for the reference number and timestamp
+    let mut reader = BitReader::new(payload);
+
+    let has_additional_elements = reader.read_bool()?;
+    let has_reference = reader.read_bool()?;
+    let has_timestamp = reader.read_bool()?;
+    let message_id = reader.read(6)? as u8;
+
+    let message_reference = if has_reference {
+        Some(reader.read(6)? as u8)
+    } else {
+        None
+    };
+
+    let timestamp = if has_timestamp {
+        Some((
+            reader.read(5)? as u8,
+            reader.read(6)? as u8,
+            reader.read(6)? as u8,
+        ))
+    } else {
+        None
+    };
+
+    let (element, complete) = decode_cpdlc_element(&mut reader, downlink)?;
+    let mut elements = vec![element];
+    let mut truncated = !complete;
+
+    if has_additional_elements && !truncated {
+        let count = reader.read(ADDITIONAL_ELEMENTS_BITS).map(|count| count + 1);
+        truncated = count.is_none();
+
+        for _ in 0..count.unwrap_or(0) {
+            let Some((element, complete)) = decode_cpdlc_element(&mut reader, downlink) else {
+                truncated = true;
+                break;
+            };
+
+            elements.push(element);
+            if !complete {
+                truncated = true;
+                break;
+            }
+        }
+    }
+
+    Some(CpdlcMessage {
+        downlink,
+        message_id,
+        message_reference,
+        timestamp,
+        elements,
+        truncated,
+    })
+}
+
+const fn uplink_element_text(element_id: u8) -> Option<&'static str> {
+    Some(match element_id {
+        0 => "UNABLE",
+        1 => "STANDBY",
+        2 => "REQUEST DEFERRED",
+        3 => "ROGER",
+        4 => "AFFIRM",
+        5 => "NEGATIVE",
+        6 => "EXPECT [altitude]",
+        19 => "MAINTAIN [altitude]",
+        20 => "CLIMB TO AND MAINTAIN [altitude]",
+        23 => "DESCEND TO AND MAINTAIN [altitude]",
+        74 => "PROCEED DIRECT TO [position]",
+        79 => "CLEARED TO [position] VIA [route clearance]",
+        80 => "CLEARED [route clearance]",
+        106 => "MAINTAIN [speed]",
+        116 => "RESUME NORMAL SPEED",
+        117 => "CONTACT [icao unit name] [frequency]",
+        120 => "MONITOR [icao unit name] [frequency]",
+        123 => "SQUAWK [beacon code]",
+        133 => "REPORT PRESENT LEVEL",
+        148 => "WHEN CAN YOU ACCEPT [altitude]",
+        157 => "CHECK STUCK MICROPHONE [frequency]",
+        159 => "ERROR [error information]",
+        160 => "NEXT DATA AUTHORITY [icao facility designation]",
+        161 => "END SERVICE",
+        162 => "SERVICE UNAVAILABLE",
+        164 => "WHEN READY",
+        165 => "THEN",
+        166 => "DUE TO TRAFFIC",
+        167 => "DUE TO AIRSPACE RESTRICTION",
+        168 => "DISREGARD",
+        169 => "[free text]",
+        179 => "SQUAWK IDENT",
+        _ => return None,
+    })
+}
+
+const fn downlink_element_text(element_id: u8) -> Option<&'static str> {
+    Some(match element_id {
+        0 => "WILCO",
+        1 => "UNABLE",
+        2 => "STANDBY",
+        3 => "ROGER",
+        4 => "AFFIRM",
+        5 => "NEGATIVE",
+        6 => "REQUEST [altitude]",
+        7 => "REQUEST BLOCK [altitude] TO [altitude]",
+        8 => "REQUEST CRUISE CLIMB TO [altitude]",
+        9 => "REQUEST CLIMB TO [altitude]",
+        10 => "REQUEST DESCENT TO [altitude]",
+        18 => "REQUEST [speed]",
+        20 => "REQUEST VOICE CONTACT",
+        22 => "REQUEST DIRECT TO [position]",
+        32 => "PRESENT ALTITUDE [altitude]",
+        34 => "PRESENT SPEED [speed]",
+        37 => "LEVEL [altitude]",
+        48 => "POSITION REPORT [position report]",
+        55 => "PAN PAN PAN",
+        56 => "MAYDAY MAYDAY MAYDAY",
+        62 => "ERROR [error information]",
+        63 => "NOT CURRENT DATA AUTHORITY",
+        65 => "DUE TO WEATHER",
+        66 => "DUE TO AIRCRAFT PERFORMANCE",
+        67 => "[free text]",
+        _ => return None,
+    })
+}
+
+fn fmt_position(position: &AdscPosition) -> String {
+    format!(
+        "{:.4} {:.4} {} ft",
+        position.latitude, position.longitude, position.altitude
+    )
+}
+
+fn fmt_angle(angle: Option<f64>) -> String {
+    angle.map_or_else(|| "invalid".to_string(), |angle| format!("{angle:.1}"))
+}
+
+impl Display for AdscGroup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Acknowledgement { contract_number } => {
+                write!(f, "Acknowledgement: Contract {contract_number}")
+            }
+            Self::NegativeAcknowledgement {
+                contract_number,
+                reason,
+            } => write!(
+                f,
+                "Negative Acknowledgement: Contract {contract_number}, Reason {reason}"
+            ),
+            Self::CancelEmergency => write!(f, "Cancel Emergency Mode"),
+            Self::BasicReport {
+                report_type,
+                position,
+                timestamp,
+                navigation_redundancy,
+                position_accuracy,
+                tcas_healthy,
+            } => write!(
+                f,
+                "{report_type}: {}, Time: {timestamp:.3}s past the hour, Redundancy: {navigation_redundancy}, Accuracy: {position_accuracy}, TCAS Healthy: {tcas_healthy}",
+                fmt_position(position)
+            ),
+            Self::FlightId(flight_id) => write!(f, "Flight ID: {flight_id}"),
+            Self::PredictedRoute {
+                next_waypoint,
+                next_waypoint_eta,
+                next_next_waypoint,
+            } => write!(
+                f,
+                "Predicted Route: Next {} in {next_waypoint_eta}s, Then {}",
+                fmt_position(next_waypoint),
+                fmt_position(next_next_waypoint)
+            ),
+            Self::EarthReference {
+                true_track,
+                ground_speed,
+                vertical_speed,
+            } => write!(
+                f,
+                "Earth Reference: Track {}, Ground Speed {ground_speed:.1} kt, Vertical Speed {vertical_speed} ft/min",
+                fmt_angle(*true_track)
+            ),
+            Self::AirReference {
+                true_heading,
+                mach,
+                vertical_speed,
+            } => write!(
+                f,
+                "Air Reference: Heading {}, Mach {mach:.3}, Vertical Speed {vertical_speed} ft/min",
+                fmt_angle(*true_heading)
+            ),
+            Self::Meteo {
+                wind_speed,
+                wind_direction,
+                temperature,
+            } => write!(
+                f,
+                "Meteo: Wind {} at {wind_speed:.1} kt, Temperature {temperature:.2} C",
+                fmt_angle(*wind_direction)
+            ),
+            Self::AirframeId { icao_address } => write!(f, "Airframe ID: {icao_address:06X}"),
+            Self::Unknown { tag, data } => {
+                write!(f, "Unknown Tag {tag}: {} bytes", data.len())
+            }
+        }
+    }
+}
+
+impl Display for AdscRequest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CancelAllContracts => write!(f, "Cancel All Contracts"),
+            Self::CancelContract { contract_number } => {
+                write!(f, "Cancel Contract {contract_number}")
+            }
+            Self::Contract {
+                contract_type,
+                contract_number,
+            } => write!(f, "{contract_type} {contract_number}"),
+            Self::LateralDeviationChange { threshold } => {
+                write!(f, "Lateral Deviation Change: {threshold:.3} nm")
+            }
+            Self::ReportingInterval { interval } => write!(f, "Reporting Interval: {interval}s"),
+            Self::Modulus { group, modulus } => write!(f, "{group}: Every {modulus} Reports"),
+            Self::VerticalRateChange { threshold } => {
+                write!(f, "Vertical Rate Change: {threshold} ft/min")
+            }
+            Self::AltitudeRange { ceiling, floor } => {
+                write!(f, "Altitude Range: {floor} ft to {ceiling} ft")
+            }
+            Self::WaypointChange => write!(f, "Waypoint Change"),
+            Self::Unknown { tag, data } => {
+                write!(f, "Unknown Tag {tag}: {} bytes", data.len())
+            }
+        }
+    }
+}
+
+impl Display for CpdlcParameter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Altitude(feet) => write!(f, "{feet} FT"),
+            Self::FlightLevel(level) => write!(f, "FL{level}"),
+            Self::BeaconCode(code) => write!(f, "{code}"),
+            Self::FreeText(text) => write!(f, "{text}"),
+        }
+    }
+}
+
+impl Display for CpdlcElement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        // the element text, with the placeholders filled in by the decoded parameters
+        let Some(mut text) = self.text else {
+            return write!(f, "Unknown element");
+        };
+
+        let mut parameters = self.parameters.iter();
+        while let Some((before, placeholder)) = text.split_once('[') {
+            let Some((placeholder, after)) = placeholder.split_once(']') else {
+                break;
+            };
+
+            write!(f, "{before}")?;
+            match parameters.next() {
+                Some(parameter) => write!(f, "{parameter}")?,
+                None => write!(f, "[{placeholder}]")?,
+            }
+            text = after;
+        }
+
+        write!(f, "{text}")
+    }
+}
+
+impl Display for CpdlcMessage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Message ID: {}", self.message_id)?;
+
+        if let Some(reference) = self.message_reference {
+            write!(f, ", Reference: {reference}")?;
+        }
+
+        if let Some((hours, minutes, seconds)) = self.timestamp {
+            write!(f, ", Time: {hours:02}:{minutes:02}:{seconds:02}")?;
+        }
+
+        for element in &self.elements {
+            write!(
+                f,
+                "; {}{}: {element}",
+                if self.downlink { "DM" } else { "UM" },
+                element.id
+            )?;
+        }
+
+        if self.truncated {
+            write!(f, "; Further Elements Not Decoded")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for Arinc622Message {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} from {} for {}{}",
+            self.imi,
+            self.ground_address,
+            self.aircraft_registration,
+            if self.crc_ok { "" } else { " (CRC FAILED)" }
+        )?;
+
+        match &self.application {
+            Arinc622Application::AdsC(groups) => {
+                for group in groups {
+                    write!(f, "; {group}")?;
+                }
+            }
+            Arinc622Application::AdsCRequest(groups) => {
+                for group in groups {
+                    write!(f, "; {group}")?;
+                }
+            }
+            Arinc622Application::Cpdlc(message) => write!(f, "; {message}")?,
+            Arinc622Application::Undecoded(payload) => {
+                write!(f, "; {} bytes not decoded", payload.len())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Write;
+
+    struct BitWriter {
+        data: Vec<u8>,
+        bits: usize,
+    }
+
+    impl BitWriter {
+        const fn new() -> Self {
+            Self {
+                data: vec![],
+                bits: 0,
+            }
+        }
+
+        fn write(&mut self, value: i64, bits: usize) {
+            for i in (0..bits).rev() {
+                if self.bits % 8 == 0 {
+                    self.data.push(0);
+                }
+                if (value >> i) & 1 == 1 {
+                    let last = self.data.len() - 1;
+                    self.data[last] |= 1 << (7 - self.bits % 8);
+                }
+                self.bits += 1;
+            }
+        }
+    }
+
+    fn to_message(imi: &str, registration: &str, payload: &[u8]) -> Vec<char> {
+        let mut crc_data = imi.as_bytes().to_vec();
+        crc_data.extend_from_slice(registration.as_bytes());
+        crc_data.extend_from_slice(payload);
+        let crc = crc16(&crc_data);
+
+        let mut text = format!("/AKLCDYA.{imi}{registration}");
+        for byte in payload.iter().chain(&crc.to_be_bytes()) {
+            let _ = write!(text, "{byte:02X}");
+        }
+
+        text.chars().collect()
+    }
+
+    #[test]
+    fn test_adsc_basic_report() {
+        let mut writer = BitWriter::new();
+        // basic report: 45 N, 90 W, 35000 ft, 1234.5 s past the hour
+        writer.write(7, 8);
+        writer.write(45 * (1 << 20) / 180, 21);
+        writer.write(-90 * (1 << 20) / 180, 21);
+        writer.write(35000 / 4, 16);
+        writer.write(1234 * 8 + 4, 15);
+        writer.write(1, 1);
+        writer.write(5, 3);
+        writer.write(1, 1);
+        writer.write(0, 2);
+        // flight id UAL123
+        writer.write(12, 8);
+        for c in "UAL123  ".bytes() {
+            writer.write(i64::from(c & 0x3f), 6);
+        }
+        // meteo: 50 kt wind from 270, -52.25 C
+        writer.write(16, 8);
+        writer.write(100, 9);
+        writer.write(0, 1);
+        writer.write(270 * 256 / 180, 9);
+        writer.write(-209, 12);
+        writer.write(0, 1);
+
+        let text = to_message("ADS", ".N123UA", &writer.data);
+        let Some(message) = decode(['B', '6'], &text, &DownlinkStatus::AirToGround) else {
+            panic!("ADS-C message was not decoded");
+        };
+
+        assert!(message.crc_ok);
+        assert_eq!(message.imi, Imi::Ads);
+        assert_eq!(message.ground_address, "AKLCDYA");
+        assert_eq!(message.aircraft_registration, "N123UA");
+
+        let Arinc622Application::AdsC(groups) = message.application else {
+            panic!("Not decoded as ADS-C");
+        };
+
+        assert_eq!(groups.len(), 3);
+        assert_eq!(
+            groups[0],
+            AdscGroup::BasicReport {
+                report_type: AdscReportType::Periodic,
+                position: AdscPosition {
+                    latitude: 45.0,
+                    longitude: -90.0,
+                    altitude: 35000,
+                },
+                timestamp: 1234.5,
+                navigation_redundancy: true,
+                position_accuracy: 5,
+                tcas_healthy: true,
+            }
+        );
+        assert_eq!(groups[1], AdscGroup::FlightId("UAL123".to_string()));
+        assert_eq!(
+            groups[2],
+            AdscGroup::Meteo {
+                wind_speed: 50.0,
+                wind_direction: Some(270.0),
+                temperature: -52.25,
+            }
+        );
+    }
+
+    #[test]
+    fn test_cpdlc_and_crc() {
+        let mut writer = BitWriter::new();
+        writer.write(0, 1);
+        writer.write(1, 1);
+        writer.write(1, 1);
+        writer.write(12, 6);
+        writer.write(7, 6);
+        writer.write(13, 5);
+        writer.write(45, 6);
+        writer.write(30, 6);
+        writer.write(0, 7);
+
+        let mut text = to_message("AT1", ".N123UA", &writer.data);
+        let Some(message) = decode(['H', '1'], &text, &DownlinkStatus::AirToGround) else {
+            panic!("CPDLC message was not decoded");
+        };
+
+        assert!(message.crc_ok);
+        assert_eq!(
+            message.application,
+            Arinc622Application::Cpdlc(CpdlcMessage {
+                downlink: true,
+                message_id: 12,
+                message_reference: Some(7),
+                timestamp: Some((13, 45, 30)),
+                elements: vec![CpdlcElement {
+                    id: 0,
+                    text: Some("WILCO"),
+                    parameters: vec![],
+                }],
+                truncated: false,
+            })
+        );
+
+        // corrupt the payload
+        text[20] = if text[20] == '0' { '1' } else { '0' };
+        let Some(message) = decode(['H', '1'], &text, &DownlinkStatus::AirToGround) else {
+            panic!("CPDLC message was not decoded");
+        };
+        assert!(!message.crc_ok);
+
+        // not ARINC 622
+        let text: Vec<char> = "/AKLCDYA.AT1.N123UAZZ".chars().collect();
+        assert_eq!(
+            decode(['H', '1'], &text, &DownlinkStatus::AirToGround),
+            None
+        );
+        assert_eq!(
+            decode(['Q', '0'], &text, &DownlinkStatus::AirToGround),
+            None
+        );
+    }
+
+    #[test]
+    fn test_cpdlc_elements() {
+        let mut writer = BitWriter::new();
+        // additional elements follow, no reference or timestamp, message 3
+        writer.write(1, 1);
+        writer.write(0, 1);
+        writer.write(0, 1);
+        writer.write(3, 6);
+        // UM20 FL350
+        writer.write(20, 8);
+        writer.write(6, 3);
+        writer.write(350 - 30, 10);
+        // two more: UM165, UM169 free text
+        writer.write(1, 2);
+        writer.write(165, 8);
+        writer.write(169, 8);
+        writer.write(9 - 1, 8);
+        for c in "DUE TO WX".bytes() {
+            writer.write(i64::from(c), 7);
+        }
+
+        let text = to_message("AT1", ".N123UA", &writer.data);
+        let Some(message) = decode(['A', 'A'], &text, &DownlinkStatus::GroundToAir) else {
+            panic!("CPDLC message was not decoded");
+        };
+
+        assert!(message.crc_ok);
+        let Arinc622Application::Cpdlc(cpdlc) = &message.application else {
+            panic!("Not decoded as CPDLC");
+        };
+
+        assert!(!cpdlc.downlink);
+        assert!(!cpdlc.truncated);
+        assert_eq!(cpdlc.message_id, 3);
+        assert_eq!(cpdlc.message_reference, None);
+        assert_eq!(cpdlc.timestamp, None);
+        assert_eq!(
+            cpdlc.elements,
+            vec![
+                CpdlcElement {
+                    id: 20,
+                    text: Some("CLIMB TO AND MAINTAIN [altitude]"),
+                    parameters: vec![CpdlcParameter::FlightLevel(350)],
+                },
+                CpdlcElement {
+                    id: 165,
+                    text: Some("THEN"),
+                    parameters: vec![],
+                },
+                CpdlcElement {
+                    id: 169,
+                    text: Some("[free text]"),
+                    parameters: vec![CpdlcParameter::FreeText("DUE TO WX".to_string())],
+                },
+            ]
+        );
+        assert_eq!(
+            message.to_string(),
+            "CPDLC from AKLCDYA for N123UA; Message ID: 3; UM20: CLIMB TO AND MAINTAIN FL350; UM165: THEN; UM169: DUE TO WX"
+        );
+
+        // DM22 has a position we do not decode, so the element after it is lost
+        let mut writer = BitWriter::new();
+        writer.write(1, 1);
+        writer.write(0, 1);
+        writer.write(0, 1);
+        writer.write(4, 6);
+        writer.write(22, 7);
+        writer.write(0, 16);
+
+        let text = to_message("AT1", ".N123UA", &writer.data);
+        let Some(message) = decode(['H', '1'], &text, &DownlinkStatus::AirToGround) else {
+            panic!("CPDLC message was not decoded");
+        };
+        let Arinc622Application::Cpdlc(cpdlc) = &message.application else {
+            panic!("Not decoded as CPDLC");
+        };
+
+        assert!(cpdlc.truncated);
+        assert_eq!(cpdlc.elements.len(), 1);
+        assert_eq!(cpdlc.elements[0].id, 22);
+        assert!(cpdlc.elements[0].parameters.is_empty());
+        assert_eq!(
+            cpdlc.to_string(),
+            "Message ID: 4; DM22: REQUEST DIRECT TO [position]; Further Elements Not Decoded"
+        );
+    }
+
+    #[test]
+    fn test_adsc_request() {
+        let mut writer = BitWriter::new();
+        // periodic contract 5, every 304 s
+        writer.write(7, 8);
+        writer.write(5, 8);
+        writer.write(11, 8);
+        writer.write(1, 2);
+        writer.write(38, 6);
+        // predicted route every other report, meteo in all of them
+        writer.write(13, 8);
+        writer.write(2, 8);
+        writer.write(16, 8);
+        writer.write(1, 8);
+        // event contract 6, FL330 to FL370 and waypoint changes
+        writer.write(8, 8);
+        writer.write(6, 8);
+        writer.write(19, 8);
+        writer.write(37000 / 4, 16);
+        writer.write(33000 / 4, 16);
+        writer.write(20, 8);
+        writer.write(99, 8);
+        writer.write(0xab, 8);
+
+        let text = to_message("ADS", ".N123UA", &writer.data);
+        let Some(message) = decode(['A', '6'], &text, &DownlinkStatus::GroundToAir) else {
+            panic!("ADS-C message was not decoded");
+        };
+
+        assert!(message.crc_ok);
+        let Arinc622Application::AdsCRequest(groups) = &message.application else {
+            panic!("Not decoded as an ADS-C request");
+        };
+
+        assert_eq!(
+            groups,
+            &vec![
+                AdscRequest::Contract {
+                    contract_type: AdscContractType::Periodic,
+                    contract_number: 5,
+                },
+                AdscRequest::ReportingInterval { interval: 304 },
+                AdscRequest::Modulus {
+                    group: AdscDataGroup::PredictedRoute,
+                    modulus: 2,
+                },
+                AdscRequest::Modulus {
+                    group: AdscDataGroup::Meteo,
+                    modulus: 1,
+                },
+                AdscRequest::Contract {
+                    contract_type: AdscContractType::Event,
+                    contract_number: 6,
+                },
+                AdscRequest::AltitudeRange {
+                    ceiling: 37000,
+                    floor: 33000,
+                },
+                AdscRequest::WaypointChange,
+                AdscRequest::Unknown {
+                    tag: 99,
+                    data: vec![0xab],
+                },
+            ]
+        );
+        assert_eq!(
+            message.to_string(),
+            "ADS-C from AKLCDYA for N123UA; Periodic Contract 5; Reporting Interval: 304s; Predicted Route: Every 2 Reports; Meteo: Every 1 Reports; Event Contract 6; Altitude Range: 33000 ft to 37000 ft; Waypoint Change; Unknown Tag 99: 1 bytes"
+        );
+    }
+
+    /// A CPDLC message with message id 1 carrying `id` with made up parameters, followed by
+    /// `then` to show where the parameters ended
+    fn cpdlc_element_payload(downlink: bool, id: u8, then: u8) -> Vec<u8> {
+        let (bits, text) = if downlink {
+            (DOWNLINK_ELEMENT_BITS, downlink_element_text(id))
+        } else {
+            (UPLINK_ELEMENT_BITS, uplink_element_text(id))
+        };
+
+        let mut writer = BitWriter::new();
+        writer.write(1, 1);
+        writer.write(0, 1);
+        writer.write(0, 1);
+        writer.write(1, 6);
+        writer.write(i64::from(id), bits);
+        let mut levels = [350, 370].into_iter().cycle();
+        for parameter_type in parameter_types(text.unwrap_or_default()) {
+            match parameter_type {
+                ParameterType::Altitude => {
+                    writer.write(6, 3);
+                    writer.write(levels.next().unwrap_or_default() - 30, 10);
+                }
+                ParameterType::BeaconCode => {
+                    for digit in [4, 7, 2, 1] {
+                        writer.write(digit, 3);
+                    }
+                }
+                ParameterType::FreeText => {
+                    writer.write(1, 8);
+                    for c in "WX".bytes() {
+                        writer.write(i64::from(c), 7);
+                    }
+                }
+                ParameterType::Unsupported => writer.write(0, 16),
+            }
+        }
+        writer.write(0, ADDITIONAL_ELEMENTS_BITS);
+        writer.write(i64::from(then), bits);
+        writer.data
+    }
+
+    #[test]
+    #[allow(clippy::too_many_lines)]
+    fn test_every_cpdlc_element() {
+        // The element as displayed, and whether the element after it could still be found
+        let uplinks: [(u8, &str, bool); 33] = [
+            (0, "UNABLE", true),
+            (1, "STANDBY", true),
+            (2, "REQUEST DEFERRED", true),
+            (3, "ROGER", true),
+            (4, "AFFIRM", true),
+            (5, "NEGATIVE", true),
+            (6, "EXPECT FL350", true),
+            (19, "MAINTAIN FL350", true),
+            (20, "CLIMB TO AND MAINTAIN FL350", true),
+            (23, "DESCEND TO AND MAINTAIN FL350", true),
+            (74, "PROCEED DIRECT TO [position]", false),
+            (79, "CLEARED TO [position] VIA [route clearance]", false),
+            (80, "CLEARED [route clearance]", false),
+            (106, "MAINTAIN [speed]", false),
+            (116, "RESUME NORMAL SPEED", true),
+            (117, "CONTACT [icao unit name] [frequency]", false),
+            (120, "MONITOR [icao unit name] [frequency]", false),
+            (123, "SQUAWK 4721", true),
+            (133, "REPORT PRESENT LEVEL", true),
+            (148, "WHEN CAN YOU ACCEPT FL350", true),
+            (157, "CHECK STUCK MICROPHONE [frequency]", false),
+            (159, "ERROR [error information]", false),
+            (
+                160,
+                "NEXT DATA AUTHORITY [icao facility designation]",
+                false,
+            ),
+            (161, "END SERVICE", true),
+            (162, "SERVICE UNAVAILABLE", true),
+            (164, "WHEN READY", true),
+            (165, "THEN", true),
+            (166, "DUE TO TRAFFIC", true),
+            (167, "DUE TO AIRSPACE RESTRICTION", true),
+            (168, "DISREGARD", true),
+            (169, "WX", true),
+            (179, "SQUAWK IDENT", true),
+            (200, "Unknown element", false),
+        ];
+        let downlinks: [(u8, &str, bool); 26] = [
+            (0, "WILCO", true),
+            (1, "UNABLE", true),
+            (2, "STANDBY", true),
+            (3, "ROGER", true),
+            (4, "AFFIRM", true),
+            (5, "NEGATIVE", true),
+            (6, "REQUEST FL350", true),
+            (7, "REQUEST BLOCK FL350 TO FL370", true),
+            (8, "REQUEST CRUISE CLIMB TO FL350", true),
+            (9, "REQUEST CLIMB TO FL350", true),
+            (10, "REQUEST DESCENT TO FL350", true),
+            (18, "REQUEST [speed]", false),
+            (20, "REQUEST VOICE CONTACT", true),
+            (22, "REQUEST DIRECT TO [position]", false),
+            (32, "PRESENT ALTITUDE FL350", true),
+            (34, "PRESENT SPEED [speed]", false),
+            (37, "LEVEL FL350", true),
+            (48, "POSITION REPORT [position report]", false),
+            (55, "PAN PAN PAN", true),
+            (56, "MAYDAY MAYDAY MAYDAY", true),
+            (62, "ERROR [error information]", false),
+            (63, "NOT CURRENT DATA AUTHORITY", true),
+            (65, "DUE TO WEATHER", true),
+            (66, "DUE TO AIRCRAFT PERFORMANCE", true),
+            (67, "WX", true),
+            (100, "Unknown element", false),
+        ];
+
+        // Every element with a text is in the tables
+        let known = |table: &[(u8, &str, bool)]| table.len() - 1;
+        assert_eq!(
+            (0..=u8::MAX)
+                .filter(|id| uplink_element_text(*id).is_some())
+                .count(),
+            known(&uplinks)
+        );
+        assert_eq!(
+            (0..1 << DOWNLINK_ELEMENT_BITS)
+                .filter(|id| downlink_element_text(*id).is_some())
+                .count(),
+            known(&downlinks)
+        );
+
+        for (downlink, table, prefix, then) in [
+            (false, &uplinks[..], "UM", "UM165: THEN"),
+            (true, &downlinks[..], "DM", "DM65: DUE TO WEATHER"),
+        ] {
+            let then_id = if downlink { 65 } else { 165 };
+            for (id, expected, complete) in table {
+                let payload = cpdlc_element_payload(downlink, *id, then_id);
+                let Some(message) = decode_cpdlc(&payload, downlink) else {
+                    panic!("{prefix}{id} was not decoded");
+                };
+                let expected = if *complete {
+                    format!("Message ID: 1; {prefix}{id}: {expected}; {then}")
+                } else {
+                    format!("Message ID: 1; {prefix}{id}: {expected}; Further Elements Not Decoded")
+                };
+                assert_eq!(message.to_string(), expected);
+                assert_eq!(message.truncated, !complete, "{prefix}{id}");
+
+                // Cut short anywhere, it decodes what it can
+                for length in 0..payload.len() {
+                    if let Some(message) = decode_cpdlc(&payload[..length], downlink) {
+                        assert!(message.elements.len() < 2 || !complete, "{prefix}{id}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_cpdlc_altitudes() {
+        // Feet, flight levels and an altitude type we do not decode
+        for (altitude_type, value, bits, expected) in [
+            (0, 1200, 12, "Message ID: 1; UM19: MAINTAIN 12000 FT"),
+            (6, 410 - 30, 10, "Message ID: 1; UM19: MAINTAIN FL410"),
+            (
+                2,
+                0,
+                16,
+                "Message ID: 1; UM19: MAINTAIN [altitude]; Further Elements Not Decoded",
+            ),
+        ] {
+            let mut writer = BitWriter::new();
+            writer.write(0, 3);
+            writer.write(1, 6);
+            writer.write(19, 8);
+            writer.write(altitude_type, 3);
+            writer.write(value, bits);
+
+            let text = to_message("AT1", ".ZK-OKG", &writer.data);
+            let Some(message) = decode(['A', 'A'], &text, &DownlinkStatus::GroundToAir) else {
+                panic!("CPDLC message was not decoded");
+            };
+            assert!(message.crc_ok);
+            let Arinc622Application::Cpdlc(cpdlc) = &message.application else {
+                panic!("Not decoded as CPDLC");
+            };
+            assert_eq!(cpdlc.to_string(), expected);
+        }
+    }
+
+    /// The fields of an ADS-C request group with their widths, what it decodes to and how it
+    /// is displayed
+    type RequestCase = (Vec<(i64, usize)>, AdscRequest, &'static str);
+
+    #[test]
+    #[allow(clippy::too_many_lines)]
+    fn test_every_adsc_request_tag() {
+        let table: Vec<RequestCase> = vec![
+            (
+                vec![(1, 8)],
+                AdscRequest::CancelAllContracts,
+                "Cancel All Contracts",
+            ),
+            (
+                vec![(2, 8), (5, 8)],
+                AdscRequest::CancelContract { contract_number: 5 },
+                "Cancel Contract 5",
+            ),
+            (
+                vec![(7, 8), (5, 8)],
+                AdscRequest::Contract {
+                    contract_type: AdscContractType::Periodic,
+                    contract_number: 5,
+                },
+                "Periodic Contract 5",
+            ),
+            (
+                vec![(8, 8), (6, 8)],
+                AdscRequest::Contract {
+                    contract_type: AdscContractType::Event,
+                    contract_number: 6,
+                },
+                "Event Contract 6",
+            ),
+            (
+                vec![(9, 8), (7, 8)],
+                AdscRequest::Contract {
+                    contract_type: AdscContractType::EmergencyPeriodic,
+                    contract_number: 7,
+                },
+                "Emergency Periodic Contract 7",
+            ),
+            (
+                vec![(10, 8), (40, 8)],
+                AdscRequest::LateralDeviationChange { threshold: 5.0 },
+                "Lateral Deviation Change: 5.000 nm",
+            ),
+            (
+                vec![(11, 8), (0, 2), (30, 6)],
+                AdscRequest::ReportingInterval { interval: 30 },
+                "Reporting Interval: 30s",
+            ),
+            (
+                vec![(11, 8), (1, 2), (38, 6)],
+                AdscRequest::ReportingInterval { interval: 304 },
+                "Reporting Interval: 304s",
+            ),
+            (
+                vec![(11, 8), (2, 2), (15, 6)],
+                AdscRequest::ReportingInterval { interval: 960 },
+                "Reporting Interval: 960s",
+            ),
+            (
+                vec![(11, 8), (3, 2), (7, 6)],
+                AdscRequest::ReportingInterval { interval: 3584 },
+                "Reporting Interval: 3584s",
+            ),
+            (
+                vec![(12, 8), (1, 8)],
+                AdscRequest::Modulus {
+                    group: AdscDataGroup::FlightId,
+                    modulus: 1,
+                },
+                "Flight ID: Every 1 Reports",
+            ),
+            (
+                vec![(13, 8), (2, 8)],
+                AdscRequest::Modulus {
+                    group: AdscDataGroup::PredictedRoute,
+                    modulus: 2,
+                },
+                "Predicted Route: Every 2 Reports",
+            ),
+            (
+                vec![(14, 8), (3, 8)],
+                AdscRequest::Modulus {
+                    group: AdscDataGroup::EarthReference,
+                    modulus: 3,
+                },
+                "Earth Reference: Every 3 Reports",
+            ),
+            (
+                vec![(15, 8), (4, 8)],
+                AdscRequest::Modulus {
+                    group: AdscDataGroup::AirReference,
+                    modulus: 4,
+                },
+                "Air Reference: Every 4 Reports",
+            ),
+            (
+                vec![(16, 8), (1, 8)],
+                AdscRequest::Modulus {
+                    group: AdscDataGroup::Meteo,
+                    modulus: 1,
+                },
+                "Meteo: Every 1 Reports",
+            ),
+            (
+                vec![(17, 8), (10, 8)],
+                AdscRequest::Modulus {
+                    group: AdscDataGroup::AirframeId,
+                    modulus: 10,
+                },
+                "Airframe ID: Every 10 Reports",
+            ),
+            (
+                vec![(18, 8), (-8, 8)],
+                AdscRequest::VerticalRateChange { threshold: -512 },
+                "Vertical Rate Change: -512 ft/min",
+            ),
+            (
+                vec![(19, 8), (41000 / 4, 16), (29000 / 4, 16)],
+                AdscRequest::AltitudeRange {
+                    ceiling: 41000,
+                    floor: 29000,
+                },
+                "Altitude Range: 29000 ft to 41000 ft",
+            ),
+            (
+                vec![(20, 8)],
+                AdscRequest::WaypointChange,
+                "Waypoint Change",
+            ),
+        ];
+
+        for (fields, expected, display) in table {
+            let mut writer = BitWriter::new();
+            for (value, bits) in &fields {
+                writer.write(*value, *bits);
+            }
+            let group = writer.data.clone();
+            // A group after it shows the whole group was read
+            writer.write(1, 8);
+
+            let text = to_message("ADS", ".9V-SWM", &writer.data);
+            let Some(message) = decode(['A', '6'], &text, &DownlinkStatus::GroundToAir) else {
+                panic!("ADS-C request {display} was not decoded");
+            };
+            assert!(message.crc_ok);
+            assert_eq!(
+                message.application,
+                Arinc622Application::AdsCRequest(vec![
+                    expected.clone(),
+                    AdscRequest::CancelAllContracts
+                ])
+            );
+            assert_eq!(
+                message.to_string(),
+                format!("ADS-C from AKLCDYA for 9V-SWM; {display}; Cancel All Contracts")
+            );
+
+            // Without its last byte a group with data is not known, so decoding stops there
+            if group.len() > 1 {
+                let tag = group[0];
+                assert_eq!(
+                    decode_adsc_request(&group[..group.len() - 1]),
+                    vec![AdscRequest::Unknown {
+                        tag,
+                        data: group[1..group.len() - 1].to_vec(),
+                    }]
+                );
+            }
+        }
+
+        // A tag we do not know ends the message, keeping the rest as data
+        assert_eq!(
+            decode_adsc_request(&[99, 0xab, 1]),
+            vec![AdscRequest::Unknown {
+                tag: 99,
+                data: vec![0xab, 1],
+            }]
+        );
+        assert_eq!(decode_adsc_request(&[]), vec![]);
+    }
+
+    #[test]
+    fn test_malformed_text() {
+        let mut writer = BitWriter::new();
+        writer.write(7, 8);
+        writer.write(45 * (1 << 20) / 180, 21);
+        writer.write(-90 * (1 << 20) / 180, 21);
+        writer.write(35000 / 4, 16);
+        writer.write(1234 * 8, 15);
+        writer.write(0, 7);
+        let adsc = to_message("ADS", ".N123UA", &writer.data);
+        let cpdlc = to_message("AT1", ".N123UA", &cpdlc_element_payload(false, 20, 165));
+
+        // Every prefix of a real message, in both directions
+        for text in [&adsc, &cpdlc] {
+            for length in 0..=text.len() {
+                for status in [DownlinkStatus::AirToGround, DownlinkStatus::GroundToAir] {
+                    if let Some(message) = decode(['H', '1'], &text[..length], &status) {
+                        let _ = message.to_string();
+                    }
+                }
+            }
+        }
+
+        // Text that only starts like ARINC 622
+        for text in [
+            "/",
+            "/.",
+            "/AKLCDYA.",
+            "/AKLCDYA.ADS",
+            "/AKLCDYA.ADS.N123UA",
+            "/AKLCDYA.ADS.N123UA0",
+            "/AKLCDYA.ADS.N123UAZZZZ",
+            "/AKLCDYA.ads.N123UA0000",
+            "/AKLCDYA.ADS.N123UÄ0000",
+            "/AKLCDYA.AÐS.N123UA0000",
+            "AKLCDYA.ADS.N123UA0000",
+        ] {
+            let text: Vec<char> = text.chars().collect();
+            assert_eq!(
+                decode(['H', '1'], &text, &DownlinkStatus::AirToGround),
+                None,
+                "{text:?}"
+            );
+        }
+
+        // Garbage after the header
+        let mut state: u32 = 1;
+        let mut next = || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            state >> 16
+        };
+        for _ in 0..500 {
+            let mut text: Vec<char> = "/AKLCDYA.".chars().collect();
+            text.extend(["ADS", "AT1", "CR1", "CC1", "DR1"][next() as usize % 5].chars());
+            text.extend(".N123UA".chars());
+            let length = next() as usize % 80;
+            text.extend((0..length).map(|_| {
+                let hex = b"0123456789ABCDEF";
+                char::from(hex[next() as usize % 16])
+            }));
+            for label in ARINC622_LABELS {
+                for status in [DownlinkStatus::AirToGround, DownlinkStatus::GroundToAir] {
+                    if let Some(message) = decode(label, &text, &status) {
+                        let _ = message.to_string();
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,1215 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// Label specific decoding of the message text. The content of most labels is airline defined,
+// so the decoders here cover the formats seen most often on the air and return `None` for
+// anything else. Decoders are looked up by label, optionally restricted to a sublabel, and
+// tried in the order they were registered until one of them recognises the text.
+//
+// Coordinates come in three flavours:
+//
+//   N35286W108525       degrees, minutes and tenths of minutes (lon is one character wider)
+//   N 401017W 831535    degrees, minutes and seconds (lon is one character wider)
+//   N 44.203,W 86.546   decimal degrees
+
+use crate::decoders::acars::AssembledACARSMessage;
+use crate::decoders::squitter::{self, GroundStation, SQUITTER_LABEL};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+const POSITION_LABELS: [[char; 2]; 4] = [['1', '5'], ['1', '6'], ['2', '0'], ['2', '2']];
+const OOOI_LABELS: [[char; 2]; 5] = [['1', '0'], ['1', '1'], ['1', '2'], ['1', '3'], ['1', '4']];
+const OOOI_Q_LABELS: [[char; 2]; 6] = [
+    ['Q', 'A'],
+    ['Q', 'B'],
+    ['Q', 'C'],
+    ['Q', 'D'],
+    ['Q', 'E'],
+    ['Q', 'F'],
+];
+const WEATHER_REQUEST_LABEL: [char; 2] = ['5', 'U'];
+const WEATHER_REPORT_LABELS: [[char; 2]; 2] = [['R', 'A'], ['C', '1']];
+/// Airline designated downlink, used for free text ETA and fuel reports
+const FREE_TEXT_LABEL: [char; 2] = ['5', 'Z'];
+/// Regions whose airport codes start with the letter alone
+const ICAO_REGIONS: [char; 5] = ['C', 'K', 'U', 'Y', 'Z'];
+/// Two letter prefixes of the airport codes of the other countries
+const ICAO_COUNTRIES: [&str; 222] = [
+    "AG", "AN", "AY", "BG", "BI", "BK", "DA", "DB", "DF", "DG", "DI", "DN", "DR", "DT", "DX", "EB",
+    "ED", "EE", "EF", "EG", "EH", "EI", "EK", "EL", "EN", "EP", "ES", "ET", "EV", "EY", "FA", "FB",
+    "FC", "FD", "FE", "FG", "FH", "FI", "FJ", "FK", "FL", "FM", "FN", "FO", "FP", "FQ", "FS", "FT",
+    "FV", "FW", "FX", "FY", "FZ", "GA", "GB", "GC", "GE", "GF", "GG", "GL", "GM", "GO", "GQ", "GS",
+    "GU", "GV", "HA", "HB", "HC", "HD", "HE", "HH", "HK", "HL", "HR", "HS", "HT", "HU", "LA", "LB",
+    "LC", "LD", "LE", "LF", "LG", "LH", "LI", "LJ", "LK", "LL", "LM", "LN", "LO", "LP", "LQ", "LR",
+    "LS", "LT", "LU", "LV", "LW", "LX", "LY", "LZ", "MB", "MD", "MG", "MH", "MK", "MM", "MN", "MP",
+    "MR", "MS", "MT", "MU", "MW", "MY", "MZ", "NC", "NF", "NG", "NI", "NL", "NS", "NT", "NV", "NW",
+    "NZ", "OA", "OB", "OE", "OI", "OJ", "OK", "OL", "OM", "OO", "OP", "OR", "OS", "OT", "OY", "PA",
+    "PB", "PC", "PF", "PG", "PH", "PJ", "PK", "PL", "PM", "PO", "PP", "PT", "PW", "RC", "RJ", "RK",
+    "RO", "RP", "SA", "SB", "SC", "SD", "SE", "SF", "SG", "SH", "SI", "SJ", "SK", "SL", "SM", "SN",
+    "SO", "SP", "SS", "SU", "SV", "SW", "SY", "TA", "TB", "TD", "TF", "TG", "TI", "TJ", "TK", "TL",
+    "TN", "TQ", "TR", "TT", "TU", "TV", "TX", "VA", "VC", "VD", "VE", "VG", "VH", "VI", "VL", "VM",
+    "VN", "VO", "VQ", "VR", "VT", "VV", "VY", "WA", "WB", "WI", "WM", "WP", "WR", "WS",
+];
+/// Four letter words that show up in the free text formats and look like airports
+const NOT_AIRPORTS: [&str; 9] = [
+    "USED", "GATE", "DATE", "LATE", "WIND", "SPOT", "CODE", "ZULU", "UNIT",
+];
+/// Keywords whose value is a fuel quantity
+const FUEL_KEYWORDS: [&str; 2] = ["FOB", "FUEL"];
+
+/// Time of day, UTC, as reported in the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ReportTime {
+    pub hour: u8,
+    pub minute: u8,
+    pub second: Option<u8>,
+}
+
+impl ReportTime {
+    /// Parse HHMM or HHMMSS
+    fn parse(text: &str) -> Option<Self> {
+        if !text.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        let hour: u8 = text.get(0..2)?.parse().ok()?;
+        let minute: u8 = text.get(2..4)?.parse().ok()?;
+        let second = match text.len() {
+            4 => None,
+            6 => Some(text.get(4..6)?.parse::<u8>().ok()?),
+            _ => return None,
+        };
+
+        if hour > 23 || minute > 59 || second.map_or(false, |second| second > 59) {
+            return None;
+        }
+
+        Some(Self {
+            hour,
+            minute,
+            second,
+        })
+    }
+}
+
+impl Display for ReportTime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)?;
+        if let Some(second) = self.second {
+            write!(f, ":{second:02}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Aircraft position report
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct PositionReport {
+    /// Latitude in degrees, positive north
+    pub latitude: f64,
+    /// Longitude in degrees, positive east
+    pub longitude: f64,
+    /// Altitude in feet
+    pub altitude: Option<i32>,
+    /// Last waypoint passed
+    pub waypoint: Option<String>,
+    /// Time the last waypoint was passed
+    pub time: Option<ReportTime>,
+    pub next_waypoint: Option<String>,
+    pub next_waypoint_eta: Option<ReportTime>,
+    pub following_waypoint: Option<String>,
+    /// Outside air temperature in degrees Celsius
+    pub temperature: Option<i32>,
+    /// Wind direction in degrees
+    pub wind_direction: Option<u16>,
+    /// Wind speed in knots
+    pub wind_speed: Option<u16>,
+}
+
+impl Display for PositionReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Position {:.4} {:.4}", self.latitude, self.longitude)?;
+        if let Some(altitude) = self.altitude {
+            write!(f, ", {altitude} ft")?;
+        }
+        if let Some(waypoint) = &self.waypoint {
+            write!(f, ", Over {waypoint}")?;
+            if let Some(time) = &self.time {
+                write!(f, " at {time}")?;
+            }
+        }
+        if let Some(next_waypoint) = &self.next_waypoint {
+            write!(f, ", Next {next_waypoint}")?;
+            if let Some(eta) = &self.next_waypoint_eta {
+                write!(f, " at {eta}")?;
+            }
+        }
+        if let Some(following_waypoint) = &self.following_waypoint {
+            write!(f, ", Then {following_waypoint}")?;
+        }
+        if let Some(temperature) = self.temperature {
+            write!(f, ", Temperature {temperature} C")?;
+        }
+        if let (Some(direction), Some(speed)) = (self.wind_direction, self.wind_speed) {
+            write!(f, ", Wind {direction:03}/{speed} kt")?;
+        }
+        Ok(())
+    }
+}
+
+/// The gate and runway events of a flight
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OooiEventType {
+    /// Pushed back from the gate
+    Out,
+    /// Took off
+    Off,
+    /// Landed
+    On,
+    /// Arrived at the gate
+    In,
+}
+
+impl OooiEventType {
+    fn from_keyword(keyword: &str) -> Option<Self> {
+        match keyword {
+            "OUT" => Some(Self::Out),
+            "OFF" => Some(Self::Off),
+            "ON" => Some(Self::On),
+            "IN" => Some(Self::In),
+            _ => None,
+        }
+    }
+
+    const fn from_label(label: [char; 2]) -> Option<Self> {
+        match label {
+            ['1', '0'] | ['Q', 'A' | 'E'] => Some(Self::Out),
+            ['1', '1'] | ['Q', 'B' | 'F'] => Some(Self::Off),
+            ['1', '2'] | ['Q', 'C'] => Some(Self::On),
+            ['1', '3'] | ['Q', 'D'] => Some(Self::In),
+            _ => None,
+        }
+    }
+}
+
+impl Display for OooiEventType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Out => write!(f, "Out"),
+            Self::Off => write!(f, "Off"),
+            Self::On => write!(f, "On"),
+            Self::In => write!(f, "In"),
+        }
+    }
+}
+
+/// Out, off, on or in report
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OooiEvent {
+    pub event: OooiEventType,
+    pub time: Option<ReportTime>,
+    /// ICAO code of the departure airport
+    pub origin: Option<String>,
+    /// ICAO code of the arrival airport
+    pub destination: Option<String>,
+    /// Fuel on board, in the units used by the airline
+    pub fuel: Option<u32>,
+}
+
+impl Display for OooiEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.event)?;
+        if let Some(time) = &self.time {
+            write!(f, " at {time}")?;
+        }
+        if let Some(origin) = &self.origin {
+            write!(f, ", From {origin}")?;
+        }
+        if let Some(destination) = &self.destination {
+            write!(f, ", To {destination}")?;
+        }
+        if let Some(fuel) = self.fuel {
+            write!(f, ", Fuel {fuel}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WeatherReportType {
+    /// Request from the aircraft for weather at the listed stations
+    Request,
+    Metar,
+    Taf,
+}
+
+impl Display for WeatherReportType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Request => write!(f, "Weather Request"),
+            Self::Metar => write!(f, "METAR"),
+            Self::Taf => write!(f, "TAF"),
+        }
+    }
+}
+
+/// Weather request or report
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct WeatherReport {
+    pub report_type: WeatherReportType,
+    /// ICAO codes of the stations requested or reported on
+    pub stations: Vec<String>,
+    /// The report itself, starting at the report type
+    pub report: Option<String>,
+}
+
+impl Display for WeatherReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.report_type, self.stations.join(" "))?;
+        if let Some(report) = &self.report {
+            write!(f, ": {report}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Estimated time of arrival report
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EtaReport {
+    pub eta: ReportTime,
+    /// ICAO code of the arrival airport
+    pub destination: Option<String>,
+    /// Fuel on board, in the units used by the airline
+    pub fuel_on_board: Option<u32>,
+}
+
+impl Display for EtaReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "ETA {}", self.eta)?;
+        if let Some(destination) = &self.destination {
+            write!(f, " at {destination}")?;
+        }
+        if let Some(fuel_on_board) = self.fuel_on_board {
+            write!(f, ", Fuel {fuel_on_board}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Fuel report, in the units used by the airline
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FuelReport {
+    pub fuel_on_board: u32,
+    pub fuel_used: Option<u32>,
+}
+
+impl Display for FuelReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Fuel {}", self.fuel_on_board)?;
+        if let Some(fuel_used) = self.fuel_used {
+            write!(f, ", Used {fuel_used}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Structured content of a message, produced by a label decoder
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LabelContent {
+    Position(PositionReport),
+    Oooi(OooiEvent),
+    Weather(WeatherReport),
+    Eta(EtaReport),
+    Fuel(FuelReport),
+    /// Ground station squitter
+    GroundStation(GroundStation),
+    /// Content produced by a decoder registered outside of this crate
+    Custom {
+        name: String,
+        fields: Vec<(String, String)>,
+    },
+}
+
+impl Display for LabelContent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Position(report) => write!(f, "{report}"),
+            Self::Oooi(event) => write!(f, "{event}"),
+            Self::Weather(report) => write!(f, "{report}"),
+            Self::Eta(report) => write!(f, "{report}"),
+            Self::Fuel(report) => write!(f, "{report}"),
+            Self::GroundStation(station) => write!(f, "{station}"),
+            Self::Custom { name, fields } => {
+                write!(f, "{name}")?;
+                for (key, value) in fields {
+                    write!(f, ", {key}: {value}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Decoder for the text of one or more labels.
+pub trait LabelDecoder: Send + Sync {
+    /// Decode the message text. Returns `None` if the text is not in a format the decoder
+    /// understands, so the next decoder registered for the label gets a go.
+    fn decode(&self, message: &AssembledACARSMessage, text: &str) -> Option<LabelContent>;
+}
+
+impl<F> LabelDecoder for F
+where
+    F: Fn(&AssembledACARSMessage, &str) -> Option<LabelContent> + Send + Sync,
+{
+    fn decode(&self, message: &AssembledACARSMessage, text: &str) -> Option<LabelContent> {
+        self(message, text)
+    }
+}
+
+struct RegisteredDecoder {
+    sublabel: Option<[char; 2]>,
+    decoder: Box<dyn LabelDecoder>,
+}
+
+/// Label decoders, keyed on the label of the message they decode.
+#[derive(Default)]
+pub struct LabelDecoderRegistry {
+    decoders: HashMap<[char; 2], Vec<RegisteredDecoder>>,
+}
+
+impl LabelDecoderRegistry {
+    /// An empty registry
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry with the decoders for position, OOOI, weather, ETA and fuel reports, and
+    /// ground station squitters
+    #[must_use]
+    pub fn with_default_decoders() -> Self {
+        let mut registry = Self::new();
+
+        for label in POSITION_LABELS {
+            registry.register(label, None, decode_position);
+        }
+        registry.register(['H', '1'], None, decode_h1_position);
+        for label in OOOI_LABELS {
+            registry.register(label, None, decode_oooi);
+        }
+        for label in OOOI_Q_LABELS {
+            registry.register(label, None, decode_oooi_q);
+        }
+        registry.register(WEATHER_REQUEST_LABEL, None, decode_weather_request);
+        for label in WEATHER_REPORT_LABELS {
+            registry.register(label, None, decode_weather_report);
+        }
+        registry.register(FREE_TEXT_LABEL, None, decode_eta);
+        registry.register(FREE_TEXT_LABEL, None, decode_fuel);
+        registry.register(SQUITTER_LABEL, None, decode_squitter);
+
+        registry
+    }
+
+    /// Register a decoder for `label`. If `sublabel` is set the decoder is only used for
+    /// messages with that sublabel.
+    pub fn register<D: LabelDecoder + 'static>(
+        &mut self,
+        label: [char; 2],
+        sublabel: Option<[char; 2]>,
+        decoder: D,
+    ) {
+        self.decoders
+            .entry(label)
+            .or_default()
+            .push(RegisteredDecoder {
+                sublabel,
+                decoder: Box::new(decoder),
+            });
+    }
+
+    /// Decode the message with the first registered decoder that recognises it.
+    #[must_use]
+    pub fn decode(&self, message: &AssembledACARSMessage) -> Option<LabelContent> {
+        let decoders = self.decoders.get(&message.label)?;
+        let text: String = message.message_text.as_ref()?.iter().collect();
+        let text = text.trim_end_matches(['\0', '\r', '\n']);
+
+        decoders
+            .iter()
+            .filter(|registered| {
+                registered
+                    .sublabel
+                    .map_or(true, |sublabel| message.sublabel == Some(sublabel))
+            })
+            .find_map(|registered| registered.decoder.decode(message, text))
+    }
+}
+
+/// Parse a coordinate pair at the start of `text`. Returns the latitude, longitude and the
+/// remaining text.
+fn parse_coordinates(text: &str) -> Option<(f64, f64, &str)> {
+    let (lat_sign, rest) = hemisphere(text, 'N', 'S')?;
+    let rest = rest.trim_start();
+    let lat_len = rest
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(rest.len());
+    let (lat_text, rest) = rest.split_at(lat_len);
+    let (lon_sign, rest) = hemisphere(rest.trim_start_matches([',', ' ']), 'E', 'W')?;
+
+    let (latitude, longitude, rest) = if lat_text.contains('.') {
+        let rest = rest.trim_start();
+        let lon_len = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let (lon_text, rest) = rest.split_at(lon_len);
+        (lat_text.parse().ok()?, lon_text.parse().ok()?, rest)
+    } else {
+        // Packed longitudes are one character wider than the latitude, padded with spaces.
+        // Five digit latitudes are in tenths of minutes, six digit ones have seconds
+        let seconds = match lat_text.len() {
+            5 => false,
+            6 => true,
+            _ => return None,
+        };
+        let lon_text = rest.get(..=lat_len)?;
+        (
+            packed_degrees(lat_text, seconds)?,
+            packed_degrees(lon_text.trim_start(), seconds)?,
+            rest.get(lat_len + 1..)?,
+        )
+    };
+
+    if latitude > 90.0 || longitude > 180.0 {
+        return None;
+    }
+
+    Some((latitude * lat_sign, longitude * lon_sign, rest))
+}
+
+fn hemisphere(text: &str, positive: char, negative: char) -> Option<(f64, &str)> {
+    text.strip_prefix(positive)
+        .map(|rest| (1.0, rest))
+        .or_else(|| text.strip_prefix(negative).map(|rest| (-1.0, rest)))
+}
+
+/// Degrees from `DDMMm` / `DDDMMm` (tenths of minutes) or `DDMMSS` / `DDDMMSS`
+fn packed_degrees(text: &str, seconds: bool) -> Option<f64> {
+    if !text.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let split = text.len().checked_sub(if seconds { 4 } else { 3 })?;
+    let degrees: f64 = text.get(..split)?.parse().ok()?;
+    let minutes = if seconds {
+        let minutes: f64 = text.get(split..split + 2)?.parse().ok()?;
+        let seconds: f64 = text.get(split + 2..)?.parse().ok()?;
+        if seconds >= 60.0 {
+            return None;
+        }
+        minutes + seconds / 60.0
+    } else {
+        text.get(split..)?.parse::<f64>().ok()? / 10.0
+    };
+
+    if minutes >= 60.0 {
+        return None;
+    }
+
+    Some(degrees + minutes / 60.0)
+}
+
+fn tokens(text: &str) -> Vec<&str> {
+    text.split(|c: char| c.is_whitespace() || c == ',' || c == '/')
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+/// Whether `token` is an ICAO airport code: four letters, starting with the prefix of a region
+/// or country
+fn is_airport(token: &str) -> bool {
+    token.len() == 4
+        && token.chars().all(|c| c.is_ascii_uppercase())
+        && (token.starts_with(ICAO_REGIONS) || ICAO_COUNTRIES.contains(&&token[..2]))
+        && !NOT_AIRPORTS.contains(&token)
+}
+
+/// The byte offset of `word` in `text`, as a whole token rather than part of a longer one
+fn find_word(text: &str, word: &str) -> Option<usize> {
+    let is_separator = |c: char| c.is_whitespace() || c == ',' || c == '/';
+
+    text.match_indices(word)
+        .map(|(start, _)| start)
+        .find(|start| {
+            let before = text[..*start].chars().next_back();
+            let after = text[start + word.len()..].chars().next();
+            before.map_or(true, is_separator) && after.map_or(true, is_separator)
+        })
+}
+
+/// The value following one of `keywords`, either as the next token or attached to the keyword
+fn keyword_value<'a>(tokens: &[&'a str], keywords: &[&str]) -> Option<&'a str> {
+    tokens.iter().enumerate().find_map(|(index, token)| {
+        keywords.iter().find_map(|keyword| {
+            if token == keyword {
+                tokens.get(index + 1).copied()
+            } else {
+                token
+                    .strip_prefix(keyword)
+                    .filter(|value| value.starts_with(|c: char| c.is_ascii_digit()))
+            }
+        })
+    })
+}
+
+fn fuel_on_board(tokens: &[&str]) -> Option<u32> {
+    keyword_value(tokens, &FUEL_KEYWORDS)?.parse().ok()
+}
+
+/// Labels 15, 16, 20 and 22. The text starts with the position, optionally preceded by the
+/// `(2` of label 15 or `POS`.
+fn decode_position(message: &AssembledACARSMessage, text: &str) -> Option<LabelContent> {
+    let text = text.trim_start_matches("(2").trim_start_matches("POS");
+    let (latitude, longitude, rest) = parse_coordinates(text.trim_start())?;
+    let fields: Vec<&str> = rest.split(',').map(str::trim).collect();
+    let tokens = tokens(rest);
+
+    // Label 16 follows the position with the altitude in feet, the others may have a flight level
+    let altitude = if message.label == ['1', '6'] {
+        fields.get(1).and_then(|altitude| altitude.parse().ok())
+    } else {
+        keyword_value(&tokens, &["FL"])
+            .and_then(|level| level.parse::<i32>().ok())
+            .and_then(|level| level.checked_mul(100))
+    };
+
+    Some(LabelContent::Position(PositionReport {
+        latitude,
+        longitude,
+        altitude,
+        ..Default::default()
+    }))
+}
+
+/// H1 position reports from the FMS:
+///
+///   POSN35286W108525,GUP,004729,320,HAHAA,010907,,M44,24559,138ECB0
+///
+/// position, waypoint, time over it, flight level, next waypoint and its ETA, the waypoint after
+/// that, temperature (M for minus), wind direction and speed, then airline specific fields.
+fn decode_h1_position(_message: &AssembledACARSMessage, text: &str) -> Option<LabelContent> {
+    let (latitude, longitude, rest) = parse_coordinates(text.strip_prefix("POS")?)?;
+    let fields: Vec<&str> = rest.split(',').map(str::trim).collect();
+    let field = |index: usize| {
+        fields
+            .get(index)
+            .filter(|field| !field.is_empty())
+            .map(|field| (*field).to_string())
+    };
+    let temperature = fields.get(7).and_then(|temperature| {
+        temperature.strip_prefix('M').map_or_else(
+            || temperature.trim_start_matches('P').parse::<i32>().ok(),
+            |minus| minus.parse::<i32>().ok().map(|value| -value),
+        )
+    });
+    let wind = fields
+        .get(8)
+        .filter(|wind| wind.len() >= 5)
+        .and_then(|wind| Some((wind.get(..3)?.parse().ok()?, wind.get(3..)?.parse().ok()?)));
+
+    Some(LabelContent::Position(PositionReport {
+        latitude,
+        longitude,
+        altitude: fields
+            .get(3)
+            .and_then(|level| level.parse::<i32>().ok())
+            .and_then(|level| level.checked_mul(100)),
+        waypoint: field(1),
+        time: fields.get(2).and_then(|time| ReportTime::parse(time)),
+        next_waypoint: field(4),
+        next_waypoint_eta: fields.get(5).and_then(|time| ReportTime::parse(time)),
+        following_waypoint: field(6),
+        temperature,
+        wind_direction: wind.map(|(direction, _)| direction),
+        wind_speed: wind.map(|(_, speed)| speed),
+    }))
+}
+
+/// Labels 10 to 14. Free text with the event as a keyword (OUT, OFF, ON, IN) followed by the
+/// time, the airports and optionally the fuel:
+///
+///   OUT 1423 KORD KLAX FOB 1234
+///
+/// Without the keyword the event comes from the label.
+fn decode_oooi(message: &AssembledACARSMessage, text: &str) -> Option<LabelContent> {
+    let tokens = tokens(text);
+    let event = tokens
+        .iter()
+        .find_map(|token| OooiEventType::from_keyword(token))
+        .or_else(|| OooiEventType::from_label(message.label))?;
+    // The fuel can be four digits too
+    let time = tokens
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| {
+            index
+                .checked_sub(1)
+                .map_or(true, |previous| !FUEL_KEYWORDS.contains(&tokens[previous]))
+        })
+        .find_map(|(_, token)| ReportTime::parse(token));
+    let mut airports = tokens.iter().filter(|token| is_airport(token));
+    let origin = airports.next().map(|airport| (*airport).to_string());
+    let destination = airports.next().map(|airport| (*airport).to_string());
+
+    if time.is_none() && origin.is_none() {
+        return None;
+    }
+
+    Some(LabelContent::Oooi(OooiEvent {
+        event,
+        time,
+        origin,
+        destination,
+        fuel: fuel_on_board(&tokens),
+    }))
+}
+
+/// Labels QA to QF. Fixed format, the airports followed by the time and, for the reports that
+/// carry it, the fuel:
+///
+///   KBOSKJFK1254 184
+///
+/// When only one airport is present it is the departure airport for out and off reports, and
+/// the arrival airport for on and in reports.
+fn decode_oooi_q(message: &AssembledACARSMessage, text: &str) -> Option<LabelContent> {
+    let event = OooiEventType::from_label(message.label)?;
+    let text = text.trim_start();
+    let airport_len = text
+        .find(|c: char| !c.is_ascii_uppercase())
+        .unwrap_or(text.len());
+    let (airports, rest) = text.split_at(airport_len);
+
+    let (first, second) = match airports.len() {
+        4 => (airports.to_string(), None),
+        8 => (airports[..4].to_string(), Some(airports[4..].to_string())),
+        _ => return None,
+    };
+    if !is_airport(&first)
+        || second
+            .as_deref()
+            .map_or(false, |second| !is_airport(second))
+    {
+        return None;
+    }
+
+    let time = ReportTime::parse(rest.get(..4)?)?;
+    let fuel = rest.get(4..).and_then(|fuel| fuel.trim().parse().ok());
+
+    let (origin, destination) = match (second, event) {
+        (Some(second), _) => (Some(first), Some(second)),
+        (None, OooiEventType::Out | OooiEventType::Off) => (Some(first), None),
+        (None, OooiEventType::On | OooiEventType::In) => (None, Some(first)),
+    };
+
+    Some(LabelContent::Oooi(OooiEvent {
+        event,
+        time: Some(time),
+        origin,
+        destination,
+        fuel,
+    }))
+}
+
+/// Label 5U. The stations are listed after STA keywords, or just as airport codes.
+fn decode_weather_request(_message: &AssembledACARSMessage, text: &str) -> Option<LabelContent> {
+    let tokens = tokens(text);
+    let mut stations: Vec<String> = tokens
+        .windows(2)
+        .filter(|pair| pair[0] == "STA" && is_airport(pair[1]))
+        .map(|pair| pair[1].to_string())
+        .collect();
+
+    if stations.is_empty() {
+        stations = tokens
+            .iter()
+            .filter(|token| is_airport(token))
+            .map(|token| (*token).to_string())
+            .collect();
+    }
+
+    if stations.is_empty() {
+        return None;
+    }
+
+    Some(LabelContent::Weather(WeatherReport {
+        report_type: WeatherReportType::Request,
+        stations,
+        report: None,
+    }))
+}
+
+/// Weather uplinks. Any METAR, SPECI or TAF in the text, up to the end of the message.
+fn decode_weather_report(_message: &AssembledACARSMessage, text: &str) -> Option<LabelContent> {
+    let tokens = tokens(text);
+    let (index, report_type) =
+        tokens
+            .iter()
+            .enumerate()
+            .find_map(|(index, token)| match *token {
+                "METAR" | "SPECI" => Some((index, WeatherReportType::Metar)),
+                "TAF" => Some((index, WeatherReportType::Taf)),
+                _ => None,
+            })?;
+    let station = tokens[index + 1..]
+        .iter()
+        .find(|token| !matches!(**token, "AMD" | "COR"))
+        .filter(|token| is_airport(token))?;
+    let start = find_word(text, tokens[index])?;
+    let report = text[start..]
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    Some(LabelContent::Weather(WeatherReport {
+        report_type,
+        stations: vec![(*station).to_string()],
+        report: Some(report),
+    }))
+}
+
+/// Free text ETA report:
+///
+///   ETA 1423 KLAX FOB 123
+fn decode_eta(_message: &AssembledACARSMessage, text: &str) -> Option<LabelContent> {
+    let tokens = tokens(text);
+    let eta = ReportTime::parse(keyword_value(&tokens, &["ETA"])?)?;
+    let destination = keyword_value(&tokens, &["DEST", "DST"])
+        .filter(|airport| is_airport(airport))
+        .or_else(|| tokens.iter().copied().find(|token| is_airport(token)))
+        .map(ToString::to_string);
+
+    Some(LabelContent::Eta(EtaReport {
+        eta,
+        destination,
+        fuel_on_board: fuel_on_board(&tokens),
+    }))
+}
+
+/// Free text fuel report:
+///
+///   FOB 12345 BURN 4321
+fn decode_fuel(_message: &AssembledACARSMessage, text: &str) -> Option<LabelContent> {
+    let tokens = tokens(text);
+
+    Some(LabelContent::Fuel(FuelReport {
+        fuel_on_board: fuel_on_board(&tokens)?,
+        fuel_used: keyword_value(&tokens, &["BURN", "USED", "FU"])
+            .and_then(|used| used.parse().ok()),
+    }))
+}
+
+fn decode_squitter(_message: &AssembledACARSMessage, text: &str) -> Option<LabelContent> {
+    squitter::decode(text).map(LabelContent::GroundStation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(label: &str, text: &str) -> AssembledACARSMessage {
+        let label: Vec<char> = label.chars().collect();
+        AssembledACARSMessage {
+            label: [label[0], label[1]],
+            message_text: Some(text.chars().collect()),
+            ..Default::default()
+        }
+    }
+
+    fn decode(label: &str, text: &str) -> Option<LabelContent> {
+        LabelDecoderRegistry::with_default_decoders().decode(&message(label, text))
+    }
+
+    fn position(label: &str, text: &str) -> PositionReport {
+        match decode(label, text) {
+            Some(LabelContent::Position(report)) => report,
+            other => panic!("expected a position report, got {other:?}"),
+        }
+    }
+
+    fn assert_close(value: f64, expected: f64) {
+        assert!(
+            (value - expected).abs() < 0.0001,
+            "{value} is not close to {expected}"
+        );
+    }
+
+    #[test]
+    fn test_position_reports() {
+        let report = position(
+            "H1",
+            "POSN35286W108525,GUP,004729,320,HAHAA,010907,,M44,24559,138ECB0",
+        );
+        assert_close(report.latitude, 35.0 + 28.6 / 60.0);
+        assert_close(report.longitude, -(108.0 + 52.5 / 60.0));
+        assert_eq!(report.altitude, Some(32000));
+        assert_eq!(report.waypoint.as_deref(), Some("GUP"));
+        assert_eq!(
+            report.time,
+            Some(ReportTime {
+                hour: 0,
+                minute: 47,
+                second: Some(29)
+            })
+        );
+        assert_eq!(report.next_waypoint.as_deref(), Some("HAHAA"));
+        assert_eq!(report.following_waypoint, None);
+        assert_eq!(report.temperature, Some(-44));
+        assert_eq!(report.wind_direction, Some(245));
+        assert_eq!(report.wind_speed, Some(59));
+
+        let report = position("15", "(2N38111W 82211266 76400-64(Z");
+        assert_close(report.latitude, 38.0 + 11.1 / 60.0);
+        assert_close(report.longitude, -(82.0 + 21.1 / 60.0));
+
+        let report = position("16", "N 44.203,W 86.546,31965,6, 290,312,M 55");
+        assert_close(report.latitude, 44.203);
+        assert_close(report.longitude, -86.546);
+        assert_eq!(report.altitude, Some(31965));
+
+        let report = position("22", "N 401017W 831535,-------,180817,3730");
+        assert_close(report.latitude, 40.0 + 10.0 / 60.0 + 17.0 / 3600.0);
+        assert_close(report.longitude, -(83.0 + 15.0 / 60.0 + 35.0 / 3600.0));
+
+        // A flight level too large to be real is dropped instead of overflowing
+        let report = position("H1", "POSN35286W108525,GUP,004729,99999999,HAHAA");
+        assert_eq!(report.altitude, None);
+
+        // H1 messages that are not position reports are left alone
+        assert_eq!(decode("H1", "REQPER,PRFE36"), None);
+        assert_eq!(decode("16", "SOME FREE TEXT"), None);
+    }
+
+    #[test]
+    fn test_oooi_events() {
+        assert_eq!(
+            decode("QA", "KBOSKJFK1254 184"),
+            Some(LabelContent::Oooi(OooiEvent {
+                event: OooiEventType::Out,
+                time: Some(ReportTime {
+                    hour: 12,
+                    minute: 54,
+                    second: None
+                }),
+                origin: Some("KBOS".to_string()),
+                destination: Some("KJFK".to_string()),
+                fuel: Some(184),
+            }))
+        );
+
+        assert_eq!(
+            decode("QD", "KJFK1402"),
+            Some(LabelContent::Oooi(OooiEvent {
+                event: OooiEventType::In,
+                time: Some(ReportTime {
+                    hour: 14,
+                    minute: 2,
+                    second: None
+                }),
+                origin: None,
+                destination: Some("KJFK".to_string()),
+                fuel: None,
+            }))
+        );
+
+        assert_eq!(
+            decode("14", "OFF 1423 KORD KLAX"),
+            Some(LabelContent::Oooi(OooiEvent {
+                event: OooiEventType::Off,
+                time: Some(ReportTime {
+                    hour: 14,
+                    minute: 23,
+                    second: None
+                }),
+                origin: Some("KORD".to_string()),
+                destination: Some("KLAX".to_string()),
+                fuel: None,
+            }))
+        );
+
+        assert_eq!(decode("QB", "GARBAGE"), None);
+
+        // Words that look like airports, and numbers that look like times, are left out
+        assert_eq!(decode("QA", "GATEINFO1254"), None);
+        assert_eq!(decode("10", "OUT GATE INFO"), None);
+        assert_eq!(decode("12", "ON 2459 1260"), None);
+        assert_eq!(
+            decode("10", "OUT 2510 KORD FOB 1234"),
+            Some(LabelContent::Oooi(OooiEvent {
+                event: OooiEventType::Out,
+                time: None,
+                origin: Some("KORD".to_string()),
+                destination: None,
+                fuel: Some(1234),
+            }))
+        );
+    }
+
+    #[test]
+    fn test_weather() {
+        assert_eq!(
+            decode(
+                "5U",
+                "01 WXRQ 6005/01 KSFO/KLAX .N405UA/TYP 4/STA KSFO/STA KLAX"
+            ),
+            Some(LabelContent::Weather(WeatherReport {
+                report_type: WeatherReportType::Request,
+                stations: vec!["KSFO".to_string(), "KLAX".to_string()],
+                report: None,
+            }))
+        );
+
+        assert_eq!(
+            decode(
+                "RA",
+                "WX REPORT\r\nMETAR KDEN 261753Z 16011KT 10SM FEW080 SCT200 27/04 A3012\r\n"
+            ),
+            Some(LabelContent::Weather(WeatherReport {
+                report_type: WeatherReportType::Metar,
+                stations: vec!["KDEN".to_string()],
+                report: Some(
+                    "METAR KDEN 261753Z 16011KT 10SM FEW080 SCT200 27/04 A3012".to_string()
+                ),
+            }))
+        );
+
+        // Gate information uplink from the receiver samples
+        assert_eq!(
+            decode(
+                "RA",
+                "QUHDQITOO.1ARR GATE INFO   \r\nPRC ARRIVAL GATE 2\r\n"
+            ),
+            None
+        );
+        assert_eq!(decode("RA", "METAR INFO 261753Z 16011KT"), None);
+        assert_eq!(decode("5U", "WXRQ STA GATE/STA INFO"), None);
+
+        // The report starts at the keyword, not at a word that contains it
+        assert_eq!(
+            decode(
+                "RA",
+                "NOTAF AMENDED\r\nTAF KDEN 261720Z 2618/2724 16012KT P6SM"
+            ),
+            Some(LabelContent::Weather(WeatherReport {
+                report_type: WeatherReportType::Taf,
+                stations: vec!["KDEN".to_string()],
+                report: Some("TAF KDEN 261720Z 2618/2724 16012KT P6SM".to_string()),
+            }))
+        );
+    }
+
+    #[test]
+    fn test_eta_and_fuel() {
+        assert_eq!(
+            decode("5Z", "ETA 1423 KLAX FOB 123"),
+            Some(LabelContent::Eta(EtaReport {
+                eta: ReportTime {
+                    hour: 14,
+                    minute: 23,
+                    second: None
+                },
+                destination: Some("KLAX".to_string()),
+                fuel_on_board: Some(123),
+            }))
+        );
+
+        assert_eq!(
+            decode("5Z", "FOB 12345 BURN 4321"),
+            Some(LabelContent::Fuel(FuelReport {
+                fuel_on_board: 12345,
+                fuel_used: Some(4321),
+            }))
+        );
+    }
+
+    #[test]
+    fn test_custom_decoder() {
+        let mut registry = LabelDecoderRegistry::new();
+        registry.register(
+            ['H', '1'],
+            Some(['D', 'F']),
+            |_message: &AssembledACARSMessage, text: &str| {
+                Some(LabelContent::Custom {
+                    name: "Engine Data".to_string(),
+                    fields: vec![("Text".to_string(), text.to_string())],
+                })
+            },
+        );
+
+        let mut message = message("H1", "A38/A32138");
+        assert_eq!(registry.decode(&message), None);
+
+        message.sublabel = Some(['D', 'F']);
+        assert_eq!(
+            registry.decode(&message),
+            Some(LabelContent::Custom {
+                name: "Engine Data".to_string(),
+                fields: vec![("Text".to_string(), "A38/A32138".to_string())],
+            })
+        );
+    }
+
+    /// The kind of content, with the event for OOOI reports
+    fn kind(content: &LabelContent) -> String {
+        match content {
+            LabelContent::Position(_) => "position".to_string(),
+            LabelContent::Oooi(event) => format!("oooi {:?}", event.event),
+            LabelContent::Weather(report) => format!("weather {:?}", report.report_type),
+            LabelContent::Eta(_) => "eta".to_string(),
+            LabelContent::Fuel(_) => "fuel".to_string(),
+            LabelContent::GroundStation(_) => "ground station".to_string(),
+            LabelContent::Custom { name, .. } => name.clone(),
+        }
+    }
+
+    /// A message for every registered label, and what it decodes to
+    const SAMPLES: [(&str, &str, &str); 24] = [
+        ("15", "(2N38111W 82211266 76400-64(Z", "position"),
+        ("16", "N 44.203,W 86.546,31965,6, 290,312,M 55", "position"),
+        ("20", "N 401017W 831535,-------,180817,3730", "position"),
+        ("22", "N 401017W 831535,-------,180817,3730", "position"),
+        (
+            "H1",
+            "POSN35286W108525,GUP,004729,320,HAHAA,010907,,M44,24559,138ECB0",
+            "position",
+        ),
+        ("10", "OUT 1423 KORD KLAX FOB 1234", "oooi Out"),
+        ("11", "OFF 1441 KORD KLAX", "oooi Off"),
+        ("12", "ON 1702 KLAX", "oooi On"),
+        ("13", "IN 1710 KLAX FOB 52", "oooi In"),
+        ("14", "OFF 1423 KORD KLAX", "oooi Off"),
+        ("10", "1423 KORD", "oooi Out"),
+        ("QA", "KBOSKJFK1254 184", "oooi Out"),
+        ("QB", "KBOSKJFK1301", "oooi Off"),
+        ("QC", "KJFK1402", "oooi On"),
+        ("QD", "KJFK1409 52", "oooi In"),
+        ("QE", "KBOS1254", "oooi Out"),
+        ("QF", "KBOS1301", "oooi Off"),
+        (
+            "5U",
+            "01 WXRQ 6005/01 KSFO/KLAX .N405UA/TYP 4/STA KSFO/STA KLAX",
+            "weather Request",
+        ),
+        (
+            "RA",
+            "WX REPORT\r\nMETAR KDEN 261753Z 16011KT 10SM FEW080 SCT200 27/04 A3012\r\n",
+            "weather Metar",
+        ),
+        (
+            "C1",
+            "TAF AMD KDEN 261720Z 2618/2724 16012KT P6SM",
+            "weather Taf",
+        ),
+        ("5Z", "ETA 1423 KLAX FOB 123", "eta"),
+        ("5Z", "FOB 12345 BURN 4321", "fuel"),
+        (
+            "SQ",
+            "02XAABQKABQ13502N10637WV136975/ARINC",
+            "ground station",
+        ),
+        ("SQ", "00XS", "ground station"),
+    ];
+
+    #[test]
+    fn test_every_registered_label() {
+        let registry = LabelDecoderRegistry::with_default_decoders();
+
+        // Every label with a decoder has a sample
+        let mut labels: Vec<[char; 2]> = registry.decoders.keys().copied().collect();
+        labels.sort_unstable();
+        let mut sampled: Vec<[char; 2]> = SAMPLES
+            .iter()
+            .map(|(label, _, _)| {
+                let label: Vec<char> = label.chars().collect();
+                [label[0], label[1]]
+            })
+            .collect();
+        sampled.sort_unstable();
+        sampled.dedup();
+        assert_eq!(labels, sampled);
+
+        for (label, text, expected) in SAMPLES {
+            assert_eq!(
+                decode(label, text).as_ref().map(kind).as_deref(),
+                Some(expected),
+                "{label} {text}"
+            );
+        }
+
+        // Labels without a decoder, and messages without text
+        assert_eq!(decode("_d", "OUT 1423 KORD KLAX"), None);
+        assert_eq!(decode("H1", ""), None);
+        let mut no_text = message("10", "");
+        no_text.message_text = None;
+        assert_eq!(registry.decode(&no_text), None);
+    }
+
+    #[test]
+    fn test_malformed_text() {
+        let registry = LabelDecoderRegistry::with_default_decoders();
+        let labels: Vec<[char; 2]> = registry.decoders.keys().copied().collect();
+
+        // Every sample cut short, and every sample under every other label
+        for (_, text, _) in SAMPLES {
+            for label in &labels {
+                for end in text.char_indices().map(|(index, _)| index) {
+                    let mut message = message("H1", &text[..end]);
+                    message.label = *label;
+                    if let Some(content) = registry.decode(&message) {
+                        let _ = content.to_string();
+                    }
+                }
+            }
+        }
+
+        // Random text made of the characters the formats are built from
+        let mut state: u32 = 11;
+        let mut next = || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            state >> 16
+        };
+        let alphabet: Vec<char> = "0123456789 ,./-()NSEWKQMPFTOURINABCXZ\r\nÄ€"
+            .chars()
+            .collect();
+        let keywords = [
+            "POS", "OUT ", "OFF ", "ON ", "IN ", "FOB ", "ETA ", "STA ", "METAR ", "TAF ", "FL ",
+            "N ", "02X",
+        ];
+        for _ in 0..3000 {
+            let mut text = keywords[next() as usize % keywords.len()].to_string();
+            let length = next() as usize % 48;
+            text.extend((0..length).map(|_| alphabet[next() as usize % alphabet.len()]));
+            let mut message = message("H1", &text);
+            message.label = labels[next() as usize % labels.len()];
+            if let Some(content) = registry.decode(&message) {
+                let _ = content.to_string();
+            }
+        }
+    }
+}
@@ -0,0 +1,1153 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// MIAM (ARINC 823) decoding. MIAM frames are sent on label MA. The first character of the text
+// identifies the frame, and the payload is base85 encoded. Payloads too long for a single ACARS
+// block are split over several blocks (all but the last ending in ETB), and larger transfers are
+// split into file segments announced by a file transfer request.
+//
+// The reassembled payload is a MIAM core PDU:
+//
+//   byte 0      version (high nibble) and PDU type (low nibble)
+//   bytes 1-3   length of the whole PDU
+//   byte 4      length of the aircraft id, followed by the aircraft id
+//   next byte   message number
+//   next byte   ack requested (1 bit), compression (3 bits), encoding (2 bits), spare (2 bits)
+//   next byte   application type, followed by the application id (2, 4 or 6 characters)
+//   CRC         CRC-32 for version 1, CRC-16 for version 2, over the uncompressed body
+//   body        the application data, compressed as indicated
+//
+// ACARS applications carry the label, sublabel and MFI of the inner message in the application
+// id, and the inner message text as the body. The inner message is run through the same
+// application and label decoders as a message received directly.
+
+use crate::decoders::acars::{AssembledACARSMessage, DownlinkStatus};
+use crate::decoders::arinc622::{self, Arinc622Message};
+use crate::decoders::labels::{LabelContent, LabelDecoderRegistry};
+use flate2::read::DeflateDecoder;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::io::Read;
+
+const MIAM_LABEL: [char; 2] = ['M', 'A'];
+/// ACARS end of transmission block. Blocks ending in this are followed by more blocks
+const ETB: char = '\u{17}';
+/// Partially received messages and files are dropped after this many seconds
+const REASSEMBLY_TIMEOUT: u64 = 300;
+/// Largest decompressed body we are willing to produce
+const MAX_BODY_LEN: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MiamPduType {
+    Data,
+    Ack,
+    Aloha,
+    AlohaReply,
+    Unknown(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MiamCompression {
+    None,
+    Deflate,
+    Unknown(u8),
+}
+
+/// The MIAM frame carried by a single ACARS message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MiamFrame {
+    /// A complete core PDU in one transfer
+    SingleTransfer,
+    FileTransferRequest {
+        file_id: u16,
+        file_size: usize,
+    },
+    FileTransferAccept {
+        file_id: u16,
+    },
+    FileSegment {
+        file_id: u16,
+        segment: u16,
+    },
+    FileTransferAbort {
+        file_id: u16,
+    },
+    Xoff,
+    Xon,
+    /// A block of a multi block message. The frame is decoded once the last block arrives
+    PartialBlock,
+}
+
+/// Application data carried by a MIAM core PDU
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MiamApplication {
+    Acars {
+        label: [char; 2],
+        sublabel: Option<[char; 2]>,
+        mfi: Option<[char; 2]>,
+        text: String,
+        arinc622: Option<Arinc622Message>,
+        /// Structured content of the inner message, for the labels we have a decoder for
+        label_content: Option<Box<LabelContent>>,
+    },
+    Other {
+        application_type: u8,
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MiamCorePdu {
+    pub version: u8,
+    pub pdu_type: MiamPduType,
+    pub aircraft_id: String,
+    pub message_number: u8,
+    pub ack_requested: bool,
+    pub compression: MiamCompression,
+    /// True if the CRC of the body was correct. If false, the application data should not be trusted
+    pub crc_ok: bool,
+    pub application: Option<MiamApplication>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MiamMessage {
+    pub frame: MiamFrame,
+    /// The core PDU, once the frame, or all the blocks or segments that make it up, are received
+    pub core: Option<MiamCorePdu>,
+}
+
+#[derive(Clone)]
+struct PendingBlocks {
+    received: u64,
+    text: String,
+}
+
+#[derive(Clone)]
+struct PendingFile {
+    received: u64,
+    file_size: usize,
+    segments: HashMap<u16, Vec<u8>>,
+}
+
+/// Keeps the state needed to put MIAM messages back together across ACARS blocks and file segments.
+#[derive(Clone, Default)]
+pub struct MiamReassembler {
+    blocks: HashMap<(String, String), PendingBlocks>,
+    files: HashMap<(String, u16), PendingFile>,
+}
+
+impl MiamReassembler {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode the MIAM frame in a received message. `now` is the current time in seconds and is
+    /// used to expire incomplete transfers. The inner ACARS message is decoded with
+    /// `label_decoders`.
+    pub fn process(
+        &mut self,
+        message: &AssembledACARSMessage,
+        now: u64,
+        label_decoders: &LabelDecoderRegistry,
+    ) -> Option<MiamMessage> {
+        if message.label != MIAM_LABEL {
+            return None;
+        }
+
+        self.blocks
+            .retain(|_, pending| now.saturating_sub(pending.received) < REASSEMBLY_TIMEOUT);
+        self.files
+            .retain(|_, pending| now.saturating_sub(pending.received) < REASSEMBLY_TIMEOUT);
+
+        let tail: String = message.aircraft_tail.unwrap_or([' '; 7]).iter().collect();
+        let block_key = (
+            tail.clone(),
+            message
+                .message_number_without_sequence
+                .unwrap_or([' '; 3])
+                .iter()
+                .collect::<String>(),
+        );
+        let text: String = message.message_text.as_ref()?.iter().collect();
+
+        if message.block_end == ETB {
+            let pending = self.blocks.entry(block_key).or_insert(PendingBlocks {
+                received: now,
+                text: String::new(),
+            });
+            pending.received = now;
+            pending.text.push_str(&text);
+
+            return Some(MiamMessage {
+                frame: MiamFrame::PartialBlock,
+                core: None,
+            });
+        }
+
+        let text = match self.blocks.remove(&block_key) {
+            Some(mut pending) => {
+                pending.text.push_str(&text);
+                pending.text
+            }
+            None => text,
+        };
+
+        let mut miam = self.process_frame(&tail, &text, &message.downlink_status, now)?;
+
+        if let Some(MiamCorePdu {
+            application:
+                Some(MiamApplication::Acars {
+                    label,
+                    sublabel,
+                    mfi,
+                    text,
+                    label_content,
+                    ..
+                }),
+            ..
+        }) = &mut miam.core
+        {
+            let inner = AssembledACARSMessage {
+                label: *label,
+                sublabel: *sublabel,
+                mfi: *mfi,
+                message_text: Some(text.chars().collect()),
+                aircraft_tail: message.aircraft_tail,
+                flight_id: message.flight_id,
+                downlink_status: message.downlink_status.clone(),
+                frequency: message.frequency,
+                ..AssembledACARSMessage::default()
+            };
+            *label_content = label_decoders.decode(&inner).map(Box::new);
+        }
+
+        Some(miam)
+    }
+
+    fn process_frame(
+        &mut self,
+        tail: &str,
+        text: &str,
+        downlink_status: &DownlinkStatus,
+        now: u64,
+    ) -> Option<MiamMessage> {
+        let mut chars = text.chars();
+        let frame_id = chars.next()?;
+        let rest = chars.as_str();
+
+        let (frame, pdu) = match frame_id {
+            'T' => (MiamFrame::SingleTransfer, Some(base85_decode(rest)?)),
+            'F' => {
+                let file_id = parse_number(rest.get(0..3)?)?;
+                let file_size: usize = rest.get(3..9)?.parse().ok()?;
+                self.files.insert(
+                    (tail.to_string(), file_id),
+                    PendingFile {
+                        received: now,
+                        file_size,
+                        segments: HashMap::new(),
+                    },
+                );
+                (MiamFrame::FileTransferRequest { file_id, file_size }, None)
+            }
+            'K' => (
+                MiamFrame::FileTransferAccept {
+                    file_id: parse_number(rest.get(0..3)?)?,
+                },
+                None,
+            ),
+            'S' => {
+                let file_id = parse_number(rest.get(0..3)?)?;
+                let segment = parse_number(rest.get(3..6)?)?;
+                let data = base85_decode(rest.get(6..)?)?;
+                let pdu = self.add_segment(tail, file_id, segment, data, now);
+                (MiamFrame::FileSegment { file_id, segment }, pdu)
+            }
+            'A' => {
+                let file_id = parse_number(rest.get(0..3)?)?;
+                self.files.remove(&(tail.to_string(), file_id));
+                (MiamFrame::FileTransferAbort { file_id }, None)
+            }
+            'Y' => (MiamFrame::Xoff, None),
+            'X' => (MiamFrame::Xon, None),
+            _ => return None,
+        };
+
+        Some(MiamMessage {
+            frame,
+            core: pdu.and_then(|pdu| parse_core_pdu(&pdu, downlink_status)),
+        })
+    }
+
+    /// Store a file segment. Returns the whole file once every byte of it has arrived
+    fn add_segment(
+        &mut self,
+        tail: &str,
+        file_id: u16,
+        segment: u16,
+        data: Vec<u8>,
+        now: u64,
+    ) -> Option<Vec<u8>> {
+        let key = (tail.to_string(), file_id);
+        let pending = self.files.get_mut(&key)?;
+        pending.received = now;
+        pending.segments.insert(segment, data);
+
+        let received: usize = pending.segments.values().map(Vec::len).sum();
+        if received < pending.file_size {
+            return None;
+        }
+
+        let pending = self.files.remove(&key)?;
+        let mut segments: Vec<(u16, Vec<u8>)> = pending.segments.into_iter().collect();
+        segments.sort_by_key(|(segment, _)| *segment);
+
+        let mut file: Vec<u8> = segments.into_iter().flat_map(|(_, data)| data).collect();
+        file.truncate(pending.file_size);
+        Some(file)
+    }
+}
+
+fn parse_number(text: &str) -> Option<u16> {
+    text.parse().ok()
+}
+
+/// Decode ASCII85. A trailing partial group of n characters produces n - 1 bytes
+fn base85_decode(text: &str) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(text.len() * 4 / 5);
+    let mut group: Vec<u32> = Vec::with_capacity(5);
+
+    for c in text.chars().filter(|c| !c.is_whitespace()) {
+        if c == 'z' && group.is_empty() {
+            output.extend_from_slice(&[0; 4]);
+            continue;
+        }
+
+        if !('!'..='u').contains(&c) {
+            return None;
+        }
+
+        group.push(u32::from(c) - u32::from('!'));
+
+        if group.len() == 5 {
+            output.extend_from_slice(&base85_group(&group)?);
+            group.clear();
+        }
+    }
+
+    if !group.is_empty() {
+        let bytes = group.len() - 1;
+        group.resize(5, 84);
+        output.extend_from_slice(&base85_group(&group)?[..bytes]);
+    }
+
+    Some(output)
+}
+
+fn base85_group(group: &[u32]) -> Option<[u8; 4]> {
+    let value = group.iter().try_fold(0_u32, |value, digit| {
+        value.checked_mul(85)?.checked_add(*digit)
+    })?;
+    Some(value.to_be_bytes())
+}
+
+fn parse_core_pdu(pdu: &[u8], downlink_status: &DownlinkStatus) -> Option<MiamCorePdu> {
+    let first = *pdu.first()?;
+    let version = first >> 4;
+    let pdu_type = match first & 0x0f {
+        0 => MiamPduType::Data,
+        1 => MiamPduType::Ack,
+        2 => MiamPduType::Aloha,
+        3 => MiamPduType::AlohaReply,
+        other => MiamPduType::Unknown(other),
+    };
+
+    let pdu_len = u32::from_be_bytes([0, *pdu.get(1)?, *pdu.get(2)?, *pdu.get(3)?]) as usize;
+    // base85 padding may leave a few extra bytes on the end
+    let pdu = pdu.get(..pdu_len)?;
+
+    let aircraft_id_len = usize::from(*pdu.get(4)?);
+    let mut position = 5;
+    let aircraft_id: String = pdu
+        .get(position..position + aircraft_id_len)?
+        .iter()
+        .map(|byte| char::from(*byte))
+        .collect();
+    position += aircraft_id_len;
+
+    let message_number = *pdu.get(position)?;
+    let flags = *pdu.get(position + 1)?;
+    let ack_requested = flags & 0x80 != 0;
+    let compression = match (flags >> 4) & 0x07 {
+        0 => MiamCompression::None,
+        1 => MiamCompression::Deflate,
+        other => MiamCompression::Unknown(other),
+    };
+    position += 2;
+
+    let mut core = MiamCorePdu {
+        version,
+        pdu_type,
+        aircraft_id: aircraft_id.trim().to_string(),
+        message_number,
+        ack_requested,
+        compression,
+        crc_ok: false,
+        application: None,
+    };
+
+    if pdu_type != MiamPduType::Data {
+        core.crc_ok = true;
+        return Some(core);
+    }
+
+    let application_type = *pdu.get(position)?;
+    position += 1;
+    let application_id_len = match application_type {
+        0 => 2,
+        1 => 4,
+        2 => 6,
+        _ => 0,
+    };
+    let application_id: Vec<char> = pdu
+        .get(position..position + application_id_len)?
+        .iter()
+        .map(|byte| char::from(*byte))
+        .collect();
+    position += application_id_len;
+
+    let crc_len = if version == 1 { 4 } else { 2 };
+    let crc = pdu.get(position..position + crc_len)?;
+    position += crc_len;
+
+    let body = decompress(pdu.get(position..)?, compression)?;
+
+    core.crc_ok = if version == 1 {
+        let mut body_crc = flate2::Crc::new();
+        body_crc.update(&body);
+        crc == body_crc.sum().to_be_bytes()
+    } else {
+        crc == arinc622::crc16(&body).to_be_bytes()
+    };
+
+    core.application = Some(if application_id_len == 0 {
+        MiamApplication::Other {
+            application_type,
+            data: body,
+        }
+    } else {
+        let label = [application_id[0], application_id[1]];
+        let text: String = body.iter().map(|byte| char::from(*byte)).collect();
+        let text_chars: Vec<char> = text.chars().collect();
+
+        MiamApplication::Acars {
+            label,
+            sublabel: application_id.get(2..4).map(|s| [s[0], s[1]]),
+            mfi: application_id.get(4..6).map(|m| [m[0], m[1]]),
+            arinc622: arinc622::decode(label, &text_chars, downlink_status),
+            text,
+            label_content: None,
+        }
+    });
+
+    Some(core)
+}
+
+fn decompress(body: &[u8], compression: MiamCompression) -> Option<Vec<u8>> {
+    match compression {
+        MiamCompression::None => Some(body.to_vec()),
+        MiamCompression::Deflate => {
+            let mut output = Vec::new();
+            DeflateDecoder::new(body)
+                .take(MAX_BODY_LEN)
+                .read_to_end(&mut output)
+                .ok()?;
+            Some(output)
+        }
+        MiamCompression::Unknown(_) => None,
+    }
+}
+
+impl Display for MiamFrame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SingleTransfer => write!(f, "Single Transfer"),
+            Self::FileTransferRequest { file_id, file_size } => {
+                write!(
+                    f,
+                    "File Transfer Request: File {file_id}, {file_size} bytes"
+                )
+            }
+            Self::FileTransferAccept { file_id } => {
+                write!(f, "File Transfer Accept: File {file_id}")
+            }
+            Self::FileSegment { file_id, segment } => {
+                write!(f, "File Segment: File {file_id}, Segment {segment}")
+            }
+            Self::FileTransferAbort { file_id } => {
+                write!(f, "File Transfer Abort: File {file_id}")
+            }
+            Self::Xoff => write!(f, "XOFF"),
+            Self::Xon => write!(f, "XON"),
+            Self::PartialBlock => write!(f, "Partial Block"),
+        }
+    }
+}
+
+impl Display for MiamMessage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.frame)?;
+
+        let Some(core) = &self.core else {
+            return Ok(());
+        };
+
+        write!(
+            f,
+            "; Core v{} {:?} from {}, Message {}{}",
+            core.version,
+            core.pdu_type,
+            core.aircraft_id,
+            core.message_number,
+            if core.crc_ok { "" } else { " (CRC FAILED)" }
+        )?;
+
+        match &core.application {
+            Some(MiamApplication::Acars {
+                label,
+                sublabel,
+                mfi,
+                text,
+                arinc622,
+                label_content,
+            }) => {
+                write!(f, "; Label: {}{}", label[0], label[1])?;
+                if let Some([first, second]) = sublabel {
+                    write!(f, ", Sublabel: {first}{second}")?;
+                }
+                if let Some([first, second]) = mfi {
+                    write!(f, ", MFI: {first}{second}")?;
+                }
+                write!(f, ", Text: {text}")?;
+                if let Some(arinc622) = arinc622 {
+                    write!(f, ", ARINC 622: {arinc622}")?;
+                }
+                if let Some(label_content) = label_content {
+                    write!(f, ", {label_content}")?;
+                }
+            }
+            Some(MiamApplication::Other {
+                application_type,
+                data,
+            }) => write!(f, "; Application {application_type}: {} bytes", data.len())?,
+            None => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn base85_encode(data: &[u8]) -> String {
+        let mut output = String::new();
+        for chunk in data.chunks(4) {
+            let mut group = [0_u8; 4];
+            group[..chunk.len()].copy_from_slice(chunk);
+            let mut value = u32::from_be_bytes(group);
+            let mut digits = [0_u8; 5];
+            for digit in digits.iter_mut().rev() {
+                *digit = (value % 85) as u8 + b'!';
+                value /= 85;
+            }
+            output.extend(
+                digits[..=chunk.len()]
+                    .iter()
+                    .map(|digit| char::from(*digit)),
+            );
+        }
+        output
+    }
+
+    /// A data PDU from N123UA carrying an H1/M1 message
+    fn data_pdu(version: u8, flags: u8, crc: &[u8], body: &[u8]) -> Vec<u8> {
+        core_pdu(version << 4, flags, b"\x01H1M1", crc, body)
+    }
+
+    /// A core PDU from N123UA. `application` is the application type followed by its id
+    fn core_pdu(first: u8, flags: u8, application: &[u8], crc: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut pdu = vec![first, 0, 0, 0, 6];
+        pdu.extend_from_slice(b"N123UA");
+        pdu.extend_from_slice(&[42, flags]);
+        pdu.extend_from_slice(application);
+        pdu.extend_from_slice(crc);
+        pdu.extend_from_slice(body);
+
+        let len = pdu.len().to_be_bytes();
+        pdu[1..4].copy_from_slice(&len[len.len() - 3..]);
+        pdu
+    }
+
+    fn build_pdu(text: &str) -> Result<Vec<u8>, std::io::Error> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(text.as_bytes())?;
+        let body = encoder.finish()?;
+
+        let mut crc = flate2::Crc::new();
+        crc.update(text.as_bytes());
+
+        Ok(data_pdu(1, 0x90, &crc.sum().to_be_bytes(), &body))
+    }
+
+    fn message(text: &str, block_end: char) -> AssembledACARSMessage {
+        AssembledACARSMessage {
+            label: MIAM_LABEL,
+            aircraft_tail: Some(['.', 'N', '1', '2', '3', 'U', 'A']),
+            message_number_without_sequence: Some(['M', '0', '1']),
+            block_end,
+            message_text: Some(text.chars().collect()),
+            ..AssembledACARSMessage::default()
+        }
+    }
+
+    #[test]
+    fn test_miam_multi_block() -> Result<(), Box<dyn std::error::Error>> {
+        let inner = "/AKLCDYA.ADS.N123UA07E5A2C".repeat(8);
+        let frame = format!("T{}", base85_encode(&build_pdu(&inner)?));
+        let (first, second) = frame.split_at(frame.len() / 2);
+
+        let mut reassembler = MiamReassembler::new();
+        let decoders = LabelDecoderRegistry::with_default_decoders();
+
+        let partial = reassembler.process(&message(first, ETB), 100, &decoders);
+        assert_eq!(
+            partial,
+            Some(MiamMessage {
+                frame: MiamFrame::PartialBlock,
+                core: None,
+            })
+        );
+
+        let Some(miam) = reassembler.process(&message(second, '\u{3}'), 101, &decoders) else {
+            panic!("MIAM message was not decoded");
+        };
+
+        assert_eq!(miam.frame, MiamFrame::SingleTransfer);
+        let Some(core) = miam.core else {
+            panic!("Core PDU was not decoded");
+        };
+        assert!(core.crc_ok);
+        assert!(core.ack_requested);
+        assert_eq!(core.compression, MiamCompression::Deflate);
+        assert_eq!(core.aircraft_id, "N123UA");
+        assert_eq!(core.message_number, 42);
+
+        let Some(MiamApplication::Acars {
+            label,
+            sublabel,
+            text,
+            ..
+        }) = core.application
+        else {
+            panic!("ACARS application was not decoded");
+        };
+        assert_eq!(label, ['H', '1']);
+        assert_eq!(sublabel, Some(['M', '1']));
+        assert_eq!(text, inner);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_miam_file_transfer() -> Result<(), Box<dyn std::error::Error>> {
+        let pdu = build_pdu("POSITION REPORT")?;
+        let (first, second) = pdu.split_at(pdu.len() / 2);
+
+        let mut reassembler = MiamReassembler::new();
+        let decoders = LabelDecoderRegistry::with_default_decoders();
+
+        let request = format!("F007{:06}", pdu.len());
+        let Some(miam) = reassembler.process(&message(&request, '\u{3}'), 100, &decoders) else {
+            panic!("File transfer request was not decoded");
+        };
+        assert_eq!(
+            miam.frame,
+            MiamFrame::FileTransferRequest {
+                file_id: 7,
+                file_size: pdu.len()
+            }
+        );
+
+        // segments may arrive out of order
+        let second = format!("S007002{}", base85_encode(second));
+        let Some(miam) = reassembler.process(&message(&second, '\u{3}'), 101, &decoders) else {
+            panic!("File segment was not decoded");
+        };
+        assert_eq!(miam.core, None);
+
+        let first = format!("S007001{}", base85_encode(first));
+        let Some(miam) = reassembler.process(&message(&first, '\u{3}'), 102, &decoders) else {
+            panic!("File segment was not decoded");
+        };
+        let Some(core) = miam.core else {
+            panic!("Core PDU was not reassembled");
+        };
+        assert!(core.crc_ok);
+
+        // the transfer is finished, more segments are not part of anything
+        let Some(miam) = reassembler.process(&message(&first, '\u{3}'), 103, &decoders) else {
+            panic!("File segment was not decoded");
+        };
+        assert_eq!(miam.core, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_miam_inner_message() -> Result<(), Box<dyn std::error::Error>> {
+        let inner = "POSN35286W108525,GUP,004729,320,HAHAA,010907,,M44,24559,138ECB0";
+        let frame = format!("T{}", base85_encode(&build_pdu(inner)?));
+
+        let mut reassembler = MiamReassembler::new();
+        let decoders = LabelDecoderRegistry::with_default_decoders();
+
+        let Some(MiamMessage {
+            core:
+                Some(MiamCorePdu {
+                    application: Some(MiamApplication::Acars { label_content, .. }),
+                    ..
+                }),
+            ..
+        }) = reassembler.process(&message(&frame, '\u{3}'), 100, &decoders)
+        else {
+            panic!("ACARS application was not decoded");
+        };
+
+        let Some(LabelContent::Position(report)) = label_content.as_deref() else {
+            panic!("Inner position report was not decoded, got {label_content:?}");
+        };
+        assert!((report.latitude - (35.0 + 28.6 / 60.0)).abs() < 0.0001);
+        assert!((report.longitude + (108.0 + 52.5 / 60.0)).abs() < 0.0001);
+        assert_eq!(report.altitude, Some(32000));
+        assert_eq!(report.waypoint.as_deref(), Some("GUP"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_miam_crc_by_version() {
+        // The published check values over "123456789": CRC-32 (zlib) for version 1 and
+        // CRC-16/GENIBUS for version 2
+        let body = b"123456789";
+        let crc32 = 0xCBF4_3926_u32.to_be_bytes();
+        let crc16 = 0xD64E_u16.to_be_bytes();
+
+        let crc_ok = |version, crc: &[u8]| {
+            parse_core_pdu(
+                &data_pdu(version, 0, crc, body),
+                &DownlinkStatus::AirToGround,
+            )
+            .map(|core| core.crc_ok)
+        };
+
+        assert_eq!(crc_ok(1, &crc32), Some(true));
+        assert_eq!(crc_ok(2, &crc16), Some(true));
+        assert_eq!(crc_ok(1, &[0, 0, crc16[0], crc16[1]]), Some(false));
+        assert_eq!(crc_ok(2, &crc32[2..]), Some(false));
+    }
+
+    fn deflate(body: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body)?;
+        encoder.finish()
+    }
+
+    fn crc(version: u8, body: &[u8]) -> Vec<u8> {
+        if version == 1 {
+            let mut crc = flate2::Crc::new();
+            crc.update(body);
+            crc.sum().to_be_bytes().to_vec()
+        } else {
+            arinc622::crc16(body).to_be_bytes().to_vec()
+        }
+    }
+
+    /// Version, flags, the body as sent, what the CRC is over, and the compression and CRC
+    /// result it decodes to
+    type CompressionCase<'a> = (u8, u8, &'a [u8], &'a [u8], Option<(MiamCompression, bool)>);
+
+    #[test]
+    fn test_miam_compression() -> Result<(), Box<dyn std::error::Error>> {
+        let text = b"/BOMCAYA.ADS.9V-SWM07E5A2C";
+        let deflated = deflate(text)?;
+        let mut corrupt = deflated.clone();
+        corrupt.truncate(corrupt.len() / 2);
+
+        let table: [CompressionCase; 9] = [
+            (1, 0x00, text, text, Some((MiamCompression::None, true))),
+            (2, 0x00, text, text, Some((MiamCompression::None, true))),
+            (
+                1,
+                0x10,
+                &deflated,
+                text,
+                Some((MiamCompression::Deflate, true)),
+            ),
+            (
+                2,
+                0x10,
+                &deflated,
+                text,
+                Some((MiamCompression::Deflate, true)),
+            ),
+            // The CRC covers the uncompressed body
+            (
+                1,
+                0x10,
+                &deflated,
+                &deflated,
+                Some((MiamCompression::Deflate, false)),
+            ),
+            (
+                2,
+                0x00,
+                text,
+                b"/BOMCAYA",
+                Some((MiamCompression::None, false)),
+            ),
+            // Nothing can be made of a body we cannot decompress
+            (1, 0x10, &corrupt, text, None),
+            (1, 0x20, text, text, None),
+            (1, 0x70, text, text, None),
+        ];
+
+        for (version, flags, body, crc_over, expected) in table {
+            let pdu = data_pdu(version, flags, &crc(version, crc_over), body);
+            let core = parse_core_pdu(&pdu, &DownlinkStatus::AirToGround);
+            assert_eq!(
+                core.as_ref().map(|core| (core.compression, core.crc_ok)),
+                expected,
+                "version {version}, flags {flags:#04x}"
+            );
+
+            let Some(core) = core else {
+                continue;
+            };
+            assert_eq!(core.version, version);
+            assert!(!core.ack_requested);
+            let Some(MiamApplication::Acars { text, .. }) = core.application else {
+                panic!("ACARS application was not decoded");
+            };
+            assert_eq!(text, "/BOMCAYA.ADS.9V-SWM07E5A2C");
+        }
+
+        // A deflated body that would expand past the limit is cut off rather than produced
+        let large = deflate(&vec![b'A'; 1024 * 1024])?;
+        assert_eq!(
+            decompress(&large, MiamCompression::Deflate).map(|body| body.len()),
+            Some(64 * 1024)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_miam_pdu_types_and_applications() {
+        let body = b"FUEL 123";
+
+        for (first, pdu_type) in [
+            (0x11, MiamPduType::Ack),
+            (0x12, MiamPduType::Aloha),
+            (0x23, MiamPduType::AlohaReply),
+            (0x15, MiamPduType::Unknown(5)),
+        ] {
+            // Only data PDUs carry an application and a CRC
+            let pdu = core_pdu(first, 0x80, &[], &[], &[]);
+            let Some(core) = parse_core_pdu(&pdu, &DownlinkStatus::GroundToAir) else {
+                panic!("{pdu_type:?} PDU was not decoded");
+            };
+            assert_eq!(core.pdu_type, pdu_type);
+            assert!(core.ack_requested);
+            assert!(core.crc_ok);
+            assert_eq!(core.application, None);
+        }
+
+        let acars = |label: [char; 2], sublabel, mfi| {
+            Some(MiamApplication::Acars {
+                label,
+                sublabel,
+                mfi,
+                text: "FUEL 123".to_string(),
+                arinc622: None,
+                label_content: None,
+            })
+        };
+        for (application, expected) in [
+            (&b"\x00H1"[..], acars(['H', '1'], None, None)),
+            (b"\x01H1DF", acars(['H', '1'], Some(['D', 'F']), None)),
+            (
+                b"\x02H1DFM3",
+                acars(['H', '1'], Some(['D', 'F']), Some(['M', '3'])),
+            ),
+            (
+                b"\x09",
+                Some(MiamApplication::Other {
+                    application_type: 9,
+                    data: body.to_vec(),
+                }),
+            ),
+        ] {
+            let pdu = core_pdu(0x20, 0x00, application, &crc(2, body), body);
+            let Some(core) = parse_core_pdu(&pdu, &DownlinkStatus::AirToGround) else {
+                panic!("Data PDU was not decoded");
+            };
+            assert_eq!(core.pdu_type, MiamPduType::Data);
+            assert!(core.crc_ok);
+            assert_eq!(core.application, expected);
+        }
+    }
+
+    #[test]
+    fn test_miam_frames() -> Result<(), Box<dyn std::error::Error>> {
+        let single = format!("T{}", base85_encode(&build_pdu("POSITION REPORT")?));
+        let table = [
+            (single.as_str(), Some(MiamFrame::SingleTransfer)),
+            (
+                "F012000345",
+                Some(MiamFrame::FileTransferRequest {
+                    file_id: 12,
+                    file_size: 345,
+                }),
+            ),
+            ("K012", Some(MiamFrame::FileTransferAccept { file_id: 12 })),
+            (
+                "S012003!!",
+                Some(MiamFrame::FileSegment {
+                    file_id: 12,
+                    segment: 3,
+                }),
+            ),
+            ("A012", Some(MiamFrame::FileTransferAbort { file_id: 12 })),
+            ("Y", Some(MiamFrame::Xoff)),
+            ("X", Some(MiamFrame::Xon)),
+            // Frames we do not know and frames cut short
+            ("", None),
+            ("Q012", None),
+            ("F012", None),
+            ("F01200034", None),
+            ("FA12000345", None),
+            ("K01", None),
+            ("S01200", None),
+            ("A", None),
+            // Characters that are not base85
+            ("T{|}~", None),
+            ("S012003vwxy", None),
+        ];
+
+        let decoders = LabelDecoderRegistry::with_default_decoders();
+        for (text, expected) in table {
+            let mut reassembler = MiamReassembler::new();
+            let miam = reassembler.process(&message(text, '\u{3}'), 100, &decoders);
+            assert_eq!(
+                miam.as_ref().map(|miam| &miam.frame),
+                expected.as_ref(),
+                "{text}"
+            );
+            if let Some(miam) = miam {
+                assert_eq!(miam.core.is_some(), text == single, "{text}");
+            }
+        }
+
+        // Only label MA is MIAM
+        let mut reassembler = MiamReassembler::new();
+        let mut not_miam = message(&single, '\u{3}');
+        not_miam.label = ['H', '1'];
+        assert_eq!(reassembler.process(&not_miam, 100, &decoders), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_miam_block_reassembly() -> Result<(), Box<dyn std::error::Error>> {
+        let inner = "/BOMCAYA.ADS.9V-SWM07E5A2C".repeat(12);
+        let frame = format!("T{}", base85_encode(&build_pdu(&inner)?));
+        let third = frame.len() / 3;
+        let blocks = [
+            &frame[..third],
+            &frame[third..2 * third],
+            &frame[2 * third..],
+        ];
+        let decoders = LabelDecoderRegistry::with_default_decoders();
+
+        let text = |miam: Option<MiamMessage>| match miam.and_then(|miam| miam.core)?.application {
+            Some(MiamApplication::Acars { text, .. }) => Some(text),
+            _ => None,
+        };
+
+        // Three blocks, with another aircraft's blocks using the same message number in between
+        let mut reassembler = MiamReassembler::new();
+        let mut other = message(blocks[1], ETB);
+        other.aircraft_tail = Some(['.', 'N', '4', '5', '6', 'U', 'A']);
+        reassembler.process(&message(blocks[0], ETB), 100, &decoders);
+        reassembler.process(&other, 100, &decoders);
+        reassembler.process(&message(blocks[1], ETB), 101, &decoders);
+        assert_eq!(
+            text(reassembler.process(&message(blocks[2], '\u{3}'), 102, &decoders)),
+            Some(inner.clone())
+        );
+
+        // Each block keeps the message alive, but a gap longer than the timeout drops it
+        for (gap, expected) in [
+            (REASSEMBLY_TIMEOUT - 1, Some(inner)),
+            (REASSEMBLY_TIMEOUT, None),
+        ] {
+            let mut reassembler = MiamReassembler::new();
+            reassembler.process(&message(blocks[0], ETB), 100, &decoders);
+            reassembler.process(&message(blocks[1], ETB), 100 + gap, &decoders);
+            assert_eq!(
+                text(reassembler.process(&message(blocks[2], '\u{3}'), 100 + 2 * gap, &decoders)),
+                expected,
+                "gap {gap}"
+            );
+        }
+
+        // A message without its first block does not decode to anything
+        let mut reassembler = MiamReassembler::new();
+        reassembler.process(&message(blocks[1], ETB), 100, &decoders);
+        assert_eq!(
+            text(reassembler.process(&message(blocks[2], '\u{3}'), 101, &decoders)),
+            None
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_miam_file_segments() -> Result<(), Box<dyn std::error::Error>> {
+        let pdu = build_pdu("OUT 1432 OFF 1441 KDEN KORD")?;
+        let segments: Vec<String> = pdu
+            .chunks(8)
+            .enumerate()
+            .map(|(segment, data)| format!("S009{:03}{}", segment + 1, base85_encode(data)))
+            .collect();
+        let request = format!("F009{:06}", pdu.len());
+        let decoders = LabelDecoderRegistry::with_default_decoders();
+
+        let send = |reassembler: &mut MiamReassembler, text: &str, now| {
+            reassembler
+                .process(&message(text, '\u{3}'), now, &decoders)
+                .and_then(|miam| miam.core)
+        };
+
+        // In reverse order, with a segment sent twice
+        let mut reassembler = MiamReassembler::new();
+        send(&mut reassembler, &request, 100);
+        let (last, others) = segments.split_first().ok_or("no segments")?;
+        for segment in others.iter().rev() {
+            assert_eq!(send(&mut reassembler, segment, 101), None);
+        }
+        assert_eq!(send(&mut reassembler, &segments[1], 101), None);
+        let Some(core) = send(&mut reassembler, last, 102) else {
+            panic!("File was not reassembled");
+        };
+        assert!(core.crc_ok);
+
+        // Segments without a request, after an abort and after the timeout are not put together
+        for (before, now) in [
+            (vec![], 101),
+            (vec![request.clone(), "A009".to_string()], 101),
+            (vec![request], 100 + REASSEMBLY_TIMEOUT),
+        ] {
+            let mut reassembler = MiamReassembler::new();
+            for text in &before {
+                send(&mut reassembler, text, 100);
+            }
+            for segment in &segments {
+                assert_eq!(send(&mut reassembler, segment, now), None, "{before:?}");
+            }
+        }
+
+        // A file announced as shorter than its segments is cut to size, which here cuts the PDU
+        // short of the length in its header
+        let mut reassembler = MiamReassembler::new();
+        send(&mut reassembler, &format!("F009{:06}", pdu.len() - 2), 100);
+        for segment in &segments {
+            assert_eq!(send(&mut reassembler, segment, 101), None);
+        }
+        assert!(reassembler.files.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_miam_malformed() -> Result<(), Box<dyn std::error::Error>> {
+        let pdu = build_pdu("POSN35286W108525,GUP,004729,320,HAHAA,010907,,M44,24559,138ECB0")?;
+        let decoders = LabelDecoderRegistry::with_default_decoders();
+
+        // Every prefix of the PDU, and of the frame carrying it
+        for length in 0..pdu.len() {
+            assert_eq!(
+                parse_core_pdu(&pdu[..length], &DownlinkStatus::AirToGround),
+                None,
+                "{length} bytes"
+            );
+        }
+        let frame = format!("T{}", base85_encode(&pdu));
+        for length in 0..frame.len() {
+            let mut reassembler = MiamReassembler::new();
+            let miam = reassembler.process(&message(&frame[..length], '\u{3}'), 100, &decoders);
+            assert_eq!(miam.and_then(|miam| miam.core), None, "{length} characters");
+        }
+
+        // Random bytes as a PDU, and random text as a frame
+        let mut state: u32 = 7;
+        let mut next = || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            state.to_be_bytes()[1]
+        };
+        let mut reassembler = MiamReassembler::new();
+        for _ in 0..1000 {
+            let length = usize::from(next() % 64);
+            let mut bytes: Vec<u8> = (0..length).map(|_| next()).collect();
+            if let Some(first) = bytes.first_mut() {
+                *first &= 0x20;
+            }
+            let _ = parse_core_pdu(&bytes, &DownlinkStatus::AirToGround);
+
+            let frame_id = ['T', 'F', 'K', 'S', 'A', 'Y', 'X'][usize::from(next() % 7)];
+            let text: String = std::iter::once(frame_id)
+                .chain(bytes.iter().map(|byte| char::from(byte % 96 + 32)))
+                .collect();
+            let block_end = if next() % 4 == 0 { ETB } else { '\u{3}' };
+            if let Some(miam) = reassembler.process(&message(&text, block_end), 100, &decoders) {
+                let _ = miam.to_string();
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,496 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// Synthetic ACARS signals, for tests and for measuring the sensitivity of the decoder.
+//
+// A block is sent as 7 bit characters with odd parity, least significant bit first, at 2400
+// bps. The bits are MSK modulated on an 1800 Hz subcarrier, which is 1200 Hz or 2400 Hz for
+// each bit, and the subcarrier is AM modulated on the carrier. The demodulator reads MSK as
+// offset QPSK, taking each bit from the I or Q axis of the subcarrier in turn and inverting
+// every other pair, so the subcarrier phase is stepped a quarter turn per bit to put each bit
+// on the axis the demodulator reads it from.
+
+#![allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+
+use crate::decoders::acars::{ACARSDecoder, DLE, ETB, ETX, SOH, STX, SYN};
+use crate::Decoder;
+use num::Complex;
+use std::f64::consts::PI;
+
+pub const BIT_RATE: f64 = 2400.0;
+pub const SUBCARRIER: f64 = 1800.0;
+/// Bits of all ones sent ahead of the block for the receiver to lock on to
+const PREKEY_BITS: usize = 128;
+const NAK: u8 = 0x15;
+/// Noise bandwidth the SNR is measured in, the channel rate of the decoder
+const CHANNEL_RATE: f64 = 12500.0;
+
+/// Set the parity bit of a 7 bit character so it has odd parity
+#[must_use]
+pub const fn with_parity(c: u8) -> u8 {
+    let c = c & 0x7f;
+    if c.count_ones() % 2 == 0 {
+        c | 0x80
+    } else {
+        c
+    }
+}
+
+/// The fields of an ACARS block. Downlinks start their text with the message number and
+/// flight id, which are left to the caller to include.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AcarsBlock {
+    pub mode: char,
+    /// Registration, right aligned in 7 characters with leading dots
+    pub tail: String,
+    /// Block id being acknowledged, `None` for a NAK
+    pub acknowledgement: Option<char>,
+    pub label: [char; 2],
+    /// Digits for downlinks, letters for uplinks
+    pub block_id: char,
+    pub text: String,
+    /// More blocks of the message follow, the block ends with ETB instead of ETX
+    pub more_blocks: bool,
+}
+
+impl AcarsBlock {
+    #[must_use]
+    pub fn new(tail: &str, label: [char; 2], block_id: char, text: &str) -> Self {
+        Self {
+            mode: '2',
+            tail: tail.to_string(),
+            acknowledgement: None,
+            label,
+            block_id,
+            text: text.to_string(),
+            more_blocks: false,
+        }
+    }
+
+    /// The characters the CRC covers, from the mode to the ETX or ETB
+    #[must_use]
+    pub fn body(&self) -> Vec<u8> {
+        let mut body = vec![self.mode as u8];
+        body.extend(format!("{:.>7}", self.tail).bytes().take(7));
+        body.push(self.acknowledgement.map_or(NAK, |ack| ack as u8));
+        body.extend(self.label.iter().map(|c| *c as u8));
+        body.push(self.block_id as u8);
+        if self.text.is_empty() {
+            body.push(ETX);
+            return body.into_iter().map(with_parity).collect();
+        }
+        body.push(STX);
+        body.extend(self.text.bytes());
+        body.push(if self.more_blocks { ETB } else { ETX });
+
+        body.into_iter().map(with_parity).collect()
+    }
+
+    /// The block as transmitted: pre-key, bit sync, character sync, the body, the CRC and the
+    /// trailing DEL.
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let body = self.body();
+        let crc = body
+            .iter()
+            .fold(0, |crc, c| ACARSDecoder::update_crc(crc, u32::from(*c)));
+
+        let mut bytes = vec![0xff; PREKEY_BITS / 8];
+        bytes.extend([with_parity(b'+'), with_parity(b'*'), SYN, SYN, SOH]);
+        bytes.extend(body);
+        bytes.extend([(crc & 0xff) as u8, (crc >> 8) as u8, DLE]);
+        bytes
+    }
+}
+
+/// Downlink text of `length` characters for test blocks: a message number, a flight id and then
+/// position reports for as long as needed
+#[must_use]
+pub fn downlink_text(length: usize) -> String {
+    "M01AUA0123"
+        .chars()
+        .chain("POSN35286W108525,125812,350,KDEN,KSFO,".chars().cycle())
+        .take(length)
+        .collect()
+}
+
+/// A downlink from N923US with `length` characters of `downlink_text`, the block tests start
+/// from before changing what they are about
+#[must_use]
+pub fn downlink_block(label: [char; 2], length: usize) -> AcarsBlock {
+    AcarsBlock::new("N923US", label, '7', &downlink_text(length))
+}
+
+/// Deterministic noise source, so a test or sensitivity sweep gives the same result every run
+#[derive(Debug, Clone)]
+struct Noise {
+    state: u64,
+}
+
+impl Noise {
+    const fn new(seed: u64) -> Self {
+        // xorshift never leaves zero
+        Self {
+            state: if seed == 0 {
+                0x9e37_79b9_7f4a_7c15
+            } else {
+                seed
+            },
+        }
+    }
+
+    /// Uniform in (0, 1]
+    fn uniform(&mut self) -> f64 {
+        // xorshift64*
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let value = self.state.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        ((value >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Complex gaussian noise with a total power of `power`
+    fn gaussian(&mut self, power: f64) -> Complex<f64> {
+        // Box-Muller
+        let radius = (-power * self.uniform().ln()).sqrt();
+        let angle = 2.0 * PI * self.uniform();
+        Complex::from_polar(radius, angle)
+    }
+}
+
+/// Turns ACARS blocks into IQ samples of a channel as received by an SDR
+#[derive(Debug, Clone)]
+pub struct AcarsModulator {
+    sample_rate: f64,
+    channel_offset: f64,
+    amplitude: f64,
+    modulation_depth: f64,
+    snr: Option<f64>,
+    frequency_offset: f64,
+    clock_drift: f64,
+    silence: f64,
+    noise: Noise,
+}
+
+impl AcarsModulator {
+    /// A clean signal at `sample_rate`, on a channel `channel_offset` Hz from the center
+    #[must_use]
+    pub const fn new(sample_rate: f64, channel_offset: f64) -> Self {
+        Self {
+            sample_rate,
+            channel_offset,
+            amplitude: 0.25,
+            modulation_depth: 0.5,
+            snr: None,
+            frequency_offset: 0.0,
+            clock_drift: 0.0,
+            silence: 0.05,
+            noise: Noise::new(1),
+        }
+    }
+
+    /// Carrier amplitude, where 1.0 is full scale
+    #[must_use]
+    pub const fn amplitude(mut self, amplitude: f64) -> Self {
+        self.amplitude = amplitude;
+        self
+    }
+
+    /// Add noise for a carrier to noise ratio of `snr` dB, measured in the 12.5 kHz the
+    /// decoder sees. `None` for a clean signal.
+    #[must_use]
+    pub const fn snr(mut self, snr: Option<f64>) -> Self {
+        self.snr = snr;
+        self
+    }
+
+    /// Error of the transmitter frequency, in Hz
+    #[must_use]
+    pub const fn frequency_offset(mut self, frequency_offset: f64) -> Self {
+        self.frequency_offset = frequency_offset;
+        self
+    }
+
+    /// Error of the transmitter bit clock, in PPM
+    #[must_use]
+    pub fn clock_drift(mut self, clock_drift: f64) -> Self {
+        self.clock_drift = clock_drift * 1e-6;
+        self
+    }
+
+    /// Seconds of noise before and after the block
+    #[must_use]
+    pub const fn silence(mut self, silence: f64) -> Self {
+        self.silence = silence;
+        self
+    }
+
+    #[must_use]
+    pub const fn seed(mut self, seed: u64) -> Self {
+        self.noise = Noise::new(seed);
+        self
+    }
+
+    fn noise_power(&self) -> f64 {
+        self.snr.map_or(0.0, |snr| {
+            self.amplitude * self.amplitude / 10f64.powf(snr / 10.0)
+                * (self.sample_rate / CHANNEL_RATE)
+        })
+    }
+
+    /// Modulate `bytes`, sent least significant bit first, into IQ samples with full scale 1.0
+    #[must_use]
+    pub fn modulate_bytes(&mut self, bytes: &[u8]) -> Vec<Complex<f32>> {
+        let bits: Vec<bool> = bytes
+            .iter()
+            .flat_map(|byte| (0..8).map(move |bit| byte & (1 << bit) != 0))
+            .collect();
+
+        // Subcarrier phase, relative to the 1800 Hz tone, at the end of each bit. It is kept in
+        // whole quarter turns: summed as floating point, the rounding error eventually tips a
+        // comparison the wrong way and inverts every other bit from there on
+        let mut quarters: i64 = 0;
+        let phases: Vec<f64> = bits
+            .iter()
+            .enumerate()
+            .map(|(k, bit)| {
+                let value = *bit == (k & 2 == 0);
+                let target = match (k & 1 == 0, value) {
+                    (true, true) => 0,
+                    (true, false) => 2,
+                    (false, true) => 1,
+                    (false, false) => 3,
+                };
+                // Always a quarter turn, one way or the other
+                quarters += if (target - quarters).rem_euclid(4) < 2 {
+                    1
+                } else {
+                    -1
+                };
+                quarters as f64 * PI / 2.0
+            })
+            .collect();
+
+        let bit_period = 1.0 / (BIT_RATE * (1.0 + self.clock_drift));
+        let burst = bits.len() as f64 * bit_period;
+        let silence = (self.silence * self.sample_rate) as usize;
+        let burst_samples = (burst * self.sample_rate) as usize;
+        let noise_power = self.noise_power();
+        let carrier = self.channel_offset + self.frequency_offset;
+
+        (0..burst_samples + 2 * silence)
+            .map(|n| {
+                let t = n as f64 / self.sample_rate;
+                let mut sample = if n >= silence && n < silence + burst_samples {
+                    let burst_t = (n - silence) as f64 / self.sample_rate;
+                    let k = ((burst_t / bit_period) as usize).min(phases.len() - 1);
+                    let start = if k == 0 { 0.0 } else { phases[k - 1] };
+                    let fraction = burst_t / bit_period - k as f64;
+                    let theta = (phases[k] - start).mul_add(fraction, start);
+                    let audio = (2.0 * PI * SUBCARRIER).mul_add(burst_t, theta).cos();
+                    let envelope = self.amplitude * self.modulation_depth.mul_add(audio, 1.0);
+
+                    Complex::from_polar(envelope, 2.0 * PI * carrier * t)
+                } else {
+                    Complex::new(0.0, 0.0)
+                };
+
+                if noise_power > 0.0 {
+                    sample += self.noise.gaussian(noise_power);
+                }
+
+                Complex::new(sample.re as f32, sample.im as f32)
+            })
+            .collect()
+    }
+
+    /// Modulate a block into IQ samples with full scale 1.0
+    #[must_use]
+    pub fn modulate(&mut self, block: &AcarsBlock) -> Vec<Complex<f32>> {
+        self.modulate_bytes(&block.to_bytes())
+    }
+}
+
+/// Convert IQ samples with full scale 1.0 to unsigned 8 bit samples, as read from a dongle
+#[must_use]
+pub fn to_cu8(samples: &[Complex<f32>]) -> Vec<u8> {
+    let convert = |value: f32| {
+        // Clamped to the range of a u8 first
+        value.mul_add(127.5, 127.5).round().clamp(0.0, 255.0) as u8
+    };
+
+    samples
+        .iter()
+        .flat_map(|sample| [convert(sample.re), convert(sample.im)])
+        .collect()
+}
+
+/// Run a decoder on samples at the channel rate, as the channel mixer would hand them over
+pub fn decode_samples(decoder: &mut dyn Decoder, samples: &[Complex<f32>]) {
+    for buffer in samples.chunks(crate::decoders::acars::RTLOUTBUFSZ) {
+        for (index, sample) in buffer.iter().enumerate() {
+            decoder.set_dm_buffer_at_index(index, *sample);
+        }
+        decoder.decode(buffer.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoders::acars::{
+        AckStatus, DownlinkStatus, MessageStatus, INTRATE_F32, MAX_BLOCK_LEN,
+    };
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_block() {
+        let bytes = downlink_block(['H', '1'], 47).to_bytes();
+
+        // Every character of the body has odd parity
+        let body = &bytes[PREKEY_BITS / 8 + 5..bytes.len() - 3];
+        assert!(body.iter().all(|c| c.count_ones() % 2 == 1));
+        let tail: Vec<u8> = body[1..8].iter().map(|c| c & 0x7f).collect();
+        assert_eq!(tail, b".N923US");
+        assert_eq!(body[8] & 0x7f, NAK);
+        assert_eq!(body[body.len() - 1], with_parity(ETX));
+
+        // The CRC of the body followed by its CRC is zero
+        let crc = bytes[PREKEY_BITS / 8 + 5..bytes.len() - 1]
+            .iter()
+            .fold(0, |crc, c| ACARSDecoder::update_crc(crc, u32::from(*c)));
+        assert_eq!(crc, 0);
+    }
+
+    fn decode(samples: &[Complex<f32>]) -> Vec<crate::decoders::acars::AssembledACARSMessage> {
+        let mut decoder = ACARSDecoder::new(0, 131_550_000, [Complex::new(0.0, 0.0); 192]);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        decoder.set_output_channel(tx);
+        decode_samples(&mut decoder, samples);
+
+        let mut messages = vec![];
+        while let Ok(message) = rx.try_recv() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[test]
+    fn test_modulate() {
+        // An uplink acknowledged, to check every header field goes where the decoder reads it
+        let mut block = AcarsBlock::new("N923US", ['_', '\x7f'], 'A', "");
+        block.acknowledgement = Some('2');
+        let samples = AcarsModulator::new(f64::from(INTRATE_F32), 0.0).modulate(&block);
+        let messages = decode(&samples);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].status, MessageStatus::Valid);
+        assert_eq!(messages[0].acknowledgement, AckStatus::Ack('2'));
+        assert_eq!(messages[0].label, ['_', 'd']);
+        assert_eq!(messages[0].downlink_status, DownlinkStatus::GroundToAir);
+        assert_eq!(messages[0].message_text, Some(vec!['\0']));
+
+        let mut block = downlink_block(['H', '1'], 47);
+        block.acknowledgement = Some('J');
+        let samples = AcarsModulator::new(f64::from(INTRATE_F32), 0.0).modulate(&block);
+        let messages = decode(&samples);
+
+        assert_eq!(messages.len(), 1);
+        let message = &messages[0];
+        assert_eq!(message.status, MessageStatus::Valid);
+        assert_eq!(message.parity_errors, 0);
+        assert_eq!(
+            message.aircraft_tail,
+            Some(['N', '9', '2', '3', 'U', 'S', ' '])
+        );
+        assert_eq!(message.acknowledgement, AckStatus::Ack('J'));
+        assert_eq!(message.label, ['H', '1']);
+        assert_eq!(message.downlink_status, DownlinkStatus::AirToGround);
+        assert_eq!(message.block_id, '7');
+        assert_eq!(message.message_number, Some(['M', '0', '1', 'A']));
+        assert_eq!(message.flight_id, Some(['U', 'A', '0', '1', '2', '3']));
+        let text: String = message.message_text.iter().flatten().collect();
+        assert_eq!(text, "POSN35286W108525,125812,350,KDEN,KSFO");
+    }
+
+    #[test]
+    fn test_long_blocks() {
+        // The longest text a block carries, and a block filling the whole buffer
+        for (length, body_length) in [(220, 234), (226, MAX_BLOCK_LEN)] {
+            let block = downlink_block(['H', '1'], length);
+            assert_eq!(block.body().len(), body_length);
+
+            let messages =
+                decode(&AcarsModulator::new(f64::from(INTRATE_F32), 0.0).modulate(&block));
+            assert_eq!(messages.len(), 1, "{length} characters");
+            assert_eq!(messages[0].status, MessageStatus::Valid);
+            assert_eq!(messages[0].parity_errors, 0);
+            assert_eq!(messages[0].corrected_bits, 0);
+            let text: String = messages[0].message_text.iter().flatten().collect();
+            assert_eq!(text, block.text[10..]);
+        }
+    }
+
+    #[test]
+    fn test_impairments() {
+        let block = downlink_block(['H', '1'], 100);
+        // Noise, a transmitter 300 Hz off frequency and a slow bit clock still decode
+        let samples = AcarsModulator::new(f64::from(INTRATE_F32), 0.0)
+            .snr(Some(20.0))
+            .frequency_offset(300.0)
+            .clock_drift(-200.0)
+            .seed(7)
+            .modulate(&block);
+        let messages = decode(&samples);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].status, MessageStatus::Valid);
+
+        // The same seed gives the same noise
+        let again = AcarsModulator::new(f64::from(INTRATE_F32), 0.0)
+            .snr(Some(20.0))
+            .seed(7)
+            .modulate(&block);
+        let different = AcarsModulator::new(f64::from(INTRATE_F32), 0.0)
+            .snr(Some(20.0))
+            .seed(8)
+            .modulate(&block);
+        assert_eq!(
+            again,
+            AcarsModulator::new(f64::from(INTRATE_F32), 0.0)
+                .snr(Some(20.0))
+                .seed(7)
+                .modulate(&block)
+        );
+        assert_ne!(again, different);
+
+        // Buried in the noise
+        let samples = AcarsModulator::new(f64::from(INTRATE_F32), 0.0)
+            .snr(Some(-10.0))
+            .modulate(&block);
+        assert!(decode(&samples).is_empty());
+    }
+
+    #[test]
+    fn test_to_cu8() {
+        assert_eq!(
+            to_cu8(&[Complex::new(1.0, -1.0), Complex::new(0.0, 2.0)]),
+            vec![255, 0, 128, 255]
+        );
+    }
+}
@@ -0,0 +1,366 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// Ground station squitters. Ground stations periodically broadcast a message on label SQ that
+// identifies the station:
+//
+//   02XAABQKABQ13502N10637WV136975/ARINC
+//
+//   02        squitter version
+//   X         fixed
+//   A         network, A for ARINC and S for SITA
+//   ABQ       IATA code of the airport the station is at (version 1 and up)
+//   KABQ      ICAO code of the airport the station is at (version 1 and up)
+//   1         station number at the airport (version 2)
+//   3502N     latitude, degrees and minutes (version 2)
+//   10637W    longitude, degrees and minutes (version 2)
+//   V136975   VDL frequency of the station in kHz, if it has one (version 2)
+//   /ARINC    free text, usually the network name (version 2)
+
+use serde::Serialize;
+use std::fmt::{self, Display, Formatter};
+
+pub const SQUITTER_LABEL: [char; 2] = ['S', 'Q'];
+
+/// The ground station network operating a station
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum GroundStationNetwork {
+    Arinc,
+    Sita,
+    Other(char),
+}
+
+impl GroundStationNetwork {
+    const fn from_char(network: char) -> Self {
+        match network {
+            'A' => Self::Arinc,
+            'S' => Self::Sita,
+            other => Self::Other(other),
+        }
+    }
+}
+
+impl Display for GroundStationNetwork {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Arinc => write!(f, "ARINC"),
+            Self::Sita => write!(f, "SITA"),
+            Self::Other(network) => write!(f, "{network}"),
+        }
+    }
+}
+
+/// Ground station identity, decoded from a squitter
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GroundStation {
+    pub version: u8,
+    pub network: GroundStationNetwork,
+    /// IATA code of the airport the station is at
+    pub iata: Option<String>,
+    /// ICAO code of the airport the station is at
+    pub icao: Option<String>,
+    /// Number of the station at the airport, when there is more than one
+    pub station_number: Option<u8>,
+    /// Latitude of the station in degrees, positive north
+    pub latitude: Option<f64>,
+    /// Longitude of the station in degrees, positive east
+    pub longitude: Option<f64>,
+    /// VDL frequency of the station in MHz
+    pub vdl_frequency: Option<f32>,
+    /// Free text at the end of the squitter
+    pub text: Option<String>,
+}
+
+impl Display for GroundStation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Ground Station v{} {}", self.version, self.network)?;
+        if let Some(icao) = &self.icao {
+            write!(f, " {icao}")?;
+        }
+        if let Some(iata) = &self.iata {
+            write!(f, " ({iata})")?;
+        }
+        if let Some(station_number) = self.station_number {
+            write!(f, " #{station_number}")?;
+        }
+        if let (Some(latitude), Some(longitude)) = (self.latitude, self.longitude) {
+            write!(f, ", Position {latitude:.4} {longitude:.4}")?;
+        }
+        if let Some(vdl_frequency) = self.vdl_frequency {
+            write!(f, ", VDL {vdl_frequency:.3}")?;
+        }
+        if let Some(text) = &self.text {
+            write!(f, ", {text}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Decode the text of a squitter. Returns `None` if the text is not a squitter.
+#[must_use]
+pub fn decode(text: &str) -> Option<GroundStation> {
+    let version: u8 = text.get(..2)?.parse().ok()?;
+    let rest = text.get(2..)?.strip_prefix('X')?;
+    let mut chars = rest.chars();
+    let network = GroundStationNetwork::from_char(chars.next()?);
+    let rest = chars.as_str();
+
+    let mut station = GroundStation {
+        version,
+        network,
+        iata: None,
+        icao: None,
+        station_number: None,
+        latitude: None,
+        longitude: None,
+        vdl_frequency: None,
+        text: None,
+    };
+
+    if version == 0 {
+        return Some(station);
+    }
+
+    let iata = rest.get(..3)?;
+    let icao = rest.get(3..7)?;
+    if !iata
+        .chars()
+        .chain(icao.chars())
+        .all(|c| c.is_ascii_alphanumeric())
+    {
+        return None;
+    }
+    station.iata = Some(iata.to_string());
+    station.icao = Some(icao.to_string());
+
+    if version < 2 {
+        return Some(station);
+    }
+
+    let rest = rest.get(7..)?;
+    let (position, text) = rest.split_once('/').unwrap_or((rest, ""));
+    station.station_number = position.get(..1).and_then(|number| number.parse().ok());
+    station.latitude = degrees_minutes(position.get(1..6)?, 'N', 'S');
+    station.longitude = degrees_minutes(position.get(6..12)?, 'E', 'W');
+    station.vdl_frequency = position
+        .get(12..)
+        .and_then(|frequency| frequency.strip_prefix('V'))
+        .filter(|frequency| frequency.chars().all(|c| c.is_ascii_digit()))
+        .and_then(|frequency| frequency.parse::<f32>().ok())
+        .map(|khz| khz / 1000.0);
+    station.text = (!text.is_empty()).then(|| text.to_string());
+
+    Some(station)
+}
+
+/// Degrees from `DDMMH` or `DDDMMH`, where H is the hemisphere
+fn degrees_minutes(text: &str, positive: char, negative: char) -> Option<f64> {
+    let hemisphere = text.chars().last()?;
+    let digits = text.get(..text.len() - 1)?;
+    let split = digits.len().checked_sub(2)?;
+    let degrees: f64 = digits.get(..split)?.parse().ok()?;
+    let minutes: f64 = digits.get(split..)?.parse().ok()?;
+
+    if minutes >= 60.0 {
+        return None;
+    }
+
+    let value = degrees + minutes / 60.0;
+
+    if hemisphere == positive {
+        Some(value)
+    } else if hemisphere == negative {
+        Some(-value)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_squitter() {
+        // From the receiver samples
+        assert_eq!(
+            decode("02XAABQKABQ13502N10637WV136975/ARINC"),
+            Some(GroundStation {
+                version: 2,
+                network: GroundStationNetwork::Arinc,
+                iata: Some("ABQ".to_string()),
+                icao: Some("KABQ".to_string()),
+                station_number: Some(1),
+                latitude: Some(35.0 + 2.0 / 60.0),
+                longitude: Some(-(106.0 + 37.0 / 60.0)),
+                vdl_frequency: Some(136.975),
+                text: Some("ARINC".to_string()),
+            })
+        );
+
+        assert_eq!(
+            decode("01XSLHREGLL"),
+            Some(GroundStation {
+                version: 1,
+                network: GroundStationNetwork::Sita,
+                iata: Some("LHR".to_string()),
+                icao: Some("EGLL".to_string()),
+                station_number: None,
+                latitude: None,
+                longitude: None,
+                vdl_frequency: None,
+                text: None,
+            })
+        );
+
+        assert_eq!(decode("HELLO"), None);
+    }
+
+    fn station(
+        version: u8,
+        network: GroundStationNetwork,
+        airport: Option<(&str, &str)>,
+    ) -> GroundStation {
+        GroundStation {
+            version,
+            network,
+            iata: airport.map(|(iata, _)| iata.to_string()),
+            icao: airport.map(|(_, icao)| icao.to_string()),
+            station_number: None,
+            latitude: None,
+            longitude: None,
+            vdl_frequency: None,
+            text: None,
+        }
+    }
+
+    #[test]
+    fn test_squitter_versions_and_variants() {
+        let arinc = GroundStationNetwork::Arinc;
+        let sita = GroundStationNetwork::Sita;
+        let table = [
+            ("00XS", station(0, sita.clone(), None)),
+            ("00XA2ORD", station(0, arinc.clone(), None)),
+            (
+                "01XAJFKKJFK",
+                station(1, arinc.clone(), Some(("JFK", "KJFK"))),
+            ),
+            (
+                "01XRDENKDEN",
+                station(1, GroundStationNetwork::Other('R'), Some(("DEN", "KDEN"))),
+            ),
+            (
+                "02XSSYDYSSY13357S15110EV136975/",
+                GroundStation {
+                    station_number: Some(1),
+                    latitude: Some(-(33.0 + 57.0 / 60.0)),
+                    longitude: Some(151.0 + 10.0 / 60.0),
+                    vdl_frequency: Some(136.975),
+                    ..station(2, sita.clone(), Some(("SYD", "YSSY")))
+                },
+            ),
+            (
+                "02XAORDKORD24159N08754W",
+                GroundStation {
+                    station_number: Some(2),
+                    latitude: Some(41.0 + 59.0 / 60.0),
+                    longitude: Some(-(87.0 + 54.0 / 60.0)),
+                    ..station(2, arinc.clone(), Some(("ORD", "KORD")))
+                },
+            ),
+            (
+                "02XSCDGLFPG 4900N00233EV136925/SITA PARIS",
+                GroundStation {
+                    latitude: Some(49.0),
+                    longitude: Some(2.0 + 33.0 / 60.0),
+                    vdl_frequency: Some(136.925),
+                    text: Some("SITA PARIS".to_string()),
+                    ..station(2, sita, Some(("CDG", "LFPG")))
+                },
+            ),
+            // A position and frequency we cannot read are left out
+            (
+                "02XAATLKATL13399N08426XV13697A/ARINC",
+                GroundStation {
+                    station_number: Some(1),
+                    vdl_frequency: None,
+                    text: Some("ARINC".to_string()),
+                    ..station(2, arinc.clone(), Some(("ATL", "KATL")))
+                },
+            ),
+            // Later versions start the same way as version 2
+            (
+                "03XAABQKABQ13502N10637W",
+                GroundStation {
+                    station_number: Some(1),
+                    latitude: Some(35.0 + 2.0 / 60.0),
+                    longitude: Some(-(106.0 + 37.0 / 60.0)),
+                    ..station(3, arinc, Some(("ABQ", "KABQ")))
+                },
+            ),
+        ];
+
+        for (text, expected) in table {
+            assert_eq!(decode(text), Some(expected), "{text}");
+        }
+
+        for text in [
+            "",
+            "0",
+            "02",
+            "02X",
+            "2XAABQKABQ",
+            "AAXAABQKABQ",
+            "02YAABQKABQ13502N10637W",
+            "01XAAB-KABQ",
+            "01XAABQ",
+            "01XAÄBQKABQ",
+            "02XAABQKABQ13502N",
+        ] {
+            assert_eq!(decode(text), None, "{text}");
+        }
+    }
+
+    #[test]
+    fn test_squitter_malformed() {
+        // Cut short, a version 2 squitter is only decoded once it has a whole position
+        let text = "02XAABQKABQ13502N10637WV136975/ARINC";
+        for length in 0..=text.len() {
+            assert_eq!(decode(&text[..length]).is_some(), length >= 23, "{length}");
+        }
+
+        let mut state: u32 = 3;
+        let mut next = || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            state >> 16
+        };
+        let alphabet: Vec<char> = "0123456789NSEWVXA/ .-Ä".chars().collect();
+        for _ in 0..2000 {
+            let length = next() as usize % 40;
+            let text: String = ["00X", "01X", "02X", "03X"][next() as usize % 4]
+                .chars()
+                .chain((0..length).map(|_| alphabet[next() as usize % alphabet.len()]))
+                .collect();
+            if let Some(station) = decode(&text) {
+                let _ = station.to_string();
+                assert!(station
+                    .latitude
+                    .map_or(true, |latitude| latitude.abs() < 100.0));
+            }
+        }
+    }
+}
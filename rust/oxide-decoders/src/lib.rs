@@ -26,9 +26,10 @@
 )]
 // #![warn(missing_docs)]
 
-use decoders::acars::AssembledACARSMessage;
+use decoders::acars::{AssembledACARSMessage, MessageStatus};
 //use num_complex::Complex;
 use num::Complex;
+use std::collections::BTreeMap;
 use tokio::sync::mpsc::UnboundedSender;
 
 #[macro_use]
@@ -36,6 +37,11 @@ extern crate log;
 
 pub mod decoders {
     pub mod acars;
+    pub mod arinc622;
+    pub mod labels;
+    pub mod miam;
+    pub mod modulator;
+    pub mod squitter;
 }
 
 /// Enum to represent the different types of decoders
@@ -46,6 +52,147 @@ pub enum ValidDecoderType {
     HFDL,
 }
 
+/// Reception statistics for a single channel of a decoder.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChannelStatistics {
+    /// Frequency of the channel in MHz
+    pub frequency: f32,
+    /// Current noise floor of the channel, in the same units as the message signal level.
+    /// `None` until it has been measured.
+    pub noise_level: Option<f32>,
+    /// Number of messages decoded on the channel
+    pub messages: u64,
+    /// Signal level of the last message decoded on the channel, `None` until one is decoded
+    pub last_signal_level: Option<f32>,
+    /// Number of decoded messages that needed error correction
+    pub corrected_messages: u64,
+    /// Number of messages dropped because the CRC check failed
+    pub crc_failures: u64,
+    /// Number of messages dropped because parity errors remained after error correction
+    pub parity_failures: u64,
+    /// Number of messages decoded with each label
+    pub labels: BTreeMap<String, u64>,
+    /// Sum of the SNR of all decoded messages. Used to produce the average SNR. Kept as f64 so
+    /// the sums of a long run can still be subtracted from each other.
+    snr_sum: f64,
+    /// Number of decoded messages with an SNR, which needs a measured noise floor
+    snr_messages: u64,
+    /// Sum of the signal level of all decoded messages. Used to produce the average signal level
+    signal_level_sum: f64,
+}
+
+impl ChannelStatistics {
+    #[must_use]
+    pub const fn new(frequency: f32) -> Self {
+        Self {
+            frequency,
+            noise_level: None,
+            messages: 0,
+            last_signal_level: None,
+            corrected_messages: 0,
+            crc_failures: 0,
+            parity_failures: 0,
+            labels: BTreeMap::new(),
+            snr_sum: 0.0,
+            snr_messages: 0,
+            signal_level_sum: 0.0,
+        }
+    }
+
+    /// Count a message that passed the error checks
+    pub fn add_message(&mut self, message: &AssembledACARSMessage) {
+        self.messages += 1;
+        self.last_signal_level = Some(message.signal_level);
+        if let Some(snr) = message.snr {
+            self.snr_sum += f64::from(snr);
+            self.snr_messages += 1;
+        }
+        self.signal_level_sum += f64::from(message.signal_level);
+
+        if message.parity_errors > 0 || message.corrected_bits > 0 {
+            self.corrected_messages += 1;
+        }
+
+        *self
+            .labels
+            .entry(message.label.iter().collect())
+            .or_default() += 1;
+    }
+
+    /// Count a message dropped by the error checks
+    pub fn add_failed_message(&mut self, status: &MessageStatus) {
+        match status {
+            MessageStatus::CrcFailed => self.crc_failures += 1,
+            MessageStatus::ParityFailed => self.parity_failures += 1,
+            MessageStatus::Valid => (),
+        }
+    }
+
+    /// Average SNR of the messages decoded on the channel once its noise floor was measured, in dB
+    #[must_use]
+    pub fn average_snr(&self) -> Option<f32> {
+        if self.snr_messages == 0 {
+            return None;
+        }
+
+        #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+        Some((self.snr_sum / self.snr_messages as f64) as f32)
+    }
+
+    /// Average signal level of all messages decoded on the channel
+    #[must_use]
+    pub fn average_signal_level(&self) -> Option<f32> {
+        if self.messages == 0 {
+            return None;
+        }
+
+        #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+        Some((self.signal_level_sum / self.messages as f64) as f32)
+    }
+
+    /// The `count` labels heard most, most heard first
+    #[must_use]
+    pub fn top_labels(&self, count: usize) -> Vec<(&str, u64)> {
+        let mut labels: Vec<(&str, u64)> = self
+            .labels
+            .iter()
+            .map(|(label, messages)| (label.as_str(), *messages))
+            .collect();
+        labels.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        labels.truncate(count);
+        labels
+    }
+
+    /// The statistics of the messages counted since `earlier` was taken. The noise and last
+    /// signal level are the current ones.
+    #[must_use]
+    pub fn since(&self, earlier: &Self) -> Self {
+        let mut labels = self.labels.clone();
+        for (label, messages) in &earlier.labels {
+            if let Some(count) = labels.get_mut(label) {
+                *count = count.saturating_sub(*messages);
+            }
+        }
+        labels.retain(|_, messages| *messages > 0);
+
+        Self {
+            frequency: self.frequency,
+            noise_level: self.noise_level,
+            messages: self.messages.saturating_sub(earlier.messages),
+            last_signal_level: self.last_signal_level,
+            corrected_messages: self
+                .corrected_messages
+                .saturating_sub(earlier.corrected_messages),
+            crc_failures: self.crc_failures.saturating_sub(earlier.crc_failures),
+            parity_failures: self.parity_failures.saturating_sub(earlier.parity_failures),
+            labels,
+            snr_sum: self.snr_sum - earlier.snr_sum,
+            snr_messages: self.snr_messages.saturating_sub(earlier.snr_messages),
+            signal_level_sum: self.signal_level_sum - earlier.signal_level_sum,
+        }
+    }
+}
+
 /// Trait to represent a decoder.
 pub trait Decoder: Send + Sync {
     /// function to pass through to the decoder implementation data read in from the SDR
@@ -53,8 +200,71 @@ pub trait Decoder: Send + Sync {
     /// function to grab the WF data iterator from the decoder implementation.
     /// Used during SDR data processing before passing the data to the decoder
     fn get_wf_iter(&self) -> std::slice::Iter<'_, Complex<f32>>;
-    /// function to set the dm buffer in the decoder to a processed value from the SDR
-    fn set_dm_buffer_at_index(&mut self, index: usize, value: f32);
+    /// function to set the dm buffer in the decoder to a processed value from the SDR.
+    /// The value is the channel sample after mixing, before envelope detection
+    fn set_dm_buffer_at_index(&mut self, index: usize, value: Complex<f32>);
+    /// function to grab the carrier offset, in Hz, of the last message decoded since the previous call
+    fn take_frequency_offset(&mut self) -> Option<f32>;
+    /// function to grab the current reception statistics of the decoder
+    fn get_channel_statistics(&self) -> ChannelStatistics;
     /// function to set the output channel for the decoder to pass processed messages to
     fn set_output_channel(&mut self, channel: UnboundedSender<AssembledACARSMessage>);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(label: [char; 2], signal_level: f32, corrected_bits: u8) -> AssembledACARSMessage {
+        AssembledACARSMessage {
+            label,
+            corrected_bits,
+            signal_level,
+            ..AssembledACARSMessage::default()
+        }
+    }
+
+    #[test]
+    fn test_channel_statistics() {
+        let mut statistics = ChannelStatistics::new(131.55);
+        statistics.add_message(&message(['H', '1'], -20.0, 0));
+        statistics.add_message(&message(['_', 'd'], -30.0, 1));
+        let earlier = statistics.clone();
+
+        statistics.add_message(&message(['H', '1'], -10.0, 0));
+        statistics.add_message(&message(['H', '1'], -20.0, 2));
+        statistics.add_message(&message(['Q', '0'], -30.0, 0));
+        statistics.add_failed_message(&MessageStatus::CrcFailed);
+        statistics.add_failed_message(&MessageStatus::ParityFailed);
+
+        assert_eq!(statistics.messages, 5);
+        assert_eq!(statistics.corrected_messages, 2);
+        assert_eq!(statistics.average_signal_level(), Some(-22.0));
+        assert_eq!(statistics.top_labels(2), vec![("H1", 3), ("Q0", 1)]);
+
+        let interval = statistics.since(&earlier);
+        assert_eq!(interval.messages, 3);
+        assert_eq!(interval.corrected_messages, 1);
+        assert_eq!(interval.crc_failures, 1);
+        assert_eq!(interval.parity_failures, 1);
+        assert_eq!(interval.average_signal_level(), Some(-20.0));
+        assert_eq!(interval.top_labels(5), vec![("H1", 2), ("Q0", 1)]);
+        assert_eq!(ChannelStatistics::new(131.55).average_signal_level(), None);
+    }
+
+    #[test]
+    fn test_channel_statistics_long_run() {
+        let mut statistics = ChannelStatistics::new(131.55);
+        for _ in 0..200_000 {
+            statistics.add_message(&message(['H', '1'], -23.7, 0));
+        }
+        let earlier = statistics.clone();
+
+        statistics.add_message(&message(['H', '1'], -10.0, 0));
+        statistics.add_message(&message(['H', '1'], -20.0, 0));
+
+        let interval = statistics.since(&earlier);
+        let average = interval.average_signal_level().unwrap_or_default();
+        assert!((average + 15.0).abs() < 0.01, "{average}");
+    }
+}
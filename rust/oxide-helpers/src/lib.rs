@@ -29,6 +29,6 @@
 /// Round a float to a given number of decimal places
 #[must_use]
 pub fn round(x: f32, decimals: u32) -> f32 {
-    let y = 10i32.pow(decimals) as f32;
+    let y = 10f32.powi(i32::try_from(decimals).unwrap_or(i32::MAX));
     (x * y).round() / y
 }
@@ -0,0 +1,651 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+#![deny(
+    clippy::pedantic,
+    //clippy::cargo,
+    clippy::nursery,
+    clippy::style,
+    clippy::correctness,
+    clippy::all,
+    clippy::unwrap_used,
+    clippy::expect_used
+)]
+// #![warn(missing_docs)]
+
+// Prometheus metrics for monitoring receivers. One `Metrics` is shared by every SDR, decoder
+// and the output, which update it as they go, and it is rendered in the Prometheus text format
+// when scraped.
+
+use custom_error::custom_error;
+use prometheus::{
+    Gauge, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    Opts, Registry, TextEncoder,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+custom_error! {pub MetricsError
+    Prometheus { source: prometheus::Error } = "Metrics error: {source}",
+}
+
+const NAMESPACE: &str = "acars_oxide";
+/// Buckets of the message signal level histogram, in dB
+const SIGNAL_LEVEL_BUCKETS: [f64; 11] = [
+    -50.0, -45.0, -40.0, -35.0, -30.0, -25.0, -20.0, -15.0, -10.0, -5.0, 0.0,
+];
+
+#[derive(Debug)]
+pub struct Metrics {
+    registry: Registry,
+    started: Instant,
+    uptime: Gauge,
+    messages: IntCounterVec,
+    crc_failures: IntCounterVec,
+    parity_failures: IntCounterVec,
+    parity_fixes: IntCounterVec,
+    corrected_bits: IntCounterVec,
+    signal_level: HistogramVec,
+    noise_level: GaugeVec,
+    sample_buffers: IntCounterVec,
+    dropped_sample_buffers: IntCounterVec,
+    device_reconnects: IntCounterVec,
+    device_connected: GaugeVec,
+    device_uptime: GaugeVec,
+    gain: GaugeVec,
+    ppm: GaugeVec,
+    output_messages: IntCounterVec,
+    duplicate_messages: IntCounter,
+    ground_stations: IntGauge,
+    /// When each SDR that is reading samples was opened. SDRs that have been opened before
+    /// stay in the map with `None` once they stop, so reopening them counts as a reconnect.
+    devices: Mutex<HashMap<String, Option<Instant>>>,
+}
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(
+    registry: &Registry,
+    collector: T,
+) -> Result<T, MetricsError> {
+    registry.register(Box::new(collector.clone()))?;
+    Ok(collector)
+}
+
+impl Metrics {
+    /// # Errors
+    ///
+    /// Returns an error if a metric could not be registered.
+    // One registration per metric
+    #[allow(clippy::too_many_lines)]
+    pub fn new() -> Result<Self, MetricsError> {
+        let registry = Registry::new_custom(Some(NAMESPACE.to_string()), None)?;
+        let channel = ["sdr", "frequency"];
+        let sdr = ["sdr"];
+
+        Ok(Self {
+            started: Instant::now(),
+            uptime: register(
+                &registry,
+                Gauge::new("uptime_seconds", "Seconds since acars-oxide started")?,
+            )?,
+            messages: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "messages_total",
+                        "Messages decoded that passed the error checks",
+                    ),
+                    &["sdr", "frequency", "label"],
+                )?,
+            )?,
+            crc_failures: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "crc_failures_total",
+                        "Blocks dropped for a CRC error that could not be fixed",
+                    ),
+                    &channel,
+                )?,
+            )?,
+            parity_failures: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "parity_failures_total",
+                        "Blocks dropped for parity errors left after error correction",
+                    ),
+                    &channel,
+                )?,
+            )?,
+            parity_fixes: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "parity_fixes_total",
+                        "Parity errors fixed by error correction",
+                    ),
+                    &channel,
+                )?,
+            )?,
+            corrected_bits: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("corrected_bits_total", "Bits flipped by error correction"),
+                    &channel,
+                )?,
+            )?,
+            signal_level: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new("signal_level_db", "Signal level of decoded messages")
+                        .buckets(SIGNAL_LEVEL_BUCKETS.to_vec()),
+                    &channel,
+                )?,
+            )?,
+            noise_level: register(
+                &registry,
+                GaugeVec::new(
+                    Opts::new("noise_level_db", "Noise floor of the channel"),
+                    &channel,
+                )?,
+            )?,
+            sample_buffers: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("sample_buffers_total", "Sample buffers read from the SDR"),
+                    &sdr,
+                )?,
+            )?,
+            dropped_sample_buffers: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "dropped_sample_buffers_total",
+                        "Sample buffers dropped because they were short",
+                    ),
+                    &sdr,
+                )?,
+            )?,
+            device_reconnects: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "device_reconnects_total",
+                        "Times the SDR was opened again after it stopped",
+                    ),
+                    &sdr,
+                )?,
+            )?,
+            device_connected: register(
+                &registry,
+                GaugeVec::new(
+                    Opts::new("device_connected", "1 while samples are read from the SDR"),
+                    &sdr,
+                )?,
+            )?,
+            device_uptime: register(
+                &registry,
+                GaugeVec::new(
+                    Opts::new(
+                        "device_uptime_seconds",
+                        "Seconds since the SDR was last opened",
+                    ),
+                    &sdr,
+                )?,
+            )?,
+            gain: register(
+                &registry,
+                GaugeVec::new(
+                    Opts::new(
+                        "gain_db",
+                        "Tuner gain of the SDR. Absent while the SDR uses AGC",
+                    ),
+                    &sdr,
+                )?,
+            )?,
+            ppm: register(
+                &registry,
+                GaugeVec::new(Opts::new("ppm", "Frequency correction of the SDR"), &sdr)?,
+            )?,
+            output_messages: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "output_messages_total",
+                        "Messages handed to each output sink",
+                    ),
+                    &["sink"],
+                )?,
+            )?,
+            duplicate_messages: register(
+                &registry,
+                IntCounter::new(
+                    "duplicate_messages_total",
+                    "Copies of a message merged by deduplication",
+                )?,
+            )?,
+            ground_stations: register(
+                &registry,
+                IntGauge::new("ground_stations", "Ground stations heard")?,
+            )?,
+            registry,
+            devices: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Render every metric in the Prometheus text format
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metrics could not be encoded.
+    pub fn encode(&self) -> Result<String, MetricsError> {
+        self.uptime.set(self.started.elapsed().as_secs_f64());
+
+        if let Ok(devices) = self.devices.lock() {
+            for (sdr, opened) in devices.iter() {
+                let uptime = opened.map_or(0.0, |opened| opened.elapsed().as_secs_f64());
+                self.device_uptime.with_label_values(&[sdr]).set(uptime);
+            }
+        }
+
+        Ok(TextEncoder::new().encode_to_string(&self.registry.gather())?)
+    }
+
+    /// The SDR was opened and is about to start reading samples
+    pub fn device_opened(&self, sdr: &str) {
+        if let Ok(mut devices) = self.devices.lock() {
+            if devices
+                .insert(sdr.to_string(), Some(Instant::now()))
+                .is_some()
+            {
+                self.device_reconnects.with_label_values(&[sdr]).inc();
+            }
+        }
+
+        self.device_connected.with_label_values(&[sdr]).set(1.0);
+    }
+
+    /// The SDR stopped reading samples
+    pub fn device_closed(&self, sdr: &str) {
+        if let Ok(mut devices) = self.devices.lock() {
+            devices.insert(sdr.to_string(), None);
+        }
+
+        self.device_connected.with_label_values(&[sdr]).set(0.0);
+    }
+
+    pub fn sample_buffer(&self, sdr: &str) {
+        self.sample_buffers.with_label_values(&[sdr]).inc();
+    }
+
+    pub fn dropped_sample_buffer(&self, sdr: &str) {
+        self.dropped_sample_buffers.with_label_values(&[sdr]).inc();
+    }
+
+    /// Tuner gain in tenths of a dB, or `None` when the tuner is on AGC
+    pub fn set_gain(&self, sdr: &str, gain: Option<i32>) {
+        match gain {
+            Some(gain) => self
+                .gain
+                .with_label_values(&[sdr])
+                .set(f64::from(gain) / 10.0),
+            None => {
+                // Not set yet is fine
+                let _ = self.gain.remove_label_values(&[sdr]);
+            }
+        }
+    }
+
+    pub fn set_ppm(&self, sdr: &str, ppm: i32) {
+        self.ppm.with_label_values(&[sdr]).set(f64::from(ppm));
+    }
+
+    /// A message was handed to the output `sink`
+    pub fn output_message(&self, sink: &str) {
+        self.output_messages.with_label_values(&[sink]).inc();
+    }
+
+    pub fn duplicate_messages(&self, count: u64) {
+        self.duplicate_messages.inc_by(count);
+    }
+
+    pub fn set_ground_stations(&self, count: usize) {
+        self.ground_stations
+            .set(i64::try_from(count).unwrap_or(i64::MAX));
+    }
+}
+
+/// The metrics of one channel of an SDR, for its decoder to update
+#[derive(Debug, Clone)]
+pub struct ChannelMetrics {
+    metrics: Arc<Metrics>,
+    sdr: String,
+    frequency: String,
+    noise_level: Gauge,
+    signal_level: Histogram,
+}
+
+impl ChannelMetrics {
+    /// The channel on `frequency`, in MHz, of the SDR with serial `sdr`
+    #[must_use]
+    pub fn new(metrics: Arc<Metrics>, sdr: &str, frequency: f32) -> Self {
+        let frequency = format!("{frequency:.3}");
+        let labels = [sdr, frequency.as_str()];
+
+        Self {
+            noise_level: metrics.noise_level.with_label_values(&labels),
+            signal_level: metrics.signal_level.with_label_values(&labels),
+            metrics,
+            sdr: sdr.to_string(),
+            frequency,
+        }
+    }
+
+    fn labels(&self) -> [&str; 2] {
+        [&self.sdr, &self.frequency]
+    }
+
+    /// A message passed the error checks, after `parity_fixes` parity errors were fixed by
+    /// flipping `corrected_bits` bits.
+    pub fn valid_message(
+        &self,
+        label: &str,
+        signal_level: f32,
+        parity_fixes: u8,
+        corrected_bits: u8,
+    ) {
+        self.metrics
+            .messages
+            .with_label_values(&[&self.sdr, &self.frequency, label])
+            .inc();
+        self.signal_level.observe(f64::from(signal_level));
+
+        if parity_fixes > 0 {
+            self.metrics
+                .parity_fixes
+                .with_label_values(&self.labels())
+                .inc_by(u64::from(parity_fixes));
+        }
+
+        if corrected_bits > 0 {
+            self.metrics
+                .corrected_bits
+                .with_label_values(&self.labels())
+                .inc_by(u64::from(corrected_bits));
+        }
+    }
+
+    pub fn crc_failure(&self) {
+        self.metrics
+            .crc_failures
+            .with_label_values(&self.labels())
+            .inc();
+    }
+
+    pub fn parity_failure(&self) {
+        self.metrics
+            .parity_failures
+            .with_label_values(&self.labels())
+            .inc();
+    }
+
+    pub fn set_noise_level(&self, noise_level: f32) {
+        self.noise_level.set(f64::from(noise_level));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() -> Result<(), MetricsError> {
+        let metrics = Arc::new(Metrics::new()?);
+        let channel = ChannelMetrics::new(metrics.clone(), "00012785", 131.55);
+
+        metrics.device_opened("00012785");
+        metrics.sample_buffer("00012785");
+        metrics.set_gain("00012785", Some(420));
+        channel.valid_message("H1", -22.5, 1, 2);
+        channel.valid_message("H1", -12.5, 0, 0);
+        channel.crc_failure();
+        channel.set_noise_level(-40.0);
+        metrics.output_message("console");
+        metrics.duplicate_messages(2);
+
+        let text = metrics.encode()?;
+        for line in [
+            r#"acars_oxide_messages_total{frequency="131.550",label="H1",sdr="00012785"} 2"#,
+            r#"acars_oxide_parity_fixes_total{frequency="131.550",sdr="00012785"} 1"#,
+            r#"acars_oxide_corrected_bits_total{frequency="131.550",sdr="00012785"} 2"#,
+            r#"acars_oxide_crc_failures_total{frequency="131.550",sdr="00012785"} 1"#,
+            r#"acars_oxide_signal_level_db_bucket{frequency="131.550",sdr="00012785",le="-20"} 1"#,
+            r#"acars_oxide_signal_level_db_count{frequency="131.550",sdr="00012785"} 2"#,
+            r#"acars_oxide_noise_level_db{frequency="131.550",sdr="00012785"} -40"#,
+            r#"acars_oxide_sample_buffers_total{sdr="00012785"} 1"#,
+            r#"acars_oxide_device_connected{sdr="00012785"} 1"#,
+            r#"acars_oxide_gain_db{sdr="00012785"} 42"#,
+            r#"acars_oxide_output_messages_total{sink="console"} 1"#,
+            "acars_oxide_duplicate_messages_total 2",
+        ] {
+            assert!(text.contains(line), "{line} missing from\n{text}");
+        }
+        assert!(text.contains("acars_oxide_uptime_seconds"));
+        assert!(!text.contains("acars_oxide_device_reconnects_total{"));
+
+        // Opening the SDR again after it stopped is a reconnect
+        metrics.device_closed("00012785");
+        assert!(metrics
+            .encode()?
+            .contains(r#"acars_oxide_device_connected{sdr="00012785"} 0"#));
+        metrics.device_opened("00012785");
+        assert!(metrics
+            .encode()?
+            .contains(r#"acars_oxide_device_reconnects_total{sdr="00012785"} 1"#));
+
+        Ok(())
+    }
+
+    /// The value of the line for `series`, if it is in `text`
+    fn value(text: &str, series: &str) -> Option<f64> {
+        text.lines()
+            .find_map(|line| line.strip_prefix(series)?.strip_prefix(' ')?.parse().ok())
+    }
+
+    #[test]
+    fn test_label_values() -> Result<(), MetricsError> {
+        let metrics = Arc::new(Metrics::new()?);
+        let first = ChannelMetrics::new(metrics.clone(), "00012785", 131.55);
+        let second = ChannelMetrics::new(metrics.clone(), "00012785", 136.975);
+        let other_sdr = ChannelMetrics::new(metrics.clone(), "00012786", 131.55);
+        // Serials come from the EEPROM, so they can hold anything
+        let odd_serial = ChannelMetrics::new(metrics.clone(), r#"RTL"2\X"#, 130.0251);
+
+        for (channel, label, count) in [
+            (&first, "H1", 3),
+            (&first, "_d", 1),
+            (&second, "H1", 2),
+            (&other_sdr, "H1", 5),
+            (&odd_serial, "SQ", 1),
+        ] {
+            for _ in 0..count {
+                channel.valid_message(label, -20.0, 0, 0);
+            }
+        }
+
+        let text = metrics.encode()?;
+        for (series, expected) in [
+            (
+                r#"acars_oxide_messages_total{frequency="131.550",label="H1",sdr="00012785"}"#,
+                3.0,
+            ),
+            (
+                r#"acars_oxide_messages_total{frequency="131.550",label="_d",sdr="00012785"}"#,
+                1.0,
+            ),
+            (
+                r#"acars_oxide_messages_total{frequency="136.975",label="H1",sdr="00012785"}"#,
+                2.0,
+            ),
+            (
+                r#"acars_oxide_messages_total{frequency="131.550",label="H1",sdr="00012786"}"#,
+                5.0,
+            ),
+            (
+                r#"acars_oxide_messages_total{frequency="130.025",label="SQ",sdr="RTL\"2\\X"}"#,
+                1.0,
+            ),
+            (
+                r#"acars_oxide_signal_level_db_count{frequency="131.550",sdr="00012785"}"#,
+                4.0,
+            ),
+            (
+                r#"acars_oxide_signal_level_db_count{frequency="131.550",sdr="00012786"}"#,
+                5.0,
+            ),
+        ] {
+            assert_eq!(value(&text, series), Some(expected), "{series} in\n{text}");
+        }
+
+        // Channels sharing labels share their series
+        let again = ChannelMetrics::new(metrics.clone(), "00012785", 131.5501);
+        again.valid_message("H1", -20.0, 0, 0);
+        assert_eq!(
+            value(
+                &metrics.encode()?,
+                r#"acars_oxide_messages_total{frequency="131.550",label="H1",sdr="00012785"}"#
+            ),
+            Some(4.0)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::too_many_lines)]
+    fn test_counter_values() -> Result<(), MetricsError> {
+        let metrics = Arc::new(Metrics::new()?);
+        let channel = ChannelMetrics::new(metrics.clone(), "00012785", 131.55);
+        let series =
+            |name: &str| format!(r#"acars_oxide_{name}{{frequency="131.550",sdr="00012785"}}"#);
+        let sdr_series = |name: &str| format!(r#"acars_oxide_{name}{{sdr="00012785"}}"#);
+
+        // Signal levels on the bucket edges count in that bucket
+        for (signal_level, parity_fixes, corrected_bits) in [
+            (-50.0, 0, 0),
+            (-20.0, 2, 3),
+            (-19.9, 1, 1),
+            (0.0, 0, 0),
+            (3.0, 0, 0),
+        ] {
+            channel.valid_message("H1", signal_level, parity_fixes, corrected_bits);
+        }
+        for _ in 0..3 {
+            channel.crc_failure();
+        }
+        channel.parity_failure();
+        channel.set_noise_level(-41.5);
+        channel.set_noise_level(-38.25);
+        for _ in 0..4 {
+            metrics.sample_buffer("00012785");
+        }
+        metrics.dropped_sample_buffer("00012785");
+        metrics.set_gain("00012785", Some(496));
+        metrics.set_ppm("00012785", -3);
+        metrics.duplicate_messages(0);
+        metrics.duplicate_messages(4);
+        metrics.set_ground_stations(3);
+        for sink in ["console", "json", "console"] {
+            metrics.output_message(sink);
+        }
+
+        let text = metrics.encode()?;
+        for (series, expected) in [
+            (
+                series("signal_level_db_bucket").replace('}', r#",le="-50"}"#),
+                1.0,
+            ),
+            (
+                series("signal_level_db_bucket").replace('}', r#",le="-20"}"#),
+                2.0,
+            ),
+            (
+                series("signal_level_db_bucket").replace('}', r#",le="-15"}"#),
+                3.0,
+            ),
+            (
+                series("signal_level_db_bucket").replace('}', r#",le="0"}"#),
+                4.0,
+            ),
+            (
+                series("signal_level_db_bucket").replace('}', r#",le="+Inf"}"#),
+                5.0,
+            ),
+            (series("signal_level_db_sum"), -86.9),
+            (series("parity_fixes_total"), 3.0),
+            (series("corrected_bits_total"), 4.0),
+            (series("crc_failures_total"), 3.0),
+            (series("parity_failures_total"), 1.0),
+            (series("noise_level_db"), -38.25),
+            (sdr_series("sample_buffers_total"), 4.0),
+            (sdr_series("dropped_sample_buffers_total"), 1.0),
+            (sdr_series("gain_db"), 49.6),
+            (sdr_series("ppm"), -3.0),
+            (
+                r#"acars_oxide_output_messages_total{sink="console"}"#.to_string(),
+                2.0,
+            ),
+            (
+                r#"acars_oxide_output_messages_total{sink="json"}"#.to_string(),
+                1.0,
+            ),
+            ("acars_oxide_duplicate_messages_total".to_string(), 4.0),
+            ("acars_oxide_ground_stations".to_string(), 3.0),
+        ] {
+            let Some(actual) = value(&text, &series) else {
+                panic!("{series} missing from\n{text}");
+            };
+            assert!(
+                (actual - expected).abs() < 1e-6,
+                "{series} is {actual}, not {expected}"
+            );
+        }
+
+        // Going back to AGC drops the gain rather than leaving the last one
+        metrics.set_gain("00012785", None);
+        let text = metrics.encode()?;
+        assert_eq!(value(&text, &sdr_series("gain_db")), None);
+
+        // Every reopen is counted, and the uptime is zero while the SDR is closed
+        for _ in 0..3 {
+            metrics.device_opened("00012785");
+            metrics.device_closed("00012785");
+        }
+        let text = metrics.encode()?;
+        assert_eq!(
+            value(&text, &sdr_series("device_reconnects_total")),
+            Some(2.0)
+        );
+        assert_eq!(
+            value(&text, &sdr_series("device_uptime_seconds")),
+            Some(0.0)
+        );
+        assert_eq!(value(&text, &sdr_series("device_connected")), Some(0.0));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,574 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// Links the messages sent between an aircraft and the ground into conversations. Every message
+// to or from an aircraft is acknowledged by the other side with a message whose ACK field is the
+// block id of the message being acknowledged. The acknowledgement is either a general response
+// (label _ DEL) or a message of its own, which in turn waits for its acknowledgement, so a
+// conversation is the chain of messages linked by acknowledgements. A message sent again with
+// the same block id before it was acknowledged is a retransmission. A message that is not
+// acknowledged in time is unanswered, and its retransmissions are flagged as such until it is
+// acknowledged or no longer heard.
+
+use oxide_decoders::decoders::acars::{
+    AckStatus, AssembledACARSMessage, DownlinkStatus, MessageStatus,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, SystemTime};
+
+/// How long we wait for an acknowledgement before flagging a message as unanswered
+pub const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_secs(60);
+/// General response, label _ DEL, which the decoder outputs as _d. Only acknowledges, so nothing
+/// waits for it to be acknowledged
+const GENERAL_RESPONSE_LABEL: [char; 2] = ['_', 'd'];
+
+/// Where a message fits in its conversation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Correlation {
+    pub conversation_id: u64,
+    /// Block id of the message this one acknowledges
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acknowledges: Option<char>,
+    /// The message was already received, and was sent again because it was not acknowledged
+    pub retransmission: bool,
+    /// The message was sent again after it went unanswered for longer than the timeout
+    pub unanswered: bool,
+}
+
+impl Display for Correlation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Conversation: {}", self.conversation_id)?;
+        if let Some(acknowledges) = self.acknowledges {
+            write!(f, ", Acknowledges: {acknowledges}")?;
+        }
+        if self.retransmission {
+            write!(f, ", RETRANSMISSION")?;
+        }
+        if self.unanswered {
+            write!(f, ", UNANSWERED")?;
+        }
+        Ok(())
+    }
+}
+
+/// A message that was not acknowledged in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnansweredMessage {
+    pub conversation_id: u64,
+    pub tail: String,
+    pub downlink_status: DownlinkStatus,
+    pub block_id: char,
+    pub label: [char; 2],
+    /// Number of times the message was heard, including retransmissions
+    pub transmissions: u32,
+}
+
+impl Display for UnansweredMessage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Conversation: {}, Tail: {}, {}, Label: {}{}, Block ID: {}, Transmissions: {}",
+            self.conversation_id,
+            self.tail,
+            self.downlink_status,
+            self.label[0],
+            self.label[1],
+            self.block_id,
+            self.transmissions
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PendingMessage {
+    conversation_id: u64,
+    downlink_status: DownlinkStatus,
+    block_id: char,
+    label: [char; 2],
+    message_text: Option<Vec<char>>,
+    last_heard: SystemTime,
+    transmissions: u32,
+    /// Reported as unanswered. Kept until it is acknowledged or no longer heard, so its
+    /// retransmissions are flagged
+    unanswered: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Correlator {
+    ack_timeout: Duration,
+    next_conversation_id: u64,
+    /// Messages waiting for an acknowledgement, keyed on the aircraft tail
+    pending: HashMap<String, Vec<PendingMessage>>,
+}
+
+impl Default for Correlator {
+    fn default() -> Self {
+        Self::new(DEFAULT_ACK_TIMEOUT)
+    }
+}
+
+impl Correlator {
+    #[must_use]
+    pub fn new(ack_timeout: Duration) -> Self {
+        Self {
+            ack_timeout,
+            next_conversation_id: 1,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Link the message to its conversation. Returns `None` for messages that are not part of
+    /// a conversation, such as broadcasts and messages that failed the error checks.
+    pub fn correlate(
+        &mut self,
+        message: &AssembledACARSMessage,
+        now: SystemTime,
+    ) -> Option<Correlation> {
+        if message.status != MessageStatus::Valid {
+            return None;
+        }
+
+        let tail: String = message.aircraft_tail?.iter().collect();
+        let tail = tail.trim().trim_start_matches('.').to_string();
+
+        // Squitters and other broadcasts are sent to all aircraft
+        if tail.is_empty() {
+            return None;
+        }
+
+        let pending = self.pending.entry(tail).or_default();
+
+        let acknowledged = match message.acknowledgement {
+            AckStatus::Ack(block_id) => pending
+                .iter()
+                .position(|waiting| {
+                    waiting.downlink_status != message.downlink_status
+                        && waiting.block_id == block_id
+                })
+                .map(|index| pending.remove(index)),
+            AckStatus::Nack => None,
+        };
+
+        if message.label == GENERAL_RESPONSE_LABEL {
+            let acknowledged = acknowledged?;
+            return Some(Correlation {
+                conversation_id: acknowledged.conversation_id,
+                acknowledges: Some(acknowledged.block_id),
+                retransmission: false,
+                unanswered: false,
+            });
+        }
+
+        if let Some(waiting) = pending.iter_mut().find(|waiting| {
+            waiting.downlink_status == message.downlink_status
+                && waiting.block_id == message.block_id
+                && waiting.label == message.label
+                && waiting.message_text == message.message_text
+        }) {
+            waiting.last_heard = now;
+            waiting.transmissions += 1;
+            return Some(Correlation {
+                conversation_id: waiting.conversation_id,
+                acknowledges: acknowledged.map(|acknowledged| acknowledged.block_id),
+                retransmission: true,
+                unanswered: waiting.unanswered,
+            });
+        }
+
+        let conversation_id = acknowledged.as_ref().map_or_else(
+            || {
+                let id = self.next_conversation_id;
+                self.next_conversation_id += 1;
+                id
+            },
+            |acknowledged| acknowledged.conversation_id,
+        );
+
+        // A new message with the block id of an older one replaces it. The block ids wrap, so
+        // the older one was either answered without us hearing it or given up on
+        pending.retain(|waiting| {
+            waiting.downlink_status != message.downlink_status
+                || waiting.block_id != message.block_id
+        });
+        pending.push(PendingMessage {
+            conversation_id,
+            downlink_status: message.downlink_status.clone(),
+            block_id: message.block_id,
+            label: message.label,
+            message_text: message.message_text.clone(),
+            last_heard: now,
+            transmissions: 1,
+            unanswered: false,
+        });
+
+        Some(Correlation {
+            conversation_id,
+            acknowledges: acknowledged.map(|acknowledged| acknowledged.block_id),
+            retransmission: false,
+            unanswered: false,
+        })
+    }
+
+    /// Return the messages that were not acknowledged within the timeout. Each is returned
+    /// once, and forgotten once it has not been heard for another timeout.
+    pub fn expire(&mut self, now: SystemTime) -> Vec<UnansweredMessage> {
+        let mut unanswered = vec![];
+        let ack_timeout = self.ack_timeout;
+
+        for (tail, pending) in &mut self.pending {
+            pending.retain_mut(|waiting| {
+                let elapsed = now.duration_since(waiting.last_heard).unwrap_or_default();
+
+                if waiting.unanswered {
+                    return elapsed <= ack_timeout.saturating_mul(2);
+                }
+
+                if elapsed > ack_timeout {
+                    waiting.unanswered = true;
+                    unanswered.push(UnansweredMessage {
+                        conversation_id: waiting.conversation_id,
+                        tail: tail.clone(),
+                        downlink_status: waiting.downlink_status.clone(),
+                        block_id: waiting.block_id,
+                        label: waiting.label,
+                        transmissions: waiting.transmissions,
+                    });
+                }
+
+                true
+            });
+        }
+
+        self.pending.retain(|_, pending| !pending.is_empty());
+        unanswered.sort_by_key(|message| message.conversation_id);
+        unanswered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(
+        downlink_status: DownlinkStatus,
+        acknowledgement: AckStatus,
+        label: [char; 2],
+        block_id: char,
+        text: &str,
+    ) -> AssembledACARSMessage {
+        AssembledACARSMessage {
+            aircraft_tail: Some(['.', 'N', '9', '2', '3', 'U', 'S']),
+            downlink_status,
+            acknowledgement,
+            label,
+            block_id,
+            message_text: (!text.is_empty()).then(|| text.chars().collect()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_conversation() {
+        let mut correlator = Correlator::default();
+        let start = SystemTime::UNIX_EPOCH;
+        let downlink = message(
+            DownlinkStatus::AirToGround,
+            AckStatus::Nack,
+            ['H', '1'],
+            '7',
+            "POSN35286W108525",
+        );
+
+        let first = correlator.correlate(&downlink, start);
+        assert_eq!(
+            first,
+            Some(Correlation {
+                conversation_id: 1,
+                acknowledges: None,
+                retransmission: false,
+                unanswered: false,
+            })
+        );
+
+        // Sent again, the ground did not acknowledge it
+        assert_eq!(
+            correlator.correlate(&downlink, start + Duration::from_secs(5)),
+            Some(Correlation {
+                conversation_id: 1,
+                acknowledges: None,
+                retransmission: true,
+                unanswered: false,
+            })
+        );
+
+        // The ground answers with a message of its own, which the aircraft acknowledges
+        let reply = message(
+            DownlinkStatus::GroundToAir,
+            AckStatus::Ack('7'),
+            ['H', '1'],
+            'J',
+            "REQPER",
+        );
+        assert_eq!(
+            correlator.correlate(&reply, start + Duration::from_secs(6)),
+            Some(Correlation {
+                conversation_id: 1,
+                acknowledges: Some('7'),
+                retransmission: false,
+                unanswered: false,
+            })
+        );
+
+        let ack = message(
+            DownlinkStatus::AirToGround,
+            AckStatus::Ack('J'),
+            ['_', 'd'],
+            '8',
+            "",
+        );
+        assert_eq!(
+            correlator.correlate(&ack, start + Duration::from_secs(7)),
+            Some(Correlation {
+                conversation_id: 1,
+                acknowledges: Some('J'),
+                retransmission: false,
+                unanswered: false,
+            })
+        );
+
+        // Everything was acknowledged
+        assert!(correlator
+            .expire(start + Duration::from_secs(600))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_unanswered() {
+        let mut correlator = Correlator::new(Duration::from_secs(30));
+        let start = SystemTime::UNIX_EPOCH;
+        let downlink = message(
+            DownlinkStatus::AirToGround,
+            AckStatus::Nack,
+            ['Q', '0'],
+            '6',
+            "",
+        );
+
+        assert!(correlator.correlate(&downlink, start).is_some());
+        assert!(correlator
+            .expire(start + Duration::from_secs(10))
+            .is_empty());
+
+        let unanswered = correlator.expire(start + Duration::from_secs(31));
+        assert_eq!(
+            unanswered,
+            vec![UnansweredMessage {
+                conversation_id: 1,
+                tail: "N923US".to_string(),
+                downlink_status: DownlinkStatus::AirToGround,
+                block_id: '6',
+                label: ['Q', '0'],
+                transmissions: 1,
+            }]
+        );
+        // Only reported once
+        assert!(correlator
+            .expire(start + Duration::from_secs(32))
+            .is_empty());
+
+        // Sent again after going unanswered
+        let retransmitted = correlator.correlate(&downlink, start + Duration::from_secs(40));
+        assert_eq!(
+            retransmitted,
+            Some(Correlation {
+                conversation_id: 1,
+                acknowledges: None,
+                retransmission: true,
+                unanswered: true,
+            })
+        );
+        assert_eq!(
+            retransmitted.as_ref().map(ToString::to_string),
+            Some("Conversation: 1, RETRANSMISSION, UNANSWERED".to_string())
+        );
+        assert_eq!(
+            serde_json::to_string(&retransmitted).ok().as_deref(),
+            Some(r#"{"conversation_id":1,"retransmission":true,"unanswered":true}"#)
+        );
+
+        // Forgotten once it isn't heard for another timeout
+        assert!(correlator
+            .expire(start + Duration::from_secs(101))
+            .is_empty());
+        assert_eq!(
+            correlator.correlate(&downlink, start + Duration::from_secs(102)),
+            Some(Correlation {
+                conversation_id: 2,
+                acknowledges: None,
+                retransmission: false,
+                unanswered: false,
+            })
+        );
+
+        // The general response only acknowledges, nothing waits for it
+        let ack = message(
+            DownlinkStatus::GroundToAir,
+            AckStatus::Ack('6'),
+            ['_', 'd'],
+            'A',
+            "",
+        );
+        assert_eq!(
+            correlator.correlate(&ack, start + Duration::from_secs(103)),
+            Some(Correlation {
+                conversation_id: 2,
+                acknowledges: Some('6'),
+                retransmission: false,
+                unanswered: false,
+            })
+        );
+        assert!(correlator
+            .expire(start + Duration::from_secs(600))
+            .is_empty());
+
+        // Broadcasts are not part of a conversation
+        let mut squitter = message(
+            DownlinkStatus::GroundToAir,
+            AckStatus::Nack,
+            ['S', 'Q'],
+            '0',
+            "02XAABQKABQ13502N10637WV136975/ARINC",
+        );
+        squitter.aircraft_tail = Some(['.'; 7]);
+        assert_eq!(correlator.correlate(&squitter, start), None);
+    }
+
+    #[test]
+    fn test_correlation_edges() {
+        let mut correlator = Correlator::new(Duration::from_secs(30));
+        let start = SystemTime::UNIX_EPOCH;
+        let conversation = |correlation: Option<Correlation>| {
+            correlation.map(|correlation| {
+                (
+                    correlation.conversation_id,
+                    correlation.acknowledges,
+                    correlation.retransmission,
+                )
+            })
+        };
+        let other_aircraft = |message: AssembledACARSMessage| AssembledACARSMessage {
+            aircraft_tail: Some(['.', '9', 'V', '-', 'S', 'W', 'M']),
+            ..message
+        };
+
+        let downlink = message(
+            DownlinkStatus::AirToGround,
+            AckStatus::Nack,
+            ['H', '1'],
+            '2',
+            "POSN35286W108525",
+        );
+        let uplink_ack = message(
+            DownlinkStatus::GroundToAir,
+            AckStatus::Ack('2'),
+            ['_', 'd'],
+            'A',
+            "",
+        );
+
+        // Two aircraft using the same block id keep their own conversations
+        assert_eq!(
+            conversation(correlator.correlate(&downlink, start)),
+            Some((1, None, false))
+        );
+        assert_eq!(
+            conversation(correlator.correlate(&other_aircraft(downlink.clone()), start)),
+            Some((2, None, false))
+        );
+        assert_eq!(
+            conversation(correlator.correlate(&other_aircraft(uplink_ack.clone()), start)),
+            Some((2, Some('2'), false))
+        );
+
+        // An ack from the same side, or for a block id we are not waiting on, acknowledges nothing
+        let same_side = message(
+            DownlinkStatus::AirToGround,
+            AckStatus::Ack('2'),
+            ['_', 'd'],
+            '3',
+            "",
+        );
+        assert_eq!(correlator.correlate(&same_side, start), None);
+        let unknown_block = message(
+            DownlinkStatus::GroundToAir,
+            AckStatus::Ack('Z'),
+            ['H', '1'],
+            'B',
+            "REQPER",
+        );
+        assert_eq!(
+            conversation(correlator.correlate(&unknown_block, start)),
+            Some((3, None, false))
+        );
+
+        // A different message with a block id already waiting replaces the older one
+        let reused = message(
+            DownlinkStatus::AirToGround,
+            AckStatus::Nack,
+            ['H', '1'],
+            '2',
+            "REQPWI",
+        );
+        assert_eq!(
+            conversation(correlator.correlate(&reused, start)),
+            Some((4, None, false))
+        );
+        assert_eq!(
+            conversation(correlator.correlate(&uplink_ack, start)),
+            Some((4, Some('2'), false))
+        );
+
+        // Failed messages are not part of a conversation, and do not acknowledge anything
+        let waiting = message(
+            DownlinkStatus::AirToGround,
+            AckStatus::Nack,
+            ['H', '1'],
+            '4',
+            "POSN35286W108525",
+        );
+        assert!(correlator.correlate(&waiting, start).is_some());
+        let failed_ack = AssembledACARSMessage {
+            status: MessageStatus::CrcFailed,
+            acknowledgement: AckStatus::Ack('4'),
+            ..uplink_ack
+        };
+        assert_eq!(correlator.correlate(&failed_ack, start), None);
+
+        // The uplink still waiting for the aircraft, and the downlink, time out only after the
+        // whole timeout
+        assert!(correlator
+            .expire(start + Duration::from_secs(30))
+            .is_empty());
+        let unanswered: Vec<(u64, char)> = correlator
+            .expire(start + Duration::from_secs(31))
+            .iter()
+            .map(|unanswered| (unanswered.conversation_id, unanswered.block_id))
+            .collect();
+        assert_eq!(unanswered, vec![(3, 'B'), (5, '4')]);
+    }
+}
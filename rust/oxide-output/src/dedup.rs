@@ -0,0 +1,310 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// Duplicate suppression. The same block is decoded more than once when dongles overlap, or
+// when the aircraft sends it again. Messages are held for the dedup window after the first
+// reception, and copies received in the meantime are merged into it, keeping the copy with the
+// strongest signal.
+
+use oxide_decoders::decoders::acars::AssembledACARSMessage;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, SystemTime};
+
+pub const DEFAULT_DEDUP_WINDOW: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct DedupKey {
+    aircraft_tail: Option<[char; 7]>,
+    label: [char; 2],
+    block_id: char,
+    message_number: Option<[char; 4]>,
+    text_hash: u64,
+}
+
+impl DedupKey {
+    fn new(message: &AssembledACARSMessage) -> Self {
+        let mut hasher = DefaultHasher::new();
+        message.message_text.hash(&mut hasher);
+
+        Self {
+            aircraft_tail: message.aircraft_tail,
+            label: message.label,
+            block_id: message.block_id,
+            message_number: message.message_number,
+            text_hash: hasher.finish(),
+        }
+    }
+}
+
+/// A message, merged with all copies of it received within the dedup window
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeduplicatedMessage {
+    /// The copy received with the strongest signal
+    pub message: AssembledACARSMessage,
+    /// When the first copy was received
+    pub received: SystemTime,
+    /// Number of copies merged, including the one kept
+    pub receptions: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Deduplicator {
+    window: Duration,
+    pending: HashMap<DedupKey, DeduplicatedMessage>,
+}
+
+impl Default for Deduplicator {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEDUP_WINDOW)
+    }
+}
+
+impl Deduplicator {
+    #[must_use]
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Add a message received at `now`. It is held until the window has passed.
+    pub fn add(&mut self, message: AssembledACARSMessage, now: SystemTime) {
+        self.pending
+            .entry(DedupKey::new(&message))
+            .and_modify(|pending| {
+                pending.receptions += 1;
+                // Dongles don't hand their copies over in the order they were received
+                pending.received = pending.received.min(now);
+                if message.signal_level > pending.message.signal_level {
+                    pending.message = message.clone();
+                }
+            })
+            .or_insert_with(|| DeduplicatedMessage {
+                message,
+                received: now,
+                receptions: 1,
+            });
+    }
+
+    /// Remove and return the messages whose window has passed, oldest first.
+    pub fn take_ready(&mut self, now: SystemTime) -> Vec<DeduplicatedMessage> {
+        let window = self.window;
+        let is_ready = |pending: &DeduplicatedMessage| {
+            now.duration_since(pending.received)
+                .map_or(false, |elapsed| elapsed >= window)
+        };
+
+        let ready_keys: Vec<DedupKey> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| is_ready(pending))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        let mut ready: Vec<DeduplicatedMessage> = ready_keys
+            .iter()
+            .filter_map(|key| self.pending.remove(key))
+            .collect();
+
+        ready.sort_by_key(|pending| pending.received);
+        ready
+    }
+
+    /// Remove and return every held message, oldest first, without waiting for the window.
+    pub fn take_all(&mut self) -> Vec<DeduplicatedMessage> {
+        let mut ready: Vec<DeduplicatedMessage> =
+            self.pending.drain().map(|(_, pending)| pending).collect();
+        ready.sort_by_key(|pending| pending.received);
+        ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(frequency: f32, signal_level: f32, text: &str) -> AssembledACARSMessage {
+        AssembledACARSMessage {
+            aircraft_tail: Some(['N', '9', '2', '3', 'U', 'S', ' ']),
+            label: ['H', '1'],
+            block_id: '7',
+            message_number: Some(['F', '7', '6', 'A']),
+            frequency,
+            signal_level,
+            message_text: Some(text.chars().collect()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_dedup() {
+        let mut dedup = Deduplicator::new(Duration::from_secs(2));
+        let start = SystemTime::UNIX_EPOCH;
+
+        dedup.add(message(130.025, -27.6, "POSN35286W108525"), start);
+        dedup.add(
+            message(130.025, -20.1, "POSN35286W108525"),
+            start + Duration::from_millis(50),
+        );
+        dedup.add(
+            message(130.025, -24.0, "POSN35286W108525"),
+            start + Duration::from_millis(500),
+        );
+        dedup.add(
+            message(130.025, -30.0, "REQPER,PRFE36"),
+            start + Duration::from_millis(600),
+        );
+
+        assert!(dedup.take_ready(start + Duration::from_secs(1)).is_empty());
+
+        let ready = dedup.take_ready(start + Duration::from_secs(2));
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].receptions, 3);
+        assert_eq!(ready[0].received, start);
+        assert!((ready[0].message.signal_level + 20.1).abs() < f32::EPSILON);
+
+        let ready = dedup.take_ready(start + Duration::from_secs(3));
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].receptions, 1);
+
+        assert!(dedup.take_ready(start + Duration::from_secs(10)).is_empty());
+
+        dedup.add(message(130.025, -30.0, "REQPER,PRFE36"), start);
+        assert_eq!(dedup.take_all().len(), 1);
+        assert!(dedup.take_all().is_empty());
+    }
+
+    #[test]
+    fn test_dedup_window_edges() {
+        let window = Duration::from_secs(2);
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let text = "POSN35286W108525";
+
+        // A copy just inside the window is merged, and the window is not extended by it
+        let mut dedup = Deduplicator::new(window);
+        dedup.add(message(130.025, -27.6, text), start);
+        dedup.add(
+            message(130.025, -27.0, text),
+            start + window - Duration::from_millis(1),
+        );
+        assert!(dedup
+            .take_ready(start + window - Duration::from_millis(1))
+            .is_empty());
+        let ready = dedup.take_ready(start + window);
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].receptions, 2);
+        assert_eq!(ready[0].received, start);
+
+        // Once output, a later copy is a new message
+        dedup.add(message(130.025, -27.6, text), start + window);
+        let ready = dedup.take_ready(start + window * 2);
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].receptions, 1);
+        assert_eq!(ready[0].received, start + window);
+
+        // A copy that arrives late, but before the held message was taken, is still merged
+        dedup.add(message(130.025, -27.6, text), start);
+        dedup.add(message(130.025, -27.6, text), start + window * 5);
+        let ready = dedup.take_ready(start + window * 5);
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].receptions, 2);
+
+        // A clock that steps back does not release anything early
+        dedup.add(message(130.025, -27.6, text), start);
+        assert!(dedup.take_ready(start - window).is_empty());
+        assert_eq!(dedup.take_ready(start + window).len(), 1);
+
+        // Without a window every message is ready straight away
+        let mut dedup = Deduplicator::new(Duration::ZERO);
+        dedup.add(message(130.025, -27.6, text), start);
+        dedup.add(message(130.025, -27.6, "REQPER,PRFE36"), start);
+        assert_eq!(dedup.take_ready(start).len(), 2);
+    }
+
+    #[test]
+    fn test_dedup_across_dongles() {
+        let start = SystemTime::UNIX_EPOCH;
+        let text = "POSN35286W108525";
+
+        // The same block heard by two dongles covering the channel, one with bits corrected
+        let weak = AssembledACARSMessage {
+            corrected_bits: 2,
+            frequency_offset: 312.0,
+            ..message(130.025, -31.5, text)
+        };
+        let strong = AssembledACARSMessage {
+            frequency_offset: -95.0,
+            ..message(130.025, -18.2, text)
+        };
+        let equal = AssembledACARSMessage {
+            frequency_offset: 40.0,
+            ..message(130.025, -18.2, text)
+        };
+
+        let mut dedup = Deduplicator::default();
+        dedup.add(weak, start);
+        dedup.add(strong.clone(), start + Duration::from_millis(3));
+        // The first copy of the strongest signal is kept
+        dedup.add(equal, start + Duration::from_millis(5));
+
+        let ready = dedup.take_all();
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].receptions, 3);
+        assert_eq!(ready[0].received, start);
+        assert_eq!(ready[0].message, strong);
+
+        // Copies that differ in any part of the key are kept apart, oldest first
+        let mut dedup = Deduplicator::default();
+        let copies = [
+            message(130.025, -20.0, text),
+            AssembledACARSMessage {
+                aircraft_tail: Some(['N', '9', '2', '4', 'U', 'S', ' ']),
+                ..message(130.025, -20.0, text)
+            },
+            AssembledACARSMessage {
+                label: ['H', '2'],
+                ..message(130.025, -20.0, text)
+            },
+            AssembledACARSMessage {
+                block_id: '8',
+                ..message(130.025, -20.0, text)
+            },
+            AssembledACARSMessage {
+                message_number: Some(['F', '7', '6', 'B']),
+                ..message(130.025, -20.0, text)
+            },
+            // A copy whose errors could not be corrected has different text
+            message(130.025, -20.0, "POSN35286W10852%"),
+            AssembledACARSMessage {
+                message_text: None,
+                ..message(130.025, -20.0, text)
+            },
+        ];
+        for (index, copy) in copies.iter().enumerate().rev() {
+            dedup.add(copy.clone(), start - Duration::from_millis(index as u64));
+        }
+        let ready = dedup.take_all();
+        assert_eq!(ready.len(), copies.len());
+        assert!(ready.iter().all(|pending| pending.receptions == 1));
+        assert!(ready
+            .windows(2)
+            .all(|pair| pair[0].received <= pair[1].received));
+        assert_eq!(ready[copies.len() - 1].message, copies[0]);
+    }
+}
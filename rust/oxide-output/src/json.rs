@@ -0,0 +1,465 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// JSON output, as described in Dev Documents/JSONSpec.MD
+
+use crate::correlator::Correlation;
+use oxide_decoders::decoders::acars::{AssembledACARSMessage, DownlinkStatus};
+use oxide_decoders::decoders::arinc622::Arinc622Message;
+use oxide_decoders::decoders::labels::LabelContent;
+use oxide_decoders::decoders::miam::MiamMessage;
+use oxide_decoders::decoders::squitter::GroundStation;
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+const DECODER_NAME: &str = "acars_oxide";
+const MESSAGE_VERSION: &str = "1.0.0";
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct DecoderInformation {
+    pub decoder_name: String,
+    pub decoder_version: String,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct MessageTimestamp {
+    pub sec: u64,
+    pub usec: u32,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct MessageInformation {
+    pub message_type: String,
+    pub message_version: String,
+    pub message_timestamp: MessageTimestamp,
+    /// Frequency in Hz
+    pub frequency: u64,
+    pub message_uuid: String,
+    /// Number of times the message was received, across all channels and dongles
+    pub receptions: u32,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OriginatorType {
+    Aircraft,
+    GroundStation,
+}
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+pub struct AircraftIds {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aircraft_registration: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aircraft_callsign: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct GroundStationIds {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ground_station_network: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ground_station_iata: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ground_station_icao: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ground_station_number: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ground_station_latitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ground_station_longitude: Option<f64>,
+}
+
+impl From<&GroundStation> for GroundStationIds {
+    fn from(station: &GroundStation) -> Self {
+        Self {
+            ground_station_network: Some(station.network.to_string()),
+            ground_station_iata: station.iata.clone(),
+            ground_station_icao: station.icao.clone(),
+            ground_station_number: station.station_number,
+            ground_station_latitude: station.latitude,
+            ground_station_longitude: station.longitude,
+        }
+    }
+}
+
+/// The content of an ACARS message
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct AcarsContent {
+    pub mode: String,
+    pub label: String,
+    pub block_id: String,
+    pub acknowledgement: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_number: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sublabel: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mfi: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    pub status: String,
+    pub parity_errors: u8,
+    pub corrected_bits: u8,
+    pub signal_level: f32,
+    /// Not measured yet when the channel's noise floor is unknown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub noise_level: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snr: Option<f32>,
+    /// Offset of the received carrier from the channel frequency, in Hz
+    pub frequency_offset: f32,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct OxideJsonMessage {
+    pub decoder_information: DecoderInformation,
+    pub message_information: MessageInformation,
+    pub originator_type: OriginatorType,
+    pub aircraft_ids: AircraftIds,
+    pub ground_station_ids: GroundStationIds,
+    pub acars: AcarsContent,
+    /// Structured content of the message text, for the labels we have a decoder for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label_content: Option<LabelContent>,
+    /// ARINC 622 application data (ADS-C, CPDLC)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arinc622: Option<Arinc622Message>,
+    /// MIAM frame and, once complete, the message it carries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub miam: Option<MiamMessage>,
+    /// The conversation the message is part of, for messages to or from an aircraft
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conversation: Option<Correlation>,
+}
+
+fn to_string(chars: &[char]) -> Option<String> {
+    let output: String = chars.iter().collect();
+    let output = output.trim().trim_start_matches('.');
+    (!output.is_empty()).then(|| output.to_string())
+}
+
+impl OxideJsonMessage {
+    /// Build the JSON representation of a message received at `timestamp`.
+    #[must_use]
+    pub fn new(message: &AssembledACARSMessage, timestamp: SystemTime) -> Self {
+        let since_epoch = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        let ground_station_ids = match &message.label_content {
+            Some(LabelContent::GroundStation(station)) => GroundStationIds::from(station),
+            _ => GroundStationIds::default(),
+        };
+
+        Self {
+            decoder_information: DecoderInformation {
+                decoder_name: DECODER_NAME.to_string(),
+                decoder_version: env!("CARGO_PKG_VERSION").to_string(),
+            },
+            message_information: MessageInformation {
+                message_type: "acars".to_string(),
+                message_version: MESSAGE_VERSION.to_string(),
+                message_timestamp: MessageTimestamp {
+                    sec: since_epoch.as_secs(),
+                    usec: since_epoch.subsec_micros(),
+                },
+                // Frequencies are positive and well within range. Round to the kHz to get rid of
+                // the f32 noise, channels are never closer than that
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                frequency: (f64::from(message.frequency) * 1000.0).round() as u64 * 1000,
+                message_uuid: Uuid::new_v4().to_string(),
+                receptions: 1,
+            },
+            originator_type: match message.downlink_status {
+                DownlinkStatus::AirToGround => OriginatorType::Aircraft,
+                DownlinkStatus::GroundToAir => OriginatorType::GroundStation,
+            },
+            aircraft_ids: AircraftIds {
+                aircraft_registration: message
+                    .aircraft_tail
+                    .as_ref()
+                    .and_then(|tail| to_string(tail)),
+                aircraft_callsign: message
+                    .flight_id
+                    .as_ref()
+                    .and_then(|flight| to_string(flight)),
+            },
+            ground_station_ids,
+            acars: AcarsContent {
+                mode: message.mode.to_string(),
+                label: message.label.iter().collect(),
+                block_id: message.block_id.to_string(),
+                acknowledgement: message.acknowledgement.to_string(),
+                message_number: message
+                    .message_number
+                    .as_ref()
+                    .and_then(|number| to_string(number)),
+                sublabel: message
+                    .sublabel
+                    .as_ref()
+                    .and_then(|sublabel| to_string(sublabel)),
+                mfi: message.mfi.as_ref().and_then(|mfi| to_string(mfi)),
+                text: message.message_text.as_ref().and_then(|text| {
+                    let text: String = text.iter().filter(|c| **c != '\0').collect();
+                    (!text.is_empty()).then_some(text)
+                }),
+                status: message.status.to_string(),
+                parity_errors: message.parity_errors,
+                corrected_bits: message.corrected_bits,
+                signal_level: message.signal_level,
+                noise_level: message.noise_level,
+                snr: message.snr,
+                frequency_offset: message.frequency_offset,
+            },
+            label_content: message.label_content.clone(),
+            arinc622: message.arinc622.clone(),
+            miam: message.miam.clone(),
+            conversation: None,
+        }
+    }
+
+    /// Serialize to a single line of JSON, without the trailing newline.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message cannot be serialized.
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num::Complex;
+    use oxide_decoders::decoders::acars::{ACARSDecoder, MessageStatus, INTRATE_F32};
+    use oxide_decoders::decoders::arinc622::{
+        AdscGroup, AdscPosition, AdscReportType, Arinc622Application, Imi,
+    };
+    use oxide_decoders::decoders::labels::LabelDecoderRegistry;
+    use oxide_decoders::decoders::miam::{
+        MiamApplication, MiamCompression, MiamCorePdu, MiamFrame, MiamPduType,
+    };
+    use oxide_decoders::decoders::modulator::{
+        decode_samples, downlink_block, AcarsBlock, AcarsModulator,
+    };
+    use oxide_decoders::Decoder;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_squitter_json() {
+        let mut message = AssembledACARSMessage {
+            mode: '2',
+            label: ['S', 'Q'],
+            aircraft_tail: Some(['.', '.', '.', '.', '.', '.', '.']),
+            frequency: 131.55,
+            downlink_status: DownlinkStatus::GroundToAir,
+            message_text: Some("02XAABQKABQ13502N10637WV136975/ARINC".chars().collect()),
+            ..Default::default()
+        };
+        message.label_content = LabelDecoderRegistry::with_default_decoders().decode(&message);
+
+        let json = OxideJsonMessage::new(&message, UNIX_EPOCH + Duration::from_millis(1500));
+
+        assert_eq!(json.originator_type, OriginatorType::GroundStation);
+        assert_eq!(json.message_information.frequency, 131_550_000);
+        assert_eq!(
+            json.message_information.message_timestamp,
+            MessageTimestamp {
+                sec: 1,
+                usec: 500_000
+            }
+        );
+        assert_eq!(json.aircraft_ids, AircraftIds::default());
+        assert_eq!(
+            json.ground_station_ids.ground_station_icao.as_deref(),
+            Some("KABQ")
+        );
+        assert_eq!(
+            json.ground_station_ids.ground_station_iata.as_deref(),
+            Some("ABQ")
+        );
+        assert_eq!(json.ground_station_ids.ground_station_number, Some(1));
+
+        let Ok(json) = json.to_json() else {
+            panic!("failed to serialize");
+        };
+        assert!(json.contains(r#""originator_type":"ground_station""#));
+        assert!(json.contains(r#""ground_station_network":"ARINC""#));
+        assert!(!json.contains("aircraft_registration"));
+        assert!(
+            json.contains(r#""label_content":{"ground_station":{"version":2,"network":"ARINC""#),
+            "{json}"
+        );
+        assert!(!json.contains("arinc622"));
+        assert!(!json.contains("miam"));
+    }
+
+    #[test]
+    fn test_decoded_content_json() {
+        let arinc622 = Arinc622Message {
+            ground_address: "BOMCAYA".to_string(),
+            imi: Imi::Ads,
+            aircraft_registration: "9V-SWM".to_string(),
+            crc_ok: true,
+            application: Arinc622Application::AdsC(vec![AdscGroup::BasicReport {
+                report_type: AdscReportType::Periodic,
+                position: AdscPosition {
+                    latitude: 12.5,
+                    longitude: 70.25,
+                    altitude: 37000,
+                },
+                timestamp: 1234.5,
+                navigation_redundancy: true,
+                position_accuracy: 7,
+                tcas_healthy: true,
+            }]),
+        };
+        let message = AssembledACARSMessage {
+            label: ['M', 'A'],
+            frequency: 131.55,
+            frequency_offset: -412.5,
+            miam: Some(MiamMessage {
+                frame: MiamFrame::SingleTransfer,
+                core: Some(MiamCorePdu {
+                    version: 2,
+                    pdu_type: MiamPduType::Data,
+                    aircraft_id: "9V-SWM".to_string(),
+                    message_number: 3,
+                    ack_requested: false,
+                    compression: MiamCompression::Deflate,
+                    crc_ok: true,
+                    application: Some(MiamApplication::Acars {
+                        label: ['H', '1'],
+                        sublabel: None,
+                        mfi: None,
+                        text: "/BOMCAYA.ADS.9V-SWM".to_string(),
+                        arinc622: Some(arinc622.clone()),
+                        label_content: None,
+                    }),
+                }),
+            }),
+            arinc622: Some(arinc622),
+            ..Default::default()
+        };
+
+        let Ok(json) = OxideJsonMessage::new(&message, UNIX_EPOCH).to_json() else {
+            panic!("failed to serialize");
+        };
+        assert!(json.contains(r#""frequency_offset":-412.5"#), "{json}");
+        assert!(
+            json.contains(r#""arinc622":{"ground_address":"BOMCAYA","imi":"ADS""#),
+            "{json}"
+        );
+        assert!(
+            json.contains(r#""application":{"ads_c":[{"basic_report":{"report_type":"periodic""#),
+            "{json}"
+        );
+        assert!(
+            json.contains(r#""miam":{"frame":"single_transfer","core":{"version":2"#),
+            "{json}"
+        );
+        assert!(
+            json.contains(r#""text":"/BOMCAYA.ADS.9V-SWM","arinc622":{"#),
+            "{json}"
+        );
+        // Only the inner message of the MIAM frame has label content, and there is none
+        assert_eq!(json.matches(r#""label_content":"#).count(), 1, "{json}");
+        assert!(
+            json.contains(r#""ads_c":[{"basic_report":"#)
+                && json.contains(r#""label_content":null}}}}"#),
+            "{json}"
+        );
+    }
+
+    #[test]
+    fn test_failed_message_json() {
+        // three characters with bad parity and a garbled CRC are beyond repair
+        let mut bytes = downlink_block(['H', '1'], 60).to_bytes();
+        let crc = bytes.len() - 3;
+        bytes[crc] ^= 0x5a;
+        bytes[crc + 1] ^= 0xa5;
+        for position in [crc - 20, crc - 14, crc - 8] {
+            bytes[position] ^= 0x02;
+        }
+        let samples = AcarsModulator::new(f64::from(INTRATE_F32), 0.0)
+            .seed(1)
+            .modulate_bytes(&bytes);
+
+        let mut decoder = ACARSDecoder::new(0, 131_550_000, [Complex::new(0.0, 0.0); 192]);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        decoder.set_output_channel(tx);
+        decode_samples(&mut decoder, &samples);
+        assert!(rx.try_recv().is_err());
+
+        decoder.set_emit_errors(true);
+        decode_samples(&mut decoder, &samples);
+        let Ok(message) = rx.try_recv() else {
+            panic!("failed message was not emitted");
+        };
+
+        assert_eq!(message.status, MessageStatus::CrcFailed);
+        assert_eq!(message.parity_errors, 3);
+        assert_eq!(message.corrected_bits, 0);
+
+        let console = message.to_string();
+        assert!(
+            console.starts_with("*** CRC FAILED - UNRELIABLE MESSAGE *** Frequency: 131.550"),
+            "{console}"
+        );
+        assert!(
+            console.contains("Reception Errors: 3, Corrected Bits: 0"),
+            "{console}"
+        );
+
+        let Ok(json) = OxideJsonMessage::new(&message, UNIX_EPOCH).to_json() else {
+            panic!("failed to serialize");
+        };
+        assert!(
+            json.contains(r#""status":"CRC FAILED","parity_errors":3,"corrected_bits":0"#),
+            "{json}"
+        );
+        // The position in the text is not decoded from a failed message
+        assert_eq!(message.label_content, None);
+        assert!(!json.contains("label_content"), "{json}");
+
+        // A squitter whose text reads fine, but whose CRC is garbled, names no ground station
+        let mut bytes =
+            AcarsBlock::new("", ['S', 'Q'], '\0', "02XAABQKABQ13502N10637WV136975/ARINC")
+                .to_bytes();
+        let crc = bytes.len() - 3;
+        bytes[crc] ^= 0x5a;
+        bytes[crc + 1] ^= 0xa5;
+        let samples = AcarsModulator::new(f64::from(INTRATE_F32), 0.0)
+            .seed(1)
+            .modulate_bytes(&bytes);
+        decode_samples(&mut decoder, &samples);
+        let Ok(message) = rx.try_recv() else {
+            panic!("failed squitter was not emitted");
+        };
+
+        assert_eq!(message.status, MessageStatus::CrcFailed);
+        assert_eq!(message.label, ['S', 'Q']);
+        assert_eq!(
+            message.message_text,
+            Some("02XAABQKABQ13502N10637WV136975/ARINC".chars().collect())
+        );
+        assert_eq!(message.label_content, None);
+        let json = OxideJsonMessage::new(&message, UNIX_EPOCH);
+        assert_eq!(json.ground_station_ids, GroundStationIds::default());
+    }
+}
@@ -26,48 +26,625 @@
 )]
 // #![warn(missing_docs)]
 
-use oxide_decoders::decoders::acars::AssembledACARSMessage;
-use tokio::sync::mpsc::UnboundedReceiver;
+use correlator::{Correlation, Correlator};
+use dedup::{DeduplicatedMessage, Deduplicator};
+use json::GroundStationIds;
+use json::OxideJsonMessage;
+use mqtt::MqttSink;
+use oxide_decoders::decoders::acars::{AssembledACARSMessage, DownlinkStatus, MessageStatus};
+use oxide_decoders::decoders::labels::LabelContent;
+use oxide_metrics::Metrics;
+use recent::RecentMessages;
+use rules::{
+    RuleSet, SINK_API, SINK_CONSOLE, SINK_JSON, SINK_MQTT, SINK_STREAM, SINK_WEBSOCKET, SINK_ZMQ,
+};
+use stations::{GroundStationTable, SharedGroundStations, GROUND_STATION_TIMEOUT};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::time::MissedTickBehavior;
+use websocket::WebSocketSink;
 #[macro_use]
 extern crate log;
 
+pub mod correlator;
+pub mod dedup;
+pub mod json;
+pub mod mqtt;
+pub mod recent;
+pub mod rules;
+pub mod stations;
+pub mod websocket;
+
+/// How often held messages are checked for the end of their dedup window, and sent messages
+/// for going unanswered
+const EXPIRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A message as handed to the message sink, after deduplication and correlation
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedMessage {
+    /// The copy received with the strongest signal
+    pub message: AssembledACARSMessage,
+    /// When the first copy was received
+    pub received: SystemTime,
+    /// Number of copies merged, including the one kept
+    pub receptions: u32,
+    /// The conversation the message is part of, for messages to or from an aircraft
+    pub conversation: Option<Correlation>,
+}
+
 pub struct OxideOutput {
     output_command_line: bool,
+    output_json: bool,
     enable_zmq: bool,
+    ground_stations: GroundStationTable,
+    shared_ground_stations: Option<SharedGroundStations>,
+    correlator: Correlator,
+    /// Merges copies of the same message. `None` when deduplication is turned off
+    dedup: Option<Deduplicator>,
+    rules: RuleSet,
+    /// Where messages go when the decoder is embedded in another application
+    message_sink: Option<UnboundedSender<DecodedMessage>>,
+    recent_messages: Option<RecentMessages>,
+    websocket: Option<WebSocketSink>,
+    mqtt: Option<MqttSink>,
+    metrics: Option<Arc<Metrics>>,
+    /// Time of the latest message, and when it arrived
+    clock: Option<(SystemTime, SystemTime)>,
     receiver_channel: UnboundedReceiver<AssembledACARSMessage>, // TODO: This is hard coded to a single message type. We need to make this generic.
 }
 
 impl OxideOutput {
+    #[must_use]
     pub fn new(
         enable_output_command_line: bool,
+        enable_output_json: bool,
         enable_output_zmq: bool,
+        dedup_window: Option<Duration>,
         receiver_channel: UnboundedReceiver<AssembledACARSMessage>,
-    ) -> OxideOutput {
-        OxideOutput {
+    ) -> Self {
+        Self {
             output_command_line: enable_output_command_line,
+            output_json: enable_output_json,
             enable_zmq: enable_output_zmq,
+            ground_stations: GroundStationTable::new(),
+            shared_ground_stations: None,
+            correlator: Correlator::default(),
+            dedup: dedup_window.map(Deduplicator::new),
+            rules: RuleSet::new(),
+            message_sink: None,
+            recent_messages: None,
+            websocket: None,
+            mqtt: None,
+            metrics: None,
+            clock: None,
             receiver_channel,
         }
     }
 
+    /// Filter the messages each sink gets. By default every sink gets every message.
+    pub fn set_rules(&mut self, rules: RuleSet) {
+        self.rules = rules;
+    }
+
+    /// Send every message the stream sink allows to `sink`, in addition to the other outputs.
+    pub fn set_message_sink(&mut self, sink: UnboundedSender<DecodedMessage>) {
+        self.message_sink = Some(sink);
+    }
+
+    /// Keep the last messages the api sink allows in `recent_messages`, for the HTTP API.
+    pub fn set_recent_messages(&mut self, recent_messages: RecentMessages) {
+        self.recent_messages = Some(recent_messages);
+    }
+
+    /// Push the messages the websocket sink allows to the clients of `websocket`.
+    pub fn set_websocket_sink(&mut self, websocket: WebSocketSink) {
+        self.websocket = Some(websocket);
+    }
+
+    /// Publish the messages the mqtt sink allows through `mqtt`.
+    pub fn set_mqtt_sink(&mut self, mqtt: MqttSink) {
+        self.mqtt = Some(mqtt);
+    }
+
+    /// Publish the ground stations heard to `ground_stations` whenever a squitter is output.
+    pub fn set_shared_ground_stations(&mut self, ground_stations: SharedGroundStations) {
+        self.shared_ground_stations = Some(ground_stations);
+    }
+
+    /// Count the messages each sink outputs, the duplicates merged and the ground stations
+    /// heard in `metrics`.
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    fn record_output(&self, sink: &str) {
+        if let Some(metrics) = &self.metrics {
+            metrics.output_message(sink);
+        }
+    }
+
+    /// The ground stations heard so far
+    #[must_use]
+    pub const fn ground_stations(&self) -> &GroundStationTable {
+        &self.ground_stations
+    }
+
+    /// Record the ground station that sent `message`, if it is a squitter, and drop the
+    /// stations no longer heard
+    fn update_ground_stations(&mut self, message: &AssembledACARSMessage, received: SystemTime) {
+        let new_station = self.ground_stations.update(message, received);
+        if let Some(heard) = new_station {
+            info!(
+                "[{: <13}] New ground station on {:.3}: {}",
+                "OUT CHANNEL", heard.frequency, heard.station
+            );
+        }
+        let new_station = new_station.is_some();
+        let pruned = self.ground_stations.prune(received, GROUND_STATION_TIMEOUT);
+
+        if new_station || pruned > 0 {
+            if let Some(metrics) = &self.metrics {
+                metrics.set_ground_stations(self.ground_stations.stations().len());
+            }
+        }
+
+        let squitter = matches!(message.label_content, Some(LabelContent::GroundStation(_)));
+        if let Some(shared) = &self.shared_ground_stations {
+            if !squitter && pruned == 0 {
+                return;
+            }
+
+            if let Ok(mut shared) = shared.lock() {
+                *shared = self
+                    .ground_stations
+                    .stations()
+                    .into_iter()
+                    .cloned()
+                    .collect();
+            }
+        }
+    }
+
+    fn output_message(&mut self, deduplicated: DeduplicatedMessage) {
+        let DeduplicatedMessage {
+            message,
+            received,
+            receptions,
+        } = deduplicated;
+        let correlation = self.correlator.correlate(&message, received);
+        let correlation_display = correlation
+            .as_ref()
+            .map_or_else(String::new, |correlation| format!(", {correlation}"));
+        let receptions_display = if receptions > 1 {
+            format!(", Receptions: {receptions}")
+        } else {
+            String::new()
+        };
+        let extra_display = correlation_display + &receptions_display;
+
+        self.update_ground_stations(&message, received);
+
+        if let Some(metrics) = &self.metrics {
+            metrics.duplicate_messages(u64::from(receptions.saturating_sub(1)));
+        }
+
+        let output_command_line =
+            self.output_command_line && self.rules.allows(SINK_CONSOLE, &message);
+
+        if output_command_line {
+            self.record_output(SINK_CONSOLE);
+        }
+
+        if output_command_line && message.status != MessageStatus::Valid {
+            warn!("[{: <13}] {}{}", "OUT CHANNEL", message, extra_display);
+        } else if output_command_line {
+            info!("[{: <13}] {}{}", "OUT CHANNEL", message, extra_display);
+        } else {
+            debug!("[{: <13}] {}{}", "OUT CHANNEL", message, extra_display);
+        }
+
+        // Built once, so every sink sees the same message UUID
+        let mut json = OxideJsonMessage::new(&message, received);
+        json.message_information.receptions = receptions;
+        json.conversation.clone_from(&correlation);
+
+        // Uplinks other than squitters don't identify the station, but the frequency does when
+        // only one station was heard on it
+        if message.downlink_status == DownlinkStatus::GroundToAir
+            && json.ground_station_ids == GroundStationIds::default()
+        {
+            if let Some(heard) = self.ground_stations.station_on_frequency(message.frequency) {
+                json.ground_station_ids = GroundStationIds::from(&heard.station);
+            }
+        }
+
+        if self.output_json && self.rules.allows(SINK_JSON, &message) {
+            match json.to_json() {
+                Ok(json) => {
+                    println!("{json}");
+                    self.record_output(SINK_JSON);
+                }
+                Err(e) => error!(
+                    "[{: <13}] Failed to serialize message: {}",
+                    "OUT CHANNEL", e
+                ),
+            }
+        }
+
+        if let Some(recent_messages) = &self.recent_messages {
+            if self.rules.allows(SINK_API, &message) {
+                recent_messages.push(json.clone());
+                self.record_output(SINK_API);
+            }
+        }
+
+        if let Some(websocket) = &self.websocket {
+            if self.rules.allows(SINK_WEBSOCKET, &message) {
+                websocket.send(&json);
+                self.record_output(SINK_WEBSOCKET);
+            }
+        }
+
+        if let Some(mqtt) = &self.mqtt {
+            if self.rules.allows(SINK_MQTT, &message) {
+                mqtt.send(&json);
+                self.record_output(SINK_MQTT);
+            }
+        }
+
+        if self.enable_zmq && self.rules.allows(SINK_ZMQ, &message) {
+            error!("[{: <13}] ZMQ output not implemented yet", "OUT CHANNEL");
+        }
+
+        if let Some(sink) = &self.message_sink {
+            if !self.rules.allows(SINK_STREAM, &message) {
+                return;
+            }
+
+            if sink
+                .send(DecodedMessage {
+                    message,
+                    received,
+                    receptions,
+                    conversation: correlation,
+                })
+                .is_err()
+            {
+                debug!("[{: <13}] Message stream closed", "OUT CHANNEL");
+                self.message_sink = None;
+            } else {
+                self.record_output(SINK_STREAM);
+            }
+        }
+    }
+
+    /// The time messages are held and expired against. It follows the time of the messages, so
+    /// a capture decoded faster than real time is deduplicated and correlated on its own clock.
+    fn now(&self) -> SystemTime {
+        let now = SystemTime::now();
+        self.clock.map_or(now, |(received, arrived)| {
+            received + now.duration_since(arrived).unwrap_or_default()
+        })
+    }
+
+    /// Output the messages whose dedup window has passed, and log the ones that went
+    /// unanswered.
+    fn expire(&mut self, now: SystemTime) {
+        if let Some(dedup) = &mut self.dedup {
+            for deduplicated in dedup.take_ready(now) {
+                self.output_message(deduplicated);
+            }
+        }
+
+        for unanswered in self.correlator.expire(now) {
+            if self.output_command_line {
+                info!(
+                    "[{: <13}] Unanswered message: {}",
+                    "OUT CHANNEL", unanswered
+                );
+            } else {
+                debug!(
+                    "[{: <13}] Unanswered message: {}",
+                    "OUT CHANNEL", unanswered
+                );
+            }
+        }
+    }
+
+    /// Output messages as they are received. Returns once every sender of the receiver
+    /// channel has been dropped.
     pub async fn monitor_receiver_channel(&mut self) {
+        // Held messages are checked on a timer, messages are handled as soon as they arrive
+        let mut expiry = tokio::time::interval(EXPIRY_INTERVAL);
+        expiry.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
         loop {
-            match self.receiver_channel.try_recv() {
-                Ok(message) => {
-                    if self.output_command_line {
-                        info!("[{: <13}] {}", "OUT CHANNEL", message);
-                    } else {
-                        debug!("[{: <13}] {}", "OUT CHANNEL", message);
-                    }
+            let message = tokio::select! {
+                message = self.receiver_channel.recv() => message,
+                _ = expiry.tick() => {
+                    self.expire(self.now());
+                    continue;
+                }
+            };
 
-                    if self.enable_zmq {
-                        error!("[{: <13}] ZMQ output not implemented yet", "OUT CHANNEL");
+            // Every decoder has stopped. Flush the messages held for deduplication
+            let Some(message) = message else {
+                if let Some(mut dedup) = self.dedup.take() {
+                    for deduplicated in dedup.take_all() {
+                        self.output_message(deduplicated);
                     }
                 }
-                Err(_) => {
-                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+                return;
+            };
+
+            let arrived = SystemTime::now();
+            let received = message.received.unwrap_or(arrived);
+            if self.clock.map_or(true, |(latest, _)| received > latest) {
+                self.clock = Some((received, arrived));
+                self.expire(received);
+            }
+
+            // Failed messages are not merged, their text can't be trusted to match
+            match &mut self.dedup {
+                Some(dedup) if message.status == MessageStatus::Valid => {
+                    dedup.add(message, received);
                 }
+                _ => self.output_message(DeduplicatedMessage {
+                    message,
+                    received,
+                    receptions: 1,
+                }),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oxide_decoders::decoders::labels::LabelDecoderRegistry;
+    use std::net::SocketAddr;
+    use tokio::sync::mpsc;
+    use websocket::ClientFilter;
+
+    #[test]
+    fn test_sinks_share_the_message() -> Result<(), Box<dyn std::error::Error>> {
+        let (_sender, receiver) = mpsc::unbounded_channel();
+        let mut output = OxideOutput::new(false, false, false, None, receiver);
+        let recent_messages = RecentMessages::new(10);
+        let websocket = WebSocketSink::new(10);
+        output.set_recent_messages(recent_messages.clone());
+        output.set_websocket_sink(websocket.clone());
+        let mut client = websocket.add_client(
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+            ClientFilter::default(),
+        );
+
+        output.output_message(DeduplicatedMessage {
+            message: AssembledACARSMessage {
+                label: ['H', '1'],
+                ..AssembledACARSMessage::default()
+            },
+            received: SystemTime::now(),
+            receptions: 1,
+        });
+
+        let latest = recent_messages.latest(1);
+        assert_eq!(latest.len(), 1);
+        let streamed = client.try_recv()?;
+        assert!(streamed
+            .as_str()
+            .contains(&latest[0].message_information.message_uuid));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_uplink_ground_station() {
+        let (_sender, receiver) = mpsc::unbounded_channel();
+        let mut output = OxideOutput::new(false, false, false, None, receiver);
+        let recent_messages = RecentMessages::new(10);
+        output.set_recent_messages(recent_messages.clone());
+        let uplink = AssembledACARSMessage {
+            label: ['H', '1'],
+            frequency: 131.55,
+            downlink_status: DownlinkStatus::GroundToAir,
+            ..AssembledACARSMessage::default()
+        };
+        let now = SystemTime::now();
+
+        let mut squitter = AssembledACARSMessage {
+            label: ['S', 'Q'],
+            frequency: 131.55,
+            downlink_status: DownlinkStatus::GroundToAir,
+            message_text: Some("02XAABQKABQ13502N10637WV136975/ARINC".chars().collect()),
+            ..AssembledACARSMessage::default()
+        };
+        squitter.label_content = LabelDecoderRegistry::with_default_decoders().decode(&squitter);
+
+        for (message, received, icao) in [
+            // Nothing is known about the frequency yet
+            (uplink.clone(), now, None),
+            (squitter, now, Some("KABQ")),
+            (uplink.clone(), now, Some("KABQ")),
+            // Downlinks are sent by the aircraft
+            (
+                AssembledACARSMessage {
+                    downlink_status: DownlinkStatus::AirToGround,
+                    ..uplink.clone()
+                },
+                now,
+                None,
+            ),
+            // The station has not been heard for too long
+            (uplink, now + GROUND_STATION_TIMEOUT * 2, None),
+        ] {
+            output.output_message(DeduplicatedMessage {
+                message,
+                received,
+                receptions: 1,
+            });
+            let latest = recent_messages.latest(1);
+            assert_eq!(
+                latest[0].ground_station_ids.ground_station_icao.as_deref(),
+                icao
+            );
+        }
+        assert!(output.ground_stations().stations().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_held_messages_expire() -> Result<(), Box<dyn std::error::Error>> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut output = OxideOutput::new(
+            false,
+            false,
+            false,
+            Some(Duration::from_millis(200)),
+            receiver,
+        );
+        let (sink, mut stream) = mpsc::unbounded_channel();
+        output.set_message_sink(sink);
+        let output = tokio::spawn(async move { output.monitor_receiver_channel().await });
+
+        // The decoder keeps running, so the message comes out once its window has passed
+        sender.send(AssembledACARSMessage {
+            label: ['H', '1'],
+            ..AssembledACARSMessage::default()
+        })?;
+        let decoded = tokio::time::timeout(Duration::from_secs(5), stream.recv()).await?;
+        assert_eq!(
+            decoded.map(|decoded| decoded.message.label),
+            Some(['H', '1'])
+        );
+
+        drop(sender);
+        output.await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_capture_timestamps() {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut output =
+            OxideOutput::new(false, false, false, Some(Duration::from_secs(2)), receiver);
+        let (sink, mut stream) = mpsc::unbounded_channel();
+        let recent_messages = RecentMessages::new(10);
+        output.set_message_sink(sink);
+        output.set_recent_messages(recent_messages.clone());
+
+        // Decoded from a recording made long ago, much faster than it was recorded
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_296_200);
+        let message = |seconds: f64, signal_level: f32, text: &str| AssembledACARSMessage {
+            received: Some(start + Duration::from_secs_f64(seconds)),
+            label: ['H', '1'],
+            signal_level,
+            message_text: Some(text.chars().collect()),
+            ..AssembledACARSMessage::default()
+        };
+        for message in [
+            // The second copy is handed over first
+            message(0.5, -12.0, "POSN35286W108525"),
+            message(0.25, -30.0, "POSN35286W108525"),
+            message(1.0, -20.0, "REQPER,PRFE36"),
+            // Sent again after the window, by the clock of the recording
+            message(5.0, -20.0, "POSN35286W108525"),
+        ] {
+            assert!(sender.send(message).is_ok());
+        }
+        drop(sender);
+        output.monitor_receiver_channel().await;
+
+        let mut decoded = Vec::new();
+        while let Ok(message) = stream.try_recv() {
+            decoded.push((message.received, message.receptions));
+        }
+        let seconds = |seconds: f64| start + Duration::from_secs_f64(seconds);
+        assert_eq!(
+            decoded,
+            [(seconds(0.25), 2), (seconds(1.0), 1), (seconds(5.0), 1)]
+        );
+
+        // The JSON is stamped with the time of the recording, newest first
+        let timestamps: Vec<(u64, u32)> = recent_messages
+            .latest(10)
+            .iter()
+            .map(|json| {
+                let timestamp = &json.message_information.message_timestamp;
+                (timestamp.sec, timestamp.usec)
+            })
+            .collect();
+        assert_eq!(
+            timestamps,
+            [
+                (1_709_296_205, 0),
+                (1_709_296_201, 0),
+                (1_709_296_200, 250_000)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dedup_across_dongles() {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut output =
+            OxideOutput::new(false, false, false, Some(Duration::from_secs(60)), receiver);
+        let (sink, mut stream) = mpsc::unbounded_channel();
+        output.set_message_sink(sink);
+
+        let message = |signal_level: f32, text: &str| AssembledACARSMessage {
+            aircraft_tail: Some(['.', 'N', '9', '2', '3', 'U', 'S']),
+            label: ['H', '1'],
+            block_id: '7',
+            frequency: 131.55,
+            signal_level,
+            message_text: Some(text.chars().collect()),
+            ..AssembledACARSMessage::default()
+        };
+
+        // Each dongle has its own sender into the output channel
+        let first_dongle = sender.clone();
+        let second_dongle = sender;
+        let sent = [
+            (&first_dongle, message(-30.0, "POSN35286W108525")),
+            (&second_dongle, message(-12.0, "POSN35286W108525")),
+            (&first_dongle, message(-25.0, "REQPER,PRFE36")),
+            // Failed copies are output on their own
+            (
+                &second_dongle,
+                AssembledACARSMessage {
+                    status: MessageStatus::CrcFailed,
+                    ..message(-10.0, "POSN35286W108525")
+                },
+            ),
+        ];
+        for (dongle, message) in sent {
+            assert!(dongle.send(message).is_ok());
+        }
+        drop(first_dongle);
+        drop(second_dongle);
+
+        // The window is long, so the merged messages only come out when the decoders stop
+        output.monitor_receiver_channel().await;
+
+        let mut decoded = Vec::new();
+        while let Ok(message) = stream.try_recv() {
+            decoded.push(message);
+        }
+        let summary: Vec<(MessageStatus, u32, f32)> = decoded
+            .iter()
+            .map(|decoded| {
+                (
+                    decoded.message.status,
+                    decoded.receptions,
+                    decoded.message.signal_level,
+                )
+            })
+            .collect();
+        assert_eq!(summary.len(), 3);
+        assert_eq!(summary[0], (MessageStatus::CrcFailed, 1, -10.0));
+        assert!(summary[1..].contains(&(MessageStatus::Valid, 2, -12.0)));
+        assert!(summary[1..].contains(&(MessageStatus::Valid, 1, -25.0)));
+    }
+}
@@ -0,0 +1,596 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// Publishes every message, as JSON, to an MQTT broker. The topic of each message comes from a
+// template, filled in from the message:
+//
+//   {station}     the name of this receiving station
+//   {label}       the ACARS label
+//   {tail}        the aircraft registration
+//   {flight}      the flight number
+//   {frequency}   the frequency in MHz
+//
+// Values the message doesn't have are published as "unknown". The station's status is published,
+// retained, to the status topic: online once connected, and offline on shutdown or, through the
+// broker's last will, when the connection is lost.
+//
+// Publishing never waits on the broker. Messages are queued for the connection task, and are
+// dropped if the queue is full while the broker is away.
+
+use crate::json::OxideJsonMessage;
+use custom_error::custom_error;
+use rumqttc::{
+    AsyncClient, ClientError, Event, EventLoop, LastWill, MqttOptions, Outgoing, Packet, QoS,
+    Transport,
+};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const DEFAULT_MQTT_PORT: u16 = 1883;
+pub const DEFAULT_TOPIC: &str = "acars/{station}/{label}/{tail}";
+pub const DEFAULT_STATUS_TOPIC: &str = "acars/{station}/status";
+/// Messages queued for the broker by default
+pub const DEFAULT_MQTT_BUFFER: usize = 100;
+const DEFAULT_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+/// Largest packet sent or received. Messages with long free text are well under this
+const MAX_PACKET_SIZE: usize = 256 * 1024;
+const STATION: &str = "{station}";
+const LABEL: &str = "{label}";
+const TAIL: &str = "{tail}";
+const FLIGHT: &str = "{flight}";
+const FREQUENCY: &str = "{frequency}";
+const TOPIC_PLACEHOLDERS: [&str; 5] = [STATION, LABEL, TAIL, FLIGHT, FREQUENCY];
+const STATUS_TOPIC_PLACEHOLDERS: [&str; 1] = [STATION];
+/// Published for values a message doesn't have
+const UNKNOWN_VALUE: &str = "unknown";
+
+custom_error! {pub MqttError
+    InvalidTopic { topic: String } = "Invalid MQTT topic {topic}. Topics can't be empty or contain wildcards",
+    UnknownPlaceholder { topic: String } = "Unknown placeholder in MQTT topic {topic}",
+    InvalidQos { qos: u8 } = "Invalid MQTT QoS {qos}, must be 0, 1 or 2",
+    Certificate { path: String, source: std::io::Error } = "Unable to read {path}: {source}",
+    Publish { source: ClientError } = "Unable to publish to MQTT: {source}",
+}
+
+/// Where and how to publish the messages
+#[derive(Debug, Clone)]
+pub struct MqttConfig {
+    host: String,
+    port: u16,
+    station: String,
+    topic: String,
+    status_topic: String,
+    qos: u8,
+    credentials: Option<(String, String)>,
+    tls: bool,
+    ca_file: Option<PathBuf>,
+    client_auth: Option<(PathBuf, PathBuf)>,
+    buffer: usize,
+    reconnect_delay: Duration,
+}
+
+impl MqttConfig {
+    /// Publish to the broker on `host` and `port` as `station`, with the default topics, at most
+    /// once. The station name is part of the client ID, so it must be unique on the broker.
+    #[must_use]
+    pub fn new(host: impl Into<String>, port: u16, station: impl Into<String>) -> Self {
+        Self {
+            host: host.into(),
+            port,
+            station: station.into(),
+            topic: DEFAULT_TOPIC.to_string(),
+            status_topic: DEFAULT_STATUS_TOPIC.to_string(),
+            qos: 0,
+            credentials: None,
+            tls: false,
+            ca_file: None,
+            client_auth: None,
+            buffer: DEFAULT_MQTT_BUFFER,
+            reconnect_delay: DEFAULT_RECONNECT_DELAY,
+        }
+    }
+
+    /// Template of the topic each message is published to
+    #[must_use]
+    pub fn topic(mut self, topic: impl Into<String>) -> Self {
+        self.topic = topic.into();
+        self
+    }
+
+    /// Template of the topic the station's status is published to. Only `{station}` is filled in.
+    #[must_use]
+    pub fn status_topic(mut self, status_topic: impl Into<String>) -> Self {
+        self.status_topic = status_topic.into();
+        self
+    }
+
+    /// Quality of service of the messages, 0, 1 or 2. The status is always published at least
+    /// once.
+    #[must_use]
+    pub const fn qos(mut self, qos: u8) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    #[must_use]
+    pub fn credentials(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Connect over TLS. The broker's certificate is checked against `ca_file`, a PEM file, or
+    /// the system's root certificates if `None`.
+    #[must_use]
+    pub fn tls(mut self, tls: bool, ca_file: Option<PathBuf>) -> Self {
+        self.tls = tls;
+        self.ca_file = ca_file;
+        self
+    }
+
+    /// Authenticate with a client certificate and its private key, both PEM files. Only used
+    /// over TLS with a CA file.
+    #[must_use]
+    pub fn client_auth(mut self, certificate: PathBuf, key: PathBuf) -> Self {
+        self.client_auth = Some((certificate, key));
+        self
+    }
+
+    /// Messages queued while the broker is slow or away, before new ones are dropped
+    #[must_use]
+    pub fn buffer(mut self, buffer: usize) -> Self {
+        // The queue needs room for at least one message
+        self.buffer = buffer.max(1);
+        self
+    }
+
+    /// How long to wait before connecting again after losing the broker
+    #[must_use]
+    pub const fn reconnect_delay(mut self, reconnect_delay: Duration) -> Self {
+        self.reconnect_delay = reconnect_delay;
+        self
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct StationStatus<'a> {
+    station: &'a str,
+    state: &'a str,
+    decoder_name: &'a str,
+    decoder_version: &'a str,
+}
+
+fn status_payload(station: &str, online: bool) -> Vec<u8> {
+    serde_json::to_vec(&StationStatus {
+        station,
+        state: if online { "online" } else { "offline" },
+        decoder_name: "acars_oxide",
+        decoder_version: env!("CARGO_PKG_VERSION"),
+    })
+    .unwrap_or_default()
+}
+
+fn validate_topic(topic: &str, placeholders: &[&str]) -> Result<(), MqttError> {
+    let rest = placeholders
+        .iter()
+        .fold(topic.to_string(), |rest, placeholder| {
+            rest.replace(placeholder, "")
+        });
+
+    if rest.contains(['{', '}']) {
+        return Err(MqttError::UnknownPlaceholder {
+            topic: topic.to_string(),
+        });
+    }
+
+    if topic.is_empty() || rest.contains(['+', '#', '\0']) {
+        return Err(MqttError::InvalidTopic {
+            topic: topic.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+/// `value` made safe for a topic level
+fn topic_value(value: Option<&str>) -> String {
+    value
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map_or_else(
+            || UNKNOWN_VALUE.to_string(),
+            |value| value.replace(['/', '+', '#', '\0'], "_"),
+        )
+}
+
+fn render_topic(topic: &str, station: &str, message: &OxideJsonMessage) -> String {
+    // Frequencies are in Hz, and exact in an f64
+    #[allow(clippy::cast_precision_loss)]
+    let frequency = format!(
+        "{:.3}",
+        message.message_information.frequency as f64 / 1_000_000.0
+    );
+
+    topic
+        .replace(STATION, &topic_value(Some(station)))
+        .replace(LABEL, &topic_value(Some(&message.acars.label)))
+        .replace(
+            TAIL,
+            &topic_value(message.aircraft_ids.aircraft_registration.as_deref()),
+        )
+        .replace(
+            FLIGHT,
+            &topic_value(message.aircraft_ids.aircraft_callsign.as_deref()),
+        )
+        .replace(FREQUENCY, &frequency)
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, MqttError> {
+    std::fs::read(path).map_err(|source| MqttError::Certificate {
+        path: path.display().to_string(),
+        source,
+    })
+}
+
+/// Publishes the messages. Clones publish through the same connection.
+#[derive(Debug, Clone)]
+pub struct MqttSink {
+    client: AsyncClient,
+    station: String,
+    topic: String,
+    status_topic: String,
+    qos: QoS,
+}
+
+impl MqttSink {
+    /// Set up the sink and its connection. Nothing is published until the connection runs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a topic or the quality of service is invalid, or if a certificate can't be read.
+    pub fn new(config: MqttConfig) -> Result<(Self, MqttConnection), MqttError> {
+        validate_topic(&config.topic, &TOPIC_PLACEHOLDERS)?;
+        validate_topic(&config.status_topic, &STATUS_TOPIC_PLACEHOLDERS)?;
+        let qos =
+            rumqttc::qos(config.qos).map_err(|_| MqttError::InvalidQos { qos: config.qos })?;
+        let status_topic = config
+            .status_topic
+            .replace(STATION, &topic_value(Some(&config.station)));
+
+        let mut options = MqttOptions::new(
+            format!("acars-oxide-{}", topic_value(Some(&config.station))),
+            config.host.clone(),
+            config.port,
+        );
+        options
+            .set_keep_alive(KEEP_ALIVE)
+            .set_max_packet_size(MAX_PACKET_SIZE, MAX_PACKET_SIZE)
+            .set_request_channel_capacity(config.buffer)
+            .set_last_will(LastWill::new(
+                &status_topic,
+                status_payload(&config.station, false),
+                QoS::AtLeastOnce,
+                true,
+            ));
+
+        if let Some((username, password)) = &config.credentials {
+            options.set_credentials(username, password);
+        }
+
+        if config.tls {
+            let transport = match &config.ca_file {
+                Some(ca_file) => {
+                    let client_auth = match &config.client_auth {
+                        Some((certificate, key)) => {
+                            Some((read_file(certificate)?, read_file(key)?))
+                        }
+                        None => None,
+                    };
+                    Transport::tls(read_file(ca_file)?, client_auth, None)
+                }
+                None => Transport::tls_with_default_config(),
+            };
+            options.set_transport(transport);
+        }
+
+        let (client, eventloop) = AsyncClient::new(options, config.buffer);
+
+        Ok((
+            Self {
+                client: client.clone(),
+                station: config.station.clone(),
+                topic: config.topic,
+                status_topic: status_topic.clone(),
+                qos,
+            },
+            MqttConnection {
+                eventloop,
+                client,
+                broker: format!("{}:{}", config.host, config.port),
+                station: config.station,
+                status_topic,
+                reconnect_delay: config.reconnect_delay,
+            },
+        ))
+    }
+
+    /// Queue `message` for the broker. Dropped, after logging why, if the queue is full.
+    pub fn send(&self, message: &OxideJsonMessage) {
+        let payload = match message.to_json() {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("[MQTT SINK] Failed to serialize message: {e}");
+                return;
+            }
+        };
+
+        if let Err(e) = self.client.try_publish(
+            render_topic(&self.topic, &self.station, message),
+            self.qos,
+            false,
+            payload,
+        ) {
+            warn!("[MQTT SINK] Dropping message, the broker is not keeping up: {e}");
+        }
+    }
+
+    /// Publish that the station is offline and close the connection. The connection task
+    /// returns once it is closed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection task has already stopped.
+    pub async fn disconnect(&self) -> Result<(), MqttError> {
+        self.client
+            .publish(
+                &self.status_topic,
+                QoS::AtLeastOnce,
+                true,
+                status_payload(&self.station, false),
+            )
+            .await?;
+        self.client.disconnect().await?;
+        Ok(())
+    }
+}
+
+/// The connection to the broker. Has to run for anything to be published.
+pub struct MqttConnection {
+    eventloop: EventLoop,
+    client: AsyncClient,
+    broker: String,
+    station: String,
+    status_topic: String,
+    reconnect_delay: Duration,
+}
+
+impl MqttConnection {
+    /// Connect to the broker and publish the queued messages, connecting again whenever the
+    /// connection is lost. Returns once the sink disconnects, or every sink has been dropped.
+    pub async fn run(mut self) {
+        let mut disconnecting = false;
+
+        loop {
+            match self.eventloop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("[MQTT SINK] Connected to {}", self.broker);
+
+                    // Replaces the last will if the previous connection was lost
+                    if let Err(e) = self.client.try_publish(
+                        &self.status_topic,
+                        QoS::AtLeastOnce,
+                        true,
+                        status_payload(&self.station, true),
+                    ) {
+                        warn!("[MQTT SINK] Unable to publish the station status: {e}");
+                    }
+                }
+                Ok(Event::Outgoing(Outgoing::Disconnect)) => disconnecting = true,
+                Ok(_) => {}
+                Err(e) if disconnecting => {
+                    debug!("[MQTT SINK] Disconnected from {}: {e}", self.broker);
+                    return;
+                }
+                Err(rumqttc::ConnectionError::RequestsDone) => return,
+                Err(e) => {
+                    warn!(
+                        "[MQTT SINK] Connection to {} lost: {e}. Connecting again in {}s",
+                        self.broker,
+                        self.reconnect_delay.as_secs_f32()
+                    );
+                    tokio::time::sleep(self.reconnect_delay).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oxide_decoders::decoders::acars::AssembledACARSMessage;
+    use std::io;
+    use std::time::SystemTime;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    const CONNECT: u8 = 1;
+    const PUBLISH: u8 = 3;
+    const DISCONNECT: u8 = 14;
+
+    fn message(label: [char; 2], tail: Option<[char; 7]>) -> OxideJsonMessage {
+        OxideJsonMessage::new(
+            &AssembledACARSMessage {
+                label,
+                frequency: 131.55,
+                aircraft_tail: tail,
+                flight_id: Some(['U', 'A', '0', '1', '2', '3']),
+                ..AssembledACARSMessage::default()
+            },
+            SystemTime::UNIX_EPOCH,
+        )
+    }
+
+    /// Packet type and body of the next packet the client sends
+    async fn read_packet(stream: &mut TcpStream) -> io::Result<(u8, u8, Vec<u8>)> {
+        let header = stream.read_u8().await?;
+        let mut length = 0;
+        for shift in (0..28).step_by(7) {
+            let byte = stream.read_u8().await?;
+            length |= usize::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).await?;
+        Ok((header >> 4, header & 0x0f, body))
+    }
+
+    /// Topic, payload and retain flag of the next packet, which has to be a publish. Publishes
+    /// sent at least once are acknowledged.
+    async fn read_publish(stream: &mut TcpStream) -> io::Result<(String, String, bool)> {
+        let (packet_type, flags, body) = read_packet(stream).await?;
+        assert_eq!(packet_type, PUBLISH);
+
+        let topic_length = usize::from(u16::from_be_bytes([body[0], body[1]]));
+        let topic = String::from_utf8_lossy(&body[2..2 + topic_length]).into_owned();
+        let mut payload = &body[2 + topic_length..];
+        if flags & 0x06 != 0 {
+            stream
+                .write_all(&[0x40, 0x02, payload[0], payload[1]])
+                .await?;
+            payload = &payload[2..];
+        }
+
+        Ok((
+            topic,
+            String::from_utf8_lossy(payload).into_owned(),
+            flags & 0x01 != 0,
+        ))
+    }
+
+    /// Accept the next connection and answer its CONNECT
+    async fn accept(listener: &TcpListener) -> io::Result<(TcpStream, String)> {
+        let (mut stream, _) = listener.accept().await?;
+        let (packet_type, _, body) = read_packet(&mut stream).await?;
+        assert_eq!(packet_type, CONNECT);
+        stream.write_all(&[0x20, 0x02, 0x00, 0x00]).await?;
+        Ok((stream, String::from_utf8_lossy(&body).into_owned()))
+    }
+
+    #[test]
+    fn test_topics() {
+        let registration = Some(['.', 'N', '1', '2', '3', 'A', 'B']);
+        let h1 = message(['H', '1'], registration);
+
+        assert_eq!(
+            render_topic(DEFAULT_TOPIC, "kabq", &h1),
+            "acars/kabq/H1/N123AB"
+        );
+        assert_eq!(
+            render_topic(
+                "{frequency}/{flight}/{tail}",
+                "kabq",
+                &message(['_', 'd'], None)
+            ),
+            "131.550/UA0123/unknown"
+        );
+        // Values can't add levels or wildcards to the topic
+        assert_eq!(
+            render_topic(
+                "acars/{station}/{label}",
+                "home/#1",
+                &message(['+', '/'], registration)
+            ),
+            "acars/home__1/__"
+        );
+
+        assert!(validate_topic(DEFAULT_TOPIC, &TOPIC_PLACEHOLDERS).is_ok());
+        assert!(validate_topic(DEFAULT_STATUS_TOPIC, &STATUS_TOPIC_PLACEHOLDERS).is_ok());
+        assert!(matches!(
+            validate_topic("acars/{station}/{label}", &STATUS_TOPIC_PLACEHOLDERS),
+            Err(MqttError::UnknownPlaceholder { .. })
+        ));
+        assert!(matches!(
+            validate_topic("acars/+/{label}", &TOPIC_PLACEHOLDERS),
+            Err(MqttError::InvalidTopic { .. })
+        ));
+        assert!(matches!(
+            validate_topic("", &TOPIC_PLACEHOLDERS),
+            Err(MqttError::InvalidTopic { .. })
+        ));
+        assert!(matches!(
+            MqttSink::new(MqttConfig::new("localhost", DEFAULT_MQTT_PORT, "kabq").qos(3)),
+            Err(MqttError::InvalidQos { qos: 3 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_publish() -> Result<(), Box<dyn std::error::Error>> {
+        // Stands in for the broker
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+
+        let (sink, connection) = MqttSink::new(
+            MqttConfig::new("127.0.0.1", port, "kabq")
+                .credentials("acars", "secret")
+                .reconnect_delay(Duration::from_millis(10)),
+        )?;
+        let connection = tokio::spawn(connection.run());
+
+        let (mut stream, connect) = accept(&listener).await?;
+        assert!(connect.contains("acars-oxide-kabq"));
+        assert!(connect.contains("acars/kabq/status"), "{connect}");
+        assert!(connect.contains(r#""state":"offline""#), "{connect}");
+        assert!(connect.contains("secret"));
+
+        let (topic, payload, retain) = read_publish(&mut stream).await?;
+        assert_eq!(topic, "acars/kabq/status");
+        assert!(payload.contains(r#""state":"online""#), "{payload}");
+        assert!(retain);
+
+        sink.send(&message(
+            ['H', '1'],
+            Some(['.', 'N', '1', '2', '3', 'A', 'B']),
+        ));
+        let (topic, payload, retain) = read_publish(&mut stream).await?;
+        assert_eq!(topic, "acars/kabq/H1/N123AB");
+        assert!(payload.contains(r#""label":"H1""#), "{payload}");
+        assert!(!retain);
+
+        // The broker goes away. The sink connects again and says it is still online
+        drop(stream);
+        let (mut stream, _) = accept(&listener).await?;
+        let (topic, payload, _) = read_publish(&mut stream).await?;
+        assert_eq!(topic, "acars/kabq/status");
+        assert!(payload.contains(r#""state":"online""#), "{payload}");
+
+        sink.disconnect().await?;
+        let (topic, payload, retain) = read_publish(&mut stream).await?;
+        assert_eq!(topic, "acars/kabq/status");
+        assert!(payload.contains(r#""state":"offline""#), "{payload}");
+        assert!(retain);
+        let (packet_type, _, _) = read_packet(&mut stream).await?;
+        assert_eq!(packet_type, DISCONNECT);
+
+        drop(stream);
+        tokio::time::timeout(Duration::from_secs(5), connection).await??;
+        Ok(())
+    }
+}
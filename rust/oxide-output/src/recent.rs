@@ -0,0 +1,122 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// The last messages output, kept for the HTTP API to show. Oldest messages are dropped once the
+// buffer is full. Messages are also broadcast as they are pushed, for the web UI's live feed.
+
+use crate::json::OxideJsonMessage;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+/// Messages kept by default
+pub const DEFAULT_RECENT_MESSAGES: usize = 100;
+
+/// Shared buffer of the last messages. Clones share the same buffer.
+#[derive(Debug, Clone)]
+pub struct RecentMessages {
+    messages: Arc<Mutex<VecDeque<OxideJsonMessage>>>,
+    capacity: usize,
+    live: broadcast::Sender<OxideJsonMessage>,
+}
+
+impl Default for RecentMessages {
+    fn default() -> Self {
+        Self::new(DEFAULT_RECENT_MESSAGES)
+    }
+}
+
+impl RecentMessages {
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        // A broadcast channel needs room for at least one message
+        let (live, _) = broadcast::channel(capacity.max(1));
+
+        Self {
+            messages: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+            live,
+        }
+    }
+
+    pub fn push(&self, message: OxideJsonMessage) {
+        // Nobody listening is not an error
+        let _ = self.live.send(message.clone());
+
+        if let Ok(mut messages) = self.messages.lock() {
+            if messages.len() >= self.capacity {
+                messages.pop_front();
+            }
+            messages.push_back(message);
+        }
+    }
+
+    /// Up to `limit` of the last messages, newest first
+    #[must_use]
+    pub fn latest(&self, limit: usize) -> Vec<OxideJsonMessage> {
+        self.messages
+            .lock()
+            .map(|messages| messages.iter().rev().take(limit).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Receive the messages pushed from now on. Receivers that fall more than the capacity
+    /// behind skip the messages they missed.
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<OxideJsonMessage> {
+        self.live.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oxide_decoders::decoders::acars::AssembledACARSMessage;
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_recent_messages() {
+        let recent = RecentMessages::new(2);
+        let mut live = recent.subscribe();
+        for label in [['H', '1'], ['Q', '0'], ['_', 'd']] {
+            let message = AssembledACARSMessage {
+                label,
+                ..AssembledACARSMessage::default()
+            };
+            recent
+                .clone()
+                .push(OxideJsonMessage::new(&message, SystemTime::UNIX_EPOCH));
+        }
+
+        let labels: Vec<String> = recent
+            .latest(5)
+            .into_iter()
+            .map(|message| message.acars.label)
+            .collect();
+        assert_eq!(labels, ["_d", "Q0"]);
+        assert_eq!(recent.latest(1).len(), 1);
+
+        // The first message was pushed out of the live feed too
+        assert!(matches!(
+            live.try_recv(),
+            Err(broadcast::error::TryRecvError::Lagged(1))
+        ));
+        assert_eq!(
+            live.try_recv().map(|message| message.acars.label),
+            Ok("Q0".to_string())
+        );
+    }
+}
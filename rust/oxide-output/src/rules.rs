@@ -0,0 +1,417 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// Per sink message filtering. Each sink has an ordered list of rules and a default action. The
+// first rule that matches a message decides if the sink gets it, and messages no rule matches
+// get the default action. A rule matches when all of its conditions match, and list conditions
+// match if any of the values does. Sinks without rules get every message.
+//
+//   [sinks.json]
+//   default = "include"
+//
+//   [[sinks.json.rules]]
+//   action = "exclude"
+//   label = ["_d", "SQ"]
+//
+//   [sinks.console]
+//   default = "exclude"
+//
+//   [[sinks.console.rules]]
+//   action = "include"
+//   label = ["H1"]
+//   text = "^POS"
+
+use custom_error::custom_error;
+use oxide_decoders::decoders::acars::{AssembledACARSMessage, DownlinkStatus};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+pub const SINK_CONSOLE: &str = "console";
+pub const SINK_JSON: &str = "json";
+pub const SINK_ZMQ: &str = "zmq";
+/// Messages handed to an embedding application through the scanner's message stream
+pub const SINK_STREAM: &str = "stream";
+/// Messages listed by the HTTP API
+pub const SINK_API: &str = "api";
+/// Messages pushed to the WebSocket clients
+pub const SINK_WEBSOCKET: &str = "websocket";
+/// Messages published to the MQTT broker
+pub const SINK_MQTT: &str = "mqtt";
+const KNOWN_SINKS: [&str; 7] = [
+    SINK_CONSOLE,
+    SINK_JSON,
+    SINK_ZMQ,
+    SINK_STREAM,
+    SINK_API,
+    SINK_WEBSOCKET,
+    SINK_MQTT,
+];
+/// Frequencies closer than this, in MHz, are the same frequency
+const FREQUENCY_TOLERANCE: f32 = 0.0005;
+
+custom_error! {pub RulesError
+    Io { source: std::io::Error } = "Unable to read the rules file: {source}",
+    Toml { source: toml::de::Error } = "Unable to parse the rules file: {source}",
+    Regex { source: regex::Error } = "Invalid text pattern in the rules file: {source}",
+    UnknownSink { sink: String } = "Unknown sink {sink} in the rules file",
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleAction {
+    #[default]
+    Include,
+    Exclude,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RuleDownlinkStatus {
+    AirToGround,
+    GroundToAir,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleConfig {
+    action: RuleAction,
+    #[serde(default)]
+    label: Vec<String>,
+    #[serde(default)]
+    sublabel: Vec<String>,
+    #[serde(default)]
+    tail: Vec<String>,
+    #[serde(default)]
+    flight: Vec<String>,
+    /// Frequencies in MHz
+    #[serde(default)]
+    frequency: Vec<f32>,
+    downlink_status: Option<RuleDownlinkStatus>,
+    min_signal_level: Option<f32>,
+    max_signal_level: Option<f32>,
+    /// Parity errors plus bits fixed by error correction
+    max_errors: Option<u16>,
+    /// Regular expression matched against the message text
+    text: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SinkConfig {
+    #[serde(default)]
+    default: RuleAction,
+    #[serde(default)]
+    rules: Vec<RuleConfig>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesConfig {
+    #[serde(default)]
+    sinks: HashMap<String, SinkConfig>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    action: RuleAction,
+    label: Vec<String>,
+    sublabel: Vec<String>,
+    tail: Vec<String>,
+    flight: Vec<String>,
+    frequency: Vec<f32>,
+    downlink_status: Option<DownlinkStatus>,
+    min_signal_level: Option<f32>,
+    max_signal_level: Option<f32>,
+    max_errors: Option<u16>,
+    text: Option<Regex>,
+}
+
+fn to_string(chars: &[char]) -> String {
+    let output: String = chars.iter().collect();
+    output.trim().trim_start_matches('.').to_string()
+}
+
+/// A list condition matches if it is empty, or the value is one of its entries
+fn matches_any(values: &[String], value: Option<String>) -> bool {
+    values.is_empty()
+        || value.map_or(false, |value| {
+            values
+                .iter()
+                .any(|candidate| candidate.eq_ignore_ascii_case(&value))
+        })
+}
+
+impl Rule {
+    fn new(config: RuleConfig) -> Result<Self, RulesError> {
+        Ok(Self {
+            action: config.action,
+            label: config.label,
+            sublabel: config.sublabel,
+            tail: config.tail,
+            flight: config.flight,
+            frequency: config.frequency,
+            downlink_status: config.downlink_status.map(|status| match status {
+                RuleDownlinkStatus::AirToGround => DownlinkStatus::AirToGround,
+                RuleDownlinkStatus::GroundToAir => DownlinkStatus::GroundToAir,
+            }),
+            min_signal_level: config.min_signal_level,
+            max_signal_level: config.max_signal_level,
+            max_errors: config.max_errors,
+            text: config.text.as_deref().map(Regex::new).transpose()?,
+        })
+    }
+
+    fn matches(&self, message: &AssembledACARSMessage) -> bool {
+        let errors = u16::from(message.parity_errors) + u16::from(message.corrected_bits);
+
+        matches_any(&self.label, Some(message.label.iter().collect()))
+            && matches_any(
+                &self.sublabel,
+                message
+                    .sublabel
+                    .as_ref()
+                    .map(|sublabel| to_string(sublabel)),
+            )
+            && matches_any(
+                &self.tail,
+                message.aircraft_tail.as_ref().map(|tail| to_string(tail)),
+            )
+            && matches_any(
+                &self.flight,
+                message.flight_id.as_ref().map(|flight| to_string(flight)),
+            )
+            && (self.frequency.is_empty()
+                || self
+                    .frequency
+                    .iter()
+                    .any(|frequency| (frequency - message.frequency).abs() < FREQUENCY_TOLERANCE))
+            && self
+                .downlink_status
+                .as_ref()
+                .map_or(true, |status| *status == message.downlink_status)
+            && self
+                .min_signal_level
+                .map_or(true, |min| message.signal_level >= min)
+            && self
+                .max_signal_level
+                .map_or(true, |max| message.signal_level <= max)
+            && self.max_errors.map_or(true, |max| errors <= max)
+            && self.text.as_ref().map_or(true, |pattern| {
+                message.message_text.as_ref().map_or(false, |text| {
+                    pattern.is_match(&text.iter().collect::<String>())
+                })
+            })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct SinkRules {
+    default: RuleAction,
+    rules: Vec<Rule>,
+}
+
+/// The filtering rules of all sinks
+#[derive(Debug, Clone, Default)]
+pub struct RuleSet {
+    sinks: HashMap<String, SinkRules>,
+}
+
+impl RuleSet {
+    /// Rules that let every message through to every sink
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse the rules from TOML.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the TOML is invalid, a text pattern is not a valid regular
+    /// expression, or a sink is unknown.
+    pub fn from_toml(text: &str) -> Result<Self, RulesError> {
+        let config: RulesConfig = toml::from_str(text)?;
+        let mut sinks = HashMap::new();
+
+        for (sink, sink_config) in config.sinks {
+            if !KNOWN_SINKS.contains(&sink.as_str()) {
+                return Err(RulesError::UnknownSink { sink });
+            }
+
+            let rules = sink_config
+                .rules
+                .into_iter()
+                .map(Rule::new)
+                .collect::<Result<Vec<_>, _>>()?;
+
+            sinks.insert(
+                sink,
+                SinkRules {
+                    default: sink_config.default,
+                    rules,
+                },
+            );
+        }
+
+        Ok(Self { sinks })
+    }
+
+    /// Read the rules from a TOML file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or the rules are invalid.
+    pub fn from_file(path: &Path) -> Result<Self, RulesError> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    /// Should `sink` get the message
+    #[must_use]
+    pub fn allows(&self, sink: &str, message: &AssembledACARSMessage) -> bool {
+        let Some(sink_rules) = self.sinks.get(sink) else {
+            return true;
+        };
+
+        let action = sink_rules
+            .rules
+            .iter()
+            .find(|rule| rule.matches(message))
+            .map_or(sink_rules.default, |rule| rule.action);
+
+        action == RuleAction::Include
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(label: &str, text: &str) -> AssembledACARSMessage {
+        let label: Vec<char> = label.chars().collect();
+        AssembledACARSMessage {
+            label: [label[0], label[1]],
+            aircraft_tail: Some(['N', '9', '2', '3', 'U', 'S', ' ']),
+            flight_id: Some(['A', 'A', '1', '0', '3', '1']),
+            frequency: 130.025,
+            signal_level: -27.6,
+            message_text: (!text.is_empty()).then(|| text.chars().collect()),
+            ..Default::default()
+        }
+    }
+
+    fn rules(text: &str) -> RuleSet {
+        match RuleSet::from_toml(text) {
+            Ok(rules) => rules,
+            Err(e) => panic!("failed to parse the rules: {e}"),
+        }
+    }
+
+    #[test]
+    fn test_exclude_noise() {
+        let rules = rules(
+            r#"
+            [[sinks.json.rules]]
+            action = "exclude"
+            label = ["_d", "SQ"]
+            "#,
+        );
+
+        assert!(!rules.allows(SINK_JSON, &message("_d", "")));
+        assert!(!rules.allows(SINK_JSON, &message("SQ", "02XAABQKABQ")));
+        assert!(rules.allows(SINK_JSON, &message("H1", "POSN35286W108525")));
+        // Sinks without rules get everything
+        assert!(rules.allows(SINK_CONSOLE, &message("_d", "")));
+    }
+
+    #[test]
+    fn test_include_only() {
+        let rules = rules(
+            r#"
+            [sinks.console]
+            default = "exclude"
+
+            [[sinks.console.rules]]
+            action = "include"
+            label = ["H1"]
+            text = "^POS"
+            tail = ["N923US"]
+            flight = ["AA1031"]
+            frequency = [130.025]
+            downlink_status = "air_to_ground"
+            min_signal_level = -30.0
+            max_errors = 0
+            "#,
+        );
+
+        assert!(rules.allows(SINK_CONSOLE, &message("H1", "POSN35286W108525")));
+        assert!(!rules.allows(SINK_CONSOLE, &message("H1", "REQPER,PRFE36")));
+        assert!(!rules.allows(SINK_CONSOLE, &message("Q0", "POSN35286W108525")));
+
+        let mut weak = message("H1", "POSN35286W108525");
+        weak.signal_level = -35.0;
+        assert!(!rules.allows(SINK_CONSOLE, &weak));
+
+        let mut corrected = message("H1", "POSN35286W108525");
+        corrected.corrected_bits = 1;
+        assert!(!rules.allows(SINK_CONSOLE, &corrected));
+
+        let mut uplink = message("H1", "POSN35286W108525");
+        uplink.downlink_status = DownlinkStatus::GroundToAir;
+        assert!(!rules.allows(SINK_CONSOLE, &uplink));
+    }
+
+    #[test]
+    fn test_first_match_wins() {
+        let rules = rules(
+            r#"
+            [sinks.zmq]
+            default = "exclude"
+
+            [[sinks.zmq.rules]]
+            action = "exclude"
+            sublabel = ["DF"]
+
+            [[sinks.zmq.rules]]
+            action = "include"
+            label = ["H1"]
+            "#,
+        );
+
+        let mut message = message("H1", "A38/A32138");
+        assert!(rules.allows(SINK_ZMQ, &message));
+
+        message.sublabel = Some(['D', 'F']);
+        assert!(!rules.allows(SINK_ZMQ, &message));
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        assert!(matches!(
+            RuleSet::from_toml("[sinks.web]\ndefault = \"exclude\""),
+            Err(RulesError::UnknownSink { .. })
+        ));
+        assert!(matches!(
+            RuleSet::from_toml("[[sinks.json.rules]]\naction = \"exclude\"\ntext = \"(\""),
+            Err(RulesError::Regex { .. })
+        ));
+        assert!(matches!(
+            RuleSet::from_toml("[[sinks.json.rules]]\naction = \"exclude\"\nlabels = [\"H1\"]"),
+            Err(RulesError::Toml { .. })
+        ));
+    }
+}
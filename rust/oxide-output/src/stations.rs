@@ -0,0 +1,226 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// Table of the ground stations heard, from their squitters. The same station can be heard on
+// several frequencies, so stations are tracked per frequency. Stations that stop sending
+// squitters are dropped after a while, so a retuned receiver doesn't keep listing them.
+
+use oxide_decoders::decoders::acars::{AssembledACARSMessage, MessageStatus};
+use oxide_decoders::decoders::labels::LabelContent;
+use oxide_decoders::decoders::squitter::GroundStation;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Stations are dropped once no squitter has been heard from them for this long. Squitters are
+/// sent every few minutes.
+pub const GROUND_STATION_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// The stations heard, published by the output for other tasks to read
+pub type SharedGroundStations = Arc<Mutex<Vec<HeardGroundStation>>>;
+
+/// A ground station heard on a frequency
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeardGroundStation {
+    pub station: GroundStation,
+    /// Frequency the station was heard on, in MHz
+    pub frequency: f32,
+    pub last_heard: SystemTime,
+    /// Signal level of the last squitter heard
+    pub signal_level: f32,
+    /// Number of squitters heard
+    pub squitters: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GroundStationTable {
+    /// Keyed on the frequency in kHz and the station identity
+    stations: HashMap<(u32, String), HeardGroundStation>,
+}
+
+impl GroundStationTable {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the squitter in `message`, if it is one. Returns the station if this is the
+    /// first time it was heard on the frequency.
+    pub fn update(
+        &mut self,
+        message: &AssembledACARSMessage,
+        now: SystemTime,
+    ) -> Option<&HeardGroundStation> {
+        let Some(LabelContent::GroundStation(station)) = &message.label_content else {
+            return None;
+        };
+
+        // Squitters that failed the CRC would fill the table with stations that do not exist
+        if message.status != MessageStatus::Valid {
+            return None;
+        }
+
+        // Frequencies are positive and well within range
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let frequency_khz = (message.frequency * 1000.0).round() as u32;
+        let identity = format!(
+            "{}/{}/{}",
+            station.network,
+            station.icao.as_deref().unwrap_or_default(),
+            station.station_number.unwrap_or_default()
+        );
+        let mut new_station = false;
+
+        let heard = self
+            .stations
+            .entry((frequency_khz, identity))
+            .and_modify(|heard| {
+                heard.station = station.clone();
+                heard.last_heard = now;
+                heard.signal_level = message.signal_level;
+                heard.squitters += 1;
+            })
+            .or_insert_with(|| {
+                new_station = true;
+                HeardGroundStation {
+                    station: station.clone(),
+                    frequency: message.frequency,
+                    last_heard: now,
+                    signal_level: message.signal_level,
+                    squitters: 1,
+                }
+            });
+
+        new_station.then_some(heard)
+    }
+
+    /// All stations heard, ordered by frequency
+    #[must_use]
+    pub fn stations(&self) -> Vec<&HeardGroundStation> {
+        let mut stations: Vec<&HeardGroundStation> = self.stations.values().collect();
+        stations.sort_by(|a, b| {
+            a.frequency
+                .total_cmp(&b.frequency)
+                .then_with(|| a.station.icao.cmp(&b.station.icao))
+        });
+        stations
+    }
+
+    /// Stations heard on `frequency`, in MHz
+    #[must_use]
+    pub fn stations_on_frequency(&self, frequency: f32) -> Vec<&HeardGroundStation> {
+        self.stations()
+            .into_iter()
+            .filter(|heard| (heard.frequency - frequency).abs() < 0.0005)
+            .collect()
+    }
+
+    /// The station sending on `frequency`, in MHz, if exactly one station was heard on it
+    #[must_use]
+    pub fn station_on_frequency(&self, frequency: f32) -> Option<&HeardGroundStation> {
+        match self.stations_on_frequency(frequency).as_slice() {
+            [heard] => Some(heard),
+            _ => None,
+        }
+    }
+
+    /// Drop the stations not heard for longer than `max_age`. Returns the number dropped.
+    pub fn prune(&mut self, now: SystemTime, max_age: Duration) -> usize {
+        let before = self.stations.len();
+        self.stations.retain(|_, heard| {
+            now.duration_since(heard.last_heard)
+                .map_or(true, |age| age <= max_age)
+        });
+        before - self.stations.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oxide_decoders::decoders::acars::DownlinkStatus;
+    use oxide_decoders::decoders::labels::LabelDecoderRegistry;
+    use std::time::Duration;
+
+    fn squitter(frequency: f32, signal_level: f32, text: &str) -> AssembledACARSMessage {
+        let mut message = AssembledACARSMessage {
+            label: ['S', 'Q'],
+            frequency,
+            signal_level,
+            downlink_status: DownlinkStatus::GroundToAir,
+            message_text: Some(text.chars().collect()),
+            ..Default::default()
+        };
+        message.label_content = LabelDecoderRegistry::with_default_decoders().decode(&message);
+        message
+    }
+
+    #[test]
+    fn test_ground_station_table() {
+        let mut table = GroundStationTable::new();
+        let start = SystemTime::UNIX_EPOCH;
+        let abq = "02XAABQKABQ13502N10637WV136975/ARINC";
+
+        assert!(table.update(&squitter(131.55, -20.0, abq), start).is_some());
+        assert!(table
+            .update(
+                &squitter(131.55, -18.0, abq),
+                start + Duration::from_secs(60)
+            )
+            .is_none());
+        assert!(table
+            .update(&squitter(130.025, -25.0, abq), start)
+            .is_some());
+        assert!(table
+            .update(&squitter(130.025, -25.0, "NOT A SQUITTER"), start)
+            .is_none());
+
+        let stations = table.stations();
+        assert_eq!(stations.len(), 2);
+        assert!((stations[0].frequency - 130.025).abs() < f32::EPSILON);
+
+        let on_frequency = table.stations_on_frequency(131.55);
+        assert_eq!(on_frequency.len(), 1);
+        assert_eq!(on_frequency[0].squitters, 2);
+        assert_eq!(on_frequency[0].last_heard, start + Duration::from_secs(60));
+        assert!((on_frequency[0].signal_level + 18.0).abs() < f32::EPSILON);
+        assert!(table.station_on_frequency(131.55).is_some());
+        assert!(table.station_on_frequency(131.725).is_none());
+
+        // A second station on the frequency makes it ambiguous
+        let dfw = "02XADFWKDFW13252N09702WV136975/ARINC";
+        assert!(table
+            .update(
+                &squitter(131.55, -30.0, dfw),
+                start + Duration::from_secs(61)
+            )
+            .is_some());
+        assert!(table.station_on_frequency(131.55).is_none());
+
+        // The stations only heard at the start are dropped, the ones heard later are kept
+        assert_eq!(
+            table.prune(start + Duration::from_secs(90), Duration::from_secs(60)),
+            1
+        );
+        assert_eq!(table.stations_on_frequency(130.025).len(), 0);
+        assert_eq!(table.stations_on_frequency(131.55).len(), 2);
+        assert_eq!(
+            table.prune(start + Duration::from_secs(200), Duration::from_secs(60)),
+            2
+        );
+        assert!(table.stations().is_empty());
+    }
+}
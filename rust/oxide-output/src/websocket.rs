@@ -0,0 +1,437 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// Pushes every message, as JSON, to the connected WebSocket clients. Clients can ask for only
+// some messages when connecting. Each parameter takes a comma separated list, and a message is
+// sent if it matches any value of every parameter given:
+//
+//   ws://host:port/?label=H1,SA&frequency=131.55&tail=N123AB
+//
+// Every client has a bounded buffer. A client that falls that many messages behind is dropped,
+// so one slow client never holds up the output.
+
+use crate::json::OxideJsonMessage;
+use axum::extract::ws::{close_code, CloseFrame, Message, Utf8Bytes, WebSocket, WebSocketUpgrade};
+use axum::extract::{ConnectInfo, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use custom_error::custom_error;
+use serde::Deserialize;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+
+/// Messages a client can fall behind by default
+pub const DEFAULT_CLIENT_BUFFER: usize = 100;
+/// Frequencies closer than this, in MHz, are the same frequency
+const FREQUENCY_TOLERANCE: f64 = 0.0005;
+/// How long a closing client gets to take the close frame
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+custom_error! {pub WebSocketError
+    InvalidFrequency { frequency: String } = "Invalid frequency {frequency}",
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FilterQuery {
+    label: Option<String>,
+    frequency: Option<String>,
+    tail: Option<String>,
+}
+
+/// The messages a client asked for. Empty lists match every message.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct ClientFilter {
+    labels: Vec<String>,
+    /// MHz
+    frequencies: Vec<f64>,
+    /// Upper case
+    tails: Vec<String>,
+}
+
+fn split(values: Option<&str>) -> impl Iterator<Item = &str> {
+    values
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+impl ClientFilter {
+    fn new(query: &FilterQuery) -> Result<Self, WebSocketError> {
+        let frequencies = split(query.frequency.as_deref())
+            .map(|frequency| {
+                frequency
+                    .parse()
+                    .map_err(|_| WebSocketError::InvalidFrequency {
+                        frequency: frequency.to_string(),
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            labels: split(query.label.as_deref()).map(str::to_string).collect(),
+            frequencies,
+            tails: split(query.tail.as_deref())
+                .map(|tail| tail.trim_start_matches('.').to_uppercase())
+                .collect(),
+        })
+    }
+
+    fn matches(&self, message: &OxideJsonMessage) -> bool {
+        // Frequencies are in Hz, and exact in an f64
+        #[allow(clippy::cast_precision_loss)]
+        let frequency = message.message_information.frequency as f64 / 1_000_000.0;
+        let tail = message
+            .aircraft_ids
+            .aircraft_registration
+            .as_deref()
+            .map(str::to_uppercase);
+
+        (self.labels.is_empty() || self.labels.contains(&message.acars.label))
+            && (self.frequencies.is_empty()
+                || self
+                    .frequencies
+                    .iter()
+                    .any(|wanted| (wanted - frequency).abs() < FREQUENCY_TOLERANCE))
+            && (self.tails.is_empty() || tail.map_or(false, |tail| self.tails.contains(&tail)))
+    }
+}
+
+#[derive(Debug)]
+struct Client {
+    address: SocketAddr,
+    filter: ClientFilter,
+    messages: Sender<Utf8Bytes>,
+}
+
+/// The connected clients. Clones share the same clients.
+#[derive(Debug, Clone)]
+pub struct WebSocketSink {
+    clients: Arc<Mutex<Vec<Client>>>,
+    buffer: usize,
+}
+
+impl Default for WebSocketSink {
+    fn default() -> Self {
+        Self::new(DEFAULT_CLIENT_BUFFER)
+    }
+}
+
+impl WebSocketSink {
+    /// A sink that drops clients once they fall `buffer` messages behind
+    #[must_use]
+    pub fn new(buffer: usize) -> Self {
+        Self {
+            clients: Arc::new(Mutex::new(vec![])),
+            // A channel needs room for at least one message
+            buffer: buffer.max(1),
+        }
+    }
+
+    /// Number of clients connected
+    #[must_use]
+    pub fn clients(&self) -> usize {
+        self.clients.lock().map_or(0, |clients| clients.len())
+    }
+
+    pub(crate) fn add_client(
+        &self,
+        address: SocketAddr,
+        filter: ClientFilter,
+    ) -> Receiver<Utf8Bytes> {
+        let (messages, receiver) = mpsc::channel(self.buffer);
+
+        if let Ok(mut clients) = self.clients.lock() {
+            clients.push(Client {
+                address,
+                filter,
+                messages,
+            });
+        }
+
+        receiver
+    }
+
+    /// Queue `message` for every client that asked for it, dropping the clients that have gone
+    /// or are too far behind.
+    pub fn send(&self, message: &OxideJsonMessage) {
+        let Ok(mut clients) = self.clients.lock() else {
+            return;
+        };
+
+        if clients.is_empty() {
+            return;
+        }
+
+        let text = match message.to_json() {
+            Ok(text) => Utf8Bytes::from(text),
+            Err(e) => {
+                error!("[WEBSOCKET SINK] Failed to serialize message: {e}");
+                return;
+            }
+        };
+
+        clients.retain(|client| {
+            if !client.filter.matches(message) {
+                return !client.messages.is_closed();
+            }
+
+            match client.messages.try_send(text.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!(
+                        "[WEBSOCKET SINK] Dropping {}, it fell {} messages behind",
+                        client.address, self.buffer
+                    );
+                    false
+                }
+                Err(TrySendError::Closed(_)) => false,
+            }
+        });
+    }
+
+    /// Accept clients on `listener` until the task is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if accepting connections fails.
+    pub async fn serve(self, listener: TcpListener) -> io::Result<()> {
+        if let Ok(address) = listener.local_addr() {
+            info!("[WEBSOCKET SINK] Streaming messages on ws://{address}/");
+        }
+
+        let router = Router::new().route("/", get(connect)).with_state(self);
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+    }
+}
+
+async fn connect(
+    State(sink): State<WebSocketSink>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Query(query): Query<FilterQuery>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let filter = match ClientFilter::new(&query) {
+        Ok(filter) => filter,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    upgrade.on_upgrade(move |socket| async move {
+        debug!("[WEBSOCKET SINK] {address} connected, {filter:?}");
+        let messages = sink.add_client(address, filter);
+        stream(socket, messages).await;
+        debug!("[WEBSOCKET SINK] {address} disconnected");
+    })
+}
+
+/// Send the client's messages until it goes away, or the sink drops it
+async fn stream(mut socket: WebSocket, mut messages: Receiver<Utf8Bytes>) {
+    loop {
+        tokio::select! {
+            message = messages.recv() => {
+                let Some(text) = message else {
+                    let close = Message::Close(Some(CloseFrame {
+                        code: close_code::AGAIN,
+                        reason: Utf8Bytes::from_static("Too far behind"),
+                    }));
+                    let _ = tokio::time::timeout(CLOSE_TIMEOUT, socket.send(close)).await;
+                    return;
+                };
+
+                if socket.send(Message::Text(text)).await.is_err() {
+                    return;
+                }
+            }
+            // Nothing is expected from the client, only that it closes
+            received = socket.recv() => match received {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oxide_decoders::decoders::acars::AssembledACARSMessage;
+    use std::time::SystemTime;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    fn message(label: &str, frequency: f32, tail: &str) -> OxideJsonMessage {
+        let mut tail_chars = ['.'; 7];
+        for (index, c) in tail.chars().enumerate() {
+            tail_chars[7 - tail.len() + index] = c;
+        }
+
+        OxideJsonMessage::new(
+            &AssembledACARSMessage {
+                label: [
+                    label.chars().next().unwrap_or_default(),
+                    label.chars().nth(1).unwrap_or_default(),
+                ],
+                frequency,
+                aircraft_tail: Some(tail_chars),
+                ..AssembledACARSMessage::default()
+            },
+            SystemTime::UNIX_EPOCH,
+        )
+    }
+
+    fn filter(label: &str, frequency: &str, tail: &str) -> Result<ClientFilter, WebSocketError> {
+        let value = |value: &str| (!value.is_empty()).then(|| value.to_string());
+        ClientFilter::new(&FilterQuery {
+            label: value(label),
+            frequency: value(frequency),
+            tail: value(tail),
+        })
+    }
+
+    /// The payload of the next WebSocket frame. Frames from the server are never masked.
+    async fn read_frame(stream: &mut TcpStream) -> io::Result<(u8, String)> {
+        let opcode = stream.read_u8().await? & 0x0f;
+        let length = match stream.read_u8().await? & 0x7f {
+            126 => u64::from(stream.read_u16().await?),
+            127 => stream.read_u64().await?,
+            length => u64::from(length),
+        };
+        let length =
+            usize::try_from(length).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let mut payload = vec![0; length];
+        stream.read_exact(&mut payload).await?;
+        Ok((opcode, String::from_utf8_lossy(&payload).into_owned()))
+    }
+
+    async fn connect(address: SocketAddr, query: &str) -> io::Result<(TcpStream, String)> {
+        let mut stream = TcpStream::connect(address).await?;
+        stream
+            .write_all(
+                format!("GET /{query} HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n")
+                    .as_bytes(),
+            )
+            .await?;
+
+        let mut head = vec![];
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(stream.read_u8().await?);
+        }
+        Ok((stream, String::from_utf8_lossy(&head).into_owned()))
+    }
+
+    #[test]
+    fn test_client_filter() -> Result<(), WebSocketError> {
+        let h1 = message("H1", 131.55, "N123AB");
+        let q0 = message("Q0", 130.025, "N456CD");
+
+        let everything = filter("", "", "")?;
+        assert_eq!(everything, ClientFilter::default());
+        assert!(everything.matches(&h1) && everything.matches(&q0));
+
+        let labels = filter("H1, SA", "", "")?;
+        assert!(labels.matches(&h1));
+        assert!(!labels.matches(&q0));
+
+        let frequencies = filter("", "130.025,136.9", "")?;
+        assert!(!frequencies.matches(&h1));
+        assert!(frequencies.matches(&q0));
+
+        let tails = filter("", "", ".n123ab")?;
+        assert!(tails.matches(&h1));
+        assert!(!tails.matches(&q0));
+
+        // Every parameter has to match
+        assert!(!filter("H1", "130.025", "")?.matches(&h1));
+        assert!(filter("H1,Q0", "131.55,130.025", "N456CD")?.matches(&q0));
+
+        assert!(matches!(
+            filter("", "131.55,abc", ""),
+            Err(WebSocketError::InvalidFrequency { frequency }) if frequency == "abc"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_slow_client() {
+        let sink = WebSocketSink::new(2);
+        let address = SocketAddr::from(([127, 0, 0, 1], 1234));
+        let mut filtered = sink.add_client(address, filter("Q0", "", "").unwrap_or_default());
+        let slow = sink.add_client(address, ClientFilter::default());
+        assert_eq!(sink.clients(), 2);
+
+        sink.send(&message("H1", 131.55, "N123AB"));
+        sink.send(&message("H1", 131.55, "N123AB"));
+        assert_eq!(sink.clients(), 2);
+
+        // The client that wasn't reading is dropped, and the other is unaffected
+        sink.send(&message("Q0", 131.55, "N123AB"));
+        assert_eq!(sink.clients(), 1);
+        assert!(filtered.try_recv().is_ok());
+        assert!(filtered.try_recv().is_err());
+        drop(slow);
+
+        // Clients that have gone are dropped even if they didn't want the message
+        drop(filtered);
+        sink.send(&message("H1", 131.55, "N123AB"));
+        assert_eq!(sink.clients(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_serve() -> Result<(), Box<dyn std::error::Error>> {
+        let sink = WebSocketSink::default();
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let server = tokio::spawn(sink.clone().serve(listener));
+
+        let (_, head) = connect(address, "?frequency=131.x").await?;
+        assert!(head.starts_with("HTTP/1.1 400"), "{head}");
+
+        let (mut everything, head) = connect(address, "").await?;
+        assert!(head.starts_with("HTTP/1.1 101"), "{head}");
+        let (mut filtered, _) = connect(address, "?label=Q0").await?;
+
+        // Clients are added once the connection is upgraded
+        while sink.clients() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        sink.send(&message("H1", 131.55, "N123AB"));
+        sink.send(&message("Q0", 130.025, "N456CD"));
+
+        let (opcode, frame) = read_frame(&mut everything).await?;
+        assert_eq!(opcode, 1);
+        assert!(frame.contains(r#""label":"H1""#), "{frame}");
+        let (_, frame) = read_frame(&mut everything).await?;
+        assert!(frame.contains(r#""label":"Q0""#), "{frame}");
+        let (_, frame) = read_frame(&mut filtered).await?;
+        assert!(frame.contains(r#""label":"Q0""#), "{frame}");
+        assert!(frame.contains(r#""aircraft_registration":"N456CD""#));
+
+        server.abort();
+        Ok(())
+    }
+}
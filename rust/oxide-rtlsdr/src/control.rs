@@ -0,0 +1,346 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// Runtime control of an SDR. The reader thread owns the decoders while `read_samples` runs, so
+// commands are queued for it. Queuing a command cancels the read, and the reader thread applies
+// the commands before it starts reading again, which also reaches a device that has stopped
+// delivering samples. The SDR publishes its state after every buffer for the controlling side
+// to read.
+
+use crate::RTLSDRError;
+use oxide_decoders::ChannelStatistics;
+use rtlsdr_mt::Controller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+/// The most channels an SDR decodes at once
+pub const MAX_CHANNELS: usize = 16;
+/// Widest spread of frequencies an SDR can receive at once, in MHz
+pub const MAX_FREQUENCY_SPREAD: f32 = 2.0;
+/// Frequencies closer than this, in MHz, are the same frequency
+const FREQUENCY_TOLERANCE: f32 = 0.0005;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SdrCommand {
+    /// Tuner gain in tenths of a dB, or `None` for the tuner's AGC. Turns auto gain off.
+    SetGain(Option<i32>),
+    SetPpm(i32),
+    /// Decode these frequencies, in MHz, instead. The decoders start over.
+    Retune(Vec<f32>),
+    /// Stop or resume decoding the channel on `frequency`, in MHz
+    SetChannelEnabled {
+        frequency: f32,
+        enabled: bool,
+    },
+    /// Close and reopen the device
+    Restart,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SdrState {
+    /// Not opened yet
+    #[default]
+    Starting,
+    /// Reading samples
+    Running,
+    /// Closed to be opened again
+    Restarting,
+    /// Opening the device failed, see the last error
+    Failed,
+    /// Reading has ended
+    Stopped,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ChannelStatus {
+    /// MHz
+    pub frequency: f32,
+    pub enabled: bool,
+    pub noise_level: Option<f32>,
+    pub messages: u64,
+    pub corrected_messages: u64,
+    pub crc_failures: u64,
+    pub parity_failures: u64,
+    pub last_signal_level: Option<f32>,
+    pub average_signal_level: Option<f32>,
+    pub average_snr: Option<f32>,
+    pub labels: BTreeMap<String, u64>,
+}
+
+impl ChannelStatus {
+    /// The channel configured on `frequency`, in MHz
+    #[must_use]
+    pub fn new(frequency: f32, statistics: &ChannelStatistics, enabled: bool) -> Self {
+        Self {
+            frequency,
+            enabled,
+            noise_level: statistics.noise_level,
+            messages: statistics.messages,
+            corrected_messages: statistics.corrected_messages,
+            crc_failures: statistics.crc_failures,
+            parity_failures: statistics.parity_failures,
+            last_signal_level: statistics.last_signal_level,
+            average_signal_level: statistics.average_signal_level(),
+            average_snr: statistics.average_snr(),
+            labels: statistics.labels.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SdrStatus {
+    pub serial: String,
+    pub state: SdrState,
+    /// Tuner gain in dB, or `None` while the tuner's AGC is on
+    pub gain: Option<f32>,
+    pub auto_gain: bool,
+    pub ppm: i32,
+    pub auto_ppm: bool,
+    /// MHz, once the device is tuned
+    pub center_frequency: Option<f32>,
+    pub channels: Vec<ChannelStatus>,
+    pub last_error: Option<String>,
+}
+
+/// The controller of an open device, shared by the reader thread, which tunes the device, and
+/// the `SdrControl`, which cancels the read to have commands applied.
+#[derive(Clone, Default)]
+pub(crate) struct SharedController {
+    controller: Arc<Mutex<Option<Controller>>>,
+    woken: Arc<AtomicBool>,
+}
+
+impl SharedController {
+    pub(crate) fn set(&self, controller: Option<Controller>) {
+        if let Ok(mut shared) = self.controller.lock() {
+            *shared = controller;
+        }
+    }
+
+    pub(crate) fn take(&self) -> Option<Controller> {
+        self.controller.lock().ok()?.take()
+    }
+
+    pub(crate) fn is_open(&self) -> bool {
+        self.controller
+            .lock()
+            .map_or(false, |controller| controller.is_some())
+    }
+
+    /// Run `f` on the controller. `None` if the device isn't open.
+    pub(crate) fn with<T>(&self, f: impl FnOnce(&mut Controller) -> T) -> Option<T> {
+        self.controller.lock().ok()?.as_mut().map(f)
+    }
+
+    /// Cancel the read, so the reader thread applies the queued commands
+    pub(crate) fn wake(&self) {
+        self.woken.store(true, Ordering::Relaxed);
+        self.with(Controller::cancel_async_read);
+    }
+
+    pub(crate) fn is_woken(&self) -> bool {
+        self.woken.load(Ordering::Relaxed)
+    }
+
+    /// Whether the read was cancelled by `wake`, clearing it
+    pub(crate) fn take_woken(&self) -> bool {
+        self.woken.swap(false, Ordering::Relaxed)
+    }
+}
+
+impl Debug for SharedController {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedController")
+            .field("open", &self.is_open())
+            .field("woken", &self.is_woken())
+            .finish()
+    }
+}
+
+/// Controls an SDR from another thread
+#[derive(Debug, Clone)]
+pub struct SdrControl {
+    pub(crate) serial: String,
+    pub(crate) commands: Sender<SdrCommand>,
+    pub(crate) status: Arc<Mutex<SdrStatus>>,
+    pub(crate) controller: SharedController,
+}
+
+impl SdrControl {
+    #[must_use]
+    pub fn serial(&self) -> &str {
+        &self.serial
+    }
+
+    /// The state the SDR last published
+    #[must_use]
+    pub fn status(&self) -> SdrStatus {
+        self.status
+            .lock()
+            .map(|status| status.clone())
+            .unwrap_or_default()
+    }
+
+    /// Queue `command` for the SDR and cancel the read, so it is applied before the next sample
+    /// buffer is decoded. Failures to apply it show up as the last error of the status.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command is invalid for this SDR, or if the SDR is not running.
+    pub fn send(&self, command: SdrCommand) -> Result<(), RTLSDRError> {
+        self.validate(&command)?;
+
+        self.commands
+            .send(command)
+            .map_err(|_| RTLSDRError::NotRunning {
+                sdr: self.serial.clone(),
+            })?;
+        self.controller.wake();
+
+        Ok(())
+    }
+
+    /// Cancel the read without a command. A reader waiting on a device that has stopped
+    /// delivering samples then notices the shutdown flag.
+    pub fn interrupt(&self) {
+        self.controller.wake();
+    }
+
+    fn validate(&self, command: &SdrCommand) -> Result<(), RTLSDRError> {
+        match command {
+            SdrCommand::Retune(frequencies) => validate_frequencies(&self.serial, frequencies),
+            SdrCommand::SetChannelEnabled { frequency, .. } => {
+                if find_channel(
+                    self.status()
+                        .channels
+                        .iter()
+                        .map(|channel| channel.frequency),
+                    *frequency,
+                )
+                .is_none()
+                {
+                    return Err(RTLSDRError::UnknownChannel {
+                        sdr: self.serial.clone(),
+                        frequency: *frequency,
+                    });
+                }
+                Ok(())
+            }
+            SdrCommand::SetGain(_) | SdrCommand::SetPpm(_) | SdrCommand::Restart => Ok(()),
+        }
+    }
+}
+
+/// Check a list of frequencies, in MHz, can be decoded by one SDR
+///
+/// # Errors
+///
+/// Returns an error if the list is empty, has too many frequencies, or spans too much.
+pub fn validate_frequencies(sdr: &str, frequencies: &[f32]) -> Result<(), RTLSDRError> {
+    let sdr = sdr.to_string();
+
+    if frequencies.is_empty() {
+        return Err(RTLSDRError::NoFrequencyProvided { sdr });
+    }
+
+    if frequencies.len() > MAX_CHANNELS {
+        return Err(RTLSDRError::TooManyFrequencies { sdr });
+    }
+
+    let lowest = frequencies.iter().copied().fold(f32::INFINITY, f32::min);
+    let highest = frequencies
+        .iter()
+        .copied()
+        .fold(f32::NEG_INFINITY, f32::max);
+    if highest - lowest > MAX_FREQUENCY_SPREAD {
+        return Err(RTLSDRError::FrequencySpreadTooLarge { sdr });
+    }
+
+    Ok(())
+}
+
+/// Index of the channel on `frequency`, in MHz
+pub(crate) fn find_channel(
+    mut frequencies: impl Iterator<Item = f32>,
+    frequency: f32,
+) -> Option<usize> {
+    frequencies.position(|channel| (channel - frequency).abs() < FREQUENCY_TOLERANCE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_send() {
+        let (commands, receiver) = mpsc::channel();
+        let control = SdrControl {
+            serial: "00012785".to_string(),
+            commands,
+            status: Arc::new(Mutex::new(SdrStatus {
+                channels: vec![ChannelStatus {
+                    frequency: 131.55,
+                    ..ChannelStatus::default()
+                }],
+                ..SdrStatus::default()
+            })),
+            controller: SharedController::default(),
+        };
+
+        assert!(control.send(SdrCommand::SetPpm(2)).is_ok());
+        assert!(control.controller.take_woken());
+        assert!(control
+            .send(SdrCommand::SetChannelEnabled {
+                frequency: 131.550_2,
+                enabled: false,
+            })
+            .is_ok());
+        assert_eq!(receiver.try_recv(), Ok(SdrCommand::SetPpm(2)));
+        assert!(receiver.try_recv().is_ok());
+
+        assert!(matches!(
+            control.send(SdrCommand::SetChannelEnabled {
+                frequency: 130.025,
+                enabled: false,
+            }),
+            Err(RTLSDRError::UnknownChannel { .. })
+        ));
+        assert!(matches!(
+            control.send(SdrCommand::Retune(vec![129.0, 131.55])),
+            Err(RTLSDRError::FrequencySpreadTooLarge { .. })
+        ));
+        assert!(matches!(
+            control.send(SdrCommand::Retune(vec![])),
+            Err(RTLSDRError::NoFrequencyProvided { .. })
+        ));
+        assert!(matches!(
+            control.send(SdrCommand::Retune(vec![131.0; 17])),
+            Err(RTLSDRError::TooManyFrequencies { .. })
+        ));
+
+        drop(receiver);
+        assert!(matches!(
+            control.send(SdrCommand::Restart),
+            Err(RTLSDRError::NotRunning { .. })
+        ));
+    }
+}
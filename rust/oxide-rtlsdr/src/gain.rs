@@ -0,0 +1,383 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// Automatic gain selection. Hardware AGC reacts to every burst, which is terrible for ACARS,
+// so instead we walk up the tuner gain table while the noise floor rises slower than the gain
+// does. Once the noise floor tracks the gain, the antenna noise dominates and more gain will not
+// improve the SNR, only eat into the headroom. Any time the samples start clipping we back off,
+// and try the higher gain again after a while in case the overload was passing interference.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Fraction of samples allowed to hit the rails of the ADC before we consider the dongle overloaded.
+const MAX_CLIP_RATIO: f32 = 0.0001;
+/// If the noise floor rises by this fraction of the gain step, the antenna noise is at least as
+/// strong as the receiver noise, so stop raising the gain.
+const NOISE_RISE_RATIO: f32 = 0.5;
+/// Evaluations to wait after backing off for overload before climbing again.
+const HOLD_OFF_EVALUATIONS: u32 = 6;
+/// The hold-off doubles each time the climb overloads again, up to this many times.
+const MAX_HOLD_OFF_DOUBLINGS: u32 = 5;
+
+#[derive(Debug)]
+pub struct AutoGain {
+    /// Supported tuner gains in tenths of a dB, lowest first
+    gains: Vec<i32>,
+    index: usize,
+    samples: u64,
+    clipped: u64,
+    evaluation_samples: u64,
+    last_noise_level: Option<f32>,
+    settled: bool,
+    /// Evaluations left before climbing again after an overload
+    hold_off: u32,
+    /// Overloads since the last clean climb, each doubling the hold-off
+    overloads: u32,
+}
+
+impl AutoGain {
+    /// Create a new controller starting at the supported gain closest to `start_gain`.
+    /// The settings are re-evaluated every `evaluation_samples` IQ samples.
+    #[must_use]
+    pub fn new(gains: &[i32], start_gain: i32, evaluation_samples: u64) -> Self {
+        let mut gains: Vec<i32> = gains.iter().copied().filter(|gain| *gain > 0).collect();
+        gains.sort_unstable();
+        gains.dedup();
+
+        let index = gains
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, gain)| (start_gain - **gain).abs())
+            .map_or(0, |(index, _)| index);
+
+        Self {
+            gains,
+            index,
+            samples: 0,
+            clipped: 0,
+            evaluation_samples,
+            last_noise_level: None,
+            settled: false,
+            hold_off: 0,
+            overloads: 0,
+        }
+    }
+
+    /// The currently selected gain in tenths of a dB.
+    #[must_use]
+    pub fn gain(&self) -> Option<i32> {
+        self.gains.get(self.index).copied()
+    }
+
+    #[must_use]
+    pub const fn is_settled(&self) -> bool {
+        self.settled
+    }
+
+    /// Count the clipped samples in a buffer of raw unsigned 8 bit IQ samples.
+    pub fn add_samples(&mut self, bytes: &[u8]) {
+        for iq in bytes.chunks_exact(2) {
+            self.samples += 1;
+            if iq.iter().any(|value| *value == 0 || *value == u8::MAX) {
+                self.clipped += 1;
+            }
+        }
+    }
+
+    /// Decide if the gain should change, given the current noise floor in dB. Returns the new
+    /// gain, in tenths of a dB, if it changed.
+    pub fn evaluate(&mut self, noise_level: Option<f32>) -> Option<i32> {
+        if self.samples < self.evaluation_samples || self.gains.is_empty() {
+            return None;
+        }
+
+        let clip_ratio = self.clipped as f32 / self.samples as f32;
+        self.samples = 0;
+        self.clipped = 0;
+
+        if clip_ratio > MAX_CLIP_RATIO {
+            // Overloaded. Back off and stay below this gain until the hold-off is over
+            self.settled = true;
+            self.last_noise_level = None;
+            self.hold_off = HOLD_OFF_EVALUATIONS << self.overloads.min(MAX_HOLD_OFF_DOUBLINGS);
+            self.overloads += 1;
+
+            if self.index == 0 {
+                return None;
+            }
+
+            self.index -= 1;
+            return self.gain();
+        }
+
+        if self.hold_off > 0 {
+            self.hold_off -= 1;
+            if self.hold_off > 0 {
+                return None;
+            }
+            // Climb again, back up to the gain that overloaded first
+            self.settled = false;
+        } else if self.settled {
+            return None;
+        } else {
+            self.overloads = 0;
+        }
+
+        let noise_level = noise_level?;
+
+        if let Some(last_noise_level) = self.last_noise_level {
+            if self.index > 0 {
+                let step = (self.gains[self.index] - self.gains[self.index - 1]) as f32 / 10.0;
+
+                if noise_level - last_noise_level >= step * NOISE_RISE_RATIO {
+                    // Antenna noise dominates. The previous gain had the same SNR with more headroom
+                    self.settled = true;
+                    self.index -= 1;
+                    return self.gain();
+                }
+            }
+        }
+
+        if self.index + 1 >= self.gains.len() {
+            self.settled = true;
+            return None;
+        }
+
+        self.last_noise_level = Some(noise_level);
+        self.index += 1;
+        self.gain()
+    }
+}
+
+/// The file the gain of the device is kept in. The serial comes from the device's EEPROM, so
+/// anything that could leave the state directory is replaced.
+fn gain_file(state_dir: &Path, serial: &str) -> PathBuf {
+    let serial: String = serial
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    state_dir.join(format!("gain_{serial}"))
+}
+
+/// Read the last gain chosen by auto gain for the device, in tenths of a dB.
+#[must_use]
+pub fn load_gain(state_dir: &Path, serial: &str) -> Option<i32> {
+    fs::read_to_string(gain_file(state_dir, serial))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Save the gain chosen by auto gain for the device so it can be used on the next start.
+///
+/// # Errors
+///
+/// Returns an error if the state directory cannot be created or the file cannot be written.
+pub fn save_gain(state_dir: &Path, serial: &str, gain: i32) -> io::Result<()> {
+    fs::create_dir_all(state_dir)?;
+    fs::write(gain_file(state_dir, serial), gain.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iq;
+    use num::Complex;
+    use oxide_decoders::decoders::acars::{ACARSDecoder, INTRATE_F32};
+    use oxide_decoders::decoders::modulator::{
+        decode_samples, downlink_block, to_cu8, AcarsModulator,
+    };
+    use oxide_decoders::Decoder;
+
+    // R820T gain table, in tenths of a dB
+    const SIMULATED_GAINS: [i32; 29] = [
+        0, 9, 14, 27, 37, 77, 87, 125, 144, 157, 166, 197, 207, 229, 254, 280, 297, 328, 338, 364,
+        372, 386, 402, 421, 434, 439, 445, 480, 496,
+    ];
+    /// Level of the signal from the antenna, relative to full scale, before any gain
+    const ANTENNA_LEVEL_DB: f64 = -47.0;
+
+    /// Simulated dongle. A block and the antenna noise around it are scaled by the gain and
+    /// quantized to 8 bits, the quantization being the receiver noise. `interference_db` adds a
+    /// carrier outside of the channel, at that level relative to full scale before any gain,
+    /// which overloads the ADC once the gain brings it to full scale. The channel filter removes
+    /// it, so the decoder never sees it.
+    struct SimulatedDongle {
+        auto_gain: AutoGain,
+        decoder: ACARSDecoder,
+        interference_db: Option<f64>,
+    }
+
+    impl SimulatedDongle {
+        fn new(start_gain: i32, interference_db: Option<f64>) -> Self {
+            Self {
+                // Every capture is a whole evaluation interval
+                auto_gain: AutoGain::new(&SIMULATED_GAINS, start_gain, 1),
+                decoder: ACARSDecoder::new(0, 131_550_000, [Complex::new(0.0, 0.0); 192]),
+                interference_db,
+            }
+        }
+
+        /// The samples from the ADC and the samples of the channel, as 8 bit IQ
+        fn capture(&self, gain: i32) -> (Vec<u8>, Vec<u8>) {
+            let gain_db = f64::from(gain) / 10.0;
+            // A short message, so most of the interval is the noise floor auto gain measures
+            let block = downlink_block(['H', '1'], 16);
+            let channel = AcarsModulator::new(f64::from(INTRATE_F32), 0.0)
+                .amplitude(10f64.powf((ANTENNA_LEVEL_DB + gain_db) / 20.0))
+                .snr(Some(20.0))
+                .silence(1.0)
+                .modulate(&block);
+
+            let Some(interference_db) = self.interference_db else {
+                let bytes = to_cu8(&channel);
+                return (bytes.clone(), bytes);
+            };
+
+            let amplitude = 10f64.powf((interference_db + gain_db) / 20.0) as f32;
+            let adc: Vec<Complex<f32>> = channel
+                .iter()
+                .enumerate()
+                .map(|(n, sample)| sample + Complex::from_polar(amplitude, n as f32 * 0.5))
+                .collect();
+
+            (to_cu8(&adc), to_cu8(&channel))
+        }
+
+        /// Run one evaluation interval through the decoder and let auto gain act on the noise
+        /// floor it measured
+        fn evaluate(&mut self) {
+            let Some(gain) = self.auto_gain.gain() else {
+                return;
+            };
+
+            let (adc, channel) = self.capture(gain);
+            self.auto_gain.add_samples(&adc);
+
+            let samples: Vec<Complex<f32>> = channel
+                .chunks_exact(2)
+                .map(|iq| iq::cu8_to_complex(iq) / 127.5)
+                .collect();
+            decode_samples(&mut self.decoder, &samples);
+
+            self.auto_gain
+                .evaluate(self.decoder.get_channel_statistics().noise_level);
+        }
+
+        fn run(&mut self, evaluations: usize) -> i32 {
+            for _ in 0..evaluations {
+                self.evaluate();
+            }
+            self.auto_gain.gain().unwrap_or_default()
+        }
+    }
+
+    #[test]
+    fn test_auto_gain() {
+        // Starting low, the gain should climb until the antenna noise dominates
+        let mut dongle = SimulatedDongle::new(0, None);
+        let gain = dongle.run(40);
+        assert!(dongle.auto_gain.is_settled());
+        assert!((150..=300).contains(&gain), "Auto gain settled on {gain}");
+
+        // Starting high on an overloaded dongle, the gain should back off below the clipping point
+        let mut dongle = SimulatedDongle::new(496, Some(-15.0));
+        let gain = dongle.run(40);
+        assert!(dongle.auto_gain.is_settled());
+        assert!(gain < 150, "Auto gain settled on {gain}");
+
+        // Climbing into the clipping point should stop there too
+        let mut dongle = SimulatedDongle::new(0, Some(-10.0));
+        let gain = dongle.run(40);
+        assert!(gain < 100, "Auto gain settled on {gain}");
+    }
+
+    #[test]
+    fn test_auto_gain_recovers() {
+        let settled_gain = SimulatedDongle::new(0, None).run(40);
+
+        // Interference overloads the dongle for a while
+        let mut dongle = SimulatedDongle::new(0, None);
+        dongle.run(40);
+        dongle.interference_db = Some(-15.0);
+        let gain = dongle.run(HOLD_OFF_EVALUATIONS as usize);
+        assert!(gain < settled_gain, "Auto gain stayed at {gain}");
+
+        // Once it is gone, the gain climbs back up after the hold-off
+        dongle.interference_db = None;
+        assert_eq!(
+            dongle.run(HOLD_OFF_EVALUATIONS as usize * 4 + 40),
+            settled_gain
+        );
+
+        // The hold-off grows while the interference is still there
+        let mut dongle = SimulatedDongle::new(0, Some(-15.0));
+        let mut gains = vec![];
+        for _ in 0..120 {
+            dongle.evaluate();
+            gains.push(dongle.auto_gain.gain().unwrap_or_default());
+        }
+        let overloads: Vec<usize> = gains
+            .windows(2)
+            .enumerate()
+            .filter_map(|(evaluation, pair)| (pair[1] < pair[0]).then_some(evaluation))
+            .collect();
+        assert!(overloads.len() >= 4, "Overloaded at {overloads:?}");
+        assert!(
+            overloads
+                .windows(3)
+                .all(|overload| overload[2] - overload[1] > overload[1] - overload[0]),
+            "Overloaded at {overloads:?}"
+        );
+    }
+
+    #[test]
+    fn test_auto_gain_persistence() -> Result<(), Box<dyn std::error::Error>> {
+        let state_dir = std::env::temp_dir().join(format!("oxide-gain-{}", std::process::id()));
+
+        assert_eq!(load_gain(&state_dir, "00000001"), None);
+        save_gain(&state_dir, "00000001", 297)?;
+        assert_eq!(load_gain(&state_dir, "00000001"), Some(297));
+        assert_eq!(load_gain(&state_dir, "00000002"), None);
+
+        // Serials can't reach outside the state directory
+        for serial in ["../../escape", "/etc/passwd", "a/b", "..", "", "SDR 01.x"] {
+            let file = gain_file(&state_dir, serial);
+            assert_eq!(file.parent(), Some(state_dir.as_path()), "{serial:?}");
+            let Some(name) = file.file_name().and_then(|name| name.to_str()) else {
+                panic!("No file name for {serial:?}");
+            };
+            assert!(name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'));
+        }
+        save_gain(&state_dir, "../gain", 420)?;
+        assert_eq!(load_gain(&state_dir, "../gain"), Some(420));
+        assert!(state_dir.join("gain____gain").exists());
+
+        std::fs::remove_dir_all(&state_dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,441 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// Reads IQ captures in the sample formats other SDR tools record in. The channel mixer was
+// written for the unsigned 8 bit samples of the dongle, so every format is converted to what
+// those samples are once the offset is removed: roughly -127.5 to 127.5. The format and rate
+// of a capture come from its SigMF metadata or WAV header when it has one.
+
+use crate::sigmf::{self, SigMfError, SigMfMetadata};
+use custom_error::custom_error;
+use num::Complex;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// DC offset of the unsigned 8 bit samples of the dongle
+pub const CU8_OFFSET: f32 = 127.37;
+/// Full scale of the internal representation
+const FULL_SCALE: f32 = 127.5;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+custom_error! {pub IqError
+    Io { source: io::Error } = "Unable to read the capture: {source}",
+    SigMf { source: SigMfError } = "{source}",
+    UnsupportedDatatype { datatype: String } = "Sample format {datatype} is not supported",
+    InvalidWav { reason: String } = "Invalid WAV file: {reason}",
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    /// Unsigned 8 bit, as recorded from the dongle
+    Cu8,
+    /// Signed 8 bit
+    Cs8,
+    /// Signed 16 bit little endian
+    Cs16,
+    /// 32 bit float little endian, full scale is 1.0
+    Cf32,
+}
+
+impl SampleFormat {
+    /// Bytes in one IQ pair
+    #[must_use]
+    pub const fn sample_size(self) -> usize {
+        match self {
+            Self::Cu8 | Self::Cs8 => 2,
+            Self::Cs16 => 4,
+            Self::Cf32 => 8,
+        }
+    }
+
+    /// The format with the given `SigMF` `core:datatype`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the datatype isn't interleaved IQ in one of the supported formats.
+    pub fn from_sigmf_datatype(datatype: &str) -> Result<Self, IqError> {
+        match datatype {
+            "cu8" => Ok(Self::Cu8),
+            "ci8" => Ok(Self::Cs8),
+            "ci16_le" => Ok(Self::Cs16),
+            "cf32_le" => Ok(Self::Cf32),
+            _ => Err(IqError::UnsupportedDatatype {
+                datatype: datatype.to_string(),
+            }),
+        }
+    }
+
+    fn to_complex(self, bytes: &[u8]) -> Complex<f32> {
+        match self {
+            Self::Cu8 => cu8_to_complex(bytes),
+            Self::Cs8 => Complex::new(
+                f32::from(i8::from_le_bytes([bytes[0]])),
+                f32::from(i8::from_le_bytes([bytes[1]])),
+            ),
+            Self::Cs16 => Complex::new(
+                f32::from(i16::from_le_bytes([bytes[0], bytes[1]])) / 32768.0 * FULL_SCALE,
+                f32::from(i16::from_le_bytes([bytes[2], bytes[3]])) / 32768.0 * FULL_SCALE,
+            ),
+            Self::Cf32 => Complex::new(
+                f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) * FULL_SCALE,
+                f32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) * FULL_SCALE,
+            ),
+        }
+    }
+}
+
+/// Convert an unsigned 8 bit IQ pair, as read from the dongle
+#[must_use]
+pub fn cu8_to_complex(iq: &[u8]) -> Complex<f32> {
+    Complex::new(f32::from(iq[0]) - CU8_OFFSET, f32::from(iq[1]) - CU8_OFFSET)
+}
+
+/// What the metadata or header of a capture says about it. Raw captures say nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CaptureInfo {
+    pub format: Option<SampleFormat>,
+    /// Samples per second
+    pub sample_rate: Option<u32>,
+    /// Center frequency in Hz
+    pub center_frequency: Option<f64>,
+    /// Time of the first sample
+    pub start_time: Option<SystemTime>,
+}
+
+impl CaptureInfo {
+    /// The format, rate, center frequency and start of a capture from its `SigMF` metadata
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the datatype isn't supported.
+    // Sample rates are positive and well within range
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn from_sigmf(metadata: &SigMfMetadata) -> Result<Self, IqError> {
+        Ok(Self {
+            format: Some(SampleFormat::from_sigmf_datatype(
+                &metadata.global.datatype,
+            )?),
+            sample_rate: metadata
+                .global
+                .sample_rate
+                .map(|sample_rate| sample_rate.round() as u32),
+            center_frequency: metadata.frequency(),
+            start_time: metadata.start_time(),
+        })
+    }
+}
+
+/// Read the header of a WAV file with two channels, I and Q, leaving `reader` at the start of
+/// the samples. WAV files don't record the center frequency.
+///
+/// # Errors
+///
+/// Returns an error if the header can't be read, isn't a WAV header, or the samples aren't
+/// two channels of 8 bit, 16 bit or 32 bit float samples.
+pub fn read_wav_header(reader: &mut impl Read) -> Result<CaptureInfo, IqError> {
+    let invalid = |reason: &str| IqError::InvalidWav {
+        reason: reason.to_string(),
+    };
+
+    let mut riff = [0u8; 12];
+    reader.read_exact(&mut riff)?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Err(invalid("missing the RIFF header"));
+    }
+
+    let mut info = CaptureInfo::default();
+
+    loop {
+        let mut chunk = [0u8; 8];
+        reader.read_exact(&mut chunk)?;
+        let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as usize;
+
+        match &chunk[0..4] {
+            b"fmt " => {
+                if size < 16 {
+                    return Err(invalid("format chunk too short"));
+                }
+                let mut fmt = vec![0u8; size + size % 2];
+                reader.read_exact(&mut fmt)?;
+
+                let mut format = u16::from_le_bytes([fmt[0], fmt[1]]);
+                let channels = u16::from_le_bytes([fmt[2], fmt[3]]);
+                let sample_rate = u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]);
+                let bits = u16::from_le_bytes([fmt[14], fmt[15]]);
+
+                // The real format is the start of the sub format GUID
+                if format == WAVE_FORMAT_EXTENSIBLE && size >= 26 {
+                    format = u16::from_le_bytes([fmt[24], fmt[25]]);
+                }
+
+                if channels != 2 {
+                    return Err(invalid("IQ needs two channels"));
+                }
+
+                info.format = Some(match (format, bits) {
+                    (WAVE_FORMAT_PCM, 8) => SampleFormat::Cu8,
+                    (WAVE_FORMAT_PCM, 16) => SampleFormat::Cs16,
+                    (WAVE_FORMAT_IEEE_FLOAT, 32) => SampleFormat::Cf32,
+                    _ => {
+                        return Err(IqError::UnsupportedDatatype {
+                            datatype: format!("WAV format {format} with {bits} bits"),
+                        })
+                    }
+                });
+                info.sample_rate = Some(sample_rate);
+            }
+            b"data" => {
+                if info.format.is_none() {
+                    return Err(invalid("data before the format chunk"));
+                }
+                return Ok(info);
+            }
+            // Chunks are padded to an even size
+            _ => {
+                io::copy(
+                    &mut reader.by_ref().take((size + size % 2) as u64),
+                    &mut io::sink(),
+                )?;
+            }
+        }
+    }
+}
+
+/// Open a capture, reading what its `SigMF` metadata or WAV header says about it. `path` can be
+/// the capture or its `.sigmf-meta` file.
+///
+/// # Errors
+///
+/// Returns an error if the capture or its metadata can't be read, or the metadata describes a
+/// format that isn't supported.
+pub fn open_capture(path: &Path) -> Result<(BufReader<File>, CaptureInfo), IqError> {
+    let is_extension =
+        |path: &Path, extension: &str| path.extension().map_or(false, |ext| ext == extension);
+
+    let (data_path, meta_path): (PathBuf, PathBuf) = if is_extension(path, sigmf::META_EXTENSION) {
+        (
+            path.with_extension(sigmf::DATA_EXTENSION),
+            path.to_path_buf(),
+        )
+    } else {
+        (path.to_path_buf(), sigmf::meta_path(path))
+    };
+
+    let mut reader = BufReader::new(File::open(&data_path)?);
+
+    let info = if meta_path.exists() {
+        CaptureInfo::from_sigmf(&SigMfMetadata::from_file(&meta_path)?)?
+    } else if path
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("wav"))
+    {
+        read_wav_header(&mut reader)?
+    } else {
+        CaptureInfo::default()
+    };
+
+    Ok((reader, info))
+}
+
+/// Reads samples in any of the supported formats
+#[derive(Debug)]
+pub struct IqReader<R> {
+    reader: R,
+    format: SampleFormat,
+    bytes: Vec<u8>,
+}
+
+impl<R: Read> IqReader<R> {
+    pub const fn new(reader: R, format: SampleFormat) -> Self {
+        Self {
+            reader,
+            format,
+            bytes: vec![],
+        }
+    }
+
+    pub const fn format(&self) -> SampleFormat {
+        self.format
+    }
+
+    /// Fill `samples`. Returns `false` once the capture ends, dropping any partial buffer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails.
+    pub fn read_samples(&mut self, samples: &mut [Complex<f32>]) -> io::Result<bool> {
+        let sample_size = self.format.sample_size();
+        self.bytes.resize(samples.len() * sample_size, 0);
+
+        let mut filled = 0;
+        while filled < self.bytes.len() {
+            match self.reader.read(&mut self.bytes[filled..]) {
+                Ok(0) => return Ok(false),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        for (sample, bytes) in samples.iter_mut().zip(self.bytes.chunks_exact(sample_size)) {
+            *sample = self.format.to_complex(bytes);
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(format: SampleFormat, bytes: &[u8]) -> Vec<Complex<f32>> {
+        let mut reader = IqReader::new(bytes, format);
+        let mut samples = vec![Complex::new(0.0, 0.0); 2];
+        assert!(matches!(reader.read_samples(&mut samples), Ok(true)));
+        // Nothing left
+        assert!(matches!(reader.read_samples(&mut samples), Ok(false)));
+        samples
+    }
+
+    fn assert_close(samples: &[Complex<f32>], expected: &[(f32, f32)]) {
+        for (sample, (re, im)) in samples.iter().zip(expected) {
+            assert!(
+                (sample.re - re).abs() < 0.01 && (sample.im - im).abs() < 0.01,
+                "{sample} is not {re}+{im}i"
+            );
+        }
+    }
+
+    #[test]
+    fn test_cu8() {
+        let samples = read(SampleFormat::Cu8, &[255, 0, 127, 128]);
+        assert_close(&samples, &[(127.63, -127.37), (-0.37, 0.63)]);
+    }
+
+    #[test]
+    fn test_cs8() {
+        let samples = read(SampleFormat::Cs8, &[127, 0x80, 0, 0xFF]);
+        assert_close(&samples, &[(127.0, -128.0), (0.0, -1.0)]);
+    }
+
+    #[test]
+    fn test_cs16() {
+        let mut bytes = vec![];
+        for value in [i16::MAX, i16::MIN, 0, -16384] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        let samples = read(SampleFormat::Cs16, &bytes);
+        assert_close(&samples, &[(127.5, -127.5), (0.0, -63.75)]);
+    }
+
+    #[test]
+    fn test_cf32() {
+        let mut bytes = vec![];
+        for value in [1.0f32, -1.0, 0.5, 0.0] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        let samples = read(SampleFormat::Cf32, &bytes);
+        assert_close(&samples, &[(127.5, -127.5), (63.75, 0.0)]);
+    }
+
+    #[test]
+    fn test_partial_buffer() {
+        let mut reader = IqReader::new([1u8, 2, 3].as_slice(), SampleFormat::Cu8);
+        let mut samples = vec![Complex::new(0.0, 0.0); 2];
+        assert!(matches!(reader.read_samples(&mut samples), Ok(false)));
+    }
+
+    fn wav(format: u16, bits: u16) -> Vec<u8> {
+        let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+        // A chunk the reader doesn't know, with padding
+        wav.extend_from_slice(b"LIST\x03\0\0\0abc\0");
+        wav.extend_from_slice(b"fmt \x10\0\0\0");
+        wav.extend_from_slice(&format.to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&2_400_000u32.to_le_bytes());
+        wav.extend_from_slice(&(2_400_000u32 * u32::from(bits) / 4).to_le_bytes());
+        wav.extend_from_slice(&(bits / 4).to_le_bytes());
+        wav.extend_from_slice(&bits.to_le_bytes());
+        wav.extend_from_slice(b"data\x04\0\0\0");
+        wav.extend_from_slice(&[255, 0, 127, 128]);
+        wav
+    }
+
+    #[test]
+    fn test_wav() -> Result<(), IqError> {
+        let wav_bytes = wav(WAVE_FORMAT_PCM, 8);
+        let mut reader = wav_bytes.as_slice();
+        let info = read_wav_header(&mut reader)?;
+        assert_eq!(
+            info,
+            CaptureInfo {
+                format: Some(SampleFormat::Cu8),
+                sample_rate: Some(2_400_000),
+                center_frequency: None,
+                start_time: None,
+            }
+        );
+        // Left at the samples
+        assert_eq!(reader, &[255, 0, 127, 128]);
+
+        assert_eq!(
+            read_wav_header(&mut wav(WAVE_FORMAT_PCM, 16).as_slice())?.format,
+            Some(SampleFormat::Cs16)
+        );
+        assert_eq!(
+            read_wav_header(&mut wav(WAVE_FORMAT_IEEE_FLOAT, 32).as_slice())?.format,
+            Some(SampleFormat::Cf32)
+        );
+        assert!(matches!(
+            read_wav_header(&mut wav(WAVE_FORMAT_PCM, 24).as_slice()),
+            Err(IqError::UnsupportedDatatype { .. })
+        ));
+        assert!(matches!(
+            read_wav_header(&mut b"not a wav file".as_slice()),
+            Err(IqError::InvalidWav { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_sigmf_capture() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join(format!("oxide-iq-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let data_path = dir.join("capture.sigmf-data");
+        std::fs::write(&data_path, [0u8; 8])?;
+        SigMfMetadata::new("ci16_le", 2_000_000.0, 130_450_000.0)
+            .write(&sigmf::meta_path(&data_path))?;
+
+        let expected = CaptureInfo {
+            format: Some(SampleFormat::Cs16),
+            sample_rate: Some(2_000_000),
+            center_frequency: Some(130_450_000.0),
+            start_time: None,
+        };
+        assert_eq!(open_capture(&data_path)?.1, expected);
+        assert_eq!(open_capture(&sigmf::meta_path(&data_path))?.1, expected);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
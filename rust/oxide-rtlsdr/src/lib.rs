@@ -25,14 +25,35 @@
     clippy::expect_used
 )]
 // #![warn(missing_docs)]
+// Channel mixing works in integer Hz and sample counts but does its math in f32.
+#![allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap,
+    clippy::cast_sign_loss
+)]
 
 #[macro_use]
 extern crate log;
+pub mod control;
+pub mod gain;
+pub mod iq;
+pub mod ppm;
+pub mod sigmf;
+
 // use num_complex::Complex;
+use control::{
+    ChannelStatus, SdrCommand, SdrControl, SdrState, SdrStatus, SharedController, MAX_CHANNELS,
+    MAX_FREQUENCY_SPREAD,
+};
+use gain::AutoGain;
+use iq::{IqReader, SampleFormat};
 use num::Complex;
 use oxide_decoders::decoders::acars::ACARSDecoder;
 use oxide_decoders::decoders::acars::{self, AssembledACARSMessage};
-use oxide_decoders::{Decoder, ValidDecoderType};
+use oxide_decoders::{ChannelStatistics, Decoder, ValidDecoderType};
+use oxide_metrics::{ChannelMetrics, Metrics};
+use ppm::PpmEstimator;
 use rtlsdr_mt::{Controller, Reader};
 use tokio::sync::mpsc::UnboundedSender;
 
@@ -42,17 +63,38 @@ use custom_error::custom_error;
 use std::ffi::c_char;
 use std::ffi::CStr;
 use std::fmt::{self, Display, Formatter};
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// How long auto gain measures at each gain before deciding on the next step
+const AUTO_GAIN_INTERVAL_SECONDS: u64 = 10;
+/// Requested gain that puts the tuner on AGC. Any gain above 500 does
+const AGC_GAIN: i32 = 1000;
+/// How long to wait before opening a restarted device, and between attempts
+const RESTART_DELAY: Duration = Duration::from_secs(2);
 
 // TODO: Can I wrap the librtlsdr logging functions to use the log crate?
 
 custom_error! {pub RTLSDRError
     DeviceNotFound { sdr: String } = "Device {sdr} not found",
     FrequencySpreadTooLarge { sdr: String } = "Frequency spread too large for device {sdr}. Must be less than 2mhz",
+    FrequencyOutsideBand { sdr: String, frequency: f32 } = "Frequency {frequency} is outside the band received by {sdr}",
     NoFrequencyProvided { sdr: String } = "No frequency provided for device {sdr}",
+    OpenFailed { sdr: String } = "Unable to open device {sdr}",
+    SettingFailed { sdr: String, setting: String } = "Unable to set {setting} on device {sdr}",
+    TooManyFrequencies { sdr: String } = "Too many frequencies for device {sdr}. At most 16 can be decoded",
+    UnknownChannel { sdr: String, frequency: f32 } = "Device {sdr} has no channel on {frequency}",
+    NotRunning { sdr: String } = "Device {sdr} is not running",
 }
 
+// The bools are independent device options, not a state machine
+#[allow(clippy::struct_excessive_bools)]
 pub struct RtlSdr {
-    ctl: Option<Controller>,
+    ctl: SharedController,
     reader: Option<Reader>,
     index: Option<u32>,
     serial: String,
@@ -61,11 +103,34 @@ pub struct RtlSdr {
     bias_tee: bool,
     rtl_mult: i32,
     frequencies: Vec<f32>,
-    channel: [Box<dyn Decoder>; 16],
+    channel: [Box<dyn Decoder>; MAX_CHANNELS],
+    channel_enabled: [bool; MAX_CHANNELS],
     decoder_type: ValidDecoderType,
+    auto_ppm: bool,
+    ppm_estimator: PpmEstimator,
+    auto_gain_enabled: bool,
+    auto_gain: Option<AutoGain>,
+    state_dir: Option<PathBuf>,
+    emit_errors: bool,
+    shutdown: Option<Arc<AtomicBool>>,
+    shared_statistics: Option<Arc<Mutex<Vec<ChannelStatistics>>>>,
+    center_frequency: Option<f32>,
+    /// Time of the first sample of the capture being decoded
+    start_time: Option<SystemTime>,
+    metrics: Option<Arc<Metrics>>,
+    output_channel: Option<UnboundedSender<AssembledACARSMessage>>,
+    /// Center frequency the channels were set up for, in Hz
+    tuned_center_frequency: Option<i32>,
+    state: SdrState,
+    last_error: Option<String>,
+    /// Reopen the device once the read has been cancelled
+    restart: bool,
+    commands: Option<Receiver<SdrCommand>>,
+    status: Option<Arc<Mutex<SdrStatus>>>,
 }
 
 impl RtlSdr {
+    #[must_use]
     pub fn new(
         serial: String,
         ppm: i32,
@@ -74,22 +139,17 @@ impl RtlSdr {
         rtl_mult: i32,
         mut frequencies: Vec<f32>,
         decoder: ValidDecoderType,
-    ) -> RtlSdr {
-        frequencies.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        let mut channels: Vec<Box<dyn Decoder>> = Vec::new();
+    ) -> Self {
+        frequencies.sort_by(f32::total_cmp);
 
         // FIXME: This feels so wasteful to create 16 decoders when we only need 1 or 2
         // But the array needs to be filled. Can we do better?
-        for _ in 0..16_usize {
-            channels.push(Box::new(ACARSDecoder::new(
-                0,
-                0,
-                [Complex::new(0.0, 0.0); 192],
-            )));
-        }
+        let channel: [Box<dyn Decoder>; MAX_CHANNELS] = array_init::array_init(|_| {
+            Box::new(ACARSDecoder::new(0, 0, [Complex::new(0.0, 0.0); 192])) as Box<dyn Decoder>
+        });
 
         Self {
-            ctl: None,
+            ctl: SharedController::default(),
             reader: None,
             index: None,
             serial,
@@ -98,37 +158,131 @@ impl RtlSdr {
             bias_tee,
             rtl_mult,
             frequencies,
-            // array_init::array_init(|i: usize| (i * i) as u32);
-            channel: array_init::from_iter(channels).unwrap(),
+            channel,
+            channel_enabled: [true; MAX_CHANNELS],
             decoder_type: decoder,
+            auto_ppm: false,
+            ppm_estimator: PpmEstimator::new(),
+            auto_gain_enabled: false,
+            auto_gain: None,
+            state_dir: None,
+            emit_errors: false,
+            shutdown: None,
+            shared_statistics: None,
+            center_frequency: None,
+            start_time: None,
+            metrics: None,
+            output_channel: None,
+            tuned_center_frequency: None,
+            state: SdrState::Starting,
+            last_error: None,
+            restart: false,
+            commands: None,
+            status: None,
         }
     }
 
-    fn get_intrate(&self) -> i32 {
+    /// Control the SDR, and follow its state, from another thread while `read_samples` runs.
+    pub fn control(&mut self) -> SdrControl {
+        let (commands, receiver) = std::sync::mpsc::channel();
+        let status = Arc::new(Mutex::new(SdrStatus::default()));
+
+        self.commands = Some(receiver);
+        self.status = Some(status.clone());
+        self.publish_status();
+
+        SdrControl {
+            serial: self.serial.clone(),
+            commands,
+            status,
+            controller: self.ctl.clone(),
+        }
+    }
+
+    /// Tune to `center_frequency`, in MHz, instead of picking the center from the frequency
+    /// list. Needed to decode captures, which were recorded at whatever center they were.
+    pub fn set_center_frequency(&mut self, center_frequency: Option<f32>) {
+        self.center_frequency = center_frequency;
+    }
+
+    /// Time the messages of a capture from `start_time`, the time of its first sample, instead
+    /// of the system clock.
+    pub fn set_start_time(&mut self, start_time: Option<SystemTime>) {
+        self.start_time = start_time;
+    }
+
+    /// Stop reading samples once `shutdown` is set. The read is cancelled from the sample
+    /// callback, so it stops within one buffer.
+    pub fn set_shutdown(&mut self, shutdown: Arc<AtomicBool>) {
+        self.shutdown = Some(shutdown);
+    }
+
+    /// Publish the channel statistics to `statistics` after every buffer, so they can be read
+    /// while `read_samples` owns the device.
+    pub fn set_shared_statistics(&mut self, statistics: Arc<Mutex<Vec<ChannelStatistics>>>) {
+        self.shared_statistics = Some(statistics);
+    }
+
+    /// Record the device, sample buffers and each channel's decoding in `metrics`.
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Output messages that failed the CRC or parity checks, flagged as such, instead of dropping them.
+    pub fn set_emit_errors(&mut self, emit_errors: bool) {
+        self.emit_errors = emit_errors;
+    }
+
+    /// Enable automatic gain selection. The gain is stepped through the gains supported by the
+    /// tuner while watching for clipping and the noise floor. If `state_dir` is set, the chosen
+    /// gain is saved there and used as the starting point the next time the device is opened.
+    pub fn set_auto_gain(&mut self, auto_gain: bool, state_dir: Option<PathBuf>) {
+        self.auto_gain_enabled = auto_gain;
+        self.state_dir = state_dir;
+    }
+
+    /// Enable automatic PPM correction. The carrier offset of each decoded message is used
+    /// to estimate the frequency error of the device, and the PPM is adjusted once the
+    /// estimate is stable.
+    pub fn set_auto_ppm(&mut self, auto_ppm: bool) {
+        self.auto_ppm = auto_ppm;
+    }
+
+    const fn get_intrate(&self) -> i32 {
         match self.decoder_type {
             ValidDecoderType::ACARS => acars::INTRATE as i32,
-            ValidDecoderType::VDL2 => 0,
-            ValidDecoderType::HFDL => 0,
+            ValidDecoderType::VDL2 | ValidDecoderType::HFDL => 0,
         }
     }
 
-    fn get_rtloutbufsz(&self) -> usize {
+    const fn get_rtloutbufsz(&self) -> usize {
         match self.decoder_type {
             ValidDecoderType::ACARS => acars::RTLOUTBUFSZ,
-            ValidDecoderType::VDL2 => 0,
-            ValidDecoderType::HFDL => 0,
+            ValidDecoderType::VDL2 | ValidDecoderType::HFDL => 0,
         }
     }
 
     fn init_channels(
         &mut self,
-        output_channel: UnboundedSender<AssembledACARSMessage>,
+        output_channel: &UnboundedSender<AssembledACARSMessage>,
         rtl_in_rate: i32,
     ) -> Result<i32, RTLSDRError> {
+        if self.frequencies.is_empty() {
+            return Err(RTLSDRError::NoFrequencyProvided {
+                sdr: self.serial.clone(),
+            });
+        }
+
+        if self.frequencies.len() > MAX_CHANNELS {
+            return Err(RTLSDRError::TooManyFrequencies {
+                sdr: self.serial.clone(),
+            });
+        }
+
         let mut channels: Vec<i32> = Vec::new();
 
-        for freq in self.frequencies.iter() {
-            let channel = ((((1000000.0 * freq) as i32) + self.get_intrate() / 2)
+        for freq in &self.frequencies {
+            let channel = ((((1_000_000.0 * freq) as i32) + self.get_intrate() / 2)
                 / self.get_intrate())
                 * self.get_intrate();
             channels.push(channel);
@@ -139,27 +293,24 @@ impl RtlSdr {
         // best way to set the center frequency. However, the original acarsdec code set the center
         // using a different method. I'm not sure why, but I'm going to keep it the same for now.
 
-        let mut center_freq_actual: i32 = channels[0];
-
-        if channels.len() > 1 {
+        let center_freq_actual: i32 = if let Some(center_frequency) = self.center_frequency {
+            (f64::from(center_frequency) * 1_000_000.0).round() as i32
+        } else if channels.len() > 1 {
             let center_freq_as_float =
                 ((self.frequencies[self.frequencies.len() - 1] + self.frequencies[0]) / 2.0)
                     .round();
-            let center_freq = (center_freq_as_float * 1000000.0) as i32;
-            info!(
-                "[{: <13}] Setting center frequency to {}",
-                self.serial, center_freq
-            );
-            center_freq_actual = center_freq;
+            (center_freq_as_float * 1_000_000.0) as i32
         } else {
-            info!(
-                "[{: <13}] Setting center frequency to {}",
-                self.serial, channels[0]
-            );
-        }
+            channels[0]
+        };
+
+        info!(
+            "[{: <13}] Setting center frequency to {}",
+            self.serial, center_freq_actual
+        );
 
         let mut channel_windows = Vec::new();
-        for channel in channels.iter() {
+        for channel in &channels {
             // AMFreq = (ch->Fr - (float)Fc) / (float)(rtlInRate) * 2.0 * M_PI;
             let am_freq = ((channel - center_freq_actual) as f32) * 2.0 * std::f32::consts::PI
                 / (rtl_in_rate as f32);
@@ -182,6 +333,15 @@ impl RtlSdr {
             let mut out_channel: ACARSDecoder =
                 ACARSDecoder::new(i as i32, channels[i], window_array);
             out_channel.set_output_channel(output_channel.clone());
+            out_channel.set_emit_errors(self.emit_errors);
+            out_channel.set_start_time(self.start_time);
+            if let Some(metrics) = &self.metrics {
+                out_channel.set_metrics(ChannelMetrics::new(
+                    metrics.clone(),
+                    &self.serial,
+                    self.frequencies[i],
+                ));
+            }
 
             self.channel[i] = Box::new(out_channel);
         }
@@ -189,149 +349,349 @@ impl RtlSdr {
         Ok(center_freq_actual)
     }
 
+    /// Open the device matching the configured serial, configure gain, PPM, center frequency
+    /// and sample rate, and initialize a decoder for each configured frequency.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the device cannot be found or opened, if the frequency list is
+    /// empty or spans more than 2 MHz, or if the device rejects one of the settings.
     pub fn open_sdr(
         &mut self,
-        output_channel: UnboundedSender<AssembledACARSMessage>,
+        output_channel: &UnboundedSender<AssembledACARSMessage>,
     ) -> Result<(), RTLSDRError> {
-        let mut device_index = None;
-        for dev in devices() {
-            if dev.serial() == self.serial {
-                device_index = Some(dev.index());
+        self.output_channel = Some(output_channel.clone());
+
+        let result = self.open_device(output_channel);
+        match &result {
+            Ok(()) => {
+                self.state = SdrState::Running;
+                self.last_error = None;
+            }
+            Err(e) => {
+                self.state = SdrState::Failed;
+                self.last_error = Some(e.to_string());
             }
         }
-        match device_index {
-            None => {
-                return Err(RTLSDRError::DeviceNotFound {
+        self.publish_status();
+
+        result
+    }
+
+    fn open_device(
+        &mut self,
+        output_channel: &UnboundedSender<AssembledACARSMessage>,
+    ) -> Result<(), RTLSDRError> {
+        let Some(idx) = find_device(&self.serial).map(|device| device.index()) else {
+            return Err(RTLSDRError::DeviceNotFound {
+                sdr: self.serial.clone(),
+            });
+        };
+
+        self.index = Some(idx);
+        let rtl_in_rate = self.get_intrate() * self.rtl_mult;
+        info!("[{: <13}] Using found device at index {}", self.serial, idx);
+
+        let (mut ctl, reader) = rtlsdr_mt::open(idx).map_err(|()| RTLSDRError::OpenFailed {
+            sdr: self.serial.clone(),
+        })?;
+
+        self.reader = Some(reader);
+
+        // remove any duplicate frequencies
+        // I cannot imagine we would EVER see this, but just in case
+
+        self.frequencies.dedup();
+        if self.frequencies.is_empty() {
+            return Err(RTLSDRError::NoFrequencyProvided {
+                sdr: self.serial.clone(),
+            });
+        }
+
+        self.configure_gain(&mut ctl, rtl_in_rate)?;
+
+        info!("[{: <13}] Setting PPM to {}", self.serial, self.ppm);
+        ctl.set_ppm(self.ppm)
+            .map_err(|()| self.setting_failed("ppm"))?;
+
+        if self.bias_tee {
+            warn!(
+                "[{: <13}] BiasTee is not supported right now. Maybe soon...",
+                self.serial
+            );
+        }
+        self.check_frequency_spread()?;
+
+        let center_freq = self.init_channels(output_channel, rtl_in_rate)?;
+        ctl.set_center_freq(center_freq as u32)
+            .map_err(|()| self.setting_failed("center frequency"))?;
+        self.tuned_center_frequency = Some(center_freq);
+        self.channel_enabled = [true; MAX_CHANNELS];
+
+        info!(
+            "[{: <13}] Setting sample rate to {}",
+            self.serial, rtl_in_rate
+        );
+        ctl.set_sample_rate(rtl_in_rate as u32)
+            .map_err(|()| self.setting_failed("sample rate"))?;
+
+        self.ctl.set(Some(ctl));
+
+        if let Some(metrics) = &self.metrics {
+            metrics.device_opened(&self.serial);
+        }
+        self.record_gain();
+        self.record_ppm();
+
+        Ok(())
+    }
+
+    // Verify freq spread less than MAX_FREQUENCY_SPREAD. This is much less complex than acarsdec
+    // but I fail to see how this is not equivalent with a lot less bullshit
+    fn check_frequency_spread(&self) -> Result<(), RTLSDRError> {
+        if self.frequencies.len() > 1
+            && self.frequencies[self.frequencies.len() - 1] - self.frequencies[0]
+                > MAX_FREQUENCY_SPREAD
+        {
+            return Err(RTLSDRError::FrequencySpreadTooLarge {
+                sdr: self.serial.clone(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Initialize a decoder for each configured frequency without opening a device, to
+    /// decode a capture recorded at `rtl_mult` times the decoder rate with `process_capture`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the frequency list is empty, spans more than 2 MHz, or has a
+    /// frequency outside the band around the center frequency.
+    pub fn init_capture(
+        &mut self,
+        output_channel: &UnboundedSender<AssembledACARSMessage>,
+    ) -> Result<(), RTLSDRError> {
+        self.output_channel = Some(output_channel.clone());
+        self.frequencies.dedup();
+        self.check_frequency_spread()?;
+
+        let rtl_in_rate = self.get_intrate() * self.rtl_mult;
+        let center_frequency = self.init_channels(output_channel, rtl_in_rate)?;
+        self.check_band(center_frequency, rtl_in_rate)?;
+        self.tuned_center_frequency = Some(center_frequency);
+
+        Ok(())
+    }
+
+    /// A capture may have been recorded on a center frequency that doesn't cover every
+    /// frequency asked for
+    fn check_band(&self, center_frequency: i32, rtl_in_rate: i32) -> Result<(), RTLSDRError> {
+        let intrate = self.get_intrate();
+
+        for frequency in &self.frequencies {
+            let channel = (((1_000_000.0 * frequency) as i32 + intrate / 2) / intrate) * intrate;
+            if (channel - center_frequency).abs() > rtl_in_rate / 2 {
+                return Err(RTLSDRError::FrequencyOutsideBand {
                     sdr: self.serial.clone(),
+                    frequency: *frequency,
                 });
             }
-            Some(idx) => {
-                self.index = Some(idx);
-                let rtl_in_rate = self.get_intrate() * self.rtl_mult;
-                info!("[{: <13}] Using found device at index {}", self.serial, idx);
+        }
 
-                let (mut ctl, reader) = rtlsdr_mt::open(self.index.unwrap()).unwrap();
+        Ok(())
+    }
 
-                self.reader = Some(reader);
+    /// Decode a capture of raw unsigned 8 bit IQ samples, as recorded from the device. A
+    /// partial buffer at the end of the capture is dropped. Returns the number of buffers
+    /// decoded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the capture fails.
+    pub fn process_capture(&mut self, capture: impl Read) -> io::Result<u64> {
+        self.process_iq(&mut IqReader::new(capture, SampleFormat::Cu8))
+    }
 
-                // remove any duplicate frequencies
-                // I cannot imagine we would EVER see this, but just in case
+    /// Decode a capture in any of the sample formats the `IqReader` understands. A partial
+    /// buffer at the end of the capture is dropped. Returns the number of buffers decoded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the capture fails.
+    pub fn process_iq(&mut self, reader: &mut IqReader<impl Read>) -> io::Result<u64> {
+        self.process_iq_with(reader, |_| Ok(()))
+    }
 
-                self.frequencies.dedup();
-                if self.frequencies.is_empty() {
-                    return Err(RTLSDRError::NoFrequencyProvided {
-                        sdr: self.serial.clone(),
-                    });
-                }
+    /// Decode a capture like `process_iq`, calling `decoded` with the number of samples decoded
+    /// so far after each buffer, once the messages it completed have been sent.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the capture fails, or the first error `decoded` returns.
+    pub fn process_iq_with(
+        &mut self,
+        reader: &mut IqReader<impl Read>,
+        mut decoded: impl FnMut(u64) -> io::Result<()>,
+    ) -> io::Result<u64> {
+        let rtloutbufz = self.get_rtloutbufsz();
+        let mut vb: [Complex<f32>; 320] = [Complex::new(0.0, 0.0); 320];
+        let mut buffer = vec![Complex::new(0.0, 0.0); rtloutbufz * self.rtl_mult as usize];
+        let mut buffers = 0;
 
-                if self.gain <= 500 {
-                    let mut gains = [0i32; 32];
-                    ctl.tuner_gains(&mut gains);
-                    debug!("[{: <13}] Using Gains: {:?}", self.serial, gains);
-                    let mut close_gain = gains[0];
-                    // loop through gains and see which value is closest to the desired gain
-                    for gain_value in gains {
-                        if gain_value == 0 {
-                            continue;
-                        }
-
-                        let err1 = i32::abs(self.gain - close_gain);
-                        let err2 = i32::abs(self.gain - gain_value);
-
-                        if err2 < err1 {
-                            trace!("[{: <13}] Found closer gain: {}", self.serial, gain_value);
-                            close_gain = gain_value;
-                        }
-                    }
+        while reader.read_samples(&mut buffer)? {
+            self.process_samples(buffer.iter().copied(), rtloutbufz, &mut vb);
+            buffers += 1;
+            decoded(buffers * buffer.len() as u64)?;
+        }
 
-                    if self.gain != close_gain {
-                        warn!(
-                            "[{: <13}] Input gain {} was normalized to a SDR supported gain of {}. Gain is set to the normalized gain.",
-                            self.serial, self.gain, close_gain
-                        );
-                        self.gain = close_gain;
-                    } else {
-                        info!("[{: <13}] setting gain to {}", self.serial, self.gain);
-                    }
+        Ok(buffers)
+    }
 
-                    ctl.disable_agc().unwrap();
-                    ctl.set_tuner_gain(self.gain).unwrap();
-                } else {
+    fn configure_gain(
+        &mut self,
+        ctl: &mut Controller,
+        rtl_in_rate: i32,
+    ) -> Result<(), RTLSDRError> {
+        if self.auto_gain_enabled {
+            let mut gains = [0i32; 32];
+            ctl.tuner_gains(&mut gains);
+            debug!("[{: <13}] Using Gains: {:?}", self.serial, gains);
+
+            let saved_gain = self
+                .state_dir
+                .as_ref()
+                .and_then(|state_dir| gain::load_gain(state_dir, &self.serial));
+
+            let start_gain = match saved_gain {
+                Some(saved_gain) => {
                     info!(
-                        "[{: <13}] Setting gain to Auto Gain Control (AGC)",
-                        self.serial
+                        "[{: <13}] Auto gain starting from saved gain of {}",
+                        self.serial, saved_gain
                     );
-                    ctl.enable_agc().unwrap();
+                    saved_gain
                 }
+                // The requested gain asked for AGC, so start in the middle of the gain range
+                None if self.gain > 500 => 250,
+                None => self.gain,
+            };
+
+            let auto_gain = AutoGain::new(
+                &gains,
+                start_gain,
+                rtl_in_rate as u64 * AUTO_GAIN_INTERVAL_SECONDS,
+            );
+            self.gain = auto_gain.gain().unwrap_or(start_gain);
+            self.auto_gain = Some(auto_gain);
 
-                info!("[{: <13}] Setting PPM to {}", self.serial, self.ppm);
-                ctl.set_ppm(self.ppm).unwrap();
+            info!(
+                "[{: <13}] Setting gain to Auto Gain, starting at {}",
+                self.serial, self.gain
+            );
 
-                if self.bias_tee {
-                    warn!(
-                        "[{: <13}] BiasTee is not supported right now. Maybe soon...",
-                        self.serial
-                    );
+            ctl.disable_agc()
+                .map_err(|()| self.setting_failed("manual gain mode"))?;
+            ctl.set_tuner_gain(self.gain)
+                .map_err(|()| self.setting_failed("tuner gain"))?;
+        } else if self.gain <= 500 {
+            let mut gains = [0i32; 32];
+            ctl.tuner_gains(&mut gains);
+            debug!("[{: <13}] Using Gains: {:?}", self.serial, gains);
+            let mut close_gain = gains[0];
+            // loop through gains and see which value is closest to the desired gain
+            for gain_value in gains {
+                if gain_value == 0 {
+                    continue;
                 }
-                // Verify freq spread less than 2mhz. This is much less complex than acarsdec
-                // but I fail to see how this is not equivalent with a lot less bullshit
 
-                if self.frequencies.len() > 1
-                    && self.frequencies[self.frequencies.len() - 1] - self.frequencies[0] > 2.0
-                {
-                    return Err(RTLSDRError::FrequencySpreadTooLarge {
-                        sdr: self.serial.clone(),
-                    });
-                }
+                let err1 = i32::abs(self.gain - close_gain);
+                let err2 = i32::abs(self.gain - gain_value);
 
-                match self.init_channels(output_channel, rtl_in_rate) {
-                    Ok(center_freq) => {
-                        ctl.set_center_freq(center_freq as u32).unwrap();
-                    }
-                    Err(e) => {
-                        return Err(e);
-                    }
+                if err2 < err1 {
+                    trace!("[{: <13}] Found closer gain: {}", self.serial, gain_value);
+                    close_gain = gain_value;
                 }
+            }
 
-                info!(
-                    "[{: <13}] Setting sample rate to {}",
-                    self.serial, rtl_in_rate
+            if self.gain == close_gain {
+                info!("[{: <13}] setting gain to {}", self.serial, self.gain);
+            } else {
+                warn!(
+                    "[{: <13}] Input gain {} was normalized to a SDR supported gain of {}. Gain is set to the normalized gain.",
+                    self.serial, self.gain, close_gain
                 );
-                ctl.set_sample_rate(rtl_in_rate as u32).unwrap();
-
-                self.ctl = Some(ctl);
+                self.gain = close_gain;
             }
-        };
+
+            ctl.disable_agc()
+                .map_err(|()| self.setting_failed("manual gain mode"))?;
+            ctl.set_tuner_gain(self.gain)
+                .map_err(|()| self.setting_failed("tuner gain"))?;
+        } else {
+            info!(
+                "[{: <13}] Setting gain to Auto Gain Control (AGC)",
+                self.serial
+            );
+            ctl.enable_agc()
+                .map_err(|()| self.setting_failed("automatic gain control"))?;
+        }
 
         Ok(())
     }
 
-    pub fn close_sdr(self) {
-        match self.ctl {
-            None => {
-                error!("[{: <13}] Device not open", self.serial);
-            }
-            Some(mut ctl) => {
-                ctl.cancel_async_read();
-            }
+    fn setting_failed(&self, setting: &str) -> RTLSDRError {
+        RTLSDRError::SettingFailed {
+            sdr: self.serial.clone(),
+            setting: setting.to_string(),
         }
     }
 
-    // TODO: This function is a duplicate of the callback in read_samples. This should be refactored
+    pub fn close_sdr(self) {
+        if self.ctl.with(Controller::cancel_async_read).is_none() {
+            error!("[{: <13}] Device not open", self.serial);
+        }
+    }
 
+    /// Mix a buffer of raw unsigned 8 bit IQ samples down to each channel and run the decoders.
     pub fn process_bytes(&mut self, bytes: &[u8], rtloutbufz: usize, vb: &mut [Complex<f32>]) {
-        let mut bytes_iterator = bytes.iter();
+        if let Some(auto_gain) = self.auto_gain.as_mut() {
+            auto_gain.add_samples(bytes);
+        }
+
+        let samples = bytes.chunks_exact(2).map(iq::cu8_to_complex);
+
+        self.process_samples(samples, rtloutbufz, vb);
+    }
+
+    /// Mix a buffer of samples down to each channel and run the decoders. The samples are
+    /// scaled like unsigned 8 bit samples with the offset removed, see the `iq` module.
+    pub fn process_samples(
+        &mut self,
+        mut samples: impl Iterator<Item = Complex<f32>>,
+        rtloutbufz: usize,
+        vb: &mut [Complex<f32>],
+    ) {
+        self.handle_commands();
 
         for m in 0..rtloutbufz {
             for vb_item in vb.iter_mut().take(self.rtl_mult as usize) {
-                *vb_item = (bytes_iterator.next().expect("Ran out of bytes!").to_owned() as f32
-                    - 127.37)
-                    + (bytes_iterator.next().expect("Ran out of bytes!").to_owned() as f32
-                        - 127.37)
-                        * Complex::i();
+                let Some(sample) = samples.next() else {
+                    error!("[{: <13}] Ran out of bytes!", self.serial);
+                    if let Some(metrics) = &self.metrics {
+                        metrics.dropped_sample_buffer(&self.serial);
+                    }
+                    return;
+                };
+                *vb_item = sample;
             }
 
-            for channel in &mut self.channel.iter_mut().take(self.frequencies.len()) {
+            for (channel, _) in self
+                .channel
+                .iter_mut()
+                .zip(self.channel_enabled)
+                .take(self.frequencies.len())
+                .filter(|(_, enabled)| *enabled)
+            {
                 let mut d: Complex<f32> = Complex::new(0.0, 0.0);
 
                 for (wf, vb_item) in vb
@@ -342,67 +702,440 @@ impl RtlSdr {
                     d += vb_item * wf;
                 }
 
-                channel.set_dm_buffer_at_index(m, d.norm());
+                channel.set_dm_buffer_at_index(m, d);
             }
         }
-        for channel in &mut self.channel.iter_mut().take(self.frequencies.len()) {
+        for (channel, _) in self
+            .channel
+            .iter_mut()
+            .zip(self.channel_enabled)
+            .take(self.frequencies.len())
+            .filter(|(_, enabled)| *enabled)
+        {
             channel.decode(rtloutbufz);
         }
+
+        if let Some(metrics) = &self.metrics {
+            metrics.sample_buffer(&self.serial);
+        }
+
+        self.update_ppm();
+        self.update_gain();
+
+        if let Some(shared_statistics) = &self.shared_statistics {
+            if let Ok(mut shared_statistics) = shared_statistics.lock() {
+                *shared_statistics = self.get_channel_statistics();
+            }
+        }
+        self.publish_status();
+    }
+
+    fn update_gain(&mut self) {
+        let noise_levels: Vec<f32> = self
+            .get_channel_statistics()
+            .iter()
+            .filter_map(|statistics| statistics.noise_level)
+            .collect();
+
+        let noise_level = if noise_levels.is_empty() {
+            None
+        } else {
+            Some(noise_levels.iter().sum::<f32>() / noise_levels.len() as f32)
+        };
+
+        let Some(auto_gain) = self.auto_gain.as_mut() else {
+            return;
+        };
+
+        let was_settled = auto_gain.is_settled();
+        let new_gain = auto_gain.evaluate(noise_level);
+        let settled = auto_gain.is_settled();
+
+        if let Some(new_gain) = new_gain {
+            let Some(result) = self.ctl.with(|ctl| ctl.set_tuner_gain(new_gain)) else {
+                return;
+            };
+
+            if result.is_err() {
+                error!(
+                    "[{: <13}] Auto gain unable to set gain to {}",
+                    self.serial, new_gain
+                );
+                return;
+            }
+
+            info!(
+                "[{: <13}] Auto gain set gain to {}{}",
+                self.serial,
+                new_gain,
+                if settled { " and settled" } else { "" }
+            );
+            self.gain = new_gain;
+            self.record_gain();
+        }
+
+        // Only a settled gain is worth starting from next time, not a step of the climb
+        if settled && (new_gain.is_some() || !was_settled) {
+            if let Some(state_dir) = &self.state_dir {
+                if let Err(e) = gain::save_gain(state_dir, &self.serial, self.gain) {
+                    warn!("[{: <13}] Unable to save auto gain: {}", self.serial, e);
+                }
+            }
+        }
+    }
+
+    /// The tuner gain, or `None` while the tuner is on AGC
+    fn manual_gain(&self) -> Option<i32> {
+        // Without auto gain, a gain above 500 puts the tuner on AGC
+        (self.auto_gain.is_some() || self.gain <= 500).then_some(self.gain)
+    }
+
+    fn record_gain(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.set_gain(&self.serial, self.manual_gain());
+        }
     }
 
-    pub async fn read_samples(mut self) {
+    fn publish_status(&self) {
+        let Some(status) = &self.status else {
+            return;
+        };
+
+        let channels = self
+            .frequencies
+            .iter()
+            .zip(self.get_channel_statistics())
+            .zip(self.channel_enabled)
+            .map(|((frequency, statistics), enabled)| {
+                ChannelStatus::new(*frequency, &statistics, enabled)
+            })
+            .collect();
+
+        if let Ok(mut status) = status.lock() {
+            *status = SdrStatus {
+                serial: self.serial.clone(),
+                state: self.state,
+                gain: self.manual_gain().map(|gain| gain as f32 / 10.0),
+                auto_gain: self.auto_gain.is_some(),
+                ppm: self.ppm,
+                auto_ppm: self.auto_ppm,
+                center_frequency: self
+                    .tuned_center_frequency
+                    .map(|center_frequency| center_frequency as f32 / 1_000_000.0),
+                channels,
+                last_error: self.last_error.clone(),
+            };
+        }
+    }
+
+    fn not_running(&self) -> RTLSDRError {
+        RTLSDRError::NotRunning {
+            sdr: self.serial.clone(),
+        }
+    }
+
+    /// Apply the commands queued by the `SdrControl`
+    fn handle_commands(&mut self) {
+        let Some(commands) = &self.commands else {
+            return;
+        };
+
+        let commands: Vec<SdrCommand> = commands.try_iter().collect();
+        if commands.is_empty() {
+            return;
+        }
+
+        for command in commands {
+            info!("[{: <13}] Applying {:?}", self.serial, command);
+
+            if let Err(e) = self.apply_command(command) {
+                error!("[{: <13}] {}", self.serial, e);
+                self.last_error = Some(e.to_string());
+            }
+        }
+
+        self.publish_status();
+    }
+
+    fn apply_command(&mut self, command: SdrCommand) -> Result<(), RTLSDRError> {
+        match command {
+            SdrCommand::SetGain(gain) => self.set_gain(gain),
+            SdrCommand::SetPpm(ppm) => self.set_ppm(ppm),
+            SdrCommand::Retune(frequencies) => self.retune(frequencies),
+            SdrCommand::SetChannelEnabled { frequency, enabled } => {
+                let Some(index) =
+                    control::find_channel(self.frequencies.iter().copied(), frequency)
+                else {
+                    return Err(RTLSDRError::UnknownChannel {
+                        sdr: self.serial.clone(),
+                        frequency,
+                    });
+                };
+
+                self.channel_enabled[index] = enabled;
+                Ok(())
+            }
+            SdrCommand::Restart => {
+                if self.ctl.with(Controller::cancel_async_read).is_none() {
+                    return Err(self.not_running());
+                }
+
+                self.restart = true;
+                Ok(())
+            }
+        }
+    }
+
+    fn set_gain(&mut self, gain: Option<i32>) -> Result<(), RTLSDRError> {
+        let Some(mut ctl) = self.ctl.take() else {
+            return Err(self.not_running());
+        };
+
+        self.auto_gain_enabled = false;
+        self.auto_gain = None;
+        self.gain = gain.unwrap_or(AGC_GAIN);
+
+        let result = self.configure_gain(&mut ctl, self.get_intrate() * self.rtl_mult);
+        self.ctl.set(Some(ctl));
+        self.record_gain();
+
+        result
+    }
+
+    fn set_ppm(&mut self, ppm: i32) -> Result<(), RTLSDRError> {
+        let Some(result) = self.ctl.with(|ctl| ctl.set_ppm(ppm)) else {
+            return Err(self.not_running());
+        };
+
+        if result.is_err() {
+            return Err(self.setting_failed("ppm"));
+        }
+
+        info!("[{: <13}] Set PPM to {}", self.serial, ppm);
+        self.ppm = ppm;
+        self.ppm_estimator.reset();
+        self.record_ppm();
+
+        Ok(())
+    }
+
+    /// Decode `frequencies` instead. If they can't be tuned, the previous frequencies are kept.
+    fn retune(&mut self, mut frequencies: Vec<f32>) -> Result<(), RTLSDRError> {
+        let Some(output_channel) = self.output_channel.clone() else {
+            return Err(self.not_running());
+        };
+
+        frequencies.sort_by(f32::total_cmp);
+        frequencies.dedup();
+        let previous = std::mem::replace(&mut self.frequencies, frequencies);
+
+        if let Err(e) = self.tune(&output_channel) {
+            self.frequencies = previous;
+            if let Err(e) = self.tune(&output_channel) {
+                error!(
+                    "[{: <13}] Unable to go back to the previous frequencies: {}",
+                    self.serial, e
+                );
+            }
+            return Err(e);
+        }
+
+        info!("[{: <13}] Retuned to {:?}", self.serial, self.frequencies);
+
+        Ok(())
+    }
+
+    fn tune(
+        &mut self,
+        output_channel: &UnboundedSender<AssembledACARSMessage>,
+    ) -> Result<(), RTLSDRError> {
+        self.check_frequency_spread()?;
+        let center_freq = self.init_channels(output_channel, self.get_intrate() * self.rtl_mult)?;
+
+        if self.ctl.with(|ctl| ctl.set_center_freq(center_freq as u32)) == Some(Err(())) {
+            return Err(self.setting_failed("center frequency"));
+        }
+
+        self.tuned_center_frequency = Some(center_freq);
+        self.channel_enabled = [true; MAX_CHANNELS];
+        self.ppm_estimator.reset();
+
+        Ok(())
+    }
+
+    fn record_ppm(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.set_ppm(&self.serial, self.ppm);
+        }
+    }
+
+    /// Current reception statistics for each configured channel.
+    #[must_use]
+    pub fn get_channel_statistics(&self) -> Vec<ChannelStatistics> {
+        self.channel
+            .iter()
+            .take(self.frequencies.len())
+            .map(|channel| channel.get_channel_statistics())
+            .collect()
+    }
+
+    fn update_ppm(&mut self) {
+        for (channel, frequency) in self.channel.iter_mut().zip(&self.frequencies) {
+            if let Some(offset) = channel.take_frequency_offset() {
+                self.ppm_estimator.add_offset(offset, *frequency);
+            }
+        }
+
+        let Some((estimate, standard_error)) = self.ppm_estimator.estimate() else {
+            return;
+        };
+
+        let Some(correction) = self.ppm_estimator.correction() else {
+            return;
+        };
+
+        let new_ppm = self.ppm + correction;
+
+        info!(
+            "[{: <13}] Estimated frequency error of {:.2} (+/- {:.2}) PPM over {} messages. PPM should be {}",
+            self.serial,
+            estimate,
+            standard_error,
+            self.ppm_estimator.len(),
+            new_ppm
+        );
+
+        if !self.auto_ppm {
+            self.ppm_estimator.reset();
+            return;
+        }
+
+        let Some(result) = self.ctl.with(|ctl| ctl.set_ppm(new_ppm)) else {
+            return;
+        };
+
+        if result.is_ok() {
+            info!("[{: <13}] Auto PPM set PPM to {}", self.serial, new_ppm);
+            self.ppm = new_ppm;
+            self.record_ppm();
+        } else {
+            error!(
+                "[{: <13}] Auto PPM unable to set PPM to {}",
+                self.serial, new_ppm
+            );
+        }
+
+        self.ppm_estimator.reset();
+    }
+
+    /// Read samples from the device until the read is cancelled or the shutdown flag is set.
+    /// Commands sent through the `SdrControl` cancel the read and are applied before reading
+    /// again, and a restarted device is reopened, retrying until it opens.
+    /// This blocks the calling thread.
+    pub fn read_samples(mut self) {
+        loop {
+            self.read_until_cancelled();
+
+            if self.ctl.take_woken() && !self.is_shutdown() {
+                self.handle_commands();
+                if !self.restart {
+                    continue;
+                }
+            }
+
+            if let Some(metrics) = &self.metrics {
+                metrics.device_closed(&self.serial);
+            }
+
+            if !self.restart || self.is_shutdown() || !self.reopen() {
+                break;
+            }
+        }
+
+        self.state = SdrState::Stopped;
+        self.publish_status();
+    }
+
+    fn is_shutdown(&self) -> bool {
+        self.shutdown
+            .as_ref()
+            .map_or(false, |shutdown| shutdown.load(Ordering::Relaxed))
+    }
+
+    /// Close the device and open it again. Returns false if shut down before it opened.
+    fn reopen(&mut self) -> bool {
+        self.restart = false;
+        // Dropping both the reader and the controller closes the device
+        self.reader = None;
+        self.ctl.set(None);
+        self.state = SdrState::Restarting;
+        self.publish_status();
+
+        let Some(output_channel) = self.output_channel.clone() else {
+            return false;
+        };
+
+        loop {
+            std::thread::sleep(RESTART_DELAY);
+
+            if self.is_shutdown() {
+                return false;
+            }
+
+            info!("[{: <13}] Reopening device", self.serial);
+            match self.open_sdr(&output_channel) {
+                Ok(()) => return true,
+                Err(e) => error!("[{: <13}] Unable to reopen device: {}", self.serial, e),
+            }
+        }
+    }
+
+    fn read_until_cancelled(&mut self) {
         let rtloutbufz = self.get_rtloutbufsz();
         let buffer_len: u32 = rtloutbufz as u32 * self.rtl_mult as u32 * 2;
         let mut vb: [Complex<f32>; 320] = [Complex::new(0.0, 0.0); 320];
 
-        match self.reader {
-            None => {
-                error!("[{: <13}] Device not open", self.serial);
-            }
+        let Some(mut reader) = self.reader.take() else {
+            error!("[{: <13}] Device not open", self.serial);
+            return;
+        };
 
-            Some(mut reader) => {
-                reader
-                    .read_async(4, buffer_len, |bytes: &[u8]| {
-                        let mut bytes_iterator = bytes.iter();
-                        println!("Read {} bytes", bytes.len());
-
-                        for m in 0..rtloutbufz {
-                            for vb_item in vb.iter_mut().take(self.rtl_mult as usize) {
-                                *vb_item = (*bytes_iterator.next().expect("Ran out of bytes!")
-                                    as f32
-                                    - 127.37_f32)
-                                    + (*bytes_iterator.next().expect("Ran out of bytes!") as f32
-                                        - 127.37_f32)
-                                        * Complex::i();
-                            }
-
-                            for channel in &mut self.channel.iter_mut().take(self.frequencies.len())
-                            {
-                                let mut d: Complex<f32> = Complex::new(0.0, 0.0);
-
-                                for (wf, vb_item) in vb
-                                    .iter()
-                                    .zip(channel.get_wf_iter())
-                                    .take(self.rtl_mult as usize)
-                                {
-                                    d += vb_item * wf;
-                                }
-
-                                channel.set_dm_buffer_at_index(m, d.norm());
-                            }
-                        }
-                        for channel in &mut self.channel.iter_mut().take(self.frequencies.len()) {
-                            channel.decode(rtloutbufz);
-                        }
-                    })
-                    .unwrap();
-            }
+        if reader
+            .read_async(4, buffer_len, |bytes: &[u8]| {
+                trace!("[{: <13}] Read {} bytes", self.serial, bytes.len());
+
+                // Buffers can still arrive after a restart has cancelled the read
+                if self.restart {
+                    return;
+                }
+
+                // A command queued before the read started may have missed it
+                if self.is_shutdown() || self.ctl.is_woken() {
+                    self.ctl.with(Controller::cancel_async_read);
+                    return;
+                }
+
+                self.process_bytes(bytes, rtloutbufz, &mut vb);
+            })
+            .is_err()
+        {
+            error!("[{: <13}] Error reading samples from device", self.serial);
         }
+
+        // Kept to read again once woken commands have been applied
+        self.reader = Some(reader);
     }
 
+    #[must_use]
     pub fn get_serial(&self) -> &str {
         &self.serial
     }
+
+    /// Samples per second read from the device, and expected in a capture
+    #[must_use]
+    pub const fn get_sample_rate(&self) -> u32 {
+        self.get_intrate().unsigned_abs() * self.rtl_mult.unsigned_abs()
+    }
 }
 
 #[derive(Debug)]
@@ -414,7 +1147,7 @@ pub struct DeviceAttributes {
 }
 
 impl DeviceAttributes {
-    fn new(index: u32, vendor: String, product: String, serial: String) -> Self {
+    const fn new(index: u32, vendor: String, product: String, serial: String) -> Self {
         Self {
             vendor,
             product,
@@ -423,18 +1156,22 @@ impl DeviceAttributes {
         }
     }
 
-    pub fn index(&self) -> u32 {
+    #[must_use]
+    pub const fn index(&self) -> u32 {
         self.index
     }
 
+    #[must_use]
     pub fn vendor(&self) -> &str {
         &self.vendor
     }
 
+    #[must_use]
     pub fn product(&self) -> &str {
         &self.product
     }
 
+    #[must_use]
     pub fn serial(&self) -> &str {
         &self.serial
     }
@@ -449,9 +1186,15 @@ impl Display for DeviceAttributes {
     }
 }
 
+/// Find the device with `serial`. If several devices share the serial, the last one is used.
+#[must_use]
+pub fn find_device(serial: &str) -> Option<DeviceAttributes> {
+    devices().filter(|device| device.serial() == serial).last()
+}
+
 /// Create an iterator over available RTL-SDR devices.
 ///
-/// The iterator yields a DeviceAttributes in index order, so the device with the first yielded
+/// The iterator yields a `DeviceAttributes` in index order, so the device with the first yielded
 /// name can be opened at index 0, and so on.
 pub fn devices() -> impl Iterator<Item = DeviceAttributes> {
     let count = unsafe { rtlsdr_sys::rtlsdr_get_device_count() };
@@ -462,14 +1205,14 @@ pub fn devices() -> impl Iterator<Item = DeviceAttributes> {
         let mut vendor_space = [0u8; 256];
         let mut product_space = [0u8; 256];
         let mut serial_space = [0u8; 256];
-        let vendor: *mut c_char = vendor_space.as_mut_ptr() as *mut c_char;
-        let product: *mut c_char = product_space.as_mut_ptr() as *mut c_char;
-        let serial: *mut c_char = serial_space.as_mut_ptr() as *mut c_char;
+        let vendor: *mut c_char = vendor_space.as_mut_ptr().cast::<c_char>();
+        let product: *mut c_char = product_space.as_mut_ptr().cast::<c_char>();
+        let serial: *mut c_char = serial_space.as_mut_ptr().cast::<c_char>();
 
         unsafe { rtlsdr_sys::rtlsdr_get_device_usb_strings(idx, vendor, product, serial) };
-        let safe_vendor = unsafe { CStr::from_ptr(vendor).to_str().unwrap() };
-        let safe_product = unsafe { CStr::from_ptr(product).to_str().unwrap() };
-        let safe_serial = unsafe { CStr::from_ptr(serial).to_str().unwrap() };
+        let safe_vendor = unsafe { CStr::from_ptr(vendor).to_string_lossy() };
+        let safe_product = unsafe { CStr::from_ptr(product).to_string_lossy() };
+        let safe_serial = unsafe { CStr::from_ptr(serial).to_string_lossy() };
 
         devices.push(DeviceAttributes::new(
             idx,
@@ -480,8 +1223,8 @@ pub fn devices() -> impl Iterator<Item = DeviceAttributes> {
     }
 
     debug!("[DEVICE INIT  ] Found {} RTL-SDR devices", devices.len());
-    for device in devices.iter() {
-        debug!("[DEVICE INIT  ] {}", device);
+    for device in &devices {
+        debug!("[DEVICE INIT  ] {device}");
     }
 
     devices.into_iter()
@@ -491,6 +1234,9 @@ pub fn devices() -> impl Iterator<Item = DeviceAttributes> {
 mod tests {
     use acars::AckStatus::{Ack, Nack};
     use acars::DownlinkStatus::{AirToGround, GroundToAir};
+    use oxide_decoders::decoders::labels::{LabelContent, PositionReport, ReportTime};
+    use oxide_decoders::decoders::modulator::{downlink_block, to_cu8, AcarsBlock, AcarsModulator};
+    use oxide_decoders::decoders::squitter::{GroundStation, GroundStationNetwork};
     use std::{fs::File, io::Read};
 
     use tokio::sync::mpsc;
@@ -500,6 +1246,7 @@ mod tests {
     // TODO: Grab a sample that includes a parity error
 
     #[test]
+    #[allow(clippy::too_many_lines)]
     fn test_acars_samples() -> Result<(), Box<dyn std::error::Error>> {
         let ppm = 0;
         let gain = 421;
@@ -508,8 +1255,9 @@ mod tests {
         let frequencies = [130.025, 130.45, 131.125, 131.55];
         let decoder_type = ValidDecoderType::ACARS;
 
-        let valid_acars_messages = [
+        let valid_acars_messages = vec![
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '5', '3', '4', 'U', 'W', ' ']),
                 acknowledgement: acars::AckStatus::Nack,
@@ -522,14 +1270,23 @@ mod tests {
                 block_start: '\u{2}',
                 block_end: '\u{3}',
                 message_text: None,
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -11.8,
+                noise_level: None,
+                snr: None,
                 frequency: 131.55,
+                frequency_offset: 0.0,
                 downlink_status: acars::DownlinkStatus::AirToGround,
                 message_number_without_sequence: Some(['S', '3', '3']),
                 message_number_sequence: Some('A'),
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '6', '6', '0', 'A', 'W', ' ']),
                 acknowledgement: Nack,
@@ -542,14 +1299,23 @@ mod tests {
                 block_start: '\u{2}',
                 block_end: '\u{3}',
                 message_text: None,
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -12.8,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: AirToGround,
                 message_number_without_sequence: Some(['S', '5', '8']),
                 message_number_sequence: Some('A'),
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '1', '4', '2', '4', '9', ' ']),
                 acknowledgement: Nack,
@@ -562,14 +1328,23 @@ mod tests {
                 block_start: '\u{2}',
                 block_end: '\u{3}',
                 message_text: Some(vec!['R', 'E', 'Q', 'P', 'R', 'G', 'C', '7', '4', 'C']),
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -29.8,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: GroundToAir,
                 message_number_without_sequence: None,
                 message_number_sequence: None,
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '9', '6', '1', 'S', 'W', ' ']),
                 acknowledgement: Nack,
@@ -598,14 +1373,23 @@ mod tests {
                     'L', 'D', 'R', 'I', 'D', 'G', 'E', ' ', ' ', ' ', ' ', '\r', '\n', ' ', '2',
                     ' ', ' ', ' ', '5', '0',
                 ]),
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -29.9,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: GroundToAir,
                 message_number_without_sequence: None,
                 message_number_sequence: None,
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '5', '3', '4', 'U', 'W', ' ']),
                 acknowledgement: Nack,
@@ -632,14 +1416,23 @@ mod tests {
                     '1', '0', '5', '4', '7', '2', '0', ',', '2', '5', '9', ',', '0', '1', '6', '3',
                     '2', ',', '4', '1', '7', ',', '0', '3', '5', '2', ',', '1', '/', 'C',
                 ]),
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -12.8,
+                noise_level: None,
+                snr: None,
                 frequency: 131.55,
+                frequency_offset: 0.0,
                 downlink_status: AirToGround,
                 message_number_without_sequence: Some(['D', '7', '5']),
                 message_number_sequence: Some('A'),
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '6', '6', '0', 'A', 'W', ' ']),
                 acknowledgement: Ack('0'),
@@ -652,14 +1445,23 @@ mod tests {
                 block_start: '\u{3}',
                 block_end: '\u{3}',
                 message_text: Some(vec!['\0']),
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -30.5,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: GroundToAir,
                 message_number_without_sequence: None,
                 message_number_sequence: None,
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '9', '2', '3', 'U', 'S', ' ']),
                 acknowledgement: Nack,
@@ -677,14 +1479,43 @@ mod tests {
                     'H', 'A', 'H', 'A', 'A', ',', '0', '1', '0', '9', '0', '7', ',', ',', 'M', '4',
                     '4', ',', '2', '4', '5', '5', '9', ',', '1', '3', '8', 'E', 'C', 'B', '0',
                 ]),
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -27.6,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: AirToGround,
                 message_number_without_sequence: Some(['F', '7', '6']),
                 message_number_sequence: Some('A'),
+                arinc622: None,
+                miam: None,
+                label_content: Some(LabelContent::Position(PositionReport {
+                    latitude: 35.0 + 28.6 / 60.0,
+                    longitude: -(108.0 + 52.5 / 60.0),
+                    altitude: Some(32000),
+                    waypoint: Some("GUP".to_string()),
+                    time: Some(ReportTime {
+                        hour: 0,
+                        minute: 47,
+                        second: Some(29),
+                    }),
+                    next_waypoint: Some("HAHAA".to_string()),
+                    next_waypoint_eta: Some(ReportTime {
+                        hour: 1,
+                        minute: 9,
+                        second: Some(7),
+                    }),
+                    following_waypoint: None,
+                    temperature: Some(-44),
+                    wind_direction: Some(245),
+                    wind_speed: Some(59),
+                })),
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '9', '2', '3', 'U', 'S', ' ']),
                 acknowledgement: Ack('7'),
@@ -699,14 +1530,23 @@ mod tests {
                 message_text: Some(vec![
                     'R', 'E', 'Q', 'P', 'E', 'R', ',', 'P', 'R', 'F', 'E', '3', '6',
                 ]),
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -30.9,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: GroundToAir,
                 message_number_without_sequence: None,
                 message_number_sequence: None,
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '9', '2', '3', 'U', 'S', ' ']),
                 acknowledgement: Ack('J'),
@@ -719,14 +1559,23 @@ mod tests {
                 block_start: '\u{2}',
                 block_end: '\u{3}',
                 message_text: None,
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -30.1,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: AirToGround,
                 message_number_without_sequence: Some(['S', '8', '9']),
                 message_number_sequence: Some('A'),
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '9', '2', '3', 'U', 'S', ' ']),
                 acknowledgement: Nack,
@@ -742,14 +1591,23 @@ mod tests {
                     'R', 'E', 'S', 'R', 'E', 'Q', '/', 'A', 'K', ',', '1', '1', '5', '8', 'A', 'F',
                     '6',
                 ]),
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -31.8,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: AirToGround,
                 message_number_without_sequence: Some(['F', '7', '7']),
                 message_number_sequence: Some('A'),
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '1', '1', '1', '7', '6', ' ']),
                 acknowledgement: Nack,
@@ -776,14 +1634,23 @@ mod tests {
                     '8', '7', 'F', 'T', ' ', 'V', '2', 'P', ' ', '1', '4', '8', 'D', '5', '6', 'F',
                     '\r', '\n',
                 ]),
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -30.8,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: GroundToAir,
                 message_number_without_sequence: None,
                 message_number_sequence: None,
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '1', '1', '1', '7', '6', ' ']),
                 acknowledgement: Ack('L'),
@@ -802,14 +1669,23 @@ mod tests {
                     'L', ',', '9', ',', '0', ',', '0', ',', ',', ',', ',', ',', '4', '3', '.', '8',
                     ',', '0', ',', '0', ',', '0', ',', ',',
                 ]),
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -29.8,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: AirToGround,
                 message_number_without_sequence: Some(['M', '4', '2']),
                 message_number_sequence: Some('A'),
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '1', '1', '1', '7', '6', ' ']),
                 acknowledgement: Ack('1'),
@@ -822,14 +1698,23 @@ mod tests {
                 block_start: '\u{3}',
                 block_end: '\u{3}',
                 message_text: Some(vec!['\0']),
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -30.3,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: GroundToAir,
                 message_number_without_sequence: None,
                 message_number_sequence: None,
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '4', '6', '6', 'U', 'A', ' ']),
                 acknowledgement: Ack('9'),
@@ -842,14 +1727,23 @@ mod tests {
                 block_start: '\u{3}',
                 block_end: '\u{3}',
                 message_text: Some(vec!['\0']),
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -29.9,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: GroundToAir,
                 message_number_without_sequence: None,
                 message_number_sequence: None,
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '4', '6', '6', 'U', 'A', ' ']),
                 acknowledgement: Nack,
@@ -876,14 +1770,23 @@ mod tests {
                     '0', '7', '8', '9', ',', '0', '8', '5', '0', ',', '1', '2', '7', '4', ',', '1',
                     ',', '1', ',', '1', '/', '1', 'N', '0', '7', '8', '4', ',', '0', '8',
                 ]),
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -22.9,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: AirToGround,
                 message_number_without_sequence: Some(['D', '9', '6']),
                 message_number_sequence: Some('D'),
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '5', '7', '2', 'U', 'W', ' ']),
                 acknowledgement: Ack('8'),
@@ -896,14 +1799,23 @@ mod tests {
                 block_start: '\u{3}',
                 block_end: '\u{3}',
                 message_text: Some(vec!['\0']),
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -30.2,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: GroundToAir,
                 message_number_without_sequence: None,
                 message_number_sequence: None,
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: None,
                 acknowledgement: Nack,
@@ -920,14 +1832,33 @@ mod tests {
                     'N', '1', '0', '6', '3', '7', 'W', 'V', '1', '3', '6', '9', '7', '5', '/', 'A',
                     'R', 'I', 'N', 'C',
                 ]),
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -20.2,
+                noise_level: None,
+                snr: None,
                 frequency: 131.55,
+                frequency_offset: 0.0,
                 downlink_status: GroundToAir,
                 message_number_without_sequence: None,
                 message_number_sequence: None,
+                arinc622: None,
+                miam: None,
+                label_content: Some(LabelContent::GroundStation(GroundStation {
+                    version: 2,
+                    network: GroundStationNetwork::Arinc,
+                    iata: Some("ABQ".to_string()),
+                    icao: Some("KABQ".to_string()),
+                    station_number: Some(1),
+                    latitude: Some(35.0 + 2.0 / 60.0),
+                    longitude: Some(-(106.0 + 37.0 / 60.0)),
+                    vdl_frequency: Some(136.975),
+                    text: Some("ARINC".to_string()),
+                })),
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '5', '7', '2', 'U', 'W', ' ']),
                 acknowledgement: Nack,
@@ -954,14 +1885,23 @@ mod tests {
                     '2', ',', '2', '3', '5', '7', '1', ',', '1', '9', '4', '7', '8', ',', '-', '3',
                     '4', '/', 'E', '1', '0', '0', '0', '0', '2', '9', '4', ',', '0', '0',
                 ]),
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -24.8,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: AirToGround,
                 message_number_without_sequence: Some(['D', '5', '5']),
                 message_number_sequence: Some('B'),
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '1', '1', '4', 'U', 'W', ' ']),
                 acknowledgement: Nack,
@@ -988,14 +1928,23 @@ mod tests {
                     'O', ' ', 'C', 'R', 'E', 'W', ' ', 'C', 'N', 'X', '\r', '\n', 'E', 'N', 'D',
                     '\r', '\n',
                 ]),
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -30.2,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: GroundToAir,
                 message_number_without_sequence: None,
                 message_number_sequence: None,
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '1', '1', '4', 'U', 'W', ' ']),
                 acknowledgement: Ack('O'),
@@ -1008,14 +1957,23 @@ mod tests {
                 block_start: '\u{2}',
                 block_end: '\u{3}',
                 message_text: None,
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -11.2,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: AirToGround,
                 message_number_without_sequence: Some(['S', '9', '5']),
                 message_number_sequence: Some('A'),
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '6', '5', '3', 'A', 'W', ' ']),
                 acknowledgement: Ack('4'),
@@ -1028,14 +1986,23 @@ mod tests {
                 block_start: '\u{3}',
                 block_end: '\u{3}',
                 message_text: Some(vec!['\0']),
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -30.3,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: GroundToAir,
                 message_number_without_sequence: None,
                 message_number_sequence: None,
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '6', '5', '3', 'A', 'W', ' ']),
                 acknowledgement: Nack,
@@ -1060,14 +2027,23 @@ mod tests {
                     '3', '/', 'C', '9', '0', '0', '0', '0', '8', '7', '6', ',', '0', '0', '0', '0',
                     '0', '7', '0', ',', '0', '5', '3', '7', ',', '2', '4', '7', '9', '6', ',', '1',
                 ]),
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -20.0,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: AirToGround,
                 message_number_without_sequence: Some(['D', '3', '4']),
                 message_number_sequence: Some('B'),
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '1', '4', '2', '2', '8', ' ']),
                 acknowledgement: Nack,
@@ -1080,14 +2056,23 @@ mod tests {
                 block_start: '\u{2}',
                 block_end: '\u{3}',
                 message_text: None,
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -31.4,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: AirToGround,
                 message_number_without_sequence: Some(['S', '6', '2']),
                 message_number_sequence: Some('A'),
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '3', '4', '1', 'F', 'R', ' ']),
                 acknowledgement: Ack('0'),
@@ -1100,14 +2085,23 @@ mod tests {
                 block_start: '\u{3}',
                 block_end: '\u{3}',
                 message_text: Some(vec!['\0']),
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -30.2,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: GroundToAir,
                 message_number_without_sequence: None,
                 message_number_sequence: None,
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '6', '5', '3', 'A', 'W', ' ']),
                 acknowledgement: Ack('5'),
@@ -1120,14 +2114,23 @@ mod tests {
                 block_start: '\u{3}',
                 block_end: '\u{3}',
                 message_text: Some(vec!['\0']),
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -30.1,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: GroundToAir,
                 message_number_without_sequence: None,
                 message_number_sequence: None,
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
             acars::AssembledACARSMessage {
+                received: None,
                 mode: '2',
                 aircraft_tail: Some(['N', '6', '5', '3', 'A', 'W', ' ']),
                 acknowledgement: Nack,
@@ -1150,12 +2153,20 @@ mod tests {
                     '0', '1', ',', '-', '0', '0', '1', '0', ',', '0', '0', ',', '2', '8', '6', '/',
                     'E', '3', '0', '0', '0', ',', '0', '1', '/',
                 ]),
+                status: acars::MessageStatus::Valid,
                 parity_errors: 0,
+                corrected_bits: 0,
                 signal_level: -21.2,
+                noise_level: None,
+                snr: None,
                 frequency: 130.025,
+                frequency_offset: 0.0,
                 downlink_status: AirToGround,
                 message_number_without_sequence: Some(['D', '3', '4']),
                 message_number_sequence: Some('C'),
+                arinc622: None,
+                miam: None,
+                label_content: None,
             },
         ];
 
@@ -1171,7 +2182,7 @@ mod tests {
 
         let (tx_channel, mut rx) = mpsc::unbounded_channel();
 
-        match rtl.init_channels(tx_channel, rtl.get_intrate() * rtl.rtl_mult) {
+        match rtl.init_channels(&tx_channel, rtl.get_intrate() * rtl.rtl_mult) {
             Ok(_) => {
                 info!("[{: <13}] Channels initialized", rtl.get_serial());
             }
@@ -1193,12 +2204,12 @@ mod tests {
 
         for i in 1..=6 {
             let mut num_reads = 0;
-            let mut file = File::open(format!("../../test data/acars_0{}.bin", i))?;
+            let mut file = File::open(format!("../../test data/acars_0{i}.bin"))?;
             loop {
                 let mut buffer = vec![];
                 let n = file
                     .by_ref()
-                    .take(buffer_len as u64)
+                    .take(u64::from(buffer_len))
                     .read_to_end(&mut buffer)?;
                 if n == 0 {
                     //return an error if we didn't read anything
@@ -1219,8 +2230,7 @@ mod tests {
 
             assert!(
                 num_reads == 100,
-                "Number of reads is not 100. Found {}",
-                num_reads
+                "Number of reads is not 100. Found {num_reads}"
             );
         }
 
@@ -1228,22 +2238,42 @@ mod tests {
 
         let mut num_messages = 0;
 
-        loop {
-            match rx.try_recv() {
-                Ok(msg) => {
-                    if num_messages < valid_acars_messages.len() {
-                        assert_eq!(
-                            msg, valid_acars_messages[num_messages],
-                            "Message {} is not equal to valid message",
-                            num_messages
-                        );
-                    }
-
-                    num_messages += 1;
-                    println!("{}", msg);
-                }
-                Err(_) => break,
+        while let Ok(msg) = rx.try_recv() {
+            // the carrier offset is measured, not decoded. Check it is sane and then ignore it
+            assert!(
+                msg.frequency_offset.abs() < 1000.0,
+                "Message {num_messages} has a carrier offset of {} Hz",
+                msg.frequency_offset
+            );
+            // same for the noise floor, which depends on what was received before the message
+            assert!(
+                msg.snr.map_or(true, f32::is_finite)
+                    && msg
+                        .noise_level
+                        .map_or(true, |level| level < msg.signal_level),
+                "Message {num_messages} has a noise level of {:?} and signal level of {}",
+                msg.noise_level,
+                msg.signal_level
+            );
+            // and the time, which is taken from the system clock
+            assert!(msg.received.is_some());
+            let msg = AssembledACARSMessage {
+                received: None,
+                frequency_offset: 0.0,
+                noise_level: None,
+                snr: None,
+                ..msg
+            };
+
+            if num_messages < valid_acars_messages.len() {
+                assert_eq!(
+                    msg, valid_acars_messages[num_messages],
+                    "Message {num_messages} is not equal to valid message"
+                );
             }
+
+            num_messages += 1;
+            println!("{msg}");
         }
 
         assert!(
@@ -1255,4 +2285,168 @@ mod tests {
 
         Ok(())
     }
+
+    /// A cu8 capture at 2 MS/s of each block at its offset from the center frequency, one after
+    /// the other, padded out to whole buffers
+    fn capture_of(blocks: &[(AcarsBlock, f64)]) -> Vec<u8> {
+        let buffer_len = acars::RTLOUTBUFSZ * 160 * 2;
+        let mut capture: Vec<u8> = blocks
+            .iter()
+            .flat_map(|(block, offset)| {
+                to_cu8(
+                    &AcarsModulator::new(f64::from(acars::INTRATE_F32 * 160.0), *offset)
+                        .snr(Some(20.0))
+                        .modulate(block),
+                )
+            })
+            .collect();
+        capture.resize((capture.len() / buffer_len + 1) * buffer_len, 127);
+        capture
+    }
+
+    #[test]
+    fn test_process_capture() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rtl = RtlSdr::new(
+            "capture".to_string(),
+            0,
+            0,
+            false,
+            160,
+            vec![130.025, 131.55],
+            ValidDecoderType::ACARS,
+        );
+        let (tx_channel, mut rx) = mpsc::unbounded_channel();
+        rtl.init_capture(&tx_channel)?;
+
+        // Two and a half buffers of silence. The partial buffer is dropped
+        let buffer_len = acars::RTLOUTBUFSZ * 160 * 2;
+        let capture: Vec<u8> = [127, 128].repeat(buffer_len * 5 / 4);
+        assert_eq!(rtl.process_capture(capture.as_slice())?, 2);
+        assert!(rx.try_recv().is_err());
+
+        // A downlink on 131.55 and a squitter on 130.025, which sits near the edge of the
+        // capture. The channels are centered on 131 MHz
+        let squitter = AcarsBlock::new("", ['S', 'Q'], 'A', "02XAABQKABQ13502N10637WV136975/ARINC");
+        let capture = capture_of(&[
+            (downlink_block(['H', '1'], 80), 550_000.0),
+            (squitter, -975_000.0),
+        ]);
+        rtl.process_capture(capture.as_slice())?;
+
+        let message = rx.try_recv()?;
+        assert!((message.frequency - 131.55).abs() < 0.001);
+        assert_eq!(message.label, ['H', '1']);
+        assert_eq!(message.downlink_status, AirToGround);
+        let message = rx.try_recv()?;
+        assert!((message.frequency - 130.025).abs() < 0.001);
+        assert_eq!(message.label, ['S', 'Q']);
+        assert_eq!(message.downlink_status, GroundToAir);
+        assert!(rx.try_recv().is_err());
+
+        // A 2 MHz wide capture centered on 129 MHz does not reach 131.55
+        let mut rtl = RtlSdr::new(
+            "capture".to_string(),
+            0,
+            0,
+            false,
+            160,
+            vec![130.025, 131.55],
+            ValidDecoderType::ACARS,
+        );
+        rtl.set_center_frequency(Some(129.0));
+        assert!(matches!(
+            rtl.init_capture(&tx_channel),
+            Err(RTLSDRError::FrequencyOutsideBand { .. })
+        ));
+
+        // The same limit on the spread of the channels as the control API checks
+        let mut rtl = RtlSdr::new(
+            "capture".to_string(),
+            0,
+            0,
+            false,
+            160,
+            vec![129.5, 129.5 + MAX_FREQUENCY_SPREAD + 0.025],
+            ValidDecoderType::ACARS,
+        );
+        assert!(matches!(
+            rtl.init_capture(&tx_channel),
+            Err(RTLSDRError::FrequencySpreadTooLarge { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_control() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rtl = RtlSdr::new(
+            "capture".to_string(),
+            0,
+            0,
+            false,
+            160,
+            vec![130.025, 131.55],
+            ValidDecoderType::ACARS,
+        );
+        let control = rtl.control();
+        assert_eq!(control.status().state, SdrState::Starting);
+
+        let (tx_channel, mut rx) = mpsc::unbounded_channel();
+        rtl.init_capture(&tx_channel)?;
+
+        // One message on each channel
+        let capture = capture_of(&[
+            (downlink_block(['H', '1'], 60), 550_000.0),
+            (downlink_block(['5', 'Z'], 30), -975_000.0),
+        ]);
+
+        // A disabled channel decodes nothing, the other one keeps decoding
+        control.send(SdrCommand::SetChannelEnabled {
+            frequency: 131.55,
+            enabled: false,
+        })?;
+        // Sending wakes the reader so it applies the command
+        assert!(rtl.ctl.take_woken());
+        rtl.process_capture(capture.as_slice())?;
+        assert_eq!(rx.try_recv()?.label, ['5', 'Z']);
+        assert!(rx.try_recv().is_err());
+
+        let status = control.status();
+        assert_eq!(status.channels.len(), 2);
+        assert!(status.channels[0].enabled);
+        assert!(!status.channels[1].enabled);
+        assert_eq!(status.channels[0].messages, 1);
+        assert_eq!(status.channels[1].messages, 0);
+
+        control.send(SdrCommand::SetChannelEnabled {
+            frequency: 131.55,
+            enabled: true,
+        })?;
+        rtl.process_capture(capture.as_slice())?;
+        assert_eq!(rx.try_recv()?.label, ['H', '1']);
+        assert_eq!(rx.try_recv()?.label, ['5', 'Z']);
+        let status = control.status();
+        assert_eq!(status.channels[0].messages, 2);
+        assert_eq!(status.channels[1].messages, 1);
+
+        // Retuning starts the channels over, and 130.025 is no longer listened to
+        control.send(SdrCommand::Retune(vec![131.55, 131.125]))?;
+        rtl.process_capture(capture.as_slice())?;
+        let status = control.status();
+        assert!((status.channels[0].frequency - 131.125).abs() < 0.001);
+        assert_eq!(status.channels[0].messages, 0);
+        assert_eq!(status.channels[1].messages, 1);
+        assert_eq!(rx.try_recv()?.label, ['H', '1']);
+        assert!(rx.try_recv().is_err());
+
+        // There's no device to set the gain on
+        control.send(SdrCommand::SetGain(Some(420)))?;
+        rtl.process_capture(capture.as_slice())?;
+        assert!(control
+            .status()
+            .last_error
+            .map_or(false, |error| error.contains("not running")));
+
+        Ok(())
+    }
 }
@@ -0,0 +1,205 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// Aggregates the carrier offsets of decoded messages into a PPM estimate for a single dongle.
+// Ground stations are assumed to be on frequency, so any consistent offset across messages
+// and channels is the error of the dongle's crystal.
+
+use std::collections::VecDeque;
+
+/// Number of estimates kept. Older estimates fall out of the window.
+const WINDOW: usize = 32;
+/// Minimum number of messages before we trust the estimate.
+const MIN_SAMPLES: usize = 10;
+/// Maximum standard error of the mean, in PPM, before we trust the estimate.
+const MAX_STANDARD_ERROR: f32 = 0.5;
+/// Single message estimates further off than this are assumed to be garbage.
+const MAX_ABS_PPM: f32 = 200.0;
+
+#[derive(Debug, Default)]
+pub struct PpmEstimator {
+    samples: VecDeque<f32>,
+}
+
+impl PpmEstimator {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            samples: VecDeque::with_capacity(WINDOW),
+        }
+    }
+
+    /// Add the carrier offset, in Hz, of a message received on `frequency_mhz`.
+    pub fn add_offset(&mut self, offset_hz: f32, frequency_mhz: f32) {
+        if frequency_mhz <= 0.0 || !offset_hz.is_finite() {
+            return;
+        }
+
+        let ppm = -offset_hz / frequency_mhz;
+
+        if ppm.abs() > MAX_ABS_PPM {
+            return;
+        }
+
+        if self.samples.len() == WINDOW {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back(ppm);
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Mean and standard error of the current window, in PPM.
+    #[must_use]
+    pub fn estimate(&self) -> Option<(f32, f32)> {
+        if self.samples.len() < 2 {
+            return None;
+        }
+
+        let n = self.samples.len() as f32;
+        let mean = self.samples.iter().sum::<f32>() / n;
+        let variance = self
+            .samples
+            .iter()
+            .map(|ppm| (ppm - mean).powi(2))
+            .sum::<f32>()
+            / (n - 1.0);
+
+        Some((mean, (variance / n).sqrt()))
+    }
+
+    /// The whole PPM correction to add to the currently applied PPM, once there are enough
+    /// consistent messages and the error is large enough to be worth correcting.
+    #[must_use]
+    pub fn correction(&self) -> Option<i32> {
+        if self.samples.len() < MIN_SAMPLES {
+            return None;
+        }
+
+        let (mean, standard_error) = self.estimate()?;
+
+        if standard_error > MAX_STANDARD_ERROR {
+            return None;
+        }
+
+        let correction = mean.round() as i32;
+
+        if correction == 0 {
+            return None;
+        }
+
+        Some(correction)
+    }
+
+    /// Clear the window. Called after a correction is applied since older estimates were
+    /// measured against the old PPM.
+    pub fn reset(&mut self) {
+        self.samples.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num::Complex;
+    use oxide_decoders::decoders::acars::{ACARSDecoder, MessageStatus, INTRATE_F32};
+    use oxide_decoders::decoders::modulator::{decode_samples, downlink_block, AcarsModulator};
+    use oxide_decoders::Decoder;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_ppm_estimator() {
+        let mut estimator = PpmEstimator::new();
+
+        // a dongle running 5 PPM fast receives a 131.55 MHz carrier about 658 Hz low
+        for i in 0..9 {
+            let jitter = if i % 2 == 0 { 20.0 } else { -20.0 };
+            estimator.add_offset(-657.75 + jitter, 131.55);
+        }
+
+        assert_eq!(estimator.correction(), None, "Not enough messages yet");
+
+        estimator.add_offset(-650.1, 130.025);
+        assert_eq!(estimator.correction(), Some(5));
+
+        // wildly wrong estimates are dropped
+        estimator.add_offset(100_000.0, 130.025);
+        assert_eq!(estimator.len(), 10);
+
+        estimator.reset();
+        assert!(estimator.is_empty());
+
+        // inconsistent offsets should never produce a correction
+        for i in 0..20 {
+            let offset = if i % 2 == 0 { 2000.0 } else { -2000.0 };
+            estimator.add_offset(offset, 130.025);
+        }
+
+        assert_eq!(estimator.correction(), None);
+    }
+
+    #[test]
+    fn test_measured_offset() -> Result<(), Box<dyn std::error::Error>> {
+        // a dongle running 5 PPM fast receives a 131.55 MHz carrier about 658 Hz low
+        let offset = -657.75;
+        let mut estimator = PpmEstimator::new();
+        let mut decoder = ACARSDecoder::new(0, 131_550_000, [Complex::new(0.0, 0.0); 192]);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        decoder.set_output_channel(tx);
+
+        // short and long blocks, since the offset is measured over the whole block
+        let lengths = [12, 60, 140, 220].iter().cycle();
+        for (seed, length) in (0..MIN_SAMPLES as u64).zip(lengths) {
+            let block = downlink_block(['H', '1'], *length);
+            let samples = AcarsModulator::new(f64::from(INTRATE_F32), 0.0)
+                .snr(Some(20.0))
+                .frequency_offset(offset)
+                .seed(seed + 1)
+                .modulate(&block);
+            decode_samples(&mut decoder, &samples);
+
+            let message = rx.try_recv()?;
+            assert_eq!(message.status, MessageStatus::Valid);
+            assert!(
+                (message.frequency_offset - offset as f32).abs() < 10.0,
+                "Measured an offset of {}",
+                message.frequency_offset
+            );
+
+            let Some(measured) = decoder.take_frequency_offset() else {
+                panic!("No frequency offset for a valid message");
+            };
+            estimator.add_offset(measured, 131.55);
+        }
+
+        let Some((estimate, _)) = estimator.estimate() else {
+            panic!("No estimate");
+        };
+        assert!((estimate - 5.0).abs() < 0.2, "Estimated {estimate} PPM");
+        assert_eq!(estimator.correction(), Some(5));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// SigMF metadata, the `.sigmf-meta` file describing a capture. Only the fields we write or
+// need to decode a capture are modelled. The gain and PPM of the dongle have no core field,
+// so they are recorded in the `oxide` extension namespace.
+// https://github.com/sigmf/SigMF/blob/main/sigmf-spec.md
+
+use chrono::DateTime;
+use custom_error::custom_error;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+pub const SIGMF_VERSION: &str = "1.0.0";
+pub const META_EXTENSION: &str = "sigmf-meta";
+pub const DATA_EXTENSION: &str = "sigmf-data";
+/// Unsigned 8 bit interleaved IQ, as read from the dongle
+pub const DATATYPE_CU8: &str = "cu8";
+
+custom_error! {pub SigMfError
+    Io { source: std::io::Error } = "Unable to access the SigMF metadata: {source}",
+    Json { source: serde_json::Error } = "Invalid SigMF metadata: {source}",
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SigMfExtension {
+    pub name: String,
+    pub version: String,
+    pub optional: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SigMfGlobal {
+    #[serde(rename = "core:datatype")]
+    pub datatype: String,
+    /// Samples per second
+    #[serde(rename = "core:sample_rate", skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<f64>,
+    #[serde(rename = "core:version")]
+    pub version: String,
+    /// Description of the hardware used to make the capture
+    #[serde(rename = "core:hw", skip_serializing_if = "Option::is_none")]
+    pub hw: Option<String>,
+    /// Name and version of the program that made the capture
+    #[serde(rename = "core:recorder", skip_serializing_if = "Option::is_none")]
+    pub recorder: Option<String>,
+    #[serde(rename = "core:description", skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(
+        rename = "core:extensions",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub extensions: Vec<SigMfExtension>,
+    /// Tuner gain in dB
+    #[serde(rename = "oxide:gain", skip_serializing_if = "Option::is_none")]
+    pub gain: Option<f32>,
+    #[serde(rename = "oxide:ppm", skip_serializing_if = "Option::is_none")]
+    pub ppm: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SigMfCapture {
+    #[serde(rename = "core:sample_start")]
+    pub sample_start: u64,
+    /// Center frequency in Hz
+    #[serde(rename = "core:frequency", skip_serializing_if = "Option::is_none")]
+    pub frequency: Option<f64>,
+    /// ISO 8601 UTC time of the first sample
+    #[serde(rename = "core:datetime", skip_serializing_if = "Option::is_none")]
+    pub datetime: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SigMfMetadata {
+    pub global: SigMfGlobal,
+    pub captures: Vec<SigMfCapture>,
+    /// Not used, but required by the spec
+    #[serde(default)]
+    pub annotations: Vec<serde_json::Value>,
+}
+
+impl SigMfMetadata {
+    /// Metadata for a single capture recorded from a dongle
+    #[must_use]
+    pub fn new(datatype: &str, sample_rate: f64, frequency: f64) -> Self {
+        Self {
+            global: SigMfGlobal {
+                datatype: datatype.to_string(),
+                sample_rate: Some(sample_rate),
+                version: SIGMF_VERSION.to_string(),
+                hw: None,
+                recorder: None,
+                description: None,
+                extensions: vec![SigMfExtension {
+                    name: "oxide".to_string(),
+                    version: SIGMF_VERSION.to_string(),
+                    optional: true,
+                }],
+                gain: None,
+                ppm: None,
+            },
+            captures: vec![SigMfCapture {
+                sample_start: 0,
+                frequency: Some(frequency),
+                datetime: None,
+            }],
+            annotations: vec![],
+        }
+    }
+
+    /// Center frequency of the first capture, in Hz
+    #[must_use]
+    pub fn frequency(&self) -> Option<f64> {
+        self.captures.first().and_then(|capture| capture.frequency)
+    }
+
+    /// Time of the first sample of the recording, from the datetime of the first capture.
+    /// `None` if it has no datetime, or one that isn't ISO 8601.
+    #[must_use]
+    pub fn start_time(&self) -> Option<SystemTime> {
+        let capture = self.captures.first()?;
+        let datetime = DateTime::parse_from_rfc3339(capture.datetime.as_deref()?).ok()?;
+        let start_time = SystemTime::from(datetime);
+
+        // The datetime is the time of the first sample of the capture, which may not be the
+        // first sample of the recording
+        match self.global.sample_rate {
+            Some(sample_rate) if sample_rate > 0.0 => start_time.checked_sub(
+                Duration::from_secs_f64(capture.sample_start as f64 / sample_rate),
+            ),
+            _ => Some(start_time),
+        }
+    }
+
+    /// Read the metadata from a `.sigmf-meta` file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read or isn't valid `SigMF` metadata.
+    pub fn from_file(path: &Path) -> Result<Self, SigMfError> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Write the metadata to a `.sigmf-meta` file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metadata can't be serialized or the file can't be written.
+    pub fn write(&self, path: &Path) -> Result<(), SigMfError> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// The metadata file that goes with the capture at `data_path`
+#[must_use]
+pub fn meta_path(data_path: &Path) -> PathBuf {
+    data_path.with_extension(META_EXTENSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata() -> Result<(), Box<dyn std::error::Error>> {
+        let mut metadata = SigMfMetadata::new(DATATYPE_CU8, 2_000_000.0, 131_000_000.0);
+        metadata.global.gain = Some(42.1);
+        metadata.global.ppm = Some(-3);
+        metadata.captures[0].datetime = Some("2024-03-01T12:30:00.000Z".to_string());
+
+        let json = serde_json::to_string(&metadata)?;
+        assert!(json.contains(r#""core:datatype":"cu8""#));
+        assert!(json.contains(r#""core:frequency":131000000.0"#));
+        assert!(json.contains(r#""oxide:ppm":-3"#));
+        assert!(!json.contains("core:hw"));
+
+        let parsed: SigMfMetadata = serde_json::from_str(&json)?;
+        assert_eq!(parsed, metadata);
+        assert_eq!(parsed.frequency(), Some(131_000_000.0));
+        assert_eq!(
+            parsed.start_time(),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_296_200))
+        );
+
+        // Captures from other tools don't have our extension or the annotations
+        let parsed: SigMfMetadata = serde_json::from_str(
+            r#"{"global":{"core:datatype":"ci16_le","core:version":"1.0.0","core:sample_rate":2400000},"captures":[{"core:sample_start":0,"core:frequency":130450000}]}"#,
+        )?;
+        assert_eq!(parsed.global.datatype, "ci16_le");
+        assert_eq!(parsed.global.gain, None);
+        assert_eq!(parsed.start_time(), None);
+
+        // A capture starting later in the recording dates the first sample of the recording
+        metadata.captures[0].sample_start = 4_000_000;
+        metadata.captures[0].datetime = Some("2024-03-01T14:30:02+02:00".to_string());
+        assert_eq!(
+            metadata.start_time(),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_296_200))
+        );
+
+        assert_eq!(
+            meta_path(Path::new("captures/acars.sigmf-data")),
+            PathBuf::from("captures/acars.sigmf-meta")
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,850 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// Library API for applications that embed the decoder. The receivers are configured with a
+// builder, and the decoded messages are returned as a stream instead of being written out.
+//
+//   let (mut messages, handle) = OxideScanner::builder()
+//       .receiver(ReceiverConfig::new("00012785", vec![130.025, 130.450, 131.550]))
+//       .start()?;
+//
+//   while let Some(message) = messages.next().await {
+//       ...
+//   }
+//
+// A receiver can also decode a capture instead of opening the device, which ends the stream
+// once the capture has been decoded. Captures are read the same way acars-oxide-decode reads
+// them, so SigMF and WAV captures describe themselves.
+
+use crate::statistics::{SharedStatistics, StatisticsCollector};
+use custom_error::custom_error;
+use oxide_decoders::decoders::acars::AssembledACARSMessage;
+use oxide_decoders::{ChannelStatistics, ValidDecoderType};
+use oxide_metrics::Metrics;
+use oxide_output::dedup::DEFAULT_DEDUP_WINDOW;
+use oxide_output::mqtt::MqttSink;
+use oxide_output::recent::RecentMessages;
+use oxide_output::rules::RuleSet;
+use oxide_output::stations::{HeardGroundStation, SharedGroundStations};
+use oxide_output::websocket::WebSocketSink;
+use oxide_output::{DecodedMessage, OxideOutput};
+use oxide_rtlsdr::control::SdrControl;
+use oxide_rtlsdr::iq::{self, IqReader, SampleFormat};
+use oxide_rtlsdr::RtlSdr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+use tokio_stream::Stream;
+
+custom_error! {pub ScannerError
+    NoReceivers = "No receivers configured",
+    NoReceiversOpened = "None of the configured receivers could be opened",
+}
+
+/// Configuration of a single RTL-SDR receiver
+#[derive(Debug, Clone)]
+pub struct ReceiverConfig {
+    serial: String,
+    frequencies: Vec<f32>,
+    ppm: i32,
+    auto_ppm: bool,
+    gain: i32,
+    auto_gain: bool,
+    bias_tee: bool,
+    rtl_mult: i32,
+    decoder_type: ValidDecoderType,
+    capture: Option<PathBuf>,
+    capture_format: Option<SampleFormat>,
+}
+
+/// A capture to decode instead of opening the device
+#[derive(Debug, Clone)]
+pub(crate) struct Capture {
+    path: PathBuf,
+    format: Option<SampleFormat>,
+}
+
+impl ReceiverConfig {
+    /// A receiver for the device with `serial`, listening on `frequencies` in MHz.
+    #[must_use]
+    pub fn new(serial: impl Into<String>, frequencies: Vec<f32>) -> Self {
+        Self {
+            serial: serial.into(),
+            frequencies,
+            ppm: 0,
+            auto_ppm: false,
+            gain: 0,
+            auto_gain: false,
+            bias_tee: false,
+            rtl_mult: 160,
+            decoder_type: ValidDecoderType::ACARS,
+            capture: None,
+            capture_format: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn ppm(mut self, ppm: i32) -> Self {
+        self.ppm = ppm;
+        self
+    }
+
+    #[must_use]
+    pub const fn auto_ppm(mut self, auto_ppm: bool) -> Self {
+        self.auto_ppm = auto_ppm;
+        self
+    }
+
+    /// Tuner gain in tenths of a dB
+    #[must_use]
+    pub const fn gain(mut self, gain: i32) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    #[must_use]
+    pub const fn auto_gain(mut self, auto_gain: bool) -> Self {
+        self.auto_gain = auto_gain;
+        self
+    }
+
+    #[must_use]
+    pub const fn bias_tee(mut self, bias_tee: bool) -> Self {
+        self.bias_tee = bias_tee;
+        self
+    }
+
+    #[must_use]
+    pub const fn rtl_mult(mut self, rtl_mult: i32) -> Self {
+        self.rtl_mult = rtl_mult;
+        self
+    }
+
+    #[must_use]
+    pub const fn decoder_type(mut self, decoder_type: ValidDecoderType) -> Self {
+        self.decoder_type = decoder_type;
+        self
+    }
+
+    /// Decode a capture instead of opening the device. The serial only names the receiver.
+    ///
+    /// The format, sample rate and center frequency are read from the `SigMF` metadata or WAV
+    /// header of the capture. Raw captures are unsigned 8 bit unless `capture_format` says
+    /// otherwise, recorded at `rtl_mult` times the decoder rate, centered the same way the
+    /// device would be.
+    #[must_use]
+    pub fn capture(mut self, capture: impl Into<PathBuf>) -> Self {
+        self.capture = Some(capture.into());
+        self
+    }
+
+    /// Sample format of the capture, over what the capture says about itself
+    #[must_use]
+    pub const fn capture_format(mut self, format: SampleFormat) -> Self {
+        self.capture_format = Some(format);
+        self
+    }
+}
+
+/// Builds an embedded scanner. By default messages are only returned through the stream, and
+/// copies of a message are merged over the default dedup window.
+#[derive(Debug)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct OxideScannerBuilder {
+    receivers: Vec<ReceiverConfig>,
+    output_command_line: bool,
+    output_json: bool,
+    output_zmq: bool,
+    dedup_window: Option<Duration>,
+    rules: RuleSet,
+    state_dir: Option<PathBuf>,
+    emit_errors: bool,
+    metrics: Option<Arc<Metrics>>,
+    websocket: Option<WebSocketSink>,
+    mqtt: Option<MqttSink>,
+    statistics_interval: Option<Duration>,
+}
+
+impl Default for OxideScannerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OxideScannerBuilder {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            receivers: vec![],
+            output_command_line: false,
+            output_json: false,
+            output_zmq: false,
+            dedup_window: Some(DEFAULT_DEDUP_WINDOW),
+            rules: RuleSet::new(),
+            state_dir: None,
+            emit_errors: false,
+            metrics: None,
+            websocket: None,
+            mqtt: None,
+            statistics_interval: None,
+        }
+    }
+
+    #[must_use]
+    pub fn receiver(mut self, receiver: ReceiverConfig) -> Self {
+        self.receivers.push(receiver);
+        self
+    }
+
+    /// Also log the messages, as the command line does
+    #[must_use]
+    pub const fn output_command_line(mut self, output_command_line: bool) -> Self {
+        self.output_command_line = output_command_line;
+        self
+    }
+
+    /// Also print the messages as JSON on stdout
+    #[must_use]
+    pub const fn output_json(mut self, output_json: bool) -> Self {
+        self.output_json = output_json;
+        self
+    }
+
+    /// Also send the messages over ZMQ
+    #[must_use]
+    pub const fn output_zmq(mut self, output_zmq: bool) -> Self {
+        self.output_zmq = output_zmq;
+        self
+    }
+
+    /// Window to merge copies of a message over. `None` turns deduplication off.
+    #[must_use]
+    pub const fn dedup_window(mut self, dedup_window: Option<Duration>) -> Self {
+        self.dedup_window = dedup_window;
+        self
+    }
+
+    /// Filter the messages each sink gets. The stream is the `stream` sink.
+    #[must_use]
+    pub fn rules(mut self, rules: RuleSet) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Directory auto gain saves the chosen gain to
+    #[must_use]
+    pub fn state_dir(mut self, state_dir: Option<PathBuf>) -> Self {
+        self.state_dir = state_dir;
+        self
+    }
+
+    /// Return messages that failed the CRC or parity checks, flagged as such
+    #[must_use]
+    pub const fn emit_errors(mut self, emit_errors: bool) -> Self {
+        self.emit_errors = emit_errors;
+        self
+    }
+
+    /// Record the receivers, decoders and output in `metrics`, to serve with `http::serve`
+    #[must_use]
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Also push the messages to the clients of `websocket`, served with `WebSocketSink::serve`
+    #[must_use]
+    pub fn websocket_sink(mut self, websocket: WebSocketSink) -> Self {
+        self.websocket = Some(websocket);
+        self
+    }
+
+    /// Also publish the messages through `mqtt`, whose connection has to be run
+    #[must_use]
+    pub fn mqtt_sink(mut self, mqtt: MqttSink) -> Self {
+        self.mqtt = Some(mqtt);
+        self
+    }
+
+    /// Log a summary of what each channel decoded every `statistics_interval`, and the totals
+    /// on shutdown. `None`, the default, logs nothing.
+    #[must_use]
+    pub const fn statistics_interval(mut self, statistics_interval: Option<Duration>) -> Self {
+        self.statistics_interval = statistics_interval;
+        self
+    }
+
+    /// Open the receivers and start decoding. Receivers that can't be opened are logged and
+    /// skipped. Must be called from within a tokio runtime.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no receivers are configured, or if none of them could be opened.
+    pub fn start(self) -> Result<(MessageStream, ScannerHandle), ScannerError> {
+        let receivers = self
+            .receivers
+            .iter()
+            .map(|receiver| {
+                let capture = receiver.capture.clone().map(|path| Capture {
+                    path,
+                    format: receiver.capture_format,
+                });
+                (self.configure(receiver), capture)
+            })
+            .collect();
+        let (message_sink, messages) = mpsc::unbounded_channel();
+        let handle = self.launch(receivers, Some(message_sink))?;
+
+        Ok((MessageStream { receiver: messages }, handle))
+    }
+
+    fn configure(&self, receiver: &ReceiverConfig) -> RtlSdr {
+        let mut sdr = RtlSdr::new(
+            receiver.serial.clone(),
+            receiver.ppm,
+            receiver.gain,
+            receiver.bias_tee,
+            receiver.rtl_mult,
+            receiver.frequencies.clone(),
+            receiver.decoder_type.clone(),
+        );
+
+        sdr.set_auto_ppm(receiver.auto_ppm);
+        sdr.set_auto_gain(receiver.auto_gain, self.state_dir.clone());
+        sdr.set_emit_errors(self.emit_errors);
+
+        sdr
+    }
+
+    /// Start reading from the configured `receivers`, with the capture each one decodes, if
+    /// any, and the output. Messages are handed to `message_sink`, if set.
+    pub(crate) fn launch(
+        self,
+        receivers: Vec<(RtlSdr, Option<Capture>)>,
+        message_sink: Option<UnboundedSender<DecodedMessage>>,
+    ) -> Result<ScannerHandle, ScannerError> {
+        if receivers.is_empty() {
+            return Err(ScannerError::NoReceivers);
+        }
+
+        let (tx_channel, rx) = mpsc::unbounded_channel();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let recent_messages = RecentMessages::default();
+        let ground_stations = Arc::new(Mutex::new(vec![]));
+        let mut statistics = vec![];
+        let mut readers = vec![];
+        let mut controls = vec![];
+
+        for (mut sdr, capture) in receivers {
+            let serial = sdr.get_serial().to_string();
+            let shared_statistics = Arc::new(Mutex::new(vec![]));
+
+            sdr.set_shutdown(shutdown.clone());
+            sdr.set_shared_statistics(shared_statistics.clone());
+            if let Some(metrics) = &self.metrics {
+                sdr.set_metrics(metrics.clone());
+            }
+            controls.push(sdr.control());
+
+            let reader = match capture {
+                Some(capture) => start_capture(sdr, capture, &tx_channel),
+                None => start_sdr(sdr, &tx_channel),
+            };
+
+            if let Some(reader) = reader {
+                statistics.push((serial, shared_statistics));
+                readers.push(reader);
+            }
+        }
+
+        if readers.is_empty() {
+            return Err(ScannerError::NoReceiversOpened);
+        }
+
+        let mut output = OxideOutput::new(
+            self.output_command_line,
+            self.output_json,
+            self.output_zmq,
+            self.dedup_window,
+            rx,
+        );
+        output.set_rules(self.rules);
+        if let Some(message_sink) = message_sink {
+            output.set_message_sink(message_sink);
+        }
+        output.set_recent_messages(recent_messages.clone());
+        output.set_shared_ground_stations(ground_stations.clone());
+        if let Some(websocket) = self.websocket {
+            output.set_websocket_sink(websocket);
+        }
+        if let Some(mqtt) = self.mqtt {
+            output.set_mqtt_sink(mqtt);
+        }
+        if let Some(metrics) = self.metrics {
+            output.set_metrics(metrics);
+        }
+
+        let output = tokio::spawn(async move {
+            output.monitor_receiver_channel().await;
+        });
+
+        Ok(ScannerHandle::new(
+            shutdown,
+            statistics,
+            readers,
+            output,
+            ScannerControl::new(controls, recent_messages, ground_stations),
+            self.statistics_interval,
+        ))
+    }
+}
+
+/// Open the SDR and read samples from it on a blocking task. Returns `None`, after logging why,
+/// if the SDR could not be opened.
+fn start_sdr(
+    mut sdr: RtlSdr,
+    tx_channel: &UnboundedSender<AssembledACARSMessage>,
+) -> Option<JoinHandle<()>> {
+    info!("[OXIDE SCANNER] Opening SDR {}", sdr.get_serial());
+    match sdr.open_sdr(tx_channel) {
+        Ok(()) => {
+            info!("[OXIDE SCANNER] SDR {} opened", sdr.get_serial());
+            Some(tokio::task::spawn_blocking(move || {
+                sdr.read_samples();
+            }))
+        }
+        Err(e) => {
+            error!(
+                "[OXIDE SCANNER] Failed to open SDR {}: {}",
+                sdr.get_serial(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Decode the capture on a blocking task, as if it was read from the SDR. Returns `None`, after
+/// logging why, if the capture could not be opened or wasn't recorded at the rate the receiver
+/// decodes.
+fn start_capture(
+    mut sdr: RtlSdr,
+    capture: Capture,
+    tx_channel: &UnboundedSender<AssembledACARSMessage>,
+) -> Option<JoinHandle<()>> {
+    let path = capture.path;
+    info!(
+        "[OXIDE SCANNER] Decoding {} as SDR {}",
+        path.display(),
+        sdr.get_serial()
+    );
+
+    let (reader, info) = match iq::open_capture(&path) {
+        Ok(capture) => capture,
+        Err(e) => {
+            error!(
+                "[OXIDE SCANNER] Failed to open capture {}: {}",
+                path.display(),
+                e
+            );
+            return None;
+        }
+    };
+
+    let sample_rate = sdr.get_sample_rate();
+    if let Some(recorded) = info.sample_rate.filter(|recorded| *recorded != sample_rate) {
+        error!(
+            "[OXIDE SCANNER] Capture {} was recorded at {} samples per second, SDR {} decodes {}. Set rtl_mult to match",
+            path.display(),
+            recorded,
+            sdr.get_serial(),
+            sample_rate
+        );
+        return None;
+    }
+
+    // Center frequencies are well within f32 precision once in MHz
+    #[allow(clippy::cast_possible_truncation)]
+    sdr.set_center_frequency(
+        info.center_frequency
+            .map(|center_frequency| (center_frequency / 1_000_000.0) as f32),
+    );
+    sdr.set_start_time(info.start_time);
+    let format = capture.format.or(info.format).unwrap_or(SampleFormat::Cu8);
+
+    if let Err(e) = sdr.init_capture(tx_channel) {
+        error!(
+            "[OXIDE SCANNER] Failed to set up SDR {}: {}",
+            sdr.get_serial(),
+            e
+        );
+        return None;
+    }
+
+    Some(tokio::task::spawn_blocking(move || {
+        if let Err(e) = sdr.process_iq(&mut IqReader::new(reader, format)) {
+            error!(
+                "[OXIDE SCANNER] Failed to read capture {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }))
+}
+
+/// The decoded messages. Ends once the scanner has shut down and every held message has been
+/// returned.
+#[derive(Debug)]
+pub struct MessageStream {
+    receiver: UnboundedReceiver<DecodedMessage>,
+}
+
+impl Stream for MessageStream {
+    type Item = DecodedMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// Reception statistics for one receiver
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceiverStatistics {
+    pub serial: String,
+    pub channels: Vec<ChannelStatistics>,
+}
+
+/// Controls the receivers of a running scanner, and shows what they decoded, for the HTTP API
+/// and the web UI
+#[derive(Debug, Clone)]
+pub struct ScannerControl {
+    sdrs: Vec<SdrControl>,
+    recent_messages: RecentMessages,
+    ground_stations: SharedGroundStations,
+}
+
+impl ScannerControl {
+    #[must_use]
+    pub const fn new(
+        sdrs: Vec<SdrControl>,
+        recent_messages: RecentMessages,
+        ground_stations: SharedGroundStations,
+    ) -> Self {
+        Self {
+            sdrs,
+            recent_messages,
+            ground_stations,
+        }
+    }
+
+    /// Every configured SDR, including those that failed to open
+    #[must_use]
+    pub fn sdrs(&self) -> &[SdrControl] {
+        &self.sdrs
+    }
+
+    #[must_use]
+    pub fn sdr(&self, serial: &str) -> Option<&SdrControl> {
+        self.sdrs.iter().find(|sdr| sdr.serial() == serial)
+    }
+
+    #[must_use]
+    pub const fn recent_messages(&self) -> &RecentMessages {
+        &self.recent_messages
+    }
+
+    /// The ground stations heard so far, ordered by frequency
+    #[must_use]
+    pub fn ground_stations(&self) -> Vec<HeardGroundStation> {
+        self.ground_stations
+            .lock()
+            .map(|stations| stations.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Controls a running scanner
+#[derive(Debug)]
+pub struct ScannerHandle {
+    shutdown: Arc<AtomicBool>,
+    receivers: SharedStatistics,
+    readers: Vec<JoinHandle<()>>,
+    output: JoinHandle<()>,
+    control: ScannerControl,
+    /// Logs the periodic summaries. `None` when summaries are turned off
+    summaries: Option<(Arc<Mutex<StatisticsCollector>>, JoinHandle<()>)>,
+}
+
+impl ScannerHandle {
+    pub(crate) fn new(
+        shutdown: Arc<AtomicBool>,
+        receivers: SharedStatistics,
+        readers: Vec<JoinHandle<()>>,
+        output: JoinHandle<()>,
+        control: ScannerControl,
+        statistics_interval: Option<Duration>,
+    ) -> Self {
+        let summaries = statistics_interval.map(|interval| {
+            let collector = Arc::new(Mutex::new(StatisticsCollector::new(receivers.clone())));
+            let task = tokio::spawn(StatisticsCollector::log_every(collector.clone(), interval));
+            (collector, task)
+        });
+
+        Self {
+            shutdown,
+            receivers,
+            readers,
+            output,
+            control,
+            summaries,
+        }
+    }
+
+    /// Control the receivers while the scanner runs
+    #[must_use]
+    pub fn control(&self) -> ScannerControl {
+        self.control.clone()
+    }
+
+    /// Current reception statistics of every receiver that was opened
+    #[must_use]
+    pub fn statistics(&self) -> Vec<ReceiverStatistics> {
+        self.receivers
+            .iter()
+            .map(|(serial, statistics)| ReceiverStatistics {
+                serial: serial.clone(),
+                channels: statistics
+                    .lock()
+                    .map(|statistics| statistics.clone())
+                    .unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    /// Stop the receivers and wait for the remaining messages to be handed to the stream.
+    pub async fn shutdown(self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        for sdr in self.control.sdrs() {
+            sdr.interrupt();
+        }
+
+        for reader in self.readers {
+            if let Err(e) = reader.await {
+                error!("[OXIDE SCANNER] Receiver task failed: {e}");
+            }
+        }
+
+        if let Err(e) = self.output.await {
+            error!("[OXIDE SCANNER] Output task failed: {e}");
+        }
+
+        if let Some((collector, task)) = self.summaries {
+            task.abort();
+            if let Ok(collector) = collector.lock() {
+                collector.log_final_summary();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num::Complex;
+    use oxide_decoders::decoders::acars::{INTRATE_F32, RTLOUTBUFSZ};
+    use oxide_decoders::decoders::modulator::{
+        downlink_block, downlink_text, to_cu8, AcarsModulator,
+    };
+    use oxide_rtlsdr::sigmf::{self, SigMfMetadata};
+    use std::time::SystemTime;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn test_start_without_receivers() {
+        assert!(matches!(
+            OxideScannerBuilder::new().start(),
+            Err(ScannerError::NoReceivers)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_message_stream() {
+        let (sink, receiver) = mpsc::unbounded_channel();
+        let mut messages = MessageStream { receiver };
+        let message = DecodedMessage {
+            message: AssembledACARSMessage::default(),
+            received: SystemTime::UNIX_EPOCH,
+            receptions: 2,
+            conversation: None,
+        };
+
+        assert!(sink.send(message.clone()).is_ok());
+        drop(sink);
+
+        assert_eq!(messages.next().await, Some(message));
+        assert_eq!(messages.next().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_decode_capture() -> Result<(), Box<dyn std::error::Error>> {
+        // A message on 131.55 and then one on 130.025, padded out to whole buffers
+        let mut capture: Vec<u8> = [
+            (downlink_block(['H', '1'], 60), 550_000.0),
+            (downlink_block(['5', 'Z'], 30), -975_000.0),
+        ]
+        .iter()
+        .flat_map(|(block, offset)| {
+            to_cu8(
+                &AcarsModulator::new(f64::from(INTRATE_F32 * 160.0), *offset)
+                    .snr(Some(20.0))
+                    .modulate(block),
+            )
+        })
+        .collect();
+        let buffer_len = RTLOUTBUFSZ * 160 * 2;
+        capture.resize((capture.len() / buffer_len + 1) * buffer_len, 127);
+
+        let path = std::env::temp_dir().join(format!("oxide-scanner-{}.cu8", std::process::id()));
+        std::fs::write(&path, capture)?;
+
+        let (messages, handle) = OxideScannerBuilder::new()
+            .receiver(ReceiverConfig::new("capture", vec![130.025, 131.55]).capture(&path))
+            .start()?;
+
+        // The stream ends once the capture has been decoded
+        let messages: Vec<DecodedMessage> =
+            tokio::time::timeout(Duration::from_secs(60), messages.collect()).await?;
+        handle.shutdown().await;
+        std::fs::remove_file(&path)?;
+
+        // Both channels feed the one stream, in the order the messages were heard
+        assert_eq!(messages.len(), 2);
+        let cases = [(131.55, ['H', '1'], 60), (130.025, ['5', 'Z'], 30)];
+        for (decoded, (frequency, label, length)) in messages.iter().zip(cases) {
+            let message = &decoded.message;
+            assert!((message.frequency - frequency).abs() < 0.001);
+            assert_eq!(message.label, label);
+            assert_eq!(
+                message.aircraft_tail,
+                Some(['N', '9', '2', '3', 'U', 'S', ' '])
+            );
+            let text: String = message.message_text.iter().flatten().collect();
+            assert_eq!(text, downlink_text(length)[10..]);
+        }
+
+        Ok(())
+    }
+
+    /// A position report `offset` Hz from the center, at 2 MS/s, padded out to whole buffers
+    fn position_report(offset: f64) -> Vec<Complex<f32>> {
+        let mut samples = AcarsModulator::new(f64::from(INTRATE_F32 * 160.0), offset)
+            .snr(Some(20.0))
+            .modulate(&downlink_block(['H', '1'], 40));
+        let buffer_len = RTLOUTBUFSZ * 160;
+        samples.resize(
+            (samples.len() / buffer_len + 1) * buffer_len,
+            Complex::new(0.0, 0.0),
+        );
+        samples
+    }
+
+    async fn decode(
+        receiver: ReceiverConfig,
+    ) -> Result<Vec<DecodedMessage>, Box<dyn std::error::Error>> {
+        let (messages, handle) = OxideScannerBuilder::new().receiver(receiver).start()?;
+        let messages = tokio::time::timeout(Duration::from_secs(60), messages.collect()).await?;
+        handle.shutdown().await;
+        Ok(messages)
+    }
+
+    #[tokio::test]
+    async fn test_capture_formats() -> Result<(), Box<dyn std::error::Error>> {
+        let dir =
+            std::env::temp_dir().join(format!("oxide-scanner-formats-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+
+        // SigMF says what the capture is, including a center the receiver wouldn't pick and when
+        // it was recorded
+        let cf32: Vec<u8> = position_report(250_000.0)
+            .iter()
+            .flat_map(|sample| [sample.re.to_le_bytes(), sample.im.to_le_bytes()])
+            .flatten()
+            .collect();
+        let sigmf_path = dir.join("capture.sigmf-data");
+        std::fs::write(&sigmf_path, cf32)?;
+        let mut metadata =
+            SigMfMetadata::new("cf32_le", f64::from(INTRATE_F32 * 160.0), 131_300_000.0);
+        metadata.captures[0].datetime = Some("2024-03-01T12:30:00.000Z".to_string());
+        metadata.write(&sigmf::meta_path(&sigmf_path))?;
+        let recorded = SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_296_200);
+
+        // A raw capture needs to be told its format. Signed 8 bit is unsigned with the top bit
+        // flipped
+        let cs8: Vec<u8> = to_cu8(&position_report(550_000.0))
+            .iter()
+            .map(|byte| byte ^ 0x80)
+            .collect();
+        let raw_path = dir.join("raw.cs8");
+        std::fs::write(&raw_path, cs8)?;
+
+        // Messages are timed from the start of the recording, or the system clock when the
+        // capture doesn't say when that was
+        let decoding = SystemTime::now();
+        for (receiver, start) in [
+            (
+                ReceiverConfig::new("sigmf", vec![131.55]).capture(&sigmf_path),
+                recorded,
+            ),
+            (
+                ReceiverConfig::new("raw", vec![130.025, 131.55])
+                    .capture(&raw_path)
+                    .capture_format(SampleFormat::Cs8),
+                decoding,
+            ),
+        ] {
+            let messages = decode(receiver).await?;
+            assert_eq!(messages.len(), 1);
+            assert!((messages[0].message.frequency - 131.55).abs() < 0.001);
+            assert_eq!(messages[0].message.label, ['H', '1']);
+            let offset = messages[0].received.duration_since(start)?;
+            assert!(offset < Duration::from_secs(10), "{offset:?}");
+        }
+
+        // The receiver decodes 2 MS/s, so a capture recorded at 2.4 MS/s is turned down
+        SigMfMetadata::new("cf32_le", f64::from(INTRATE_F32 * 192.0), 131_300_000.0)
+            .write(&sigmf::meta_path(&sigmf_path))?;
+        let start = OxideScannerBuilder::new()
+            .receiver(ReceiverConfig::new("sigmf", vec![131.55]).capture(&sigmf_path))
+            .start();
+        assert!(matches!(start, Err(ScannerError::NoReceiversOpened)));
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_missing_capture() {
+        let start = OxideScannerBuilder::new()
+            .receiver(ReceiverConfig::new("capture", vec![131.55]).capture("/nonexistent/capture"))
+            .start();
+        assert!(matches!(start, Err(ScannerError::NoReceiversOpened)));
+    }
+}
@@ -0,0 +1,655 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// HTTP servers for monitoring and controlling a running scanner. Prometheus scrapes the metrics
+// from `/metrics`. The REST API lists the SDRs and the last messages, and changes the SDRs while
+// they run. Commands are applied between sample buffers, so they are answered with 202 Accepted
+// and their outcome shows in the SDR's status.
+//
+//   GET  /api/sdrs                                   every SDR, its channels and statistics
+//   GET  /api/sdrs/{serial}
+//   PUT  /api/sdrs/{serial}/gain                     {"gain": 42.0}, or null for the tuner AGC
+//   PUT  /api/sdrs/{serial}/ppm                      {"ppm": 2}
+//   PUT  /api/sdrs/{serial}/frequencies              {"frequencies": [130.025, 131.55]}
+//   PUT  /api/sdrs/{serial}/channels/{frequency}     {"enabled": false}
+//   POST /api/sdrs/{serial}/restart
+//   GET  /api/messages?limit=20                      the last messages, newest first
+//   GET  /api/ground-stations                        the ground stations heard, by frequency
+//
+// The API server also serves the web UI, see `web`.
+
+use crate::{web, ScannerControl};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use oxide_metrics::Metrics;
+use oxide_output::stations::HeardGroundStation;
+use oxide_rtlsdr::control::{SdrCommand, SdrControl};
+use oxide_rtlsdr::RTLSDRError;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tokio::net::TcpListener;
+
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+/// Messages returned when the request doesn't give a limit
+const DEFAULT_MESSAGE_LIMIT: usize = 20;
+const MIN_GAIN: f32 = 0.0;
+const MAX_GAIN: f32 = 60.0;
+
+#[derive(Debug, Serialize)]
+struct ApiError {
+    error: String,
+}
+
+fn api_error(status: StatusCode, error: &impl ToString) -> Response {
+    (
+        status,
+        Json(ApiError {
+            error: error.to_string(),
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct GainRequest {
+    /// dB, or `None` for the tuner AGC
+    gain: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PpmRequest {
+    ppm: i32,
+}
+
+#[derive(Debug, Deserialize)]
+struct FrequenciesRequest {
+    frequencies: Vec<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChannelRequest {
+    enabled: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessagesQuery {
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct GroundStationStatus {
+    network: String,
+    iata: Option<String>,
+    icao: Option<String>,
+    station_number: Option<u8>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    /// MHz
+    frequency: f32,
+    /// Seconds since the epoch
+    last_heard: u64,
+    signal_level: f32,
+    squitters: u64,
+}
+
+impl GroundStationStatus {
+    pub(crate) fn new(heard: &HeardGroundStation) -> Self {
+        Self {
+            network: heard.station.network.to_string(),
+            iata: heard.station.iata.clone(),
+            icao: heard.station.icao.clone(),
+            station_number: heard.station.station_number,
+            latitude: heard.station.latitude,
+            longitude: heard.station.longitude,
+            frequency: heard.frequency,
+            last_heard: heard
+                .last_heard
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            signal_level: heard.signal_level,
+            squitters: heard.squitters,
+        }
+    }
+}
+
+fn router(metrics: Arc<Metrics>) -> Router {
+    Router::new()
+        .route("/metrics", get(get_metrics))
+        .with_state(metrics)
+}
+
+async fn get_metrics(State(metrics): State<Arc<Metrics>>) -> Response {
+    match metrics.encode() {
+        Ok(text) => ([(header::CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)], text).into_response(),
+        Err(e) => {
+            error!("[HTTP SERVER] {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+fn api_router(control: ScannerControl) -> Router {
+    Router::new()
+        .route("/api/sdrs", get(list_sdrs))
+        .route("/api/sdrs/{serial}", get(get_sdr))
+        .route("/api/sdrs/{serial}/gain", put(set_gain))
+        .route("/api/sdrs/{serial}/ppm", put(set_ppm))
+        .route("/api/sdrs/{serial}/frequencies", put(set_frequencies))
+        .route(
+            "/api/sdrs/{serial}/channels/{frequency}",
+            put(set_channel_enabled),
+        )
+        .route("/api/sdrs/{serial}/restart", post(restart))
+        .route("/api/messages", get(list_messages))
+        .route("/api/ground-stations", get(list_ground_stations))
+        .merge(web::router())
+        .with_state(control)
+}
+
+async fn list_sdrs(State(control): State<ScannerControl>) -> Response {
+    let sdrs: Vec<_> = control.sdrs().iter().map(SdrControl::status).collect();
+    Json(sdrs).into_response()
+}
+
+async fn get_sdr(State(control): State<ScannerControl>, Path(serial): Path<String>) -> Response {
+    control.sdr(&serial).map_or_else(
+        || unknown_sdr(&serial),
+        |sdr| Json(sdr.status()).into_response(),
+    )
+}
+
+fn unknown_sdr(serial: &str) -> Response {
+    api_error(StatusCode::NOT_FOUND, &format!("Unknown SDR {serial}"))
+}
+
+/// Queue `command` for the SDR with `serial`
+fn send(control: &ScannerControl, serial: &str, command: SdrCommand) -> Response {
+    let Some(sdr) = control.sdr(serial) else {
+        return unknown_sdr(serial);
+    };
+
+    match sdr.send(command) {
+        Ok(()) => StatusCode::ACCEPTED.into_response(),
+        Err(e @ RTLSDRError::NotRunning { .. }) => api_error(StatusCode::CONFLICT, &e),
+        Err(e) => api_error(StatusCode::BAD_REQUEST, &e),
+    }
+}
+
+async fn set_gain(
+    State(control): State<ScannerControl>,
+    Path(serial): Path<String>,
+    Json(request): Json<GainRequest>,
+) -> Response {
+    match request.gain {
+        Some(gain) if !(MIN_GAIN..=MAX_GAIN).contains(&gain) => api_error(
+            StatusCode::BAD_REQUEST,
+            &format!("Gain must be between {MIN_GAIN} and {MAX_GAIN}"),
+        ),
+        // Tenths of a dB, which fits easily
+        #[allow(clippy::cast_possible_truncation)]
+        gain => send(
+            &control,
+            &serial,
+            SdrCommand::SetGain(gain.map(|gain| (gain * 10.0).round() as i32)),
+        ),
+    }
+}
+
+async fn set_ppm(
+    State(control): State<ScannerControl>,
+    Path(serial): Path<String>,
+    Json(request): Json<PpmRequest>,
+) -> Response {
+    send(&control, &serial, SdrCommand::SetPpm(request.ppm))
+}
+
+async fn set_frequencies(
+    State(control): State<ScannerControl>,
+    Path(serial): Path<String>,
+    Json(request): Json<FrequenciesRequest>,
+) -> Response {
+    send(&control, &serial, SdrCommand::Retune(request.frequencies))
+}
+
+async fn set_channel_enabled(
+    State(control): State<ScannerControl>,
+    Path((serial, frequency)): Path<(String, f32)>,
+    Json(request): Json<ChannelRequest>,
+) -> Response {
+    send(
+        &control,
+        &serial,
+        SdrCommand::SetChannelEnabled {
+            frequency,
+            enabled: request.enabled,
+        },
+    )
+}
+
+async fn restart(State(control): State<ScannerControl>, Path(serial): Path<String>) -> Response {
+    send(&control, &serial, SdrCommand::Restart)
+}
+
+async fn list_messages(
+    State(control): State<ScannerControl>,
+    Query(query): Query<MessagesQuery>,
+) -> Response {
+    Json(
+        control
+            .recent_messages()
+            .latest(query.limit.unwrap_or(DEFAULT_MESSAGE_LIMIT)),
+    )
+    .into_response()
+}
+
+async fn list_ground_stations(State(control): State<ScannerControl>) -> Response {
+    let stations: Vec<_> = control
+        .ground_stations()
+        .iter()
+        .map(GroundStationStatus::new)
+        .collect();
+    Json(stations).into_response()
+}
+
+/// Serve the metrics on `listener` until the task is dropped.
+///
+/// # Errors
+///
+/// Returns an error if accepting connections fails.
+pub async fn serve(listener: TcpListener, metrics: Arc<Metrics>) -> io::Result<()> {
+    if let Ok(address) = listener.local_addr() {
+        info!("[HTTP SERVER] Serving metrics on http://{address}/metrics");
+    }
+
+    axum::serve(listener, router(metrics)).await
+}
+
+/// Serve the REST API and the web UI on `listener` until the task is dropped.
+///
+/// # Errors
+///
+/// Returns an error if accepting connections fails.
+pub async fn serve_api(listener: TcpListener, control: ScannerControl) -> io::Result<()> {
+    if let Ok(address) = listener.local_addr() {
+        info!("[HTTP SERVER] Serving the web UI on http://{address}/ and the API on http://{address}/api");
+    }
+
+    axum::serve(listener, api_router(control)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oxide_decoders::decoders::acars::AssembledACARSMessage;
+    use oxide_decoders::decoders::squitter::{GroundStation, GroundStationNetwork};
+    use oxide_decoders::ValidDecoderType;
+    use oxide_output::json::OxideJsonMessage;
+    use oxide_output::recent::RecentMessages;
+    use oxide_rtlsdr::RtlSdr;
+    use std::sync::Mutex;
+    use std::time::{Duration, SystemTime};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    async fn request(
+        address: std::net::SocketAddr,
+        method: &str,
+        path: &str,
+        body: &str,
+    ) -> io::Result<String> {
+        let mut stream = TcpStream::connect(address).await?;
+        stream
+            .write_all(
+                format!(
+                    "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                    body.len()
+                )
+                .as_bytes(),
+            )
+            .await?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        Ok(response)
+    }
+
+    async fn get(address: std::net::SocketAddr, path: &str) -> io::Result<String> {
+        request(address, "GET", path, "").await
+    }
+
+    /// The payload of the next WebSocket frame. Frames from the server are never masked.
+    async fn read_frame(stream: &mut TcpStream) -> io::Result<String> {
+        let _opcode = stream.read_u8().await?;
+        let length = match stream.read_u8().await? & 0x7f {
+            126 => u64::from(stream.read_u16().await?),
+            127 => stream.read_u64().await?,
+            length => u64::from(length),
+        };
+        let length =
+            usize::try_from(length).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let mut payload = vec![0; length];
+        stream.read_exact(&mut payload).await?;
+        Ok(String::from_utf8_lossy(&payload).into_owned())
+    }
+
+    /// Open the live feed of the web UI
+    async fn connect_live(address: std::net::SocketAddr) -> io::Result<TcpStream> {
+        let mut stream = TcpStream::connect(address).await?;
+        stream
+            .write_all(
+                b"GET /api/live HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+            )
+            .await?;
+        let mut head = vec![];
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(stream.read_u8().await?);
+        }
+        if !head.starts_with(b"HTTP/1.1 101") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                String::from_utf8_lossy(&head).into_owned(),
+            ));
+        }
+        Ok(stream)
+    }
+
+    /// Send a WebSocket frame from the page. Frames from the client are always masked.
+    async fn write_frame(stream: &mut TcpStream, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let length = u8::try_from(payload.len())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut frame = vec![0x80 | opcode, 0x80 | length];
+        frame.extend_from_slice(&mask);
+        frame.extend(
+            payload
+                .iter()
+                .zip(mask.iter().cycle())
+                .map(|(byte, mask)| byte ^ mask),
+        );
+        stream.write_all(&frame).await
+    }
+
+    /// The next update of the live feed
+    async fn next_update(
+        stream: &mut TcpStream,
+    ) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+        Ok(serde_json::from_str(&read_frame(stream).await?)?)
+    }
+
+    fn message(label: [char; 2]) -> OxideJsonMessage {
+        OxideJsonMessage::new(
+            &AssembledACARSMessage {
+                label,
+                ..AssembledACARSMessage::default()
+            },
+            SystemTime::UNIX_EPOCH,
+        )
+    }
+
+    fn sdr() -> RtlSdr {
+        RtlSdr::new(
+            "00012785".to_string(),
+            0,
+            420,
+            false,
+            160,
+            vec![130.025, 131.55],
+            ValidDecoderType::ACARS,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_serve_metrics() -> Result<(), Box<dyn std::error::Error>> {
+        let metrics = Arc::new(Metrics::new()?);
+        metrics.sample_buffer("00012785");
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let server = tokio::spawn(serve(listener, metrics));
+
+        let response = get(address, "/metrics").await?;
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.contains(PROMETHEUS_CONTENT_TYPE));
+        assert!(response.contains(r#"acars_oxide_sample_buffers_total{sdr="00012785"} 1"#));
+
+        let response = get(address, "/").await?;
+        assert!(response.starts_with("HTTP/1.1 404"), "{response}");
+
+        server.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_serve_api() -> Result<(), Box<dyn std::error::Error>> {
+        let mut sdr = sdr();
+        let recent_messages = RecentMessages::new(10);
+        recent_messages.push(message(['H', '1']));
+        let control = ScannerControl::new(
+            vec![sdr.control()],
+            recent_messages,
+            Arc::new(Mutex::new(vec![])),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let server = tokio::spawn(serve_api(listener, control));
+
+        let response = get(address, "/api/sdrs").await?;
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.contains(r#""serial":"00012785","state":"starting","gain":42.0"#));
+        assert!(response.contains(r#""frequency":131.55,"enabled":true"#));
+
+        let response = get(address, "/api/sdrs/00012785").await?;
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        let response = get(address, "/api/sdrs/unknown").await?;
+        assert!(response.starts_with("HTTP/1.1 404"), "{response}");
+
+        let response = get(address, "/api/messages?limit=1").await?;
+        assert!(response.contains(r#""label":"H1""#), "{response}");
+
+        let response = get(address, "/api/ground-stations").await?;
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("[]"), "{response}");
+
+        for (method, path, body) in [
+            ("PUT", "/api/sdrs/00012785/gain", r#"{"gain": 38.6}"#),
+            ("PUT", "/api/sdrs/00012785/gain", r#"{"gain": null}"#),
+            ("PUT", "/api/sdrs/00012785/ppm", r#"{"ppm": -2}"#),
+            (
+                "PUT",
+                "/api/sdrs/00012785/frequencies",
+                r#"{"frequencies": [131.125, 131.55]}"#,
+            ),
+            (
+                "PUT",
+                "/api/sdrs/00012785/channels/131.55",
+                r#"{"enabled": false}"#,
+            ),
+            ("POST", "/api/sdrs/00012785/restart", ""),
+        ] {
+            let response = request(address, method, path, body).await?;
+            assert!(response.starts_with("HTTP/1.1 202"), "{path}: {response}");
+        }
+
+        for (path, body) in [
+            ("/api/sdrs/00012785/gain", r#"{"gain": 80}"#),
+            (
+                "/api/sdrs/00012785/frequencies",
+                r#"{"frequencies": [129.0, 131.55]}"#,
+            ),
+            ("/api/sdrs/00012785/channels/136.9", r#"{"enabled": false}"#),
+        ] {
+            let response = request(address, "PUT", path, body).await?;
+            assert!(response.starts_with("HTTP/1.1 400"), "{path}: {response}");
+        }
+
+        // Commands can't be sent once the SDR is gone
+        drop(sdr);
+        let response = request(address, "POST", "/api/sdrs/00012785/restart", "").await?;
+        assert!(response.starts_with("HTTP/1.1 409"), "{response}");
+
+        server.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_serve_web() -> Result<(), Box<dyn std::error::Error>> {
+        let mut sdr = sdr();
+        let recent_messages = RecentMessages::new(10);
+        recent_messages.push(message(['H', '1']));
+        let ground_stations = vec![HeardGroundStation {
+            station: GroundStation {
+                version: 2,
+                network: GroundStationNetwork::Arinc,
+                iata: Some("ABQ".to_string()),
+                icao: Some("KABQ".to_string()),
+                station_number: Some(1),
+                latitude: Some(35.033),
+                longitude: Some(-106.617),
+                vdl_frequency: Some(136.975),
+                text: None,
+            },
+            frequency: 131.55,
+            last_heard: SystemTime::UNIX_EPOCH + Duration::from_secs(60),
+            signal_level: -20.0,
+            squitters: 3,
+        }];
+        let control = ScannerControl::new(
+            vec![sdr.control()],
+            recent_messages.clone(),
+            Arc::new(Mutex::new(ground_stations)),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let server = tokio::spawn(serve_api(listener, control));
+
+        let response = get(address, "/").await?;
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.contains("<title>ACARS Oxide</title>"));
+        let response = get(address, "/app.js").await?;
+        assert!(response.contains("text/javascript"), "{response}");
+        let response = get(address, "/style.css").await?;
+        assert!(response.contains("text/css"), "{response}");
+
+        let response = get(address, "/api/ground-stations").await?;
+        assert!(
+            response.contains(r#""network":"ARINC","iata":"ABQ","icao":"KABQ""#),
+            "{response}"
+        );
+        assert!(response.contains(r#""last_heard":60"#), "{response}");
+
+        let mut stream = connect_live(address).await?;
+
+        // The recent messages come first, then the status
+        let frame = read_frame(&mut stream).await?;
+        assert!(
+            frame.starts_with(r#"{"type":"message","message":{"#),
+            "{frame}"
+        );
+        assert!(frame.contains(r#""label":"H1""#), "{frame}");
+        let frame = read_frame(&mut stream).await?;
+        assert!(frame.starts_with(r#"{"type":"status","sdrs":[{"serial":"00012785""#));
+        assert!(frame.contains(r#""icao":"KABQ""#), "{frame}");
+
+        recent_messages.push(message(['Q', '0']));
+        let mut frame = read_frame(&mut stream).await?;
+        while frame.starts_with(r#"{"type":"status""#) {
+            frame = read_frame(&mut stream).await?;
+        }
+        assert!(frame.contains(r#""label":"Q0""#), "{frame}");
+
+        server.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_web_live_feed() -> Result<(), Box<dyn std::error::Error>> {
+        let mut sdr = sdr();
+        // Room for three messages, in the recent messages and in the live feed
+        let recent_messages = RecentMessages::new(3);
+        for label in [['H', '1'], ['Q', '0'], ['5', 'Z'], ['S', 'A']] {
+            recent_messages.push(message(label));
+        }
+        let control = ScannerControl::new(
+            vec![sdr.control()],
+            recent_messages.clone(),
+            Arc::new(Mutex::new(vec![])),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let server = tokio::spawn(serve_api(listener, control));
+
+        let mut stream = connect_live(address).await?;
+
+        // Only the messages still kept are replayed, oldest first
+        let mut replayed = vec![];
+        for _ in 0..3 {
+            let update = next_update(&mut stream).await?;
+            replayed.push(update["message"]["acars"]["label"].clone());
+        }
+        assert_eq!(replayed, ["Q0", "5Z", "SA"]);
+
+        let status = next_update(&mut stream).await?;
+        assert_eq!(status["type"], "status");
+        assert_eq!(status["sdrs"][0]["serial"], "00012785");
+        assert_eq!(status["sdrs"][0]["channels"][1]["frequency"], 131.55);
+        assert_eq!(status["ground_stations"], serde_json::json!([]));
+
+        // Text from the page is ignored
+        write_frame(&mut stream, 0x1, b"hello").await?;
+
+        // Far more messages than the live feed holds. The feed skips the ones it missed, but
+        // keeps going, in order and without repeats
+        let live: Vec<String> = (0..10).map(|index| format!("L{index}")).collect();
+        for label in &live {
+            let chars: Vec<char> = label.chars().collect();
+            recent_messages.push(message([chars[0], chars[1]]));
+        }
+        let mut received = vec![];
+        while received.last() != live.last() {
+            let update = next_update(&mut stream).await?;
+            if let Some(label) = update["message"]["acars"]["label"].as_str() {
+                received.push(label.to_string());
+            }
+        }
+        let positions: Vec<Option<usize>> = received
+            .iter()
+            .map(|label| live.iter().position(|live| live == label))
+            .collect();
+        assert!(positions.iter().all(Option::is_some), "{received:?}");
+        assert!(
+            positions.windows(2).all(|pair| pair[0] < pair[1]),
+            "{received:?}"
+        );
+
+        // Once the page closes, the feed stops and the connection is closed
+        write_frame(&mut stream, 0x8, &[]).await?;
+        let mut rest = vec![];
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut rest)).await??;
+
+        server.abort();
+        Ok(())
+    }
+}
@@ -29,62 +29,149 @@
 #[macro_use]
 extern crate log;
 
-use oxide_output::OxideOutput;
+pub mod builder;
+pub mod http;
+pub mod statistics;
+mod web;
+
+pub use builder::{
+    MessageStream, OxideScannerBuilder, ReceiverConfig, ReceiverStatistics, ScannerControl,
+    ScannerError, ScannerHandle,
+};
+
+use oxide_metrics::Metrics;
+use oxide_output::mqtt::MqttSink;
+use oxide_output::rules::RuleSet;
+use oxide_output::websocket::WebSocketSink;
 use oxide_rtlsdr::RtlSdr;
-use tokio::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
 
 pub struct OxideScanner {
     sdrs: [RtlSdr; 8],
     enable_output_command_line: bool,
+    enable_output_json: bool,
     enable_output_zmq: bool,
+    dedup_window: Option<Duration>,
+    rules: RuleSet,
+    metrics: Option<Arc<Metrics>>,
+    websocket: Option<WebSocketSink>,
+    mqtt: Option<MqttSink>,
+    statistics_interval: Option<Duration>,
     number_of_sdrs: usize,
 }
 
 impl OxideScanner {
+    #[must_use]
     pub fn new(
         sdrs: [RtlSdr; 8],
         number_of_sdrs: usize,
         enable_output_command_line: bool,
+        enable_output_json: bool,
         enable_output_zmq: bool,
-    ) -> OxideScanner {
-        OxideScanner {
+        dedup_window: Option<Duration>,
+    ) -> Self {
+        Self {
             sdrs,
-            number_of_sdrs,
             enable_output_command_line,
+            enable_output_json,
             enable_output_zmq,
+            dedup_window,
+            rules: RuleSet::new(),
+            metrics: None,
+            websocket: None,
+            mqtt: None,
+            statistics_interval: None,
+            number_of_sdrs,
         }
     }
 
-    pub async fn run(self) {
-        let mut valid_sdrs: u8 = 0;
-        let (tx_channel, rx) = mpsc::unbounded_channel();
-        let mut output =
-            OxideOutput::new(self.enable_output_command_line, self.enable_output_zmq, rx);
+    /// Configure a scanner to embed in another application.
+    #[must_use]
+    pub fn builder() -> OxideScannerBuilder {
+        OxideScannerBuilder::new()
+    }
 
-        tokio::spawn(async move {
-            output.monitor_receiver_channel().await;
-        });
+    /// Filter the messages each output sink gets.
+    pub fn set_rules(&mut self, rules: RuleSet) {
+        self.rules = rules;
+    }
+
+    /// Record the SDRs, decoders and output in `metrics`.
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
 
-        for mut sdr in self.sdrs.into_iter().take(self.number_of_sdrs) {
-            info!("[OXIDE SCANNER] Opening SDR {}", sdr.get_serial());
-            match sdr.open_sdr(tx_channel.clone()) {
-                Ok(_) => {
-                    valid_sdrs += 1;
-                    info!("[OXIDE SCANNER] SDR {} opened", sdr.get_serial());
-                    tokio::spawn(async move {
-                        sdr.read_samples().await;
-                    });
-                }
-                Err(e) => {
-                    error!(
-                        "[OXIDE SCANNER] Failed to open SDR {}: {}",
-                        sdr.get_serial(),
-                        e
-                    );
-                }
-            }
+    /// Push the messages to the clients of `websocket`.
+    pub fn set_websocket_sink(&mut self, websocket: WebSocketSink) {
+        self.websocket = Some(websocket);
+    }
+
+    /// Publish the messages through `mqtt`.
+    pub fn set_mqtt_sink(&mut self, mqtt: MqttSink) {
+        self.mqtt = Some(mqtt);
+    }
+
+    /// Log a summary of what each channel decoded every `statistics_interval`, and the totals
+    /// on shutdown.
+    pub fn set_statistics_interval(&mut self, statistics_interval: Option<Duration>) {
+        self.statistics_interval = statistics_interval;
+    }
+
+    /// Open every configured SDR and start reading samples from it. Decoded messages are
+    /// handed to the output task. Must be called from within a tokio runtime.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no SDRs are configured, or if none of them could be opened.
+    pub fn run(self) -> Result<ScannerHandle, ScannerError> {
+        let mut builder = OxideScannerBuilder::new()
+            .output_command_line(self.enable_output_command_line)
+            .output_json(self.enable_output_json)
+            .output_zmq(self.enable_output_zmq)
+            .dedup_window(self.dedup_window)
+            .rules(self.rules)
+            .statistics_interval(self.statistics_interval);
+        if let Some(metrics) = self.metrics {
+            builder = builder.metrics(metrics);
         }
+        if let Some(websocket) = self.websocket {
+            builder = builder.websocket_sink(websocket);
+        }
+        if let Some(mqtt) = self.mqtt {
+            builder = builder.mqtt_sink(mqtt);
+        }
+
+        let receivers = self
+            .sdrs
+            .into_iter()
+            .take(self.number_of_sdrs)
+            .map(|sdr| (sdr, None))
+            .collect();
 
-        assert!(valid_sdrs > 0, "No valid SDRs found. Exiting program.");
+        builder.launch(receivers, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oxide_decoders::ValidDecoderType;
+
+    #[tokio::test]
+    async fn test_run_without_sdrs() {
+        let sdrs = std::array::from_fn(|n| {
+            RtlSdr::new(
+                format!("0000000{n}"),
+                0,
+                0,
+                false,
+                160,
+                vec![131.55],
+                ValidDecoderType::ACARS,
+            )
+        });
+        let scanner = OxideScanner::new(sdrs, 0, false, false, false, None);
+        assert!(matches!(scanner.run(), Err(ScannerError::NoReceivers)));
     }
 }
@@ -0,0 +1,324 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// Periodic summary of what each receiver decoded, for an at a glance view in the log. Every
+// interval the counts since the previous summary are logged, and the totals once the scanner
+// shuts down.
+
+use oxide_decoders::ChannelStatistics;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Labels listed in each line of the summary
+const TOP_LABELS: usize = 5;
+
+/// The statistics each SDR publishes, by serial
+pub type SharedStatistics = Vec<(String, Arc<Mutex<Vec<ChannelStatistics>>>)>;
+
+#[derive(Debug)]
+pub struct StatisticsCollector {
+    receivers: SharedStatistics,
+    /// The statistics of every receiver at the last interval summary
+    reported: Vec<Vec<ChannelStatistics>>,
+}
+
+impl StatisticsCollector {
+    #[must_use]
+    pub fn new(receivers: SharedStatistics) -> Self {
+        Self {
+            reported: vec![vec![]; receivers.len()],
+            receivers,
+        }
+    }
+
+    fn current(&self) -> Vec<Vec<ChannelStatistics>> {
+        self.receivers
+            .iter()
+            .map(|(_, statistics)| {
+                statistics
+                    .lock()
+                    .map(|statistics| statistics.clone())
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    fn summary_lines(&self, statistics: &[Vec<ChannelStatistics>]) -> Vec<String> {
+        self.receivers
+            .iter()
+            .zip(statistics)
+            .flat_map(|((serial, _), channels)| {
+                channels
+                    .iter()
+                    .map(move |channel| summary_line(serial, channel))
+            })
+            .collect()
+    }
+
+    /// A line per channel counting the messages since the previous call
+    pub fn interval_summary(&mut self) -> Vec<String> {
+        let current = self.current();
+        let interval: Vec<Vec<ChannelStatistics>> = current
+            .iter()
+            .zip(&self.reported)
+            .map(|(channels, reported)| {
+                channels
+                    .iter()
+                    .map(|channel| {
+                        // Retuning an SDR starts its channels over
+                        reported
+                            .iter()
+                            .find(|reported| {
+                                (reported.frequency - channel.frequency).abs() < f32::EPSILON
+                                    && reported.messages <= channel.messages
+                            })
+                            .map_or_else(|| channel.clone(), |reported| channel.since(reported))
+                    })
+                    .collect()
+            })
+            .collect();
+
+        self.reported = current;
+        self.summary_lines(&interval)
+    }
+
+    /// A line per channel counting every message since the scanner started
+    #[must_use]
+    pub fn final_summary(&self) -> Vec<String> {
+        self.summary_lines(&self.current())
+    }
+
+    /// Log the interval summary every `interval`. Runs until the task is dropped.
+    pub async fn log_every(collector: Arc<Mutex<Self>>, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick is immediate
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+
+            let Ok(mut collector) = collector.lock() else {
+                return;
+            };
+
+            info!("[OXIDE SCANNER] Statistics since the last summary");
+            for line in collector.interval_summary() {
+                info!("[OXIDE SCANNER] {line}");
+            }
+        }
+    }
+
+    pub fn log_final_summary(&self) {
+        info!("[OXIDE SCANNER] Statistics since start");
+        for line in self.final_summary() {
+            info!("[OXIDE SCANNER] {line}");
+        }
+    }
+}
+
+fn summary_line(serial: &str, channel: &ChannelStatistics) -> String {
+    let top_labels = channel
+        .top_labels(TOP_LABELS)
+        .iter()
+        .map(|(label, messages)| format!("{label} ({messages})"))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!(
+        "{serial} {:.3}: Messages: {}, Corrected: {}, Dropped: {} parity / {} CRC, Noise Level: {}, Average Signal Level: {}, Average SNR: {}, Top Labels: {}",
+        channel.frequency,
+        channel.messages,
+        channel.corrected_messages,
+        channel.parity_failures,
+        channel.crc_failures,
+        channel
+            .noise_level
+            .map_or_else(|| "n/a".to_string(), |level| format!("{level:.1}")),
+        channel
+            .average_signal_level()
+            .map_or_else(|| "n/a".to_string(), |level| format!("{level:.1}")),
+        channel
+            .average_snr()
+            .map_or_else(|| "n/a".to_string(), |snr| format!("{snr:.1}")),
+        if top_labels.is_empty() {
+            "n/a"
+        } else {
+            &top_labels
+        }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oxide_decoders::decoders::acars::{AssembledACARSMessage, MessageStatus};
+
+    #[test]
+    fn test_summaries() {
+        let mut channel = ChannelStatistics::new(131.55);
+        let message = AssembledACARSMessage {
+            label: ['H', '1'],
+            signal_level: -20.0,
+            snr: Some(12.5),
+            ..AssembledACARSMessage::default()
+        };
+        channel.add_message(&message);
+
+        let shared = Arc::new(Mutex::new(vec![channel.clone()]));
+        let mut collector =
+            StatisticsCollector::new(vec![("00012785".to_string(), shared.clone())]);
+
+        assert_eq!(
+            collector.interval_summary(),
+            vec!["00012785 131.550: Messages: 1, Corrected: 0, Dropped: 0 parity / 0 CRC, Noise Level: n/a, Average Signal Level: -20.0, Average SNR: 12.5, Top Labels: H1 (1)"]
+        );
+
+        channel.add_failed_message(&MessageStatus::CrcFailed);
+        if let Ok(mut shared) = shared.lock() {
+            *shared = vec![channel];
+        }
+
+        assert_eq!(
+            collector.interval_summary(),
+            vec!["00012785 131.550: Messages: 0, Corrected: 0, Dropped: 0 parity / 1 CRC, Noise Level: n/a, Average Signal Level: n/a, Average SNR: n/a, Top Labels: n/a"]
+        );
+        assert_eq!(
+            collector.final_summary(),
+            vec!["00012785 131.550: Messages: 1, Corrected: 0, Dropped: 0 parity / 1 CRC, Noise Level: n/a, Average Signal Level: -20.0, Average SNR: 12.5, Top Labels: H1 (1)"]
+        );
+    }
+
+    fn add(channel: &mut ChannelStatistics, label: &str, signal_level: f32, snr: Option<f32>) {
+        let label: Vec<char> = label.chars().collect();
+        channel.add_message(&AssembledACARSMessage {
+            label: [label[0], label[1]],
+            signal_level,
+            snr,
+            corrected_bits: u8::from(signal_level < -35.0),
+            ..AssembledACARSMessage::default()
+        });
+    }
+
+    fn publish(shared: &Arc<Mutex<Vec<ChannelStatistics>>>, channels: &[ChannelStatistics]) {
+        if let Ok(mut shared) = shared.lock() {
+            *shared = channels.to_vec();
+        }
+    }
+
+    #[test]
+    fn test_interval_averages() {
+        let mut channel = ChannelStatistics::new(131.55);
+        let shared = Arc::new(Mutex::new(vec![]));
+        let mut collector =
+            StatisticsCollector::new(vec![("00012785".to_string(), shared.clone())]);
+
+        // The noise floor is measured part way through the first interval, so only the later
+        // messages have an SNR
+        add(&mut channel, "H1", -10.0, None);
+        add(&mut channel, "H1", -20.0, Some(20.0));
+        add(&mut channel, "_d", -30.0, Some(10.0));
+        channel.noise_level = Some(-40.0);
+        publish(&shared, &[channel.clone()]);
+        assert_eq!(
+            collector.interval_summary(),
+            vec!["00012785 131.550: Messages: 3, Corrected: 0, Dropped: 0 parity / 0 CRC, Noise Level: -40.0, Average Signal Level: -20.0, Average SNR: 15.0, Top Labels: H1 (2), _d (1)"]
+        );
+
+        // Each interval averages its own messages
+        add(&mut channel, "SQ", -40.0, Some(1.0));
+        add(&mut channel, "SQ", -50.0, Some(-9.0));
+        channel.add_failed_message(&MessageStatus::ParityFailed);
+        channel.noise_level = Some(-41.0);
+        publish(&shared, &[channel.clone()]);
+        assert_eq!(
+            collector.interval_summary(),
+            vec!["00012785 131.550: Messages: 2, Corrected: 2, Dropped: 1 parity / 0 CRC, Noise Level: -41.0, Average Signal Level: -45.0, Average SNR: -4.0, Top Labels: SQ (2)"]
+        );
+
+        // Nothing heard
+        assert_eq!(
+            collector.interval_summary(),
+            vec!["00012785 131.550: Messages: 0, Corrected: 0, Dropped: 0 parity / 0 CRC, Noise Level: -41.0, Average Signal Level: n/a, Average SNR: n/a, Top Labels: n/a"]
+        );
+
+        // Since start, across all the intervals
+        assert_eq!(
+            collector.final_summary(),
+            vec!["00012785 131.550: Messages: 5, Corrected: 2, Dropped: 1 parity / 0 CRC, Noise Level: -41.0, Average Signal Level: -30.0, Average SNR: 5.5, Top Labels: H1 (2), SQ (2), _d (1)"]
+        );
+    }
+
+    #[test]
+    fn test_interval_rollover() {
+        let first = Arc::new(Mutex::new(vec![]));
+        let second = Arc::new(Mutex::new(vec![]));
+        let mut collector = StatisticsCollector::new(vec![
+            ("00012785".to_string(), first.clone()),
+            ("00012786".to_string(), second.clone()),
+        ]);
+
+        let mut low = ChannelStatistics::new(130.025);
+        let mut high = ChannelStatistics::new(131.55);
+        let mut other = ChannelStatistics::new(136.975);
+        for (index, label) in ["H1", "H1", "H1", "5Z", "5Z", "QA", "QB", "QC", "Q0"]
+            .iter()
+            .enumerate()
+        {
+            add(&mut low, label, -20.0, None);
+            if index % 3 == 0 {
+                add(&mut high, "_d", -25.0, None);
+            }
+        }
+        add(&mut other, "SQ", -15.0, None);
+        publish(&first, &[low.clone(), high.clone()]);
+        publish(&second, &[other.clone()]);
+
+        // A line per channel, in receiver order, with the labels heard most first and ties in
+        // label order
+        let lines = collector.interval_summary();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("00012785 130.025: Messages: 9,"));
+        assert!(lines[0].ends_with("Top Labels: H1 (3), 5Z (2), Q0 (1), QA (1), QB (1)"));
+        assert!(lines[1].starts_with("00012785 131.550: Messages: 3,"));
+        assert!(lines[2].starts_with("00012786 136.975: Messages: 1,"));
+
+        // The second SDR is retuned and the first one restarted, which starts their channels
+        // over. Everything they have is new since the last summary
+        let mut retuned = ChannelStatistics::new(131.825);
+        add(&mut retuned, "H1", -30.0, None);
+        let mut restarted = ChannelStatistics::new(130.025);
+        add(&mut restarted, "5Z", -22.0, None);
+        add(&mut restarted, "5Z", -24.0, None);
+        add(&mut high, "_d", -25.0, None);
+        publish(&first, &[restarted, high]);
+        publish(&second, &[retuned]);
+
+        let lines = collector.interval_summary();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("00012785 130.025: Messages: 2,"));
+        assert!(lines[0].contains("Average Signal Level: -23.0"));
+        assert!(lines[1].starts_with("00012785 131.550: Messages: 1,"));
+        assert!(lines[2].starts_with("00012786 131.825: Messages: 1,"));
+
+        // A receiver whose statistics can't be read has no lines rather than stopping the others
+        let _ = std::thread::spawn(move || {
+            let _guard = second.lock();
+            panic!("poison the statistics");
+        })
+        .join();
+        assert_eq!(collector.interval_summary().len(), 2);
+    }
+}
@@ -0,0 +1,244 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+// The web UI, served alongside the REST API. The page and its assets are built into the binary.
+// The page follows the scanner over a WebSocket, which first sends the recent messages, oldest
+// first, then every message as it is output, and the state of the SDRs and the ground stations
+// heard every second:
+//
+//   {"type": "message", "message": {...}}            the JSON output of the message
+//   {"type": "status", "sdrs": [...], "ground_stations": [...]}
+
+use crate::http::GroundStationStatus;
+use crate::ScannerControl;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use oxide_output::json::OxideJsonMessage;
+use oxide_output::recent::DEFAULT_RECENT_MESSAGES;
+use oxide_rtlsdr::control::{SdrControl, SdrStatus};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+const INDEX_HTML: &str = include_str!("../web/index.html");
+const APP_JS: &str = include_str!("../web/app.js");
+const STYLE_CSS: &str = include_str!("../web/style.css");
+/// How often the state of the SDRs is sent
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum LiveUpdate<'a> {
+    Message {
+        message: &'a OxideJsonMessage,
+    },
+    Status {
+        sdrs: Vec<SdrStatus>,
+        ground_stations: Vec<GroundStationStatus>,
+    },
+}
+
+pub fn router() -> Router<ScannerControl> {
+    Router::new()
+        .route("/", get(index))
+        .route("/app.js", get(app_js))
+        .route("/style.css", get(style_css))
+        .route("/api/live", get(live))
+}
+
+async fn index() -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+        INDEX_HTML,
+    )
+        .into_response()
+}
+
+async fn app_js() -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/javascript; charset=utf-8")],
+        APP_JS,
+    )
+        .into_response()
+}
+
+async fn style_css() -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/css; charset=utf-8")],
+        STYLE_CSS,
+    )
+        .into_response()
+}
+
+async fn live(State(control): State<ScannerControl>, upgrade: WebSocketUpgrade) -> Response {
+    upgrade.on_upgrade(move |socket| follow(socket, control))
+}
+
+/// Send `update` as a text frame. Fails once the page has gone.
+async fn send(socket: &mut WebSocket, update: &LiveUpdate<'_>) -> Result<(), axum::Error> {
+    match serde_json::to_string(update) {
+        Ok(text) => socket.send(Message::Text(text.into())).await,
+        Err(e) => {
+            error!("[HTTP SERVER] Failed to serialize live update: {e}");
+            Ok(())
+        }
+    }
+}
+
+fn status(control: &ScannerControl) -> LiveUpdate<'static> {
+    LiveUpdate::Status {
+        sdrs: control.sdrs().iter().map(SdrControl::status).collect(),
+        ground_stations: control
+            .ground_stations()
+            .iter()
+            .map(GroundStationStatus::new)
+            .collect(),
+    }
+}
+
+/// The recent messages sent to a page when it connects
+struct Replay {
+    /// Newest first, as they come from the recent messages
+    recent: Vec<OxideJsonMessage>,
+    already_sent: HashSet<String>,
+}
+
+impl Replay {
+    fn new(recent: Vec<OxideJsonMessage>) -> Self {
+        let already_sent = recent
+            .iter()
+            .map(|message| message.message_information.message_uuid.clone())
+            .collect();
+
+        Self {
+            recent,
+            already_sent,
+        }
+    }
+
+    /// The messages to send, oldest first
+    fn messages(&self) -> impl Iterator<Item = &OxideJsonMessage> {
+        self.recent.iter().rev()
+    }
+
+    /// Whether the live `message` was already sent by the replay. Each is only skipped once.
+    fn is_duplicate(&mut self, message: &OxideJsonMessage) -> bool {
+        self.already_sent
+            .remove(&message.message_information.message_uuid)
+    }
+}
+
+/// Feed the page on `socket` until it goes away
+async fn follow(mut socket: WebSocket, control: ScannerControl) {
+    // Subscribe before reading the recent messages, so none are missed in between. Messages
+    // pushed in between show up in both, and are only sent once.
+    let mut messages = control.recent_messages().subscribe();
+    let mut replay = Replay::new(control.recent_messages().latest(DEFAULT_RECENT_MESSAGES));
+
+    for message in replay.messages() {
+        if send(&mut socket, &LiveUpdate::Message { message })
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+
+    let mut status_timer = tokio::time::interval(STATUS_INTERVAL);
+
+    loop {
+        let sent = tokio::select! {
+            message = messages.recv() => match message {
+                Ok(message) => {
+                    if replay.is_duplicate(&message) {
+                        continue;
+                    }
+                    send(&mut socket, &LiveUpdate::Message { message: &message }).await
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    debug!("[HTTP SERVER] Live feed fell behind, skipped {skipped} messages");
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            },
+            _ = status_timer.tick() => send(&mut socket, &status(&control)).await,
+            // Nothing is expected from the page, only that it closes
+            received = socket.recv() => match received {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+                Some(Ok(_)) => continue,
+            },
+        };
+
+        if sent.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oxide_decoders::decoders::acars::AssembledACARSMessage;
+    use oxide_output::recent::RecentMessages;
+    use std::time::SystemTime;
+
+    fn message(label: [char; 2]) -> OxideJsonMessage {
+        OxideJsonMessage::new(
+            &AssembledACARSMessage {
+                label,
+                ..AssembledACARSMessage::default()
+            },
+            SystemTime::UNIX_EPOCH,
+        )
+    }
+
+    fn labels<'a>(messages: impl Iterator<Item = &'a OxideJsonMessage>) -> Vec<String> {
+        messages
+            .map(|message| message.acars.label.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_replay_then_live() {
+        let recent_messages = RecentMessages::new(10);
+        recent_messages.push(message(['H', '1']));
+
+        // Q0 is pushed after subscribing but before the replay is read, so it is in both
+        let mut live = recent_messages.subscribe();
+        recent_messages.push(message(['Q', '0']));
+        let mut replay = Replay::new(recent_messages.latest(DEFAULT_RECENT_MESSAGES));
+        recent_messages.push(message(['5', 'Z']));
+
+        assert_eq!(labels(replay.messages()), ["H1", "Q0"]);
+
+        let mut sent = vec![];
+        while let Ok(message) = live.try_recv() {
+            if !replay.is_duplicate(&message) {
+                sent.push(message);
+            }
+        }
+        assert_eq!(labels(sent.iter()), ["5Z"]);
+
+        // Each message is only skipped once
+        let q0 = replay.recent[0].clone();
+        assert!(!replay.is_duplicate(&q0));
+    }
+}
@@ -0,0 +1,255 @@
+// Live view of a running scanner. Everything arrives over the /api/live WebSocket: the recent
+// messages followed by every new one, and the state of the SDRs and ground stations every second.
+
+"use strict";
+
+const MAX_MESSAGES = 500;
+const RECONNECT_DELAY_MS = 2000;
+// Signal levels are in dB. The bars span this range
+const LEVEL_MIN = -50;
+const LEVEL_MAX = 0;
+
+const messages = [];
+// Messages decoded per channel at the last status, to light up the channels that decoded since
+const channelMessages = new Map();
+
+const $ = (id) => document.getElementById(id);
+
+function element(tag, className, text) {
+  const node = document.createElement(tag);
+  if (className) {
+    node.className = className;
+  }
+  if (text !== undefined && text !== null) {
+    node.textContent = text;
+  }
+  return node;
+}
+
+function formatTime(seconds) {
+  return new Date(seconds * 1000).toLocaleTimeString();
+}
+
+function formatLevel(level) {
+  return level === null || level === undefined ? "-" : `${level.toFixed(1)} dB`;
+}
+
+function levelPercent(level) {
+  const clamped = Math.min(Math.max(level, LEVEL_MIN), LEVEL_MAX);
+  return ((clamped - LEVEL_MIN) / (LEVEL_MAX - LEVEL_MIN)) * 100;
+}
+
+// Filters
+
+function filterValue(id) {
+  return $(id).value.trim().toUpperCase();
+}
+
+function matches(message) {
+  const label = filterValue("filter-label");
+  const tail = filterValue("filter-tail");
+  const flight = filterValue("filter-flight");
+  const ids = message.aircraft_ids;
+
+  return (
+    (!label || message.acars.label.toUpperCase() === label) &&
+    (!tail || (ids.aircraft_registration || "").toUpperCase().includes(tail)) &&
+    (!flight || (ids.aircraft_callsign || "").toUpperCase().includes(flight))
+  );
+}
+
+// Messages
+
+function renderMessage(message) {
+  const information = message.message_information;
+  const ids = message.aircraft_ids;
+  const stations = message.ground_station_ids;
+  const node = element("div", "message");
+  if (message.acars.status !== "Valid") {
+    node.classList.add("failed");
+  }
+
+  const header = element("div", "message-header");
+  header.append(
+    element("span", "muted", formatTime(information.message_timestamp.sec)),
+    element("span", null, `${(information.frequency / 1e6).toFixed(3)} MHz`),
+    element("strong", null, `Label ${message.acars.label}`)
+  );
+  if (ids.aircraft_registration) {
+    header.append(element("span", null, `Tail ${ids.aircraft_registration}`));
+  }
+  if (ids.aircraft_callsign) {
+    header.append(element("span", null, `Flight ${ids.aircraft_callsign}`));
+  }
+  if (stations.ground_station_icao) {
+    header.append(element("span", null, `Station ${stations.ground_station_icao}`));
+  }
+  header.append(element("span", "muted", formatLevel(message.acars.signal_level)));
+  if (information.receptions > 1) {
+    header.append(element("span", "muted", `x${information.receptions}`));
+  }
+  if (message.acars.status !== "Valid") {
+    header.append(element("span", "sdr-error", message.acars.status));
+  }
+  node.append(header);
+
+  if (message.acars.text) {
+    node.append(element("pre", null, message.acars.text));
+  }
+
+  return node;
+}
+
+function renderMessages() {
+  const list = $("messages");
+  const shown = messages.filter(matches);
+  list.replaceChildren(...shown.map(renderMessage));
+  $("message-count").textContent = `${shown.length} of ${messages.length}`;
+}
+
+function addMessage(message) {
+  messages.unshift(message);
+  if (messages.length > MAX_MESSAGES) {
+    messages.pop();
+  }
+
+  if ($("pause").checked) {
+    return;
+  }
+
+  if (matches(message)) {
+    const list = $("messages");
+    list.prepend(renderMessage(message));
+    while (list.childElementCount > MAX_MESSAGES) {
+      list.lastElementChild.remove();
+    }
+  }
+  $("message-count").textContent = `${$("messages").childElementCount} of ${messages.length}`;
+}
+
+// Channels and ground stations
+
+function renderChannel(serial, channel) {
+  const key = `${serial}/${channel.frequency}`;
+  const previous = channelMessages.get(key);
+  channelMessages.set(key, channel.messages);
+
+  const node = element("div", "channel");
+  if (!channel.enabled) {
+    node.classList.add("disabled");
+  }
+
+  const activity = element("span", "activity");
+  if (previous !== undefined && channel.messages > previous) {
+    activity.classList.add("active");
+    // Fade out again on the next frame
+    requestAnimationFrame(() => requestAnimationFrame(() => activity.classList.remove("active")));
+  }
+
+  // Nothing has been measured on a channel that hasn't received anything yet
+  let level;
+  if (channel.last_signal_level === null && channel.noise_level === null) {
+    level = element("span", "muted", "-");
+  } else {
+    level = element("div", "level");
+    if (channel.last_signal_level !== null) {
+      const signal = element("div", "signal");
+      signal.style.width = `${levelPercent(channel.last_signal_level)}%`;
+      level.append(signal);
+    }
+    if (channel.noise_level !== null) {
+      const noise = element("div", "noise");
+      noise.style.left = `${levelPercent(channel.noise_level)}%`;
+      level.append(noise);
+    }
+  }
+  level.title = `Signal ${formatLevel(channel.last_signal_level)}, noise ${formatLevel(channel.noise_level)}`;
+
+  node.append(
+    activity,
+    element("span", null, channel.frequency.toFixed(3)),
+    level,
+    element(
+      "span",
+      "muted",
+      `${channel.messages} msgs, ${channel.crc_failures + channel.parity_failures} failed`
+    )
+  );
+  return node;
+}
+
+function renderSdr(sdr) {
+  const node = element("div", "sdr");
+  const title = element("div", "sdr-title");
+  const gain = sdr.gain === null ? "AGC" : `${sdr.gain.toFixed(1)} dB`;
+  title.append(
+    element("span", null, sdr.serial),
+    element("span", "muted", `${sdr.state}, gain ${gain}, ${sdr.ppm} ppm`)
+  );
+  node.append(title);
+
+  if (sdr.last_error) {
+    node.append(element("div", "sdr-error", sdr.last_error));
+  }
+
+  node.append(...sdr.channels.map((channel) => renderChannel(sdr.serial, channel)));
+  return node;
+}
+
+function renderGroundStation(station) {
+  const row = element("tr");
+  const name = [station.icao, station.iata && `(${station.iata})`, station.station_number && `#${station.station_number}`]
+    .filter(Boolean)
+    .join(" ");
+  row.append(
+    element("td", null, station.frequency.toFixed(3)),
+    element("td", null, name || "-"),
+    element("td", null, station.network),
+    element("td", null, formatLevel(station.signal_level)),
+    element("td", null, station.squitters),
+    element("td", "muted", formatTime(station.last_heard))
+  );
+  return row;
+}
+
+function renderStatus(status) {
+  $("sdrs").replaceChildren(...status.sdrs.map(renderSdr));
+  $("ground-stations").replaceChildren(...status.ground_stations.map(renderGroundStation));
+}
+
+// Connection
+
+function connect() {
+  const scheme = location.protocol === "https:" ? "wss:" : "ws:";
+  const socket = new WebSocket(`${scheme}//${location.host}/api/live`);
+
+  socket.addEventListener("open", () => {
+    $("connection").textContent = "Connected";
+    $("connection").className = "connected";
+    // The recent messages are sent again on every connection
+    messages.length = 0;
+    renderMessages();
+  });
+
+  socket.addEventListener("message", (event) => {
+    const update = JSON.parse(event.data);
+    if (update.type === "message") {
+      addMessage(update.message);
+    } else if (update.type === "status") {
+      renderStatus(update);
+    }
+  });
+
+  socket.addEventListener("close", () => {
+    $("connection").textContent = "Disconnected";
+    $("connection").className = "disconnected";
+    setTimeout(connect, RECONNECT_DELAY_MS);
+  });
+}
+
+for (const id of ["filter-label", "filter-tail", "filter-flight", "pause"]) {
+  $(id).addEventListener("input", renderMessages);
+}
+$("filters").addEventListener("submit", (event) => event.preventDefault());
+
+connect();
@@ -0,0 +1,221 @@
+// Copyright (C) 2023-2024 Fred Clausen
+
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 51 Franklin Street, Fifth Floor, Boston, MA  02110-1301, USA
+
+#![deny(
+    clippy::pedantic,
+    //clippy::cargo,
+    clippy::nursery,
+    clippy::style,
+    clippy::correctness,
+    clippy::all,
+    clippy::unwrap_used,
+    clippy::expect_used
+)]
+// #![warn(missing_docs)]
+
+#[macro_use]
+extern crate log;
+
+use chrono::{SecondsFormat, Utc};
+use clap::Parser;
+use custom_error::custom_error;
+use oxide_decoders::decoders::acars::{INTRATE, RTLOUTBUFSZ};
+use oxide_rtlsdr::find_device;
+use oxide_rtlsdr::sigmf::{meta_path, SigMfError, SigMfMetadata, DATATYPE_CU8};
+use sdre_rust_logging::SetupLogging;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+const DEFAULT_BUFFERS: u32 = 100;
+
+custom_error! {GrabberError
+    DeviceNotFound { serial: String } = "Device {serial} not found",
+    OpenFailed { serial: String } = "Unable to open device {serial}",
+    SettingFailed { setting: String } = "Unable to set {setting}",
+    ReadFailed = "Error reading samples from device",
+    Io { source: std::io::Error } = "Unable to write the capture: {source}",
+    SigMf { source: SigMfError } = "{source}",
+    Duration { input: String } = "The duration must be a positive number of seconds, got {input}",
+}
+
+#[derive(Parser, Debug, Clone, Default)]
+#[command(
+    name = "Sample Grabber",
+    author,
+    version,
+    about,
+    long_about = "Sample Grabber is a simple program that enables you to grab raw samples from an RTLSDR device. \
+    The samples are written as a SigMF recording, with a .sigmf-meta file next to the capture describing it."
+)]
+struct Input {
+    /// Set the log level. debug, trace, info are valid options. Info is default.
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    logging: u8,
+    /// Serial number of the device to record from
+    #[clap(short, long, value_parser)]
+    serial: String,
+    /// Center frequency in Hz
+    #[clap(short, long, value_parser, default_value = "131000000")]
+    center_freq: u32,
+    /// Tuner gain in tenths of a dB
+    #[clap(short, long, value_parser, default_value = "421")]
+    gain: i32,
+    #[clap(short, long, value_parser, default_value = "0")]
+    ppm: i32,
+    /// Number of buffers to record. Default is 100.
+    #[clap(
+        short,
+        long,
+        visible_alias = "num-samples",
+        value_parser,
+        conflicts_with = "duration"
+    )]
+    buffers: Option<u32>,
+    /// Number of seconds to record, instead of a number of buffers
+    #[clap(short, long, value_parser = parse_duration)]
+    duration: Option<f64>,
+    /// The capture. The metadata is written next to it, with the .sigmf-meta extension
+    #[clap(short, long, value_parser, default_value = "acars.sigmf-data")]
+    output_file: PathBuf,
+    #[clap(short, long, value_parser, default_value = "160")]
+    rtl_mult: u32,
+}
+
+impl Input {
+    /// The number of buffers to record, rounding a duration up to whole buffers
+    // The duration is a few minutes at most, which is well within range
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    fn buffers(&self) -> u32 {
+        self.duration.map_or_else(
+            || self.buffers.unwrap_or(DEFAULT_BUFFERS),
+            |duration| (duration * INTRATE as f64 / RTLOUTBUFSZ as f64).ceil() as u32,
+        )
+    }
+}
+
+fn parse_duration(input: &str) -> Result<f64, GrabberError> {
+    match input.parse::<f64>() {
+        Ok(duration) if duration.is_finite() && duration > 0.0 => Ok(duration),
+        _ => Err(GrabberError::Duration {
+            input: input.to_string(),
+        }),
+    }
+}
+
+// The decoder rate and buffer size are small constants
+#[allow(clippy::cast_possible_truncation)]
+fn grab(args: &Input) -> Result<(), GrabberError> {
+    let sample_rate = INTRATE as u32 * args.rtl_mult;
+    let buffer_len = RTLOUTBUFSZ as u32 * args.rtl_mult * 2; // rtl buf z * rtl_mult * 2
+    let mut buffers = args.buffers();
+
+    let device = find_device(&args.serial).ok_or_else(|| GrabberError::DeviceNotFound {
+        serial: args.serial.clone(),
+    })?;
+
+    let (mut ctl, mut reader) =
+        rtlsdr_mt::open(device.index()).map_err(|()| GrabberError::OpenFailed {
+            serial: args.serial.clone(),
+        })?;
+    let setting_failed = |setting: &str| GrabberError::SettingFailed {
+        setting: setting.to_string(),
+    };
+
+    ctl.disable_agc()
+        .map_err(|()| setting_failed("manual gain mode"))?;
+    ctl.set_tuner_gain(args.gain)
+        .map_err(|()| setting_failed("tuner gain"))?;
+    ctl.set_center_freq(args.center_freq)
+        .map_err(|()| setting_failed("center frequency"))?;
+    ctl.set_ppm(args.ppm).map_err(|()| setting_failed("ppm"))?;
+    ctl.set_sample_rate(sample_rate)
+        .map_err(|()| setting_failed("sample rate"))?;
+
+    let mut metadata = SigMfMetadata::new(
+        DATATYPE_CU8,
+        f64::from(sample_rate),
+        f64::from(args.center_freq),
+    );
+    metadata.global.hw = Some(format!(
+        "{} {} SN: {}",
+        device.vendor(),
+        device.product(),
+        device.serial()
+    ));
+    metadata.global.recorder = Some(format!("sample-grabber {}", env!("CARGO_PKG_VERSION")));
+    // The device reports the gain it actually used, in tenths of a dB
+    #[allow(clippy::cast_precision_loss)]
+    let gain = ctl.tuner_gain() as f32 / 10.0;
+    metadata.global.gain = Some(gain);
+    metadata.global.ppm = Some(args.ppm);
+    metadata.captures[0].datetime = Some(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true));
+
+    // Written first, so a capture cut short by an error is still described
+    let meta_path = meta_path(&args.output_file);
+    metadata.write(&meta_path)?;
+    info!("Wrote metadata to {}", meta_path.display());
+
+    info!(
+        "Recording {} buffers from {} at {} Hz to {}",
+        buffers,
+        args.serial,
+        args.center_freq,
+        args.output_file.display()
+    );
+
+    let mut file = BufWriter::new(File::create(&args.output_file)?);
+    let mut write_error = None;
+
+    reader
+        .read_async(4, buffer_len, |bytes| {
+            if buffers == 0 || write_error.is_some() {
+                ctl.cancel_async_read();
+                return;
+            }
+
+            buffers -= 1;
+            if let Err(e) = file.write_all(bytes) {
+                write_error = Some(e);
+            }
+        })
+        .map_err(|()| GrabberError::ReadFailed)?;
+
+    if let Some(e) = write_error {
+        return Err(e.into());
+    }
+    file.flush()?;
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = Input::parse();
+    args.logging.enable_logging();
+
+    match grab(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}